@@ -0,0 +1,1380 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::logger::{Compression, HistoryFormat};
+use crate::sealed::{self, Seal};
+use crate::secrets::{self, EncryptedSecret, DEFAULT_MASTER_ENV};
+
+pub const APP_DIR: &str = "rustchat-cli";
+const CONFIG_FILE: &str = "config.toml";
+const PROJECT_FILE: &str = ".rustchat.toml";
+
+static CONFIG_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct AppConfig {
+    pub default_provider: Option<String>,
+    #[serde(default)]
+    pub ui: UiConfig,
+    #[serde(default)]
+    pub sessions: SessionsConfig,
+    #[serde(default, skip_serializing_if = "UsageConfig::is_default")]
+    pub usage: UsageConfig,
+    #[serde(default, skip_serializing_if = "HooksConfig::is_default")]
+    pub hooks: HooksConfig,
+    #[serde(default, skip_serializing_if = "ToolsConfig::is_default")]
+    pub tools: ToolsConfig,
+    #[serde(default, skip_serializing_if = "KnowledgeConfig::is_default")]
+    pub knowledge: KnowledgeConfig,
+    #[serde(default, skip_serializing_if = "TelemetryConfig::is_default")]
+    pub telemetry: TelemetryConfig,
+    #[serde(default, skip_serializing_if = "AuditConfig::is_default")]
+    pub audit: AuditConfig,
+    #[serde(default, skip_serializing_if = "CacheConfig::is_default")]
+    pub cache: CacheConfig,
+    #[serde(default, skip_serializing_if = "ContextConfig::is_default")]
+    pub context: ContextConfig,
+    #[serde(default, skip_serializing_if = "WebhookConfig::is_default")]
+    pub webhook: WebhookConfig,
+    /// `[webhooks.<name>]`: targets picked with `--webhook <name>`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub webhooks: BTreeMap<String, WebhookTarget>,
+    #[serde(default)]
+    pub providers: BTreeMap<String, ProviderConfig>,
+    /// Named presets selected with `--profile`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, ProfileConfig>,
+    /// `[alias]`: shortcuts such as `explain = "message --profile teacher"`,
+    /// expanded before the command line is parsed
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub alias: BTreeMap<String, String>,
+    /// `.rustchat.toml` found above the working directory; never saved back
+    #[serde(skip)]
+    pub project: Option<ProjectConfig>,
+    /// Set when the file itself is encrypted (`config encrypt`)
+    #[serde(skip)]
+    pub seal: Option<Seal>,
+}
+
+/// Per-project overrides from the nearest `.rustchat.toml`, so a repository
+/// can pin its own provider, model and persona.
+#[derive(Debug, Clone, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectConfig {
+    #[serde(default)]
+    pub default_provider: Option<String>,
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub system: Option<String>,
+    /// Relative paths are taken from the directory holding the file
+    #[serde(default)]
+    pub history_dir: Option<PathBuf>,
+    #[serde(skip)]
+    pub path: PathBuf,
+}
+
+impl ProjectConfig {
+    /// The closest `.rustchat.toml` in `dir` or any of its parents.
+    pub fn discover(dir: &Path) -> Result<Option<Self>> {
+        let Some(path) = dir
+            .ancestors()
+            .map(|dir| dir.join(PROJECT_FILE))
+            .find(|path| path.is_file())
+        else {
+            return Ok(None);
+        };
+        let data = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let mut project: ProjectConfig =
+            toml::from_str(&data).with_context(|| format!("failed to parse {}", path.display()))?;
+        if let (Some(history_dir), Some(root)) = (&project.history_dir, path.parent()) {
+            project.history_dir = Some(root.join(history_dir));
+        }
+        project.path = path;
+        Ok(Some(project))
+    }
+}
+
+/// `[profiles.<name>]`: provider, model, system prompt, temperature and
+/// webhook target chosen together by `--profile <name>`. Flags given
+/// alongside it still win.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ProfileConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    /// `[webhooks]` target posted to unless `--webhook` or `--webhook-url`
+    /// names another
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webhook: Option<String>,
+}
+
+/// Presentation defaults shared by `chat` and `message`; CLI flags win.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct UiConfig {
+    /// Print a latency/token/cost line after every reply
+    #[serde(default)]
+    pub stats: bool,
+    /// REPL prompt template; supports {provider}, {model}, {turn} and {tokens}
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+    /// Notify when a reply takes longer than this many seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notify_after_secs: Option<u64>,
+    /// How to notify: `desktop`, `bell`, or `both`
+    #[serde(default)]
+    pub notify_method: NotifyMethod,
+    /// Word-wrap streamed replies to the terminal width; on unless `false`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wrap: Option<bool>,
+    /// Line editor mode for the REPL (`emacs` or `vi`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edit_mode: Option<EditModeSetting>,
+    /// Extra REPL key bindings, e.g. `"alt-enter" = "newline"`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub keybindings: BTreeMap<String, KeyAction>,
+}
+
+/// Where auto-saved sessions live; `--save <file>` always writes a plain file.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct SessionsConfig {
+    /// `files` (one JSON/Markdown file per session) or `sqlite`
+    #[serde(default)]
+    pub store: StoreKind,
+    /// SQLite database path; defaults to `sessions.db` in the data directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database: Option<PathBuf>,
+    /// Pruning applied after each auto-save
+    #[serde(default, skip_serializing_if = "RetentionConfig::is_unset")]
+    pub retention: RetentionConfig,
+    /// Object storage every finished session is also uploaded to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote: Option<RemoteConfig>,
+}
+
+/// `[usage]`: the local log of each request's tokens and cost that `usage
+/// report` reads.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct UsageConfig {
+    /// Set to false to stop recording
+    #[serde(default = "default_usage_track")]
+    pub track: bool,
+    /// JSONL log; defaults to `usage.jsonl` in the data directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    /// Spending limits by provider name, checked against the log
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub budgets: BTreeMap<String, BudgetConfig>,
+}
+
+impl Default for UsageConfig {
+    fn default() -> Self {
+        Self {
+            track: true,
+            path: None,
+            budgets: BTreeMap::new(),
+        }
+    }
+}
+
+/// `[usage.budgets.<provider>]`: USD limits per local calendar day and month.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BudgetConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monthly: Option<f64>,
+    #[serde(default)]
+    pub enforce: BudgetEnforcement,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BudgetEnforcement {
+    /// Warn once the limit is reached, but keep sending
+    #[default]
+    Soft,
+    /// Refuse new requests until the day or month is over
+    Hard,
+}
+
+impl UsageConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// `[hooks]`: shell commands that see each request and reply as JSON on
+/// stdin and may print a changed copy on stdout.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct HooksConfig {
+    /// Runs before a request is sent and may rewrite its messages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_request: Option<String>,
+    /// Runs on each reply and may rewrite its text
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_response: Option<String>,
+}
+
+impl HooksConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// `[tools]`: whether tool calls the model makes in `chat --tools` run.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct ToolsConfig {
+    /// Policy for every tool without its own entry in `per_tool`
+    #[serde(default)]
+    pub approval: ApprovalPolicy,
+    /// Policies by tool name (`shell`, `read_file`, plugin tools, ...)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub per_tool: BTreeMap<String, ApprovalPolicy>,
+    /// Plugin tools trusted to only read, which `read-only` approval runs
+    /// unasked; a plugin's own word is not enough
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trusted_read_only: Vec<String>,
+}
+
+impl ToolsConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn policy_for(&self, tool: &str) -> ApprovalPolicy {
+        self.per_tool.get(tool).copied().unwrap_or(self.approval)
+    }
+
+    pub fn trusts_read_only(&self, tool: &str) -> bool {
+        self.trusted_read_only.iter().any(|trusted| trusted == tool)
+    }
+}
+
+/// `[knowledge]`: where `index add` keeps embedded document chunks.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct KnowledgeConfig {
+    /// Backend for new indexes: `sqlite` (the default), `qdrant` or `pgvector`
+    #[serde(default)]
+    pub store: VectorStoreKind,
+    /// Index database; defaults to `indexes.db` in the data directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database: Option<PathBuf>,
+    /// Qdrant REST URL, or the PostgREST URL in front of pgvector
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Environment variable holding the API key sent to `url`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_env: Option<String>,
+}
+
+impl KnowledgeConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// `[telemetry]`: where spans for commands, requests and retries are sent.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct TelemetryConfig {
+    /// OTLP/HTTP collector, e.g. `http://localhost:4318`; spans are posted to
+    /// `<endpoint>/v1/traces`. Unset, `OTEL_EXPORTER_OTLP_ENDPOINT` is used
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+    /// `service.name` on every span; defaults to `OTEL_SERVICE_NAME`, then
+    /// `rustchat-cli`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service_name: Option<String>,
+    /// Extra headers for the collector; `OTEL_EXPORTER_OTLP_HEADERS` overrides
+    /// a header of the same name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+}
+
+impl TelemetryConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Calls `f` on every header value, since collectors take their
+    /// credentials in headers; a header set to `None` is dropped.
+    fn for_each_secret(&mut self, mut f: impl FnMut(&mut Option<String>)) {
+        self.headers.retain(|_, value| {
+            let mut secret = Some(std::mem::take(value));
+            f(&mut secret);
+            secret.map(|secret| *value = secret).is_some()
+        });
+    }
+}
+
+/// `[audit]`: an append-only log of every request sent, for orgs that must
+/// account for what went to which model. Off unless enabled.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct AuditConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// JSONL log; defaults to `audit.jsonl` in the data directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    /// Log the full system prompt and messages, not just their SHA-256
+    #[serde(default)]
+    pub include_prompts: bool,
+}
+
+impl AuditConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// `[cache]`: replies kept on disk so an identical request is answered
+/// without being sent again. Off unless `ttl` is set.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct CacheConfig {
+    /// How long a reply stays fresh, e.g. `"1h"` or `"7d"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<String>,
+    /// Defaults to `responses/` in the cache directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dir: Option<PathBuf>,
+}
+
+impl CacheConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// `[context]`: how a long chat history is cut down before each request so it
+/// fits the model's context window.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct ContextConfig {
+    #[serde(default)]
+    pub strategy: ContextStrategy,
+    /// Messages kept by `sliding-window` and `keep-system-last-n` (default 20)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_last: Option<usize>,
+    /// Token budget for `token-budget`; for `drop-oldest` and `summarize` it
+    /// replaces the model's known context window
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<usize>,
+    /// Fold older turns into a summary once the history nears the window
+    #[serde(default)]
+    pub summarize: bool,
+    /// Model that writes the summary; defaults to the chat model
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary_model: Option<String>,
+    /// Share of the window that triggers a summary (default 0.75)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summarize_at: Option<f64>,
+}
+
+impl ContextConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ContextStrategy {
+    /// Send the whole history
+    #[default]
+    None,
+    /// The last `keep_last` messages, the system prompt counting as the first
+    SlidingWindow,
+    /// Drop the oldest turns only once the history outgrows the context window
+    DropOldest,
+    /// The system prompt plus the last `keep_last` messages
+    KeepSystemLastN,
+    /// The newest turns that fit in `max_tokens`, system prompt included
+    TokenBudget,
+}
+
+impl ContextStrategy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::SlidingWindow => "sliding-window",
+            Self::DropOldest => "drop-oldest",
+            Self::KeepSystemLastN => "keep-system-last-n",
+            Self::TokenBudget => "token-budget",
+        }
+    }
+}
+
+/// `[webhook]`: how `--webhook-url` posts are authenticated and retried.
+/// Each secret can be given inline or through the environment variable named
+/// by its `_env` field.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct WebhookConfig {
+    /// Sent as `Authorization: Bearer <token>`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bearer_token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bearer_token_env: Option<String>,
+    /// Header the API key goes in; defaults to `X-API-Key`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_header: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_env: Option<String>,
+    /// Key for an HMAC-SHA256 signature over the body, sent as `sha256=<hex>`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing_secret: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing_secret_env: Option<String>,
+    /// Header the signature goes in; defaults to `X-Rustchat-Signature`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature_header: Option<String>,
+    /// minijinja template for the body, in place of the transcript in the
+    /// `--save-format` format
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<PathBuf>,
+    /// Content type of templated bodies; defaults to `application/json`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    /// Further attempts after a failed post; defaults to 3
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+    /// Where undelivered transcripts wait for the next run; defaults to
+    /// `webhook-queue/` in the data directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queue_dir: Option<PathBuf>,
+}
+
+impl WebhookConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Calls `f` on every secret stored inline.
+    fn for_each_secret(&mut self, mut f: impl FnMut(&mut Option<String>)) {
+        f(&mut self.bearer_token);
+        f(&mut self.api_key);
+        f(&mut self.signing_secret);
+    }
+
+    /// These settings, with whatever they leave unset taken from `base`.
+    pub fn with_defaults(&self, base: &WebhookConfig) -> WebhookConfig {
+        let or = |own: &Option<String>, base: &Option<String>| own.clone().or(base.clone());
+        WebhookConfig {
+            bearer_token: or(&self.bearer_token, &base.bearer_token),
+            bearer_token_env: or(&self.bearer_token_env, &base.bearer_token_env),
+            api_key_header: or(&self.api_key_header, &base.api_key_header),
+            api_key: or(&self.api_key, &base.api_key),
+            api_key_env: or(&self.api_key_env, &base.api_key_env),
+            signing_secret: or(&self.signing_secret, &base.signing_secret),
+            signing_secret_env: or(&self.signing_secret_env, &base.signing_secret_env),
+            signature_header: or(&self.signature_header, &base.signature_header),
+            template: self.template.clone().or(base.template.clone()),
+            content_type: or(&self.content_type, &base.content_type),
+            retries: self.retries.or(base.retries),
+            queue_dir: self.queue_dir.clone().or(base.queue_dir.clone()),
+        }
+    }
+}
+
+/// `[webhooks.<name>]`: a receiver with its own URL, body format and events.
+/// Settings it leaves out come from `[webhook]`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct WebhookTarget {
+    pub url: String,
+    /// Body format; defaults to `--save-format`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<HistoryFormat>,
+    /// What gets posted; defaults to `["session"]`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<WebhookEvent>,
+    #[serde(flatten)]
+    pub settings: WebhookConfig,
+}
+
+/// What a webhook post carries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookEvent {
+    /// The whole transcript of a finished session
+    #[default]
+    Session,
+    /// One completed exchange of a chat session, as it happens
+    Turn,
+}
+
+impl WebhookEvent {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WebhookEvent::Session => "session",
+            WebhookEvent::Turn => "turn",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum VectorStoreKind {
+    #[default]
+    Sqlite,
+    Qdrant,
+    Pgvector,
+}
+
+impl VectorStoreKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Sqlite => "sqlite",
+            Self::Qdrant => "qdrant",
+            Self::Pgvector => "pgvector",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "sqlite" => Some(Self::Sqlite),
+            "qdrant" => Some(Self::Qdrant),
+            "pgvector" => Some(Self::Pgvector),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ApprovalPolicy {
+    /// Confirm every call on the terminal
+    #[default]
+    Ask,
+    /// Run calls that only read without asking; confirm the rest
+    ReadOnly,
+    /// Refuse every call
+    Deny,
+}
+
+impl ApprovalPolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "ask" => Some(Self::Ask),
+            "read-only" => Some(Self::ReadOnly),
+            "deny" => Some(Self::Deny),
+            _ => None,
+        }
+    }
+}
+
+fn default_usage_track() -> bool {
+    true
+}
+
+/// `[sessions.remote]`: an S3-compatible bucket, a GCS bucket or a WebDAV
+/// collection, selected with `kind`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteConfig {
+    #[serde(flatten)]
+    pub target: RemoteTarget,
+    /// Prepended to object names, e.g. `"laptop/"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    /// Further attempts after a failed upload
+    #[serde(default = "default_remote_retries")]
+    pub retries: u32,
+}
+
+fn default_remote_retries() -> u32 {
+    3
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum RemoteTarget {
+    S3 {
+        bucket: String,
+        #[serde(default = "default_s3_region")]
+        region: String,
+        /// Custom endpoint (MinIO, R2, ...); buckets are then addressed by path
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        endpoint: Option<String>,
+        /// Falls back to `AWS_ACCESS_KEY_ID` / `AWS_SECRET_ACCESS_KEY`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        access_key_id: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        secret_access_key: Option<String>,
+    },
+    Gcs {
+        bucket: String,
+        service_account_file: PathBuf,
+    },
+    Webdav {
+        /// Collection URL the transcripts are PUT into
+        url: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        username: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        password: Option<String>,
+    },
+}
+
+impl RemoteTarget {
+    /// Calls `f` on every credential stored in the target.
+    fn for_each_secret(&mut self, mut f: impl FnMut(&mut Option<String>)) {
+        match self {
+            RemoteTarget::S3 {
+                access_key_id,
+                secret_access_key,
+                ..
+            } => {
+                f(access_key_id);
+                f(secret_access_key);
+            }
+            RemoteTarget::Gcs { .. } => {}
+            RemoteTarget::Webdav { password, .. } => f(password),
+        }
+    }
+}
+
+fn default_s3_region() -> String {
+    "us-east-1".to_string()
+}
+
+/// `[sessions.retention]`: sessions older than `older_than` (e.g. `"90d"`) are
+/// deleted, then the oldest ones until the total fits in `max_size` (`"200MB"`).
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct RetentionConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub older_than: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<String>,
+    /// Compress session files older than this (e.g. `"30d"`); file store only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compress_after: Option<String>,
+    /// `gzip` or `zstd`
+    #[serde(default)]
+    pub compression: Compression,
+}
+
+impl RetentionConfig {
+    fn is_unset(&self) -> bool {
+        self.older_than.is_none() && self.max_size.is_none() && self.compress_after.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreKind {
+    #[default]
+    Files,
+    Sqlite,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifyMethod {
+    Desktop,
+    Bell,
+    #[default]
+    Both,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EditModeSetting {
+    Emacs,
+    Vi,
+}
+
+/// Actions that can be bound to keys in the REPL line editor.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyAction {
+    /// Insert a newline instead of submitting
+    Newline,
+    /// Submit the (possibly multi-line) input
+    Submit,
+    /// Open the current input in `$VISUAL`/`$EDITOR`
+    Editor,
+}
+
+impl AppConfig {
+    pub fn load() -> Result<Self> {
+        Self::load_from(&config_path()?)
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self {
+                project: load_project(),
+                ..Self::default()
+            });
+        }
+        let data = fs::read_to_string(path)
+            .with_context(|| format!("failed to read config at {}", path.display()))?;
+        let (data, seal) = match sealed::open(&data)? {
+            Some((plaintext, seal)) => (plaintext, Some(seal)),
+            None => (data, None),
+        };
+        let mut cfg = Self::from_toml(&data)?;
+        cfg.seal = seal;
+        Ok(cfg)
+    }
+
+    /// A config from plaintext TOML, with the project config for the current
+    /// directory attached as `load` would.
+    pub fn from_toml(data: &str) -> Result<Self> {
+        let mut cfg: AppConfig =
+            toml::from_str(data).with_context(|| "failed to parse config file (toml)")?;
+        cfg.project = load_project();
+        Ok(cfg)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        self.save_to(&config_path()?)
+    }
+
+    pub fn save_to(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create config dir {}", parent.display()))?;
+        }
+        let mut data = toml::to_string_pretty(self)?;
+        if let Some(seal) = &self.seal {
+            data = seal.seal(&data)?;
+        }
+        crate::utils::write_atomic(path, data.as_bytes())
+            .with_context(|| format!("failed to write config at {}", path.display()))?;
+        Ok(())
+    }
+
+    /// A copy of the config for `config export`, with API keys handled as
+    /// `secrets` says.
+    pub fn export(&self, secrets: &SecretExport) -> Result<AppConfig> {
+        let mut exported = self.clone();
+        for (name, provider) in &mut exported.providers {
+            let (api_key, encrypted_api_key) = provider.stored_secret_mut();
+            match secrets {
+                SecretExport::Keep => {}
+                SecretExport::Redact => {
+                    *api_key = None;
+                    *encrypted_api_key = None;
+                }
+                SecretExport::Reencrypt {
+                    passphrase,
+                    env_label,
+                    new_passphrase,
+                } => {
+                    *encrypted_api_key = secrets::reencrypt_secret(
+                        api_key.as_deref(),
+                        encrypted_api_key.as_ref(),
+                        *passphrase,
+                        env_label,
+                        new_passphrase,
+                    )
+                    .with_context(|| format!("cannot re-encrypt the API key of '{name}'"))?;
+                    *api_key = None;
+                }
+            }
+        }
+        if matches!(secrets, SecretExport::Redact) {
+            if let Some(remote) = &mut exported.sessions.remote {
+                remote.target.for_each_secret(|secret| *secret = None);
+            }
+            exported.webhook.for_each_secret(|secret| *secret = None);
+            exported.telemetry.for_each_secret(|secret| *secret = None);
+            for target in exported.webhooks.values_mut() {
+                target.settings.for_each_secret(|secret| *secret = None);
+            }
+        }
+        Ok(exported)
+    }
+
+    /// Whether the file itself holds credentials for the remote session store,
+    /// a webhook target or the telemetry collector, as opposed to naming an
+    /// environment variable.
+    pub fn stores_service_credentials(&self) -> bool {
+        let mut found = false;
+        let mut check = |secret: &mut Option<String>| found |= secret.is_some();
+        let mut copy = self.clone();
+        if let Some(remote) = &mut copy.sessions.remote {
+            remote.target.for_each_secret(&mut check);
+        }
+        copy.webhook.for_each_secret(&mut check);
+        copy.telemetry.for_each_secret(&mut check);
+        for target in copy.webhooks.values_mut() {
+            target.settings.for_each_secret(&mut check);
+        }
+        found
+    }
+
+    /// A copy for `config show`: keys and credentials are cut down to their
+    /// last four characters and encrypted blobs to a marker.
+    pub fn masked(&self) -> AppConfig {
+        let mut masked = self.clone();
+        for provider in masked.providers.values_mut() {
+            let (api_key, encrypted_api_key) = provider.stored_secret_mut();
+            mask_secret(api_key);
+            if let Some(encrypted) = encrypted_api_key {
+                for field in [
+                    &mut encrypted.salt,
+                    &mut encrypted.nonce,
+                    &mut encrypted.ciphertext,
+                ] {
+                    *field = mask(field);
+                }
+            }
+        }
+        if let Some(remote) = &mut masked.sessions.remote {
+            remote.target.for_each_secret(mask_secret);
+        }
+        masked.webhook.for_each_secret(mask_secret);
+        masked.telemetry.for_each_secret(mask_secret);
+        for target in masked.webhooks.values_mut() {
+            target.settings.for_each_secret(mask_secret);
+        }
+        masked
+    }
+
+    /// Re-encrypts every encrypted API key under `new_passphrase` and returns
+    /// how many there were; a config sealed with the passphrase is resealed
+    /// under the new one as well. Nothing changes unless all keys decrypt.
+    pub fn rotate_passphrase(&mut self, passphrase: &str, new_passphrase: &str) -> Result<usize> {
+        let mut rotated = self.providers.clone();
+        let mut count = 0;
+        for (name, provider) in &mut rotated {
+            let (_, encrypted_api_key) = provider.stored_secret_mut();
+            if encrypted_api_key.is_none() {
+                continue;
+            }
+            *encrypted_api_key = secrets::reencrypt_secret(
+                None,
+                encrypted_api_key.as_ref(),
+                Some(passphrase),
+                DEFAULT_MASTER_ENV,
+                new_passphrase,
+            )
+            .with_context(|| format!("cannot decrypt the API key of '{name}'"))?;
+            count += 1;
+        }
+        self.providers = rotated;
+        if self.sealed_with_passphrase() {
+            self.seal = Some(Seal::with_passphrase(new_passphrase.to_string()));
+        }
+        Ok(count)
+    }
+
+    /// Whether the whole file is encrypted with the master passphrase.
+    pub fn sealed_with_passphrase(&self) -> bool {
+        self.seal
+            .as_ref()
+            .is_some_and(|seal| seal.key == sealed::SealKey::Passphrase)
+    }
+
+    /// Merges providers and profiles from an exported config, replacing
+    /// entries with the same name. Returns how many of each were imported.
+    pub fn import(&mut self, imported: AppConfig) -> (usize, usize) {
+        let counts = (imported.providers.len(), imported.profiles.len());
+        if self.default_provider.is_none() {
+            self.default_provider = imported.default_provider;
+        }
+        self.providers.extend(imported.providers);
+        self.profiles.extend(imported.profiles);
+        self.alias.extend(imported.alias);
+        counts
+    }
+
+    pub fn upsert_provider(&mut self, name: String, cfg: ProviderConfig) {
+        self.providers.insert(name, cfg);
+    }
+
+    pub fn remove_provider(&mut self, name: &str) -> bool {
+        self.providers.remove(name).is_some()
+    }
+
+    pub fn require_provider(&self, provider: &str) -> Result<&ProviderConfig> {
+        self.providers
+            .get(provider)
+            .ok_or_else(|| anyhow!("provider '{provider}' not found in config"))
+    }
+
+    pub fn infer_default_provider(&self, explicit: &Option<String>) -> Result<String> {
+        if let Some(name) = explicit {
+            return Ok(name.clone());
+        }
+        self.project
+            .as_ref()
+            .and_then(|project| project.default_provider.clone())
+            .or_else(|| self.default_provider.clone())
+            .ok_or_else(|| anyhow!("no provider selected and no default configured"))
+    }
+}
+
+/// `sk-...abcd` becomes `****abcd`; anything shorter than eight characters is
+/// hidden completely.
+pub fn mask(secret: &str) -> String {
+    let chars: Vec<char> = secret.chars().collect();
+    if chars.len() < 8 {
+        return "****".to_string();
+    }
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("****{tail}")
+}
+
+fn mask_secret(secret: &mut Option<String>) {
+    if let Some(value) = secret {
+        *value = mask(value);
+    }
+}
+
+/// A broken project file is reported but never stops the global config from
+/// loading.
+fn load_project() -> Option<ProjectConfig> {
+    let cwd = std::env::current_dir().ok()?;
+    ProjectConfig::discover(&cwd).unwrap_or_else(|err| {
+        tracing::warn!("ignoring project config: {err:#}");
+        None
+    })
+}
+
+/// Points every later `config_path()` at `path` (`--config` / `RUSTCHAT_CONFIG`).
+pub fn use_config_path(path: PathBuf) {
+    let _ = CONFIG_OVERRIDE.set(path);
+}
+
+/// Whether the config file on disk is encrypted, so a failure to open it must
+/// not fall back to an empty config that a later save would write over it.
+pub fn config_is_encrypted() -> bool {
+    config_path()
+        .and_then(|path| Ok(fs::read_to_string(path)?))
+        .is_ok_and(|data| sealed::is_sealed(&data))
+}
+
+pub fn config_path() -> Result<PathBuf> {
+    if let Some(path) = CONFIG_OVERRIDE.get() {
+        return Ok(path.clone());
+    }
+    let base = dirs::config_dir().ok_or_else(|| anyhow!("unable to locate platform config dir"))?;
+    Ok(base.join(APP_DIR).join(CONFIG_FILE))
+}
+
+/// How `config export` treats API keys stored in the config.
+pub enum SecretExport<'a> {
+    /// Copy them as they are
+    Keep,
+    /// Drop plain and encrypted keys (and remote upload credentials)
+    Redact,
+    /// Encrypt every key under a new passphrase
+    Reencrypt {
+        passphrase: Option<&'a str>,
+        env_label: &'a str,
+        new_passphrase: &'a str,
+    },
+}
+
+pub fn default_cache_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join(APP_DIR))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ProviderConfig {
+    Google(GoogleProviderConfig),
+    Anthropic(ApiKeyProviderConfig),
+    Openai(ApiKeyProviderConfig),
+}
+
+impl ProviderConfig {
+    pub fn default_model(&self) -> Option<&str> {
+        match self {
+            ProviderConfig::Google(cfg) => cfg.default_model.as_deref(),
+            ProviderConfig::Anthropic(cfg) | ProviderConfig::Openai(cfg) => {
+                cfg.default_model.as_deref()
+            }
+        }
+    }
+
+    pub fn default_system(&self) -> Option<&str> {
+        match self {
+            ProviderConfig::Google(cfg) => cfg.system.as_deref(),
+            ProviderConfig::Anthropic(cfg) | ProviderConfig::Openai(cfg) => cfg.system.as_deref(),
+        }
+    }
+
+    pub fn has_encrypted_secret(&self) -> bool {
+        match self {
+            ProviderConfig::Google(cfg) => cfg.encrypted_api_key.is_some(),
+            ProviderConfig::Anthropic(cfg) | ProviderConfig::Openai(cfg) => {
+                cfg.encrypted_api_key.is_some()
+            }
+        }
+    }
+
+    fn stored_secret_mut(&mut self) -> (&mut Option<String>, &mut Option<EncryptedSecret>) {
+        match self {
+            ProviderConfig::Google(cfg) => (&mut cfg.api_key, &mut cfg.encrypted_api_key),
+            ProviderConfig::Anthropic(cfg) | ProviderConfig::Openai(cfg) => {
+                (&mut cfg.api_key, &mut cfg.encrypted_api_key)
+            }
+        }
+    }
+
+    pub fn kind(&self) -> ProviderKind {
+        match self {
+            ProviderConfig::Google(_) => ProviderKind::Google,
+            ProviderConfig::Anthropic(_) => ProviderKind::Anthropic,
+            ProviderConfig::Openai(_) => ProviderKind::Openai,
+        }
+    }
+
+    /// Where this provider's requests go: its kind with the base URL, or the
+    /// project and location for Google. Two providers with the same name in
+    /// different configs only share cached or recorded replies if this agrees.
+    pub fn endpoint(&self) -> serde_json::Value {
+        match self {
+            ProviderConfig::Google(cfg) => serde_json::json!({
+                "kind": "google",
+                "project_id": cfg.project_id,
+                "location": cfg.location,
+            }),
+            ProviderConfig::Anthropic(cfg) => {
+                serde_json::json!({"kind": "anthropic", "base_url": cfg.base_url})
+            }
+            ProviderConfig::Openai(cfg) => {
+                serde_json::json!({"kind": "openai", "base_url": cfg.base_url})
+            }
+        }
+    }
+
+    /// `self` with the fields set in `update` (of the same kind) applied. A
+    /// new key replaces every stored form of the old one.
+    pub fn updated_with(&self, update: ProviderConfig, new_key: bool) -> ProviderConfig {
+        match (self.clone(), update) {
+            (ProviderConfig::Google(mut cfg), ProviderConfig::Google(update)) => {
+                if new_key {
+                    cfg.api_key = update.api_key;
+                    cfg.encrypted_api_key = update.encrypted_api_key;
+                    cfg.api_key_env = update.api_key_env;
+                    cfg.api_key_cmd = update.api_key_cmd;
+                    cfg.keychain = update.keychain;
+                }
+                cfg.service_account_file = update.service_account_file.or(cfg.service_account_file);
+                cfg.project_id = update.project_id.or(cfg.project_id);
+                cfg.location = update.location.or(cfg.location);
+                cfg.default_model = update.default_model.or(cfg.default_model);
+                cfg.system = update.system.or(cfg.system);
+                ProviderConfig::Google(cfg)
+            }
+            (ProviderConfig::Anthropic(cfg), ProviderConfig::Anthropic(update)) => {
+                ProviderConfig::Anthropic(cfg.updated_with(update, new_key))
+            }
+            (ProviderConfig::Openai(cfg), ProviderConfig::Openai(update)) => {
+                ProviderConfig::Openai(cfg.updated_with(update, new_key))
+            }
+            (_, update) => update,
+        }
+    }
+
+    pub fn uses_keychain(&self) -> bool {
+        match self {
+            ProviderConfig::Google(cfg) => cfg.keychain,
+            ProviderConfig::Anthropic(cfg) | ProviderConfig::Openai(cfg) => cfg.keychain,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct GoogleProviderConfig {
+    pub service_account_file: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encrypted_api_key: Option<EncryptedSecret>,
+    /// Environment variable holding the API key, read when the provider is built
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_env: Option<String>,
+    /// Shell command printing the API key (1Password, pass, vault, ...)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_cmd: Option<String>,
+    /// The API key is kept in the OS keychain under the provider's name
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub keychain: bool,
+    pub project_id: Option<String>,
+    pub location: Option<String>,
+    pub default_model: Option<String>,
+    /// System prompt used when `--system` is not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ApiKeyProviderConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encrypted_api_key: Option<EncryptedSecret>,
+    /// Environment variable holding the API key, read when the provider is built
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_env: Option<String>,
+    /// Shell command printing the API key (1Password, pass, vault, ...)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_cmd: Option<String>,
+    /// The API key is kept in the OS keychain under the provider's name
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub keychain: bool,
+    pub base_url: Option<String>,
+    pub default_model: Option<String>,
+    /// System prompt used when `--system` is not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+}
+
+impl ApiKeyProviderConfig {
+    fn updated_with(mut self, update: ApiKeyProviderConfig, new_key: bool) -> Self {
+        if new_key {
+            self.api_key = update.api_key;
+            self.encrypted_api_key = update.encrypted_api_key;
+            self.api_key_env = update.api_key_env;
+            self.api_key_cmd = update.api_key_cmd;
+            self.keychain = update.keychain;
+        }
+        self.base_url = update.base_url.or(self.base_url);
+        self.default_model = update.default_model.or(self.default_model);
+        self.system = update.system.or(self.system);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProviderKind {
+    Google,
+    Anthropic,
+    Openai,
+}
+
+impl ProviderKind {
+    /// The kind a provider named after one, e.g. `openai`, has.
+    pub fn infer(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "google" => Some(Self::Google),
+            "anthropic" => Some(Self::Anthropic),
+            "openai" => Some(Self::Openai),
+            _ => None,
+        }
+    }
+}
+
+pub fn ensure_permissions(path: &Path) -> Result<()> {
+    #[cfg(not(unix))]
+    let _ = path;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        const PERM: u32 = 0o600;
+        let metadata = fs::metadata(path)?;
+        if metadata.permissions().mode() & 0o777 != PERM {
+            let mut perm = metadata.permissions();
+            perm.set_mode(PERM);
+            fs::set_permissions(path, perm)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secrets::Kdf;
+
+    #[test]
+    fn project_file_is_found_from_subdirectories() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().to_path_buf();
+        let nested = root.join("src").join("deep");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(
+            root.join(PROJECT_FILE),
+            "default_provider = \"claude\"\nmodel = \"claude-3-haiku\"\nhistory_dir = \"chats\"\n",
+        )
+        .unwrap();
+        let project = ProjectConfig::discover(&nested).unwrap().unwrap();
+        assert_eq!(project.history_dir, Some(root.join("chats")));
+
+        let cfg = AppConfig {
+            default_provider: Some("openai".into()),
+            project: Some(project),
+            ..AppConfig::default()
+        };
+        assert_eq!(cfg.infer_default_provider(&None).unwrap(), "claude");
+    }
+
+    #[test]
+    fn alternate_config_files_are_isolated() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("config").join("work.toml");
+        let cfg = AppConfig {
+            default_provider: Some("work-openai".into()),
+            ..AppConfig::default()
+        };
+        cfg.save_to(&path).unwrap();
+        let loaded = AppConfig::load_from(&path).unwrap();
+        assert_eq!(loaded.default_provider.as_deref(), Some("work-openai"));
+    }
+
+    #[test]
+    fn export_redacts_or_reencrypts_keys() {
+        let cfg: AppConfig = toml::from_str(
+            r#"
+            default_provider = "openai"
+            [providers.openai]
+            type = "openai"
+            api_key = "sk-plain"
+            [sessions.remote]
+            kind = "webdav"
+            url = "https://dav.example.com"
+            password = "hunter2"
+            "#,
+        )
+        .unwrap();
+
+        let redacted = toml::to_string(&cfg.export(&SecretExport::Redact).unwrap()).unwrap();
+        assert!(!redacted.contains("sk-plain"));
+        assert!(!redacted.contains("hunter2"));
+
+        let moved = cfg
+            .export(&SecretExport::Reencrypt {
+                passphrase: None,
+                env_label: DEFAULT_MASTER_ENV,
+                new_passphrase: "new-machine",
+            })
+            .unwrap();
+        let ProviderConfig::Openai(openai) = &moved.providers["openai"] else {
+            panic!("expected openai provider");
+        };
+        assert!(openai.api_key.is_none());
+        let key = secrets::resolve_secret(
+            None,
+            openai.encrypted_api_key.as_ref(),
+            Some("new-machine"),
+            DEFAULT_MASTER_ENV,
+        )
+        .unwrap();
+        assert_eq!(key.as_deref(), Some("sk-plain"));
+
+        let mut other = AppConfig::default();
+        assert_eq!(other.import(moved), (1, 0));
+        assert_eq!(other.default_provider.as_deref(), Some("openai"));
+    }
+
+    #[test]
+    fn show_masks_keys_and_credentials() {
+        let cfg: AppConfig = toml::from_str(
+            r#"
+            [providers.openai]
+            type = "openai"
+            api_key = "sk-proj-1234567890abcd"
+            [providers.short]
+            type = "anthropic"
+            api_key = "abc"
+            [sessions.remote]
+            kind = "s3"
+            bucket = "chats"
+            secret_access_key = "wJalrXUtnFEMI/K7MDENG"
+            "#,
+        )
+        .unwrap();
+        let shown = toml::to_string(&cfg.masked()).unwrap();
+        assert!(shown.contains("api_key = \"****abcd\""));
+        assert!(shown.contains("api_key = \"****\""));
+        assert!(shown.contains("secret_access_key = \"****DENG\""));
+        assert!(!shown.contains("1234567890"));
+    }
+
+    #[test]
+    fn collector_headers_are_treated_as_credentials() {
+        let cfg: AppConfig = toml::from_str(
+            r#"
+            [telemetry]
+            endpoint = "https://api.honeycomb.io"
+            headers = { x-honeycomb-team = "hcaik_0123456789wxyz" }
+            "#,
+        )
+        .unwrap();
+        let shown = toml::to_string(&cfg.masked()).unwrap();
+        assert!(shown.contains("x-honeycomb-team = \"****wxyz\""));
+        assert!(!shown.contains("0123456789"));
+        let redacted = cfg.export(&SecretExport::Redact).unwrap();
+        assert!(redacted.telemetry.headers.is_empty());
+        assert_eq!(
+            redacted.telemetry.endpoint.as_deref(),
+            Some("https://api.honeycomb.io")
+        );
+        assert!(cfg.stores_service_credentials());
+        assert!(!redacted.stores_service_credentials());
+    }
+
+    #[test]
+    fn rotation_is_all_or_nothing() {
+        let encrypt = |key: &str, passphrase: &str| {
+            secrets::maybe_encrypt_secret(
+                Some(key.into()),
+                true,
+                Some(passphrase),
+                "UNUSED",
+                Kdf::Pbkdf2,
+            )
+            .unwrap()
+            .1
+        };
+        let provider = |encrypted_api_key| {
+            ProviderConfig::Openai(ApiKeyProviderConfig {
+                encrypted_api_key,
+                ..ApiKeyProviderConfig::default()
+            })
+        };
+        let mut cfg = AppConfig::default();
+        cfg.upsert_provider("a".into(), provider(encrypt("sk-a", "old")));
+        cfg.upsert_provider("b".into(), provider(encrypt("sk-b", "other")));
+        cfg.upsert_provider("plain".into(), provider(None));
+
+        let err = cfg.rotate_passphrase("old", "new").unwrap_err();
+        assert!(err.to_string().contains("'b'"));
+        let decrypts = |cfg: &AppConfig, name: &str, passphrase: &str| {
+            let ProviderConfig::Openai(provider) = &cfg.providers[name] else {
+                unreachable!();
+            };
+            secrets::resolve_secret(
+                None,
+                provider.encrypted_api_key.as_ref(),
+                Some(passphrase),
+                "UNUSED",
+            )
+            .is_ok()
+        };
+        assert!(decrypts(&cfg, "a", "old"));
+
+        cfg.upsert_provider("b".into(), provider(encrypt("sk-b", "old")));
+        assert_eq!(cfg.rotate_passphrase("old", "new").unwrap(), 2);
+        assert!(decrypts(&cfg, "a", "new"));
+        assert!(decrypts(&cfg, "b", "new"));
+
+        // A file sealed with the passphrase follows it, keys or not.
+        let mut sealed = AppConfig {
+            seal: Some(Seal::with_passphrase("old".into())),
+            ..AppConfig::default()
+        };
+        assert_eq!(sealed.rotate_passphrase("old", "new").unwrap(), 0);
+        let file: toml::Table =
+            toml::from_str(&sealed.seal.as_ref().unwrap().seal("x = 1").unwrap()).unwrap();
+        let secret: EncryptedSecret = file["encrypted"].clone().try_into().unwrap();
+        assert_eq!(secrets::decrypt_secret("new", &secret).unwrap(), "x = 1");
+        assert!(secrets::decrypt_secret("old", &secret).is_err());
+    }
+}
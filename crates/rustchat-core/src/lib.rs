@@ -0,0 +1,37 @@
+//! The multi-provider chat client behind `rustchat-cli`: Gemini, Claude and
+//! OpenAI-compatible providers, the config file that describes them,
+//! encrypted secrets, streaming and saved conversation history.
+//!
+//! ```no_run
+//! use rustchat_core::config::AppConfig;
+//! use rustchat_core::secrets::DEFAULT_MASTER_ENV;
+//! use rustchat_core::{build_provider, CancellationToken, ChatMessage, ChatRequestOptions};
+//!
+//! # async fn demo() -> anyhow::Result<()> {
+//! let cfg = AppConfig::load()?;
+//! let openai = cfg.require_provider("openai")?;
+//! let provider = build_provider("openai", openai, None, DEFAULT_MASTER_ENV).await?;
+//! let messages = [ChatMessage::user("Name a prime number.")];
+//! let options = ChatRequestOptions::default();
+//! let reply = provider
+//!     .chat("gpt-4o-mini", None, &messages, &options, &CancellationToken::new())
+//!     .await?;
+//! println!("{}", reply.text);
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod config;
+pub mod logger;
+pub mod provider;
+pub mod sealed;
+pub mod secrets;
+pub mod streaming;
+pub mod tokens;
+pub mod trace;
+pub mod utils;
+
+pub use provider::{
+    build_provider, CancellationToken, ChatMessage, ChatRequestOptions, ChatResponse, DynProvider,
+    MessageRole, Provider, TokenUsage,
+};
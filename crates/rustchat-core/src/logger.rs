@@ -0,0 +1,989 @@
+use std::collections::VecDeque;
+use std::fmt::Write as FmtWrite;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use crate::config::APP_DIR;
+use crate::provider::{ChatMessage, MessageRole, ReplyMeta, TokenUsage, ToolCall};
+use crate::utils;
+
+const HISTORY_SUBDIR: &str = "history";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryFormat {
+    Json,
+    Markdown,
+    /// One OpenAI fine-tuning record (`{"messages": [...]}`) per line
+    Jsonl,
+    /// The JSON document as YAML, for hand-edited transcripts
+    Yaml,
+    /// ShareGPT dataset record (`from`/`value` turns); loads back as JSON
+    ShareGpt,
+}
+
+impl HistoryFormat {
+    /// Formats told apart by file extension; ShareGPT files are `.json`.
+    pub const ALL: [HistoryFormat; 4] = [
+        HistoryFormat::Json,
+        HistoryFormat::Markdown,
+        HistoryFormat::Jsonl,
+        HistoryFormat::Yaml,
+    ];
+
+    /// The format saved under a file extension, if any.
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        match ext {
+            "yml" => Some(HistoryFormat::Yaml),
+            _ => Self::ALL
+                .into_iter()
+                .find(|format| format.extension() == ext),
+        }
+    }
+
+    /// The format matching `path`'s extension (ignoring a compression
+    /// suffix), JSON when none does.
+    pub fn for_path(path: &Path) -> Self {
+        let (path, _) = Compression::split(path);
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(Self::from_extension)
+            .unwrap_or(HistoryFormat::Json)
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            HistoryFormat::Json => "json",
+            HistoryFormat::Markdown => "md",
+            HistoryFormat::Jsonl => "jsonl",
+            HistoryFormat::Yaml => "yaml",
+            HistoryFormat::ShareGpt => "json",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            HistoryFormat::Json | HistoryFormat::ShareGpt => "application/json",
+            HistoryFormat::Markdown => "text/markdown; charset=utf-8",
+            HistoryFormat::Jsonl => "application/jsonl",
+            HistoryFormat::Yaml => "application/yaml",
+        }
+    }
+}
+
+/// Compression applied to a transcript file, chosen by a `.gz`/`.zst` suffix
+/// after the format extension (e.g. `20240501-120000-openai.json.zst`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub const ALL: [Compression; 2] = [Compression::Gzip, Compression::Zstd];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compression::Gzip => "gz",
+            Compression::Zstd => "zst",
+        }
+    }
+
+    /// Splits a compression suffix off `path`, if it has one.
+    pub fn split(path: &Path) -> (PathBuf, Option<Compression>) {
+        let found = Self::ALL.into_iter().find(|compression| {
+            path.extension()
+                .is_some_and(|ext| ext == compression.extension())
+        });
+        match found {
+            Some(compression) => (path.with_extension(""), Some(compression)),
+            None => (path.to_path_buf(), None),
+        }
+    }
+
+    /// `path` with this compression's suffix added.
+    pub fn apply_to(&self, path: &Path) -> PathBuf {
+        let mut name = path.as_os_str().to_os_string();
+        name.push(".");
+        name.push(self.extension());
+        PathBuf::from(name)
+    }
+
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(match self {
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()?
+            }
+            Compression::Zstd => zstd::encode_all(data, 0)?,
+        })
+    }
+
+    pub fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(match self {
+            Compression::Gzip => {
+                let mut out = Vec::new();
+                GzDecoder::new(data).read_to_end(&mut out)?;
+                out
+            }
+            Compression::Zstd => zstd::decode_all(data)?,
+        })
+    }
+}
+
+/// Writes a history file, compressing it when `path` ends in `.gz`/`.zst`.
+pub fn write_history_file(path: &Path, payload: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create log directory {}", parent.display()))?;
+    }
+    let bytes = match Compression::split(path).1 {
+        Some(compression) => compression.compress(payload)?,
+        None => payload.to_vec(),
+    };
+    utils::write_atomic(path, &bytes)
+        .with_context(|| format!("failed to write log to {}", path.display()))
+}
+
+/// Reads a history file, decompressing `.gz`/`.zst` files.
+pub fn read_history_file(path: &Path) -> Result<String> {
+    let bytes =
+        fs::read(path).with_context(|| format!("failed to read history {}", path.display()))?;
+    let bytes = match Compression::split(path).1 {
+        Some(compression) => compression
+            .decompress(&bytes)
+            .with_context(|| format!("failed to decompress {}", path.display()))?,
+        None => bytes,
+    };
+    String::from_utf8(bytes).with_context(|| format!("{} is not UTF-8 text", path.display()))
+}
+
+/// One saved message. Everything past `role`/`content` is optional so bare
+/// role/content arrays from older versions still load.
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct SerializableMessage {
+    role: String,
+    content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<ToolCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timestamp: Option<DateTime<Utc>>,
+    /// Provider and model that produced an assistant turn
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    provider: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    usage: Option<TokenUsage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    latency_ms: Option<u64>,
+}
+
+impl SerializableMessage {
+    fn from_message(message: &ChatMessage) -> Self {
+        let reply = message.reply.as_ref();
+        Self {
+            role: message.role.to_string(),
+            content: message.content.clone(),
+            tool_calls: message.tool_calls.clone(),
+            tool_call_id: message.tool_call_id.clone(),
+            timestamp: message.timestamp,
+            provider: reply.map(|reply| reply.provider.clone()),
+            model: reply.map(|reply| reply.model.clone()),
+            usage: reply.and_then(|reply| reply.usage),
+            latency_ms: reply.map(|reply| reply.latency.as_millis() as u64),
+        }
+    }
+
+    fn reply_meta(&self) -> Option<ReplyMeta> {
+        Some(ReplyMeta {
+            provider: self.provider.clone()?,
+            model: self.model.clone()?,
+            usage: self.usage,
+            latency: Duration::from_millis(self.latency_ms.unwrap_or_default()),
+        })
+    }
+}
+
+/// Session-level details stored alongside a saved transcript. Every field is
+/// optional so transcripts written before it existed still load.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct SessionMeta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+    /// Name given with `--title` or `/title`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Labels given with `--tag`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+impl SessionMeta {
+    pub fn new(provider: &str, model: &str) -> Self {
+        Self {
+            provider: Some(provider.to_string()),
+            model: Some(model.to_string()),
+            created_at: Some(Utc::now()),
+            title: None,
+            tags: Vec::new(),
+        }
+    }
+}
+
+/// A saved conversation as read back from disk.
+pub struct Transcript {
+    pub meta: SessionMeta,
+    pub system: Option<String>,
+    pub messages: Vec<ChatMessage>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SessionDocument {
+    #[serde(default)]
+    meta: SessionMeta,
+    messages: Vec<SerializableMessage>,
+}
+
+/// OpenAI chat fine-tuning record; the JSONL format has one per line.
+#[derive(Serialize, Deserialize)]
+struct JsonlRecord {
+    messages: Vec<JsonlMessage>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonlMessage {
+    role: String,
+    #[serde(default)]
+    content: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<JsonlToolCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonlToolCall {
+    id: String,
+    #[serde(rename = "type")]
+    kind: String,
+    function: JsonlFunction,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonlFunction {
+    name: String,
+    /// JSON-encoded arguments, as OpenAI expects
+    arguments: String,
+}
+
+/// One ShareGPT turn; exports hold an array of `{"id", "conversations"}`.
+#[derive(Serialize, Deserialize)]
+struct ShareGptTurn {
+    from: String,
+    value: String,
+}
+
+/// Marker for the metadata comment embedded in markdown transcripts.
+const MARKDOWN_META_PREFIX: &str = "<!-- rustchat-session ";
+
+pub fn save_history(
+    path: &Path,
+    format: HistoryFormat,
+    meta: &SessionMeta,
+    system: Option<&str>,
+    messages: &[ChatMessage],
+) -> Result<()> {
+    let payload = render_history(format, meta, system, messages)?;
+    write_history_file(path, payload.as_bytes())
+}
+
+/// Renders a transcript in `format` exactly as [`save_history`] writes it.
+pub fn render_history(
+    format: HistoryFormat,
+    meta: &SessionMeta,
+    system: Option<&str>,
+    messages: &[ChatMessage],
+) -> Result<String> {
+    match format {
+        HistoryFormat::Json => {
+            let document = SessionDocument {
+                meta: meta.clone(),
+                messages: collect_serializable_messages(system, messages),
+            };
+            Ok(serde_json::to_string_pretty(&document)?)
+        }
+        HistoryFormat::Markdown => {
+            let mut markdown = render_markdown_payload(system, messages);
+            let meta_line = format!(
+                "{MARKDOWN_META_PREFIX}{} -->\n\n",
+                serde_json::to_string(meta)?
+            );
+            let header_len = markdown.find("\n\n").map_or(0, |idx| idx + 2);
+            markdown.insert_str(header_len, &meta_line);
+            Ok(markdown)
+        }
+        HistoryFormat::Jsonl => build_jsonl_record(system, messages),
+        HistoryFormat::Yaml => build_yaml_payload(meta, system, messages),
+        HistoryFormat::ShareGpt => build_sharegpt_payload(meta, system, messages),
+    }
+}
+
+/// Loads a transcript written by [`save_history`] (format chosen by extension), splitting a leading system entry out of the message list. Bare
+/// JSON arrays from older versions and webhook payloads are accepted too.
+pub fn load_history(path: &Path) -> Result<Transcript> {
+    let data = read_history_file(path)?;
+    let (meta, entries) = match HistoryFormat::for_path(path) {
+        HistoryFormat::Markdown => parse_markdown_payload(&data),
+        HistoryFormat::Jsonl => (
+            SessionMeta::default(),
+            parse_jsonl_record(&data)
+                .with_context(|| format!("failed to parse history {} (jsonl)", path.display()))?,
+        ),
+        HistoryFormat::Yaml => {
+            let document: SessionDocument = serde_yaml::from_str(&data)
+                .with_context(|| format!("failed to parse history {} (yaml)", path.display()))?;
+            (document.meta, document.messages)
+        }
+        HistoryFormat::Json | HistoryFormat::ShareGpt => {
+            let value: serde_json::Value = serde_json::from_str(&data)
+                .with_context(|| format!("failed to parse history {} (json)", path.display()))?;
+            if let Some(record) = sharegpt_record(&value) {
+                (
+                    SessionMeta::default(),
+                    parse_sharegpt_record(record).with_context(|| {
+                        format!("failed to parse history {} (sharegpt)", path.display())
+                    })?,
+                )
+            } else if value.is_array() {
+                (SessionMeta::default(), serde_json::from_value(value)?)
+            } else {
+                let document: SessionDocument =
+                    serde_json::from_value(value).with_context(|| {
+                        format!("failed to parse history {} (json)", path.display())
+                    })?;
+                (document.meta, document.messages)
+            }
+        }
+    };
+    let (system, messages) = split_entries(entries)
+        .with_context(|| format!("failed to read history {}", path.display()))?;
+    Ok(Transcript {
+        meta,
+        system,
+        messages,
+    })
+}
+
+/// The messages as the array a JSON history file holds, system prompt first.
+pub fn messages_to_json(system: Option<&str>, messages: &[ChatMessage]) -> serde_json::Value {
+    serde_json::to_value(collect_serializable_messages(system, messages))
+        .unwrap_or(serde_json::Value::Null)
+}
+
+/// Reads back an array written by [`messages_to_json`].
+pub fn messages_from_json(value: serde_json::Value) -> Result<(Option<String>, Vec<ChatMessage>)> {
+    split_entries(serde_json::from_value(value)?)
+}
+
+/// Splits a leading system entry off the saved messages.
+fn split_entries(entries: Vec<SerializableMessage>) -> Result<(Option<String>, Vec<ChatMessage>)> {
+    let mut system = None;
+    let mut messages = Vec::with_capacity(entries.len());
+    for (index, entry) in entries.into_iter().enumerate() {
+        let role = MessageRole::parse(&entry.role)
+            .ok_or_else(|| anyhow!("unknown role '{}'", entry.role))?;
+        if index == 0 && role == MessageRole::System {
+            system = Some(entry.content);
+        } else {
+            let reply = entry.reply_meta();
+            let mut message = ChatMessage::new(role, entry.content);
+            message.timestamp = entry.timestamp;
+            message.reply = reply;
+            message.tool_calls = entry.tool_calls;
+            message.tool_call_id = entry.tool_call_id;
+            messages.push(message);
+        }
+    }
+    Ok((system, messages))
+}
+
+pub async fn send_history_webhook(
+    url: &str,
+    format: HistoryFormat,
+    system: Option<&str>,
+    messages: &[ChatMessage],
+) -> Result<()> {
+    let body = webhook_payload(format, system, messages)?;
+    Client::new()
+        .post(url)
+        .header("content-type", format.content_type())
+        .body(body)
+        .send()
+        .await
+        .with_context(|| format!("failed to reach webhook {url}"))?
+        .error_for_status()
+        .with_context(|| format!("webhook {url} returned error status"))?;
+    Ok(())
+}
+
+/// The body [`send_history_webhook`] posts: the transcript in `format`,
+/// without session metadata.
+pub fn webhook_payload(
+    format: HistoryFormat,
+    system: Option<&str>,
+    messages: &[ChatMessage],
+) -> Result<String> {
+    match format {
+        HistoryFormat::Json => build_json_payload(system, messages),
+        HistoryFormat::Markdown => Ok(render_markdown_payload(system, messages)),
+        HistoryFormat::Jsonl => build_jsonl_record(system, messages),
+        HistoryFormat::ShareGpt => {
+            build_sharegpt_payload(&SessionMeta::default(), system, messages)
+        }
+        HistoryFormat::Yaml => build_yaml_payload(&SessionMeta::default(), system, messages),
+    }
+}
+
+pub fn default_history_dir() -> Option<PathBuf> {
+    let base = dirs::data_local_dir().or_else(dirs::config_dir)?;
+    Some(base.join(APP_DIR).join(HISTORY_SUBDIR))
+}
+
+/// Id for a new auto-saved session, e.g. `20240501-123045-openai`. The file
+/// store uses it as the file name.
+pub fn timestamped_session_id(provider: &str) -> String {
+    session_id_at(provider, Utc::now())
+}
+
+pub fn session_id_at(provider: &str, now: DateTime<Utc>) -> String {
+    let stamp = now.format("%Y%m%d-%H%M%S");
+    format!("{stamp}-{}", sanitized_provider(provider))
+}
+
+fn sanitized_provider(provider: &str) -> String {
+    let mut sanitized = provider
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | '0'..='9' | '-' => c,
+            'A'..='Z' => c.to_ascii_lowercase(),
+            _ => '-',
+        })
+        .collect::<String>();
+    let trimmed = sanitized.trim_matches('-');
+    if trimmed.is_empty() {
+        sanitized.clear();
+        sanitized.push_str("session");
+        sanitized
+    } else {
+        trimmed.to_string()
+    }
+}
+
+fn build_json_payload(system: Option<&str>, messages: &[ChatMessage]) -> Result<String> {
+    let entries = collect_serializable_messages(system, messages);
+    let json = serde_json::to_string_pretty(&entries)?;
+    Ok(json)
+}
+
+fn collect_serializable_messages(
+    system: Option<&str>,
+    messages: &[ChatMessage],
+) -> Vec<SerializableMessage> {
+    let mut serializable = Vec::with_capacity(messages.len() + 1);
+    if let Some(system_text) = system {
+        serializable.push(SerializableMessage {
+            role: "system".to_string(),
+            content: system_text.to_string(),
+            ..Default::default()
+        });
+    }
+    serializable.extend(messages.iter().map(SerializableMessage::from_message));
+    serializable
+}
+
+fn build_yaml_payload(
+    meta: &SessionMeta,
+    system: Option<&str>,
+    messages: &[ChatMessage],
+) -> Result<String> {
+    let document = SessionDocument {
+        meta: meta.clone(),
+        messages: collect_serializable_messages(system, messages),
+    };
+    Ok(serde_yaml::to_string(&document)?)
+}
+
+/// Writes a one-conversation ShareGPT dataset. Tool calls use the
+/// `function_call`/`observation` turns understood by common fine-tuning tools.
+fn build_sharegpt_payload(
+    meta: &SessionMeta,
+    system: Option<&str>,
+    messages: &[ChatMessage],
+) -> Result<String> {
+    let mut turns = Vec::with_capacity(messages.len() + 1);
+    for entry in collect_serializable_messages(system, messages) {
+        let from = match entry.role.as_str() {
+            "user" => "human",
+            "assistant" => "gpt",
+            "tool" => "observation",
+            other => other,
+        };
+        if !entry.content.is_empty() || entry.tool_calls.is_empty() {
+            turns.push(ShareGptTurn {
+                from: from.to_string(),
+                value: entry.content,
+            });
+        }
+        for call in entry.tool_calls {
+            turns.push(ShareGptTurn {
+                from: "function_call".to_string(),
+                value: serde_json::json!({ "name": call.name, "arguments": call.arguments })
+                    .to_string(),
+            });
+        }
+    }
+    let id = meta
+        .created_at
+        .map(|created| session_id_at(meta.provider.as_deref().unwrap_or("session"), created))
+        .unwrap_or_else(|| "session".to_string());
+    let dataset = serde_json::json!([{ "id": id, "conversations": turns }]);
+    Ok(serde_json::to_string_pretty(&dataset)?)
+}
+
+/// The first conversation's turns if `value` is a ShareGPT dataset.
+fn sharegpt_record(value: &serde_json::Value) -> Option<&serde_json::Value> {
+    value
+        .as_array()?
+        .first()?
+        .get("conversations")
+        .filter(|turns| turns.is_array())
+}
+
+fn parse_sharegpt_record(record: &serde_json::Value) -> Result<Vec<SerializableMessage>> {
+    let turns: Vec<ShareGptTurn> = serde_json::from_value(record.clone())?;
+    let mut entries: Vec<SerializableMessage> = Vec::with_capacity(turns.len());
+    let mut unanswered = VecDeque::new();
+    for (index, turn) in turns.into_iter().enumerate() {
+        let role = match turn.from.as_str() {
+            "human" | "user" => "user",
+            "gpt" | "assistant" => "assistant",
+            "system" => "system",
+            "observation" | "tool" => "tool",
+            "function_call" => {
+                #[derive(Deserialize)]
+                struct Call {
+                    name: String,
+                    #[serde(default)]
+                    arguments: serde_json::Value,
+                }
+                let call: Call = serde_json::from_str(&turn.value)?;
+                let call = ToolCall {
+                    id: format!("call-{index}"),
+                    name: call.name,
+                    arguments: call.arguments,
+                };
+                unanswered.push_back(call.id.clone());
+                match entries.last_mut() {
+                    Some(last) if last.role == "assistant" => last.tool_calls.push(call),
+                    _ => entries.push(SerializableMessage {
+                        role: "assistant".to_string(),
+                        content: String::new(),
+                        tool_calls: vec![call],
+                        ..Default::default()
+                    }),
+                }
+                continue;
+            }
+            other => bail!("unknown ShareGPT speaker '{other}'"),
+        };
+        entries.push(SerializableMessage {
+            role: role.to_string(),
+            tool_call_id: (role == "tool").then(|| unanswered.pop_front()).flatten(),
+            content: turn.value,
+            ..Default::default()
+        });
+    }
+    Ok(entries)
+}
+
+fn build_jsonl_record(system: Option<&str>, messages: &[ChatMessage]) -> Result<String> {
+    let messages = collect_serializable_messages(system, messages)
+        .into_iter()
+        .map(|entry| JsonlMessage {
+            content: (!entry.content.is_empty() || entry.tool_calls.is_empty())
+                .then_some(entry.content),
+            role: entry.role,
+            tool_calls: entry
+                .tool_calls
+                .into_iter()
+                .map(|call| JsonlToolCall {
+                    id: call.id,
+                    kind: "function".to_string(),
+                    function: JsonlFunction {
+                        name: call.name,
+                        arguments: call.arguments.to_string(),
+                    },
+                })
+                .collect(),
+            tool_call_id: entry.tool_call_id,
+        })
+        .collect();
+    let mut line = serde_json::to_string(&JsonlRecord { messages })?;
+    line.push('\n');
+    Ok(line)
+}
+
+/// Reads the first record of a JSONL transcript.
+fn parse_jsonl_record(data: &str) -> Result<Vec<SerializableMessage>> {
+    let line = data
+        .lines()
+        .find(|line| !line.trim().is_empty())
+        .ok_or_else(|| anyhow!("file is empty"))?;
+    let record: JsonlRecord = serde_json::from_str(line)?;
+    record
+        .messages
+        .into_iter()
+        .map(|message| {
+            let tool_calls = message
+                .tool_calls
+                .into_iter()
+                .map(|call| {
+                    Ok(ToolCall {
+                        id: call.id,
+                        name: call.function.name,
+                        arguments: serde_json::from_str(&call.function.arguments)?,
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Ok(SerializableMessage {
+                role: message.role,
+                content: message.content.unwrap_or_default(),
+                tool_calls,
+                tool_call_id: message.tool_call_id,
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+fn render_markdown_payload(system: Option<&str>, messages: &[ChatMessage]) -> String {
+    let mut buf = String::with_capacity(128);
+    buf.push_str("# Chat Transcript\n\n");
+    if let Some(system_text) = system {
+        append_markdown_entry(&mut buf, "system", system_text);
+    }
+    for message in messages {
+        append_markdown_entry(&mut buf, &message.role.to_string(), &message.content);
+    }
+    buf
+}
+
+fn append_markdown_entry(buf: &mut String, role: &str, content: &str) {
+    let _ = writeln!(buf, "## {role}\n\n{content}\n");
+}
+
+/// Reverses [`render_markdown_payload`]. Tool calls are not part of the
+/// markdown format, so they cannot be recovered.
+fn parse_markdown_payload(data: &str) -> (SessionMeta, Vec<SerializableMessage>) {
+    let mut meta = SessionMeta::default();
+    let mut entries: Vec<SerializableMessage> = Vec::new();
+    for line in data.lines() {
+        if let Some(role) = line
+            .strip_prefix("## ")
+            .filter(|role| MessageRole::parse(role).is_some())
+        {
+            entries.push(SerializableMessage {
+                role: role.to_string(),
+                ..Default::default()
+            });
+        } else if let Some(entry) = entries.last_mut() {
+            entry.content.push_str(line);
+            entry.content.push('\n');
+        } else if let Some(json) = line
+            .strip_prefix(MARKDOWN_META_PREFIX)
+            .and_then(|rest| rest.strip_suffix(" -->"))
+        {
+            meta = serde_json::from_str(json).unwrap_or_default();
+        }
+    }
+    for entry in &mut entries {
+        entry.content = entry.content.trim().to_string();
+    }
+    (meta, entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde_json::Value;
+
+    #[test]
+    fn json_payload_includes_system() {
+        let messages = vec![
+            ChatMessage::user("Hello"),
+            ChatMessage::assistant("Hi there"),
+        ];
+        let json = build_json_payload(Some("Stay helpful"), &messages).expect("json payload");
+        let value: Value = serde_json::from_str(&json).expect("valid json");
+        assert_eq!(value[0]["role"], "system");
+        assert_eq!(value[1]["role"], "user");
+        assert_eq!(value[2]["role"], "assistant");
+    }
+
+    #[test]
+    fn markdown_payload_captures_roles() {
+        let messages = vec![ChatMessage::user("Ping"), ChatMessage::assistant("Pong")];
+        let md = render_markdown_payload(None, &messages);
+        assert!(md.contains("## user"));
+        assert!(md.contains("## assistant"));
+        assert!(md.contains("Pong"));
+    }
+
+    #[test]
+    fn json_history_round_trips() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("load.json");
+        let messages = vec![ChatMessage::user("Hi"), ChatMessage::assistant("Hello")];
+        let meta = SessionMeta::new("openai", "gpt-4o");
+        save_history(
+            &path,
+            HistoryFormat::Json,
+            &meta,
+            Some("Be brief"),
+            &messages,
+        )
+        .expect("save");
+        let transcript = load_history(&path).expect("load");
+        assert_eq!(transcript.meta, meta);
+        assert_eq!(transcript.system.as_deref(), Some("Be brief"));
+        assert_eq!(transcript.messages.len(), 2);
+        assert_eq!(transcript.messages[1].role, MessageRole::Assistant);
+        assert_eq!(transcript.messages[1].content, "Hello");
+    }
+
+    #[test]
+    fn json_history_keeps_reply_details() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("reply.json");
+        let reply = ReplyMeta {
+            provider: "anthropic".to_string(),
+            model: "claude-3-haiku".to_string(),
+            usage: Some(TokenUsage {
+                input_tokens: 40,
+                output_tokens: 9,
+            }),
+            latency: Duration::from_millis(1200),
+        };
+        let messages = vec![
+            ChatMessage::user("Hi"),
+            ChatMessage::assistant("Hello").with_reply(reply.clone()),
+        ];
+        save_history(
+            &path,
+            HistoryFormat::Json,
+            &SessionMeta::default(),
+            None,
+            &messages,
+        )
+        .expect("save");
+        let transcript = load_history(&path).expect("load");
+        assert_eq!(transcript.messages[0].timestamp, messages[0].timestamp);
+        assert_eq!(transcript.messages[0].reply, None);
+        assert_eq!(transcript.messages[1].reply, Some(reply));
+
+        // Bare role/content arrays from older versions still load.
+        fs::write(&path, r#"[{"role":"user","content":"Hi"}]"#).expect("write");
+        let legacy = load_history(&path).expect("load legacy");
+        assert_eq!(legacy.messages[0].content, "Hi");
+        assert_eq!(legacy.messages[0].timestamp, None);
+    }
+
+    #[test]
+    fn hand_written_markdown_loads() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("script.md");
+        fs::write(
+            &path,
+            "## System\nAnswer in French.\n\n## User\nHello\n\n## Assistant\nBonjour\n\n## User\nThanks\n",
+        )
+        .expect("write");
+        let transcript = load_history(&path).expect("load");
+        assert_eq!(transcript.system.as_deref(), Some("Answer in French."));
+        let roles: Vec<MessageRole> = transcript.messages.iter().map(|m| m.role).collect();
+        assert_eq!(
+            roles,
+            [MessageRole::User, MessageRole::Assistant, MessageRole::User]
+        );
+    }
+
+    #[test]
+    fn yaml_history_round_trips() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("load.yml");
+        let call = ToolCall {
+            id: "call-1".to_string(),
+            name: "shell".to_string(),
+            arguments: serde_json::json!({ "command": "ls -la" }),
+        };
+        let messages = vec![
+            ChatMessage::user("Line one\nline two: with a colon"),
+            ChatMessage::assistant_with_tools("", vec![call.clone()]),
+            ChatMessage::tool_result("call-1", "a.txt"),
+        ];
+        let meta = SessionMeta {
+            title: Some("Files".to_string()),
+            ..SessionMeta::new("google", "gemini-pro")
+        };
+        save_history(
+            &path,
+            HistoryFormat::Yaml,
+            &meta,
+            Some("Be brief"),
+            &messages,
+        )
+        .expect("save");
+        let transcript = load_history(&path).expect("load");
+        assert_eq!(transcript.meta, meta);
+        assert_eq!(transcript.system.as_deref(), Some("Be brief"));
+        assert_eq!(transcript.messages[0].content, messages[0].content);
+        assert_eq!(transcript.messages[1].tool_calls, vec![call]);
+        assert_eq!(
+            transcript.messages[2].tool_call_id.as_deref(),
+            Some("call-1")
+        );
+    }
+
+    #[test]
+    fn sharegpt_export_loads_back() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("sharegpt.json");
+        let call = ToolCall {
+            id: "call-1".to_string(),
+            name: "shell".to_string(),
+            arguments: serde_json::json!({ "command": "ls" }),
+        };
+        let messages = vec![
+            ChatMessage::user("List files"),
+            ChatMessage::assistant_with_tools("Checking.", vec![call]),
+            ChatMessage::tool_result("call-1", "a.txt"),
+            ChatMessage::assistant("One file."),
+        ];
+        let meta = SessionMeta::new("openai", "gpt-4o");
+        let payload = render_history(HistoryFormat::ShareGpt, &meta, Some("Be brief"), &messages)
+            .expect("render");
+        let value: Value = serde_json::from_str(&payload).expect("valid json");
+        let froms: Vec<&str> = value[0]["conversations"]
+            .as_array()
+            .expect("turns")
+            .iter()
+            .filter_map(|turn| turn["from"].as_str())
+            .collect();
+        assert_eq!(
+            froms,
+            [
+                "system",
+                "human",
+                "gpt",
+                "function_call",
+                "observation",
+                "gpt"
+            ]
+        );
+
+        fs::write(&path, payload).expect("write");
+        let transcript = load_history(&path).expect("load");
+        assert_eq!(transcript.system.as_deref(), Some("Be brief"));
+        assert_eq!(transcript.messages.len(), 4);
+        let loaded_call = &transcript.messages[1].tool_calls[0];
+        assert_eq!(loaded_call.name, "shell");
+        assert_eq!(
+            transcript.messages[2].tool_call_id.as_deref(),
+            Some(loaded_call.id.as_str())
+        );
+    }
+
+    #[test]
+    fn jsonl_record_uses_openai_shape() {
+        let call = ToolCall {
+            id: "call-1".to_string(),
+            name: "shell".to_string(),
+            arguments: serde_json::json!({ "command": "ls" }),
+        };
+        let messages = vec![
+            ChatMessage::user("List files"),
+            ChatMessage::assistant_with_tools("", vec![call.clone()]),
+            ChatMessage::tool_result("call-1", "a.txt"),
+            ChatMessage::assistant("There is one file."),
+        ];
+        let line = build_jsonl_record(Some("Be brief"), &messages).expect("jsonl");
+        assert_eq!(line.matches('\n').count(), 1);
+        let value: Value = serde_json::from_str(&line).expect("valid json");
+        assert_eq!(value["messages"][0]["role"], "system");
+        assert!(value["messages"][2]["content"].is_null());
+        assert_eq!(
+            value["messages"][2]["tool_calls"][0]["function"]["arguments"],
+            r#"{"command":"ls"}"#
+        );
+        assert_eq!(value["messages"][3]["tool_call_id"], "call-1");
+
+        let entries = parse_jsonl_record(&line).expect("parse");
+        assert_eq!(entries.len(), 5);
+        assert_eq!(entries[2].tool_calls, vec![call]);
+    }
+
+    #[test]
+    fn markdown_history_round_trips() {
+        let meta = SessionMeta {
+            title: Some("Release notes".to_string()),
+            tags: vec!["work".to_string()],
+            ..SessionMeta::new("anthropic", "claude-3-5-sonnet")
+        };
+        let messages = vec![
+            ChatMessage::user("Question?"),
+            ChatMessage::assistant("First line\n\n## Not a role\n\nLast line"),
+        ];
+        let markdown =
+            render_history(HistoryFormat::Markdown, &meta, None, &messages).expect("render");
+        assert!(markdown.starts_with("# Chat Transcript\n\n<!-- rustchat-session "));
+        let (parsed_meta, entries) = parse_markdown_payload(&markdown);
+        assert_eq!(parsed_meta, meta);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].content, "Question?");
+        assert_eq!(
+            entries[1].content,
+            "First line\n\n## Not a role\n\nLast line"
+        );
+    }
+
+    #[test]
+    fn timestamped_id_is_deterministic() {
+        let now = Utc
+            .with_ymd_and_hms(2024, 5, 1, 12, 30, 45)
+            .single()
+            .expect("valid timestamp");
+        assert_eq!(
+            session_id_at("Prod#Provider", now),
+            "20240501-123045-prod-provider"
+        );
+    }
+}
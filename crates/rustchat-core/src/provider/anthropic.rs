@@ -0,0 +1,567 @@
+use anyhow::{anyhow, Context, Result};
+use async_stream::try_stream;
+use async_trait::async_trait;
+use futures::{pin_mut, StreamExt};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{self, ApiKeyProviderConfig};
+use crate::provider::{
+    until_canceled, CancellationToken, ChatMessage, ChatRequestOptions, ChatResponse, Citation,
+    ContentBlocked, MessageRole, Provider, RequestPreview, TokenUsage, ToolCall,
+};
+use crate::streaming::{ChatStream, StreamEvent};
+use crate::trace::ResponseExt;
+use crate::{secrets, trace};
+
+const DEFAULT_BASE_URL: &str = "https://api.anthropic.com";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+pub struct AnthropicProvider {
+    config: ApiKeyProviderConfig,
+    client: Client,
+    api_key: String,
+    base_url: String,
+}
+
+impl AnthropicProvider {
+    pub async fn new(
+        name: String,
+        mut config: ApiKeyProviderConfig,
+        passphrase: Option<&str>,
+        env_label: &str,
+    ) -> Result<Self> {
+        let api_key = secrets::require_secret(
+            config.api_key.as_deref(),
+            config.encrypted_api_key.as_ref(),
+            passphrase,
+            env_label,
+            &format!("anthropic provider '{name}' requires --api-key"),
+        )?;
+        trace::register_secret(&api_key);
+        config.api_key = Some(api_key.clone());
+        config.encrypted_api_key = None;
+        let client = Client::builder().build()?;
+        let base_url = config
+            .base_url
+            .clone()
+            .unwrap_or_else(|| DEFAULT_BASE_URL.to_string());
+
+        Ok(Self {
+            config,
+            client,
+            api_key,
+            base_url,
+        })
+    }
+
+    fn endpoint(&self) -> String {
+        format!("{}/v1/messages", self.base_url.trim_end_matches('/'))
+    }
+
+    fn build_payload(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+        stream: bool,
+    ) -> AnthropicRequest {
+        let model = if model.trim().is_empty() {
+            self.config
+                .default_model
+                .clone()
+                .unwrap_or_else(|| "claude-3-sonnet-20240229".to_string())
+        } else {
+            model.to_string()
+        };
+
+        let mut system_prompts: Vec<String> = Vec::new();
+        if let Some(s) = system {
+            system_prompts.push(s.to_string());
+        }
+
+        let mut converted: Vec<AnthropicMessage> = Vec::new();
+        for msg in messages {
+            match msg.role {
+                MessageRole::System => system_prompts.push(msg.content.clone()),
+                MessageRole::Tool => {
+                    // Results for one assistant turn must share a single user message.
+                    let block = AnthropicContent::ToolResult {
+                        tool_use_id: msg.tool_call_id.clone().unwrap_or_default(),
+                        content: msg.content.clone(),
+                    };
+                    match converted.last_mut() {
+                        Some(last)
+                            if last.role == "user"
+                                && last
+                                    .content
+                                    .iter()
+                                    .all(|c| matches!(c, AnthropicContent::ToolResult { .. })) =>
+                        {
+                            last.content.push(block)
+                        }
+                        _ => converted.push(AnthropicMessage {
+                            role: "user".to_string(),
+                            content: vec![block],
+                        }),
+                    }
+                }
+                _ => converted.push(AnthropicMessage::from_chat(msg)),
+            }
+        }
+
+        let tools = (!options.tools.is_empty()).then(|| {
+            options
+                .tools
+                .iter()
+                .map(|tool| AnthropicTool {
+                    name: tool.name.clone(),
+                    description: tool.description.clone(),
+                    input_schema: tool.parameters.clone(),
+                })
+                .collect()
+        });
+
+        AnthropicRequest {
+            model,
+            max_tokens: options.max_output_tokens.unwrap_or(1024).max(1),
+            temperature: options.temperature,
+            system: if system_prompts.is_empty() {
+                None
+            } else {
+                Some(system_prompts.join("\n"))
+            },
+            messages: converted,
+            stream,
+            tool_choice: (tools.is_some() && options.forbid_tool_calls)
+                .then_some(AnthropicToolChoice { kind: "none" }),
+            tools,
+        }
+    }
+
+    fn request_builder(&self) -> reqwest::RequestBuilder {
+        self.request_to(self.endpoint())
+    }
+
+    fn request_to(&self, url: String) -> reqwest::RequestBuilder {
+        self.client
+            .post(url)
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+    }
+
+    fn parse_stream_event(payload: &str) -> Result<Vec<StreamEvent>> {
+        let trimmed = payload.trim();
+        if trimmed.is_empty() || trimmed == "[DONE]" {
+            return Ok(Vec::new());
+        }
+
+        let event: AnthropicStreamEvent = serde_json::from_str(trimmed)
+            .with_context(|| format!("failed to parse anthropic stream event: {trimmed}"))?;
+        let mut events = Vec::new();
+        if let Some(text) = event.text_fragment() {
+            if !text.is_empty() {
+                events.push(StreamEvent::Text(text.to_string()));
+            }
+        }
+        if let Some(usage) = event.usage() {
+            events.push(StreamEvent::Usage(usage));
+        }
+        if let Some(reason) = event.stop_reason() {
+            events.push(StreamEvent::Finish(reason.to_string()));
+        }
+        Ok(events)
+    }
+}
+
+#[async_trait]
+impl Provider for AnthropicProvider {
+    async fn chat(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+        cancel: &CancellationToken,
+    ) -> Result<ChatResponse> {
+        let payload = self.build_payload(model, system, messages, options, false);
+        let response = until_canceled(cancel, trace::send(self.request_builder().json(&payload)))
+            .await?
+            .context("anthropic request failed")?
+            .check_status()
+            .context("anthropic api error")?;
+        let response: AnthropicMessageResponse = until_canceled(cancel, trace::json(response))
+            .await?
+            .context("failed to parse anthropic response")?;
+
+        let tool_calls: Vec<ToolCall> = response
+            .content
+            .iter()
+            .filter_map(AnthropicContentBlock::tool_call)
+            .collect();
+        let text = match response.merged_text() {
+            Some(text) => text,
+            None if !tool_calls.is_empty() => String::new(),
+            None if response.stop_reason.as_deref() == Some("refusal") => {
+                return Err(ContentBlocked {
+                    provider: "anthropic",
+                    reason: "refusal".to_string(),
+                }
+                .into())
+            }
+            None => return Err(anyhow!("anthropic response missing text")),
+        };
+        Ok(ChatResponse {
+            text,
+            usage: response.usage.map(TokenUsage::from),
+            citations: Citation::dedup(
+                response
+                    .content
+                    .iter()
+                    .flat_map(|block| &block.citations)
+                    .map(|cited| Citation {
+                        url: cited.url.clone(),
+                        title: cited.title.clone(),
+                    }),
+            ),
+            finish_reason: response.stop_reason,
+            tool_calls,
+        })
+    }
+
+    async fn count_tokens(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+    ) -> Result<Option<usize>> {
+        let payload = self.build_payload(model, system, messages, options, false);
+        // The counting endpoint takes the prompt alone, without sampling settings.
+        let mut payload = serde_json::to_value(payload)?;
+        if let Some(fields) = payload.as_object_mut() {
+            for field in ["max_tokens", "stream", "temperature"] {
+                fields.remove(field);
+            }
+        }
+        let url = format!("{}/count_tokens", self.endpoint());
+        let response = trace::send(self.request_to(url).json(&payload))
+            .await
+            .context("anthropic token count failed")?
+            .check_status()
+            .context("anthropic api error")?;
+        let counted: AnthropicTokenCount = trace::json(response)
+            .await
+            .context("failed to parse anthropic token count")?;
+        Ok(Some(counted.input_tokens as usize))
+    }
+
+    fn preview_chat(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+    ) -> Result<RequestPreview> {
+        let payload = self.build_payload(model, system, messages, options, false);
+        Ok(RequestPreview::post(self.endpoint(), payload)?
+            .header("x-api-key", config::mask(&self.api_key))
+            .header("anthropic-version", ANTHROPIC_VERSION))
+    }
+
+    async fn stream_chat(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+        cancel: &CancellationToken,
+    ) -> Result<ChatStream> {
+        let payload = self.build_payload(model, system, messages, options, true);
+        let request = self
+            .request_builder()
+            .header("accept", "text/event-stream")
+            .json(&payload);
+        let response = until_canceled(cancel, trace::send(request))
+            .await?
+            .context("anthropic stream request failed")?
+            .check_status()
+            .context("anthropic stream api error")?;
+
+        let body = response.bytes_stream();
+        let cancel = cancel.clone();
+        let stream = try_stream! {
+            let mut buffer = String::new();
+            let mut event_payload = String::new();
+            pin_mut!(body);
+
+            while let Some(chunk) = until_canceled(&cancel, body.next()).await? {
+                let chunk = chunk.context("anthropic stream chunk error")?;
+                let text = String::from_utf8_lossy(&chunk);
+                buffer.push_str(&text);
+
+                while let Some(pos) = buffer.find('\n') {
+                    let mut line: String = buffer.drain(..=pos).collect();
+                    if line.ends_with('\n') {
+                        line.pop();
+                    }
+                    if line.ends_with('\r') {
+                        line.pop();
+                    }
+
+                    if line.is_empty() {
+                        if !event_payload.is_empty() {
+                            for event in Self::parse_stream_event(&event_payload)? {
+                                yield event;
+                            }
+                            event_payload.clear();
+                        }
+                        continue;
+                    }
+
+                    if let Some(rest) = line.strip_prefix("data:") {
+                        event_payload.push_str(rest.trim_start());
+                    }
+                }
+            }
+
+            if !event_payload.trim().is_empty() {
+                for event in Self::parse_stream_event(&event_payload)? {
+                    yield event;
+                }
+            }
+        };
+
+        Ok(Box::pin(stream) as ChatStream)
+    }
+}
+
+#[derive(Serialize)]
+struct AnthropicRequest {
+    model: String,
+    max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    messages: Vec<AnthropicMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(default)]
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<Vec<AnthropicTool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<AnthropicToolChoice>,
+}
+
+#[derive(Deserialize)]
+struct AnthropicTokenCount {
+    input_tokens: u32,
+}
+
+#[derive(Serialize)]
+struct AnthropicToolChoice {
+    #[serde(rename = "type")]
+    kind: &'static str,
+}
+
+#[derive(Serialize)]
+struct AnthropicTool {
+    name: String,
+    description: String,
+    input_schema: serde_json::Value,
+}
+
+#[derive(Serialize)]
+struct AnthropicMessage {
+    role: String,
+    content: Vec<AnthropicContent>,
+}
+
+impl AnthropicMessage {
+    fn from_chat(message: &ChatMessage) -> Self {
+        let role = match message.role {
+            MessageRole::User => "user",
+            MessageRole::Assistant => "assistant",
+            MessageRole::System | MessageRole::Tool => "user",
+        }
+        .to_string();
+
+        let mut content = Vec::new();
+        if !message.content.is_empty() || message.tool_calls.is_empty() {
+            content.push(AnthropicContent::Text {
+                text: message.content.clone(),
+            });
+        }
+        content.extend(
+            message
+                .tool_calls
+                .iter()
+                .map(|call| AnthropicContent::ToolUse {
+                    id: call.id.clone(),
+                    name: call.name.clone(),
+                    input: call.arguments.clone(),
+                }),
+        );
+
+        Self { role, content }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum AnthropicContent {
+    Text {
+        text: String,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        input: serde_json::Value,
+    },
+    ToolResult {
+        tool_use_id: String,
+        content: String,
+    },
+}
+
+#[derive(Deserialize)]
+struct AnthropicMessageResponse {
+    content: Vec<AnthropicContentBlock>,
+    #[serde(default)]
+    stop_reason: Option<String>,
+    #[serde(default)]
+    usage: Option<AnthropicUsage>,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+struct AnthropicUsage {
+    #[serde(default)]
+    input_tokens: u32,
+    #[serde(default)]
+    output_tokens: u32,
+}
+
+impl From<AnthropicUsage> for TokenUsage {
+    fn from(value: AnthropicUsage) -> Self {
+        TokenUsage {
+            input_tokens: value.input_tokens,
+            output_tokens: value.output_tokens,
+        }
+    }
+}
+
+impl AnthropicMessageResponse {
+    fn merged_text(&self) -> Option<String> {
+        let mut out = String::new();
+        for block in &self.content {
+            if let Some(text) = &block.text {
+                out.push_str(text);
+            }
+        }
+        if out.trim().is_empty() {
+            None
+        } else {
+            Some(out)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct AnthropicContentBlock {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    text: Option<String>,
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    input: Option<serde_json::Value>,
+    #[serde(default)]
+    citations: Vec<AnthropicCitation>,
+}
+
+/// A web search result or document location backing a text block.
+#[derive(Deserialize)]
+struct AnthropicCitation {
+    #[serde(default)]
+    url: Option<String>,
+    #[serde(default, alias = "document_title")]
+    title: Option<String>,
+}
+
+impl AnthropicContentBlock {
+    fn tool_call(&self) -> Option<ToolCall> {
+        if self.kind != "tool_use" {
+            return None;
+        }
+        Some(ToolCall {
+            id: self.id.clone()?,
+            name: self.name.clone()?,
+            arguments: self.input.clone().unwrap_or_default(),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct AnthropicStreamEvent {
+    #[serde(rename = "type")]
+    event_type: String,
+    #[serde(default)]
+    delta: Option<AnthropicStreamDelta>,
+    #[serde(default)]
+    content_block: Option<AnthropicContentBlock>,
+    #[serde(default)]
+    message: Option<AnthropicStreamMessage>,
+    #[serde(default)]
+    usage: Option<AnthropicUsage>,
+}
+
+#[derive(Deserialize)]
+struct AnthropicStreamMessage {
+    #[serde(default)]
+    usage: Option<AnthropicUsage>,
+}
+
+impl AnthropicStreamEvent {
+    fn text_fragment(&self) -> Option<&str> {
+        match self.event_type.as_str() {
+            "content_block_delta" => self.delta.as_ref().and_then(|delta| delta.text.as_deref()),
+            "content_block_start" => self
+                .content_block
+                .as_ref()
+                .and_then(|block| block.text.as_deref()),
+            _ => None,
+        }
+    }
+
+    /// `message_start` carries the input count, `message_delta` the output count.
+    fn usage(&self) -> Option<TokenUsage> {
+        match self.event_type.as_str() {
+            "message_start" => self
+                .message
+                .as_ref()
+                .and_then(|message| message.usage)
+                .map(TokenUsage::from),
+            "message_delta" => self.usage.map(TokenUsage::from),
+            _ => None,
+        }
+    }
+
+    fn stop_reason(&self) -> Option<&str> {
+        match self.event_type.as_str() {
+            "message_delta" => self.delta.as_ref()?.stop_reason.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct AnthropicStreamDelta {
+    #[serde(default)]
+    text: Option<String>,
+    #[serde(default)]
+    stop_reason: Option<String>,
+}
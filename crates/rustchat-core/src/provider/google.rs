@@ -0,0 +1,898 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use async_stream::try_stream;
+use async_trait::async_trait;
+use futures::{pin_mut, StreamExt};
+use hyper::client::HttpConnector;
+use hyper_rustls::HttpsConnector;
+use parking_lot::Mutex;
+use reqwest::{Client, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use tokio::time::{sleep, Duration};
+use yup_oauth2::{
+    authenticator::Authenticator, read_service_account_key, AccessToken,
+    ServiceAccountAuthenticator,
+};
+
+use crate::config::{self, GoogleProviderConfig};
+use crate::provider::{
+    until_canceled, CancellationToken, ChatMessage, ChatRequestOptions, ChatResponse, Citation,
+    ContentBlocked, MessageRole, Provider, RequestPreview, TokenUsage, ToolCall,
+};
+use crate::streaming::{ChatStream, StreamEvent};
+use crate::trace::ResponseExt;
+use crate::{secrets, trace};
+
+const BASE_URL: &str = "https://generativelanguage.googleapis.com/v1";
+const DEFAULT_EMBEDDING_MODEL: &str = "text-embedding-004";
+const GENERATIVE_SCOPE: &str = "https://www.googleapis.com/auth/generative-language";
+
+type GoogleAuthenticator = Authenticator<HttpsConnector<HttpConnector>>;
+
+pub struct GoogleProvider {
+    name: String,
+    config: GoogleProviderConfig,
+    client: Client,
+    authenticator: Option<Arc<GoogleAuthenticator>>,
+    cached_token: Mutex<Option<AccessToken>>,
+}
+
+impl GoogleProvider {
+    pub async fn new(
+        name: String,
+        mut config: GoogleProviderConfig,
+        passphrase: Option<&str>,
+        env_label: &str,
+    ) -> Result<Self> {
+        let resolved_api_key = secrets::resolve_secret(
+            config.api_key.as_deref(),
+            config.encrypted_api_key.as_ref(),
+            passphrase,
+            env_label,
+        )?;
+        if let Some(key) = &resolved_api_key {
+            trace::register_secret(key);
+        }
+        config.api_key = resolved_api_key;
+        config.encrypted_api_key = None;
+
+        let client = Client::builder().build()?;
+        let authenticator = match config.service_account_file.as_ref() {
+            Some(path) => {
+                let key = read_service_account_key(path).await.with_context(|| {
+                    format!("failed to read service account JSON at {}", path.display())
+                })?;
+                let auth = ServiceAccountAuthenticator::builder(key)
+                    .build()
+                    .await
+                    .context("failed to build google authenticator")?;
+                Some(Arc::new(auth))
+            }
+            None => None,
+        };
+
+        if authenticator.is_none() && config.api_key.is_none() {
+            return Err(anyhow!(
+                "google provider '{}' requires --service-account or --api-key",
+                name
+            ));
+        }
+
+        Ok(Self {
+            name,
+            config,
+            client,
+            authenticator,
+            cached_token: Mutex::new(None),
+        })
+    }
+
+    async fn ensure_token(&self) -> Result<Option<String>> {
+        if self.config.api_key.is_some() {
+            return Ok(None);
+        }
+        let auth = match &self.authenticator {
+            Some(a) => a,
+            None => {
+                return Err(anyhow!(
+                    "service account not configured for google provider"
+                ))
+            }
+        };
+
+        {
+            let cached = self.cached_token.lock();
+            if let Some(token) = cached.as_ref() {
+                if !token.is_expired() {
+                    if let Some(value) = token.token() {
+                        return Ok(Some(value.to_string()));
+                    }
+                }
+            }
+        }
+
+        let token = auth
+            .token(&[GENERATIVE_SCOPE])
+            .await
+            .context("failed to obtain oauth token")?;
+        let bearer = token
+            .token()
+            .map(|value| value.to_string())
+            .ok_or_else(|| anyhow!("oauth token response missing access_token"))?;
+        {
+            let mut cached = self.cached_token.lock();
+            *cached = Some(token);
+        }
+        Ok(Some(bearer))
+    }
+
+    async fn execute_request(
+        &self,
+        model: &str,
+        payload: &GeminiRequest,
+        cancel: &CancellationToken,
+    ) -> Result<GeminiResponse> {
+        let url = format!("{BASE_URL}/models/{model}:generateContent");
+        self.with_retries(&url, payload, cancel, |response| async move {
+            let response = response.check_status().context("google api error")?;
+            let payload: GeminiResponse = trace::json(response)
+                .await
+                .context("failed to deserialize gemini response")?;
+            Ok(payload)
+        })
+        .await
+    }
+
+    async fn execute_stream_request(
+        &self,
+        model: &str,
+        payload: &GeminiRequest,
+        cancel: &CancellationToken,
+    ) -> Result<ChatStream> {
+        let url = format!("{BASE_URL}/models/{model}:streamGenerateContent");
+        self.with_retries(&url, payload, cancel, |response| {
+            let cancel = cancel.clone();
+            async move {
+                if let Err(err) = response.error_for_status_ref() {
+                    let err = trace::status_error(err, response.headers());
+                    let text = response.text().await.unwrap_or_default();
+                    return Err(err.context(format!("google stream api error: {text}")));
+                }
+                let body = response.bytes_stream();
+                let stream = try_stream! {
+                    let mut buffer = String::new();
+                    let mut last_snapshot = String::new();
+                    pin_mut!(body);
+
+                    while let Some(chunk) = until_canceled(&cancel, body.next()).await? {
+                        let chunk = chunk.context("stream chunk error")?;
+                        let text = String::from_utf8_lossy(&chunk);
+                        buffer.push_str(&text);
+
+                        while let Some(events) = Self::try_extract_json(&mut buffer)? {
+                            for event in Self::snapshot_deltas(&mut last_snapshot, events) {
+                                yield event;
+                            }
+                        }
+                    }
+
+                    if !buffer.trim().is_empty() {
+                        if let Some(events) = Self::try_extract_json(&mut buffer)? {
+                            for event in Self::snapshot_deltas(&mut last_snapshot, events) {
+                                yield event;
+                            }
+                        }
+                    }
+                };
+
+                Ok(Box::pin(stream) as ChatStream)
+            }
+        })
+        .await
+    }
+
+    fn try_extract_json(buffer: &mut String) -> Result<Option<Vec<StreamEvent>>> {
+        let trimmed = buffer.trim_start();
+        if trimmed.is_empty() {
+            buffer.clear();
+            return Ok(None);
+        }
+
+        if let Some(stripped) = trimmed.strip_prefix("data:") {
+            *buffer = stripped.trim_start().to_string();
+            return Self::try_extract_json(buffer);
+        }
+
+        let first_char = trimmed.chars().next().unwrap();
+        if first_char != '{' && first_char != '[' {
+            if let Some(pos) = trimmed.find(['{', '[']) {
+                *buffer = trimmed[pos..].to_string();
+                return Self::try_extract_json(buffer);
+            } else {
+                buffer.clear();
+                return Ok(None);
+            }
+        }
+
+        let end_char = if first_char == '{' { '}' } else { ']' };
+        let mut depth = 0;
+        let mut in_string = false;
+        let mut escape = false;
+        let mut end_pos = None;
+
+        for (i, ch) in trimmed.char_indices() {
+            if escape {
+                escape = false;
+                continue;
+            }
+
+            match ch {
+                '\\' if in_string => escape = true,
+                '"' => in_string = !in_string,
+                c if c == first_char && !in_string => depth += 1,
+                c if c == end_char && !in_string => {
+                    depth -= 1;
+                    if depth == 0 {
+                        end_pos = Some(i + ch.len_utf8());
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(pos) = end_pos {
+            let json_str = &trimmed[..pos];
+            let result = Self::parse_stream_payload(json_str)?;
+            *buffer = trimmed[pos..].to_string();
+            Ok(Some(result))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Sends `payload` to `url`, retrying rate limits and network failures,
+    /// until `handler` takes the response or `cancel` fires.
+    async fn with_retries<P, F, Fut, T>(
+        &self,
+        url: &str,
+        payload: &P,
+        cancel: &CancellationToken,
+        handler: F,
+    ) -> Result<T>
+    where
+        P: Serialize,
+        F: Fn(Response) -> Fut,
+        Fut: std::future::Future<Output = Result<T>>,
+    {
+        let mut last_err: Option<anyhow::Error> = None;
+
+        for attempt in 0..3 {
+            let mut request = self.client.post(url).json(payload);
+            request = self.apply_auth(request).await?;
+
+            match until_canceled(cancel, trace::send(request)).await? {
+                Ok(response) => {
+                    if response.status() == StatusCode::TOO_MANY_REQUESTS && attempt < 2 {
+                        let delay = Duration::from_millis(500 * (attempt as u64 + 1));
+                        trace::log(
+                            trace::REQUESTS,
+                            &format!(
+                                "http: rate limited, retry {} of 2 in {delay:?}",
+                                attempt + 1
+                            ),
+                        );
+                        until_canceled(cancel, sleep(delay)).await?;
+                        continue;
+                    }
+                    match until_canceled(cancel, handler(response)).await? {
+                        Ok(value) => return Ok(value),
+                        Err(err) => {
+                            last_err = Some(err);
+                            break;
+                        }
+                    }
+                }
+                Err(err) => {
+                    last_err = Some(err.into());
+                    let delay = Duration::from_millis(250 * (attempt as u64 + 1));
+                    if attempt < 2 {
+                        trace::log(
+                            trace::REQUESTS,
+                            &format!("http: retry {} of 2 in {delay:?}", attempt + 1),
+                        );
+                    }
+                    until_canceled(cancel, sleep(delay)).await?;
+                }
+            }
+        }
+
+        Err(last_err.unwrap_or_else(|| anyhow!("failed to call google api")))
+    }
+
+    async fn apply_auth(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::RequestBuilder> {
+        if let Some(key) = &self.config.api_key {
+            Ok(request.query(&[("key", key)]))
+        } else if let Some(token) = self.ensure_token().await? {
+            Ok(request.bearer_auth(token))
+        } else {
+            Err(anyhow!("google provider '{}' lacks credentials", self.name))
+        }
+    }
+
+    fn build_payload(
+        &self,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+    ) -> GeminiRequest {
+        let system_instruction = system.map(|text| GeminiContent {
+            role: "system".to_string(),
+            parts: vec![GeminiPart::text(text)],
+        });
+
+        let mut contents: Vec<GeminiContent> = Vec::new();
+        for msg in messages {
+            match msg.role {
+                MessageRole::System => {}
+                MessageRole::Tool => {
+                    let part = GeminiPart {
+                        function_response: Some(GeminiFunctionResponse {
+                            name: msg.tool_name(messages).unwrap_or_default().to_string(),
+                            response: serde_json::json!({ "content": msg.content }),
+                        }),
+                        ..Default::default()
+                    };
+                    // Responses to one model turn are grouped into a single content.
+                    match contents.last_mut() {
+                        Some(last)
+                            if last.role == "user"
+                                && last.parts.iter().all(|p| p.function_response.is_some()) =>
+                        {
+                            last.parts.push(part)
+                        }
+                        _ => contents.push(GeminiContent {
+                            role: "user".to_string(),
+                            parts: vec![part],
+                        }),
+                    }
+                }
+                MessageRole::User | MessageRole::Assistant => {
+                    let mut parts = Vec::new();
+                    if !msg.content.is_empty() || msg.tool_calls.is_empty() {
+                        parts.push(GeminiPart::text(&msg.content));
+                    }
+                    parts.extend(msg.tool_calls.iter().map(|call| GeminiPart {
+                        function_call: Some(GeminiFunctionCall {
+                            name: call.name.clone(),
+                            args: call.arguments.clone(),
+                        }),
+                        ..Default::default()
+                    }));
+                    contents.push(GeminiContent {
+                        role: if msg.role == MessageRole::User {
+                            "user"
+                        } else {
+                            "model"
+                        }
+                        .to_string(),
+                        parts,
+                    });
+                }
+            }
+        }
+
+        let tools = (!options.tools.is_empty()).then(|| {
+            vec![GeminiTool {
+                function_declarations: options
+                    .tools
+                    .iter()
+                    .map(|tool| GeminiFunctionDeclaration {
+                        name: tool.name.clone(),
+                        description: tool.description.clone(),
+                        parameters: tool.parameters.clone(),
+                    })
+                    .collect(),
+            }]
+        });
+
+        GeminiRequest {
+            contents,
+            tool_config: (tools.is_some() && options.forbid_tool_calls).then_some(
+                GeminiToolConfig {
+                    function_calling_config: GeminiFunctionCallingConfig { mode: "NONE" },
+                },
+            ),
+            tools,
+            system_instruction,
+            generation_config: Some(GeminiGenerationConfig {
+                temperature: options.temperature,
+                max_output_tokens: options.max_output_tokens,
+            }),
+        }
+    }
+
+    fn parse_stream_payload(payload: &str) -> Result<Vec<StreamEvent>> {
+        let body = payload.trim();
+        if body.is_empty() || body == "[DONE]" {
+            return Ok(Vec::new());
+        }
+
+        if body.starts_with('[') {
+            let chunks: Vec<GeminiStreamChunk> = serde_json::from_str(body)
+                .with_context(|| format!("failed to parse stream chunk array: {body}"))?;
+            Ok(chunks.iter().flat_map(GeminiStreamChunk::events).collect())
+        } else {
+            let chunk: GeminiStreamChunk = serde_json::from_str(body)
+                .with_context(|| format!("failed to parse stream chunk: {body}"))?;
+            Ok(chunk.events())
+        }
+    }
+
+    /// Gemini may resend the full text so far; turn text snapshots into deltas.
+    fn snapshot_deltas(snapshot: &mut String, events: Vec<StreamEvent>) -> Vec<StreamEvent> {
+        events
+            .into_iter()
+            .filter_map(|event| match event {
+                StreamEvent::Text(text) => {
+                    let delta = Self::extract_delta(snapshot, &text);
+                    (!delta.is_empty()).then_some(StreamEvent::Text(delta))
+                }
+                other => Some(other),
+            })
+            .collect()
+    }
+
+    fn extract_delta(snapshot: &mut String, incoming: &str) -> String {
+        if incoming.is_empty() {
+            return String::new();
+        }
+
+        if incoming.starts_with(snapshot.as_str()) {
+            let delta = &incoming[snapshot.len()..];
+            *snapshot = incoming.to_string();
+            return delta.to_string();
+        }
+
+        if snapshot.starts_with(incoming) {
+            return String::new();
+        }
+
+        *snapshot = incoming.to_string();
+        incoming.to_string()
+    }
+}
+
+#[async_trait]
+impl Provider for GoogleProvider {
+    async fn chat(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+        cancel: &CancellationToken,
+    ) -> Result<ChatResponse> {
+        let payload = self.build_payload(system, messages, options);
+
+        let response = self.execute_request(model, &payload, cancel).await?;
+        let Some(candidate) = response.candidates.first() else {
+            let blocked = response
+                .prompt_feedback
+                .as_ref()
+                .and_then(|feedback| feedback.block_reason.clone());
+            return Err(match blocked {
+                Some(reason) => ContentBlocked {
+                    provider: "gemini",
+                    reason,
+                }
+                .into(),
+                None => anyhow!("gemini response missing content"),
+            });
+        };
+        let stamp = chrono::Utc::now().timestamp_millis();
+        let tool_calls: Vec<ToolCall> = candidate
+            .content
+            .parts
+            .iter()
+            .filter_map(|part| part.function_call.as_ref())
+            .enumerate()
+            .map(|(index, call)| ToolCall {
+                // Gemini has no call ids; synthesize unique ones for the history.
+                id: format!("gemini-{stamp}-{index}"),
+                name: call.name.clone(),
+                arguments: call.args.clone(),
+            })
+            .collect();
+        let text = match candidate.content.text() {
+            Some(text) => text,
+            None if !tool_calls.is_empty() => String::new(),
+            None => {
+                return Err(match candidate.finish_reason.as_deref() {
+                    Some(
+                        reason @ ("SAFETY" | "BLOCKLIST" | "PROHIBITED_CONTENT" | "SPII"
+                        | "RECITATION" | "IMAGE_SAFETY"),
+                    ) => ContentBlocked {
+                        provider: "gemini",
+                        reason: reason.to_string(),
+                    }
+                    .into(),
+                    _ => anyhow!("gemini response missing content"),
+                })
+            }
+        };
+        Ok(ChatResponse {
+            text,
+            usage: response.usage_metadata.map(TokenUsage::from),
+            finish_reason: candidate.finish_reason.clone(),
+            tool_calls,
+            citations: candidate.citations(),
+        })
+    }
+
+    fn preview_chat(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+    ) -> Result<RequestPreview> {
+        let payload = self.build_payload(system, messages, options);
+        let url = format!("{BASE_URL}/models/{model}:generateContent");
+        Ok(match &self.config.api_key {
+            Some(key) => RequestPreview::post(format!("{url}?key={}", config::mask(key)), payload)?,
+            // Shown without fetching a real token.
+            None => RequestPreview::post(url, payload)?
+                .header("authorization", "Bearer <service account access token>"),
+        })
+    }
+
+    async fn stream_chat(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+        cancel: &CancellationToken,
+    ) -> Result<ChatStream> {
+        let payload = self.build_payload(system, messages, options);
+        self.execute_stream_request(model, &payload, cancel).await
+    }
+
+    async fn count_tokens(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+    ) -> Result<Option<usize>> {
+        let mut request = serde_json::to_value(self.build_payload(system, messages, options))?;
+        if let Some(fields) = request.as_object_mut() {
+            fields.remove("generation_config");
+            fields.insert("model".to_string(), format!("models/{model}").into());
+        }
+        let url = format!("{BASE_URL}/models/{model}:countTokens");
+        let payload = serde_json::json!({ "generateContentRequest": request });
+        let counted: GeminiTokenCount = self
+            .with_retries(
+                &url,
+                &payload,
+                &CancellationToken::new(),
+                |response| async move {
+                    let response = response
+                        .check_status()
+                        .context("google token count api error")?;
+                    trace::json(response)
+                        .await
+                        .context("failed to deserialize gemini token count")
+                },
+            )
+            .await?;
+        Ok(Some(counted.total_tokens as usize))
+    }
+
+    async fn embed(&self, model: &str, inputs: &[String]) -> Result<Vec<Vec<f32>>> {
+        let model = if model.trim().is_empty() {
+            DEFAULT_EMBEDDING_MODEL
+        } else {
+            model
+        };
+        let url = format!("{BASE_URL}/models/{model}:batchEmbedContents");
+        let requests: Vec<_> = inputs
+            .iter()
+            .map(|text| {
+                serde_json::json!({
+                    "model": format!("models/{model}"),
+                    "content": { "parts": [{ "text": text }] },
+                })
+            })
+            .collect();
+        let payload = serde_json::json!({ "requests": requests });
+        let response: GeminiEmbedResponse = self
+            .with_retries(
+                &url,
+                &payload,
+                &CancellationToken::new(),
+                |response| async move {
+                    let response = response
+                        .check_status()
+                        .context("google embeddings api error")?;
+                    trace::json(response)
+                        .await
+                        .context("failed to deserialize gemini embeddings")
+                },
+            )
+            .await?;
+        Ok(response
+            .embeddings
+            .into_iter()
+            .map(|embedding| embedding.values)
+            .collect())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiTokenCount {
+    total_tokens: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct GeminiEmbedResponse {
+    #[serde(default)]
+    embeddings: Vec<GeminiEmbedding>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GeminiEmbedding {
+    values: Vec<f32>,
+}
+
+#[derive(Debug, Serialize)]
+struct GeminiRequest {
+    contents: Vec<GeminiContent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<Vec<GeminiTool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_config: Option<GeminiToolConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system_instruction: Option<GeminiContent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    generation_config: Option<GeminiGenerationConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+struct GeminiContent {
+    role: String,
+    parts: Vec<GeminiPart>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+struct GeminiPart {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(
+        default,
+        alias = "functionCall",
+        skip_serializing_if = "Option::is_none"
+    )]
+    function_call: Option<GeminiFunctionCall>,
+    #[serde(
+        default,
+        alias = "functionResponse",
+        skip_serializing_if = "Option::is_none"
+    )]
+    function_response: Option<GeminiFunctionResponse>,
+}
+
+impl GeminiPart {
+    fn text(text: &str) -> Self {
+        Self {
+            text: Some(text.to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct GeminiFunctionCall {
+    name: String,
+    #[serde(default)]
+    args: serde_json::Value,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct GeminiFunctionResponse {
+    name: String,
+    response: serde_json::Value,
+}
+
+#[derive(Debug, Serialize)]
+struct GeminiTool {
+    function_declarations: Vec<GeminiFunctionDeclaration>,
+}
+
+#[derive(Debug, Serialize)]
+struct GeminiToolConfig {
+    function_calling_config: GeminiFunctionCallingConfig,
+}
+
+#[derive(Debug, Serialize)]
+struct GeminiFunctionCallingConfig {
+    mode: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+struct GeminiFunctionDeclaration {
+    name: String,
+    description: String,
+    parameters: serde_json::Value,
+}
+
+#[derive(Debug, Serialize)]
+struct GeminiGenerationConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_output_tokens: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiResponse {
+    /// Absent when the prompt itself was blocked
+    #[serde(default)]
+    candidates: Vec<GeminiCandidate>,
+    #[serde(default)]
+    usage_metadata: Option<GeminiUsage>,
+    #[serde(default)]
+    prompt_feedback: Option<GeminiPromptFeedback>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiPromptFeedback {
+    #[serde(default)]
+    block_reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiCandidate {
+    /// Absent when the reply was blocked
+    #[serde(default)]
+    content: GeminiContent,
+    #[serde(default)]
+    finish_reason: Option<String>,
+    #[serde(default)]
+    citation_metadata: Option<GeminiCitationMetadata>,
+    #[serde(default)]
+    grounding_metadata: Option<GeminiGroundingMetadata>,
+}
+
+impl GeminiCandidate {
+    /// Recitation sources followed by Google Search grounding results.
+    fn citations(&self) -> Vec<Citation> {
+        let recited = self
+            .citation_metadata
+            .iter()
+            .flat_map(|metadata| &metadata.citations)
+            .map(|source| Citation {
+                url: source.uri.clone(),
+                title: source.title.clone(),
+            });
+        let grounded = self
+            .grounding_metadata
+            .iter()
+            .flat_map(|metadata| &metadata.grounding_chunks)
+            .filter_map(|chunk| chunk.web.as_ref())
+            .map(|web| Citation {
+                url: web.uri.clone(),
+                title: web.title.clone(),
+            });
+        Citation::dedup(recited.chain(grounded))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiCitationMetadata {
+    /// `citations` on Vertex AI, `citationSources` on the Gemini API
+    #[serde(default, alias = "citationSources")]
+    citations: Vec<GeminiCitationSource>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiCitationSource {
+    #[serde(default)]
+    uri: Option<String>,
+    #[serde(default)]
+    title: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiGroundingMetadata {
+    #[serde(default)]
+    grounding_chunks: Vec<GeminiGroundingChunk>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiGroundingChunk {
+    #[serde(default)]
+    web: Option<GeminiCitationSource>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+struct GeminiUsage {
+    #[serde(default)]
+    prompt_token_count: u32,
+    #[serde(default)]
+    candidates_token_count: u32,
+}
+
+impl From<GeminiUsage> for TokenUsage {
+    fn from(value: GeminiUsage) -> Self {
+        TokenUsage {
+            input_tokens: value.prompt_token_count,
+            output_tokens: value.candidates_token_count,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiStreamChunk {
+    #[serde(default)]
+    candidates: Vec<GeminiCandidate>,
+    #[serde(default)]
+    usage_metadata: Option<GeminiUsage>,
+}
+
+impl GeminiContent {
+    fn text(&self) -> Option<String> {
+        let mut buf = String::new();
+        for part in &self.parts {
+            if let Some(piece) = part.text.as_ref() {
+                buf.push_str(piece);
+            }
+        }
+        if buf.is_empty() {
+            None
+        } else {
+            Some(buf)
+        }
+    }
+}
+
+impl GeminiStreamChunk {
+    fn merge_text(&self) -> Option<String> {
+        self.candidates
+            .first()
+            .and_then(|candidate| candidate.content.text())
+    }
+
+    fn events(&self) -> Vec<StreamEvent> {
+        let mut events: Vec<StreamEvent> = self
+            .merge_text()
+            .map(StreamEvent::Text)
+            .into_iter()
+            .collect();
+        if let Some(usage) = self.usage_metadata {
+            events.push(StreamEvent::Usage(usage.into()));
+        }
+        let reason = self
+            .candidates
+            .first()
+            .and_then(|c| c.finish_reason.clone());
+        events.extend(reason.map(StreamEvent::Finish));
+        events
+    }
+}
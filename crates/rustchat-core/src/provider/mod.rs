@@ -0,0 +1,104 @@
+mod trait_provider;
+
+pub mod anthropic;
+pub mod google;
+pub mod openai;
+
+use anyhow::Result;
+
+pub use tokio_util::sync::CancellationToken;
+pub use trait_provider::{
+    until_canceled, Canceled, ChatMessage, ChatRequestOptions, ChatResponse, Citation,
+    ContentBlocked, DynProvider, MessageRole, Provider, ReplyMeta, RequestPreview, TokenUsage,
+    ToolCall, ToolSpec,
+};
+
+use crate::config::{ApiKeyProviderConfig, ProviderConfig};
+use crate::secrets;
+
+/// Builds the client for the provider configured as `name`. `passphrase`
+/// unlocks an encrypted API key; `env_label` is the variable that should have
+/// held it, for error messages.
+pub async fn build_provider(
+    name: &str,
+    cfg: &ProviderConfig,
+    passphrase: Option<&str>,
+    env_label: &str,
+) -> Result<trait_provider::DynProvider> {
+    Ok(match cfg {
+        ProviderConfig::Google(google_cfg) => {
+            let mut google_cfg = google_cfg.clone();
+            if let Some(key) = external_api_key(
+                name,
+                google_cfg.api_key_env.as_deref(),
+                google_cfg.api_key_cmd.as_deref(),
+                google_cfg.keychain,
+            )
+            .await?
+            {
+                google_cfg.api_key = Some(key);
+            }
+            let provider =
+                google::GoogleProvider::new(name.into(), google_cfg, passphrase, env_label).await?;
+            std::sync::Arc::new(provider) as trait_provider::DynProvider
+        }
+        ProviderConfig::Anthropic(anthropic_cfg) => {
+            let provider = anthropic::AnthropicProvider::new(
+                name.into(),
+                with_external_api_key(name, anthropic_cfg).await?,
+                passphrase,
+                env_label,
+            )
+            .await?;
+            std::sync::Arc::new(provider) as trait_provider::DynProvider
+        }
+        ProviderConfig::Openai(openai_cfg) => {
+            let provider = openai::OpenAiProvider::new(
+                name.into(),
+                with_external_api_key(name, openai_cfg).await?,
+                passphrase,
+                env_label,
+            )
+            .await?;
+            std::sync::Arc::new(provider) as trait_provider::DynProvider
+        }
+    })
+}
+
+/// Resolves `api_key_env` / `api_key_cmd` / `keychain` so the key itself never has to live in
+/// the config.
+async fn with_external_api_key(
+    name: &str,
+    cfg: &ApiKeyProviderConfig,
+) -> Result<ApiKeyProviderConfig> {
+    let mut cfg = cfg.clone();
+    if let Some(key) = external_api_key(
+        name,
+        cfg.api_key_env.as_deref(),
+        cfg.api_key_cmd.as_deref(),
+        cfg.keychain,
+    )
+    .await?
+    {
+        cfg.api_key = Some(key);
+    }
+    Ok(cfg)
+}
+
+async fn external_api_key(
+    name: &str,
+    api_key_env: Option<&str>,
+    api_key_cmd: Option<&str>,
+    keychain: bool,
+) -> Result<Option<String>> {
+    if let Some(var) = api_key_env {
+        return secrets::api_key_from_env(name, var).map(Some);
+    }
+    if let Some(command) = api_key_cmd {
+        return secrets::api_key_from_command(name, command).await.map(Some);
+    }
+    if keychain {
+        return secrets::keychain_api_key(name).await.map(Some);
+    }
+    Ok(None)
+}
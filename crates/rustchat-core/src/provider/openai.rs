@@ -0,0 +1,546 @@
+use anyhow::{anyhow, Context, Result};
+use async_stream::try_stream;
+use async_trait::async_trait;
+use futures::{pin_mut, StreamExt};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{self, ApiKeyProviderConfig};
+use crate::provider::{
+    until_canceled, CancellationToken, ChatMessage, ChatRequestOptions, ChatResponse, Citation,
+    ContentBlocked, MessageRole, Provider, RequestPreview, TokenUsage, ToolCall,
+};
+use crate::streaming::{ChatStream, StreamEvent};
+use crate::trace::ResponseExt;
+use crate::{secrets, trace};
+
+const DEFAULT_BASE_URL: &str = "https://api.openai.com";
+const DEFAULT_EMBEDDING_MODEL: &str = "text-embedding-3-small";
+
+pub struct OpenAiProvider {
+    config: ApiKeyProviderConfig,
+    client: Client,
+    api_key: String,
+    base_url: String,
+}
+
+impl OpenAiProvider {
+    pub async fn new(
+        name: String,
+        mut config: ApiKeyProviderConfig,
+        passphrase: Option<&str>,
+        env_label: &str,
+    ) -> Result<Self> {
+        let api_key = secrets::require_secret(
+            config.api_key.as_deref(),
+            config.encrypted_api_key.as_ref(),
+            passphrase,
+            env_label,
+            &format!("openai provider '{name}' requires --api-key"),
+        )?;
+        trace::register_secret(&api_key);
+        config.api_key = Some(api_key.clone());
+        config.encrypted_api_key = None;
+        let client = Client::builder().build()?;
+        let base_url = config
+            .base_url
+            .clone()
+            .unwrap_or_else(|| DEFAULT_BASE_URL.to_string());
+
+        Ok(Self {
+            config,
+            client,
+            api_key,
+            base_url,
+        })
+    }
+
+    fn endpoint(&self) -> String {
+        format!(
+            "{}/v1/chat/completions",
+            self.base_url.trim_end_matches('/')
+        )
+    }
+
+    /// Whether to ask for token usage at the end of a stream. Only OpenAI
+    /// itself is asked: compatible servers and older Azure deployments reject
+    /// `stream_options`, and their streams simply come without usage.
+    fn stream_usage(&self) -> bool {
+        reqwest::Url::parse(&self.base_url)
+            .is_ok_and(|url| url.host_str() == Some("api.openai.com"))
+    }
+
+    fn build_payload(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+        stream: bool,
+    ) -> OpenAiRequest {
+        let selected_model = if model.trim().is_empty() {
+            self.config
+                .default_model
+                .clone()
+                .unwrap_or_else(|| "gpt-4o-mini".to_string())
+        } else {
+            model.to_string()
+        };
+
+        let mut converted = Vec::new();
+        if let Some(system_prompt) = system {
+            converted.push(OpenAiMessage::new("system", system_prompt));
+        }
+        for msg in messages {
+            match msg.role {
+                MessageRole::System => {
+                    converted.push(OpenAiMessage::new("system", &msg.content));
+                }
+                MessageRole::User => converted.push(OpenAiMessage::new("user", &msg.content)),
+                MessageRole::Assistant => {
+                    let mut message = OpenAiMessage::new("assistant", &msg.content);
+                    if !msg.tool_calls.is_empty() {
+                        if msg.content.is_empty() {
+                            message.content = None;
+                        }
+                        message.tool_calls = Some(
+                            msg.tool_calls
+                                .iter()
+                                .map(OpenAiToolCall::from_call)
+                                .collect(),
+                        );
+                    }
+                    converted.push(message);
+                }
+                MessageRole::Tool => {
+                    let mut message = OpenAiMessage::new("tool", &msg.content);
+                    message.tool_call_id = msg.tool_call_id.clone();
+                    converted.push(message);
+                }
+            }
+        }
+
+        let tools = (!options.tools.is_empty()).then(|| {
+            options
+                .tools
+                .iter()
+                .map(|tool| OpenAiTool {
+                    kind: "function",
+                    function: OpenAiFunction {
+                        name: tool.name.clone(),
+                        description: tool.description.clone(),
+                        parameters: tool.parameters.clone(),
+                    },
+                })
+                .collect()
+        });
+
+        OpenAiRequest {
+            model: selected_model,
+            messages: converted,
+            max_tokens: options.max_output_tokens,
+            temperature: options.temperature,
+            stream,
+            stream_options: (stream && self.stream_usage()).then_some(OpenAiStreamOptions {
+                include_usage: true,
+            }),
+            tool_choice: (tools.is_some() && options.forbid_tool_calls).then_some("none"),
+            tools,
+        }
+    }
+
+    fn request_builder(&self) -> reqwest::RequestBuilder {
+        self.client
+            .post(self.endpoint())
+            .header("authorization", format!("Bearer {}", self.api_key))
+    }
+
+    fn parse_stream_event(payload: &str) -> Result<Vec<StreamEvent>> {
+        let trimmed = payload.trim();
+        if trimmed.is_empty() || trimmed == "[DONE]" {
+            return Ok(Vec::new());
+        }
+
+        let chunk: OpenAiStreamChunk = serde_json::from_str(trimmed)
+            .with_context(|| format!("failed to parse openai stream chunk: {trimmed}"))?;
+        let mut events = Vec::new();
+        for choice in chunk.choices {
+            if let Some(delta) = choice.delta {
+                if let Some(content) = delta.content {
+                    if !content.is_empty() {
+                        events.push(StreamEvent::Text(content));
+                    }
+                }
+            }
+            if let Some(reason) = choice.finish_reason {
+                events.push(StreamEvent::Finish(reason));
+            }
+        }
+        if let Some(usage) = chunk.usage {
+            events.push(StreamEvent::Usage(usage.into()));
+        }
+        Ok(events)
+    }
+}
+
+#[async_trait]
+impl Provider for OpenAiProvider {
+    async fn chat(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+        cancel: &CancellationToken,
+    ) -> Result<ChatResponse> {
+        let payload = self.build_payload(model, system, messages, options, false);
+        let response = until_canceled(cancel, trace::send(self.request_builder().json(&payload)))
+            .await?
+            .context("openai request failed")?
+            .check_status()
+            .context("openai api error")?;
+        let response: OpenAiResponse = until_canceled(cancel, trace::json(response))
+            .await?
+            .context("failed to parse openai response")?;
+
+        let choice = response
+            .choices
+            .first()
+            .ok_or_else(|| anyhow!("openai response missing content"))?;
+        let tool_calls = choice
+            .message
+            .tool_calls
+            .iter()
+            .map(OpenAiToolCall::to_call)
+            .collect::<Result<Vec<_>>>()?;
+        let text = choice.message.content.clone().unwrap_or_default();
+        if text.is_empty() && tool_calls.is_empty() {
+            if choice.finish_reason.as_deref() == Some("content_filter") {
+                return Err(ContentBlocked {
+                    provider: "openai",
+                    reason: "content_filter".to_string(),
+                }
+                .into());
+            }
+            return Err(anyhow!("openai response missing content"));
+        }
+        Ok(ChatResponse {
+            text,
+            usage: response.usage.map(TokenUsage::from),
+            finish_reason: choice.finish_reason.clone(),
+            tool_calls,
+            citations: Citation::dedup(choice.message.annotations.iter().filter_map(|note| {
+                let cited = note.url_citation.as_ref()?;
+                Some(Citation {
+                    url: Some(cited.url.clone()),
+                    title: cited.title.clone(),
+                })
+            })),
+        })
+    }
+
+    fn preview_chat(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+    ) -> Result<RequestPreview> {
+        let payload = self.build_payload(model, system, messages, options, false);
+        Ok(RequestPreview::post(self.endpoint(), payload)?.header(
+            "authorization",
+            format!("Bearer {}", config::mask(&self.api_key)),
+        ))
+    }
+
+    async fn stream_chat(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+        cancel: &CancellationToken,
+    ) -> Result<ChatStream> {
+        let payload = self.build_payload(model, system, messages, options, true);
+        let request = self
+            .request_builder()
+            .header("accept", "text/event-stream")
+            .json(&payload);
+        let response = until_canceled(cancel, trace::send(request))
+            .await?
+            .context("openai stream request failed")?
+            .check_status()
+            .context("openai stream api error")?;
+
+        let body = response.bytes_stream();
+        let cancel = cancel.clone();
+        let stream = try_stream! {
+            let mut buffer = String::new();
+            let mut event_payload = String::new();
+            pin_mut!(body);
+
+            while let Some(chunk) = until_canceled(&cancel, body.next()).await? {
+                let chunk = chunk.context("openai stream chunk error")?;
+                let text = String::from_utf8_lossy(&chunk);
+                buffer.push_str(&text);
+
+                while let Some(pos) = buffer.find('\n') {
+                    let mut line: String = buffer.drain(..=pos).collect();
+                    if line.ends_with('\n') {
+                        line.pop();
+                    }
+                    if line.ends_with('\r') {
+                        line.pop();
+                    }
+
+                    if line.is_empty() {
+                        if !event_payload.is_empty() {
+                            for event in Self::parse_stream_event(&event_payload)? {
+                                yield event;
+                            }
+                            event_payload.clear();
+                        }
+                        continue;
+                    }
+
+                    if let Some(rest) = line.strip_prefix("data:") {
+                        let trimmed = rest.trim_start();
+                        if trimmed == "[DONE]" {
+                            event_payload.clear();
+                            continue;
+                        }
+                        event_payload.push_str(trimmed);
+                    }
+                }
+            }
+
+            if !event_payload.trim().is_empty() {
+                for event in Self::parse_stream_event(&event_payload)? {
+                    yield event;
+                }
+            }
+        };
+
+        Ok(Box::pin(stream) as ChatStream)
+    }
+
+    async fn embed(&self, model: &str, inputs: &[String]) -> Result<Vec<Vec<f32>>> {
+        let model = if model.trim().is_empty() {
+            DEFAULT_EMBEDDING_MODEL
+        } else {
+            model
+        };
+        let url = format!("{}/v1/embeddings", self.base_url.trim_end_matches('/'));
+        let request = self
+            .client
+            .post(url)
+            .header("authorization", format!("Bearer {}", self.api_key))
+            .json(&serde_json::json!({ "model": model, "input": inputs }));
+        let response = trace::send(request)
+            .await
+            .context("openai embeddings request failed")?
+            .check_status()
+            .context("openai embeddings api error")?;
+        let mut response: OpenAiEmbedResponse = trace::json(response)
+            .await
+            .context("failed to parse openai embeddings")?;
+        response.data.sort_by_key(|item| item.index);
+        Ok(response
+            .data
+            .into_iter()
+            .map(|item| item.embedding)
+            .collect())
+    }
+}
+
+#[derive(Deserialize)]
+struct OpenAiEmbedResponse {
+    data: Vec<OpenAiEmbedding>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiEmbedding {
+    #[serde(default)]
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+#[derive(Serialize)]
+struct OpenAiRequest {
+    model: String,
+    messages: Vec<OpenAiMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(default)]
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<OpenAiStreamOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<Vec<OpenAiTool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<&'static str>,
+}
+
+#[derive(Serialize)]
+struct OpenAiTool {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    function: OpenAiFunction,
+}
+
+#[derive(Serialize)]
+struct OpenAiFunction {
+    name: String,
+    description: String,
+    parameters: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize)]
+struct OpenAiToolCall {
+    id: String,
+    #[serde(rename = "type", default = "function_kind")]
+    kind: String,
+    function: OpenAiFunctionCall,
+}
+
+#[derive(Serialize, Deserialize)]
+struct OpenAiFunctionCall {
+    name: String,
+    /// JSON-encoded argument object
+    #[serde(default)]
+    arguments: String,
+}
+
+fn function_kind() -> String {
+    "function".to_string()
+}
+
+impl OpenAiToolCall {
+    fn from_call(call: &ToolCall) -> Self {
+        Self {
+            id: call.id.clone(),
+            kind: function_kind(),
+            function: OpenAiFunctionCall {
+                name: call.name.clone(),
+                arguments: call.arguments.to_string(),
+            },
+        }
+    }
+
+    fn to_call(&self) -> Result<ToolCall> {
+        let arguments = if self.function.arguments.trim().is_empty() {
+            serde_json::Value::Object(Default::default())
+        } else {
+            serde_json::from_str(&self.function.arguments).with_context(|| {
+                format!("invalid arguments for tool call '{}'", self.function.name)
+            })?
+        };
+        Ok(ToolCall {
+            id: self.id.clone(),
+            name: self.function.name.clone(),
+            arguments,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct OpenAiStreamOptions {
+    include_usage: bool,
+}
+
+#[derive(Serialize)]
+struct OpenAiMessage {
+    role: String,
+    content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_calls: Option<Vec<OpenAiToolCall>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
+}
+
+impl OpenAiMessage {
+    fn new(role: &str, content: &str) -> Self {
+        Self {
+            role: role.to_string(),
+            content: Some(content.to_string()),
+            tool_calls: None,
+            tool_call_id: None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct OpenAiResponse {
+    choices: Vec<OpenAiChoice>,
+    #[serde(default)]
+    usage: Option<OpenAiUsage>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiUsage {
+    #[serde(default)]
+    prompt_tokens: u32,
+    #[serde(default)]
+    completion_tokens: u32,
+}
+
+impl From<OpenAiUsage> for TokenUsage {
+    fn from(value: OpenAiUsage) -> Self {
+        TokenUsage {
+            input_tokens: value.prompt_tokens,
+            output_tokens: value.completion_tokens,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct OpenAiChoice {
+    message: OpenAiChoiceMessage,
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiChoiceMessage {
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<OpenAiToolCall>,
+    /// `url_citation` annotations from web search models
+    #[serde(default)]
+    annotations: Vec<OpenAiAnnotation>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiAnnotation {
+    #[serde(default)]
+    url_citation: Option<OpenAiUrlCitation>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiUrlCitation {
+    url: String,
+    #[serde(default)]
+    title: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiStreamChunk {
+    #[serde(default)]
+    choices: Vec<OpenAiStreamChoice>,
+    #[serde(default)]
+    usage: Option<OpenAiUsage>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiStreamChoice {
+    delta: Option<OpenAiStreamDelta>,
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiStreamDelta {
+    #[allow(dead_code)]
+    role: Option<String>,
+    content: Option<String>,
+}
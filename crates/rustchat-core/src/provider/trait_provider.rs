@@ -0,0 +1,319 @@
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+use crate::streaming::ChatStream;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageRole {
+    System,
+    User,
+    Assistant,
+    /// Result of a tool call requested by the assistant
+    Tool,
+}
+
+impl MessageRole {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "system" => Some(MessageRole::System),
+            "user" => Some(MessageRole::User),
+            "assistant" => Some(MessageRole::Assistant),
+            "tool" => Some(MessageRole::Tool),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for MessageRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageRole::System => write!(f, "system"),
+            MessageRole::User => write!(f, "user"),
+            MessageRole::Assistant => write!(f, "assistant"),
+            MessageRole::Tool => write!(f, "tool"),
+        }
+    }
+}
+
+/// A function the model may call, described with a JSON schema.
+#[derive(Clone, Debug)]
+pub struct ToolSpec {
+    pub name: String,
+    pub description: String,
+    pub parameters: serde_json::Value,
+}
+
+/// A tool invocation requested by the model.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: serde_json::Value,
+}
+
+/// Where an assistant turn came from and what it cost.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReplyMeta {
+    pub provider: String,
+    pub model: String,
+    pub usage: Option<TokenUsage>,
+    pub latency: Duration,
+}
+
+#[derive(Clone, Debug)]
+pub struct ChatMessage {
+    pub role: MessageRole,
+    pub content: String,
+    /// Tool calls requested by an assistant turn
+    pub tool_calls: Vec<ToolCall>,
+    /// For `Tool` messages, the id of the call this result answers
+    pub tool_call_id: Option<String>,
+    /// When the message was created; unknown for older saved transcripts
+    pub timestamp: Option<DateTime<Utc>>,
+    /// For assistant turns, the provider/model that produced it
+    pub reply: Option<ReplyMeta>,
+}
+
+impl ChatMessage {
+    pub fn new<S: Into<String>>(role: MessageRole, content: S) -> Self {
+        Self {
+            role,
+            content: content.into(),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+            timestamp: Some(Utc::now()),
+            reply: None,
+        }
+    }
+
+    pub fn with_reply(self, reply: ReplyMeta) -> Self {
+        Self {
+            reply: Some(reply),
+            ..self
+        }
+    }
+
+    pub fn assistant_with_tools<S: Into<String>>(content: S, tool_calls: Vec<ToolCall>) -> Self {
+        Self {
+            tool_calls,
+            ..Self::new(MessageRole::Assistant, content)
+        }
+    }
+
+    pub fn tool_result<S: Into<String>>(call_id: &str, content: S) -> Self {
+        Self {
+            tool_call_id: Some(call_id.to_string()),
+            ..Self::new(MessageRole::Tool, content)
+        }
+    }
+
+    /// Name of the tool a `Tool` message answers, looked up in `history`.
+    pub fn tool_name<'a>(&self, history: &'a [ChatMessage]) -> Option<&'a str> {
+        let id = self.tool_call_id.as_deref()?;
+        history
+            .iter()
+            .flat_map(|message| message.tool_calls.iter())
+            .find(|call| call.id == id)
+            .map(|call| call.name.as_str())
+    }
+
+    pub fn user<S: Into<String>>(content: S) -> Self {
+        Self::new(MessageRole::User, content)
+    }
+
+    pub fn assistant<S: Into<String>>(content: S) -> Self {
+        Self::new(MessageRole::Assistant, content)
+    }
+}
+
+/// Token accounting reported by a provider for a single request.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub input_tokens: u32,
+    pub output_tokens: u32,
+}
+
+impl TokenUsage {
+    /// Merges a (possibly partial) usage report into this one. Streaming APIs
+    /// report input and output counts in separate events, so zero fields are
+    /// treated as "not reported".
+    pub fn absorb(&mut self, other: TokenUsage) {
+        if other.input_tokens > 0 {
+            self.input_tokens = other.input_tokens;
+        }
+        if other.output_tokens > 0 {
+            self.output_tokens = other.output_tokens;
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ChatResponse {
+    pub text: String,
+    pub usage: Option<TokenUsage>,
+    pub finish_reason: Option<String>,
+    pub tool_calls: Vec<ToolCall>,
+    /// Sources the reply cites, when the API reports them
+    pub citations: Vec<Citation>,
+}
+
+/// A source a reply draws on (web search results, grounding, documents).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Citation {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+}
+
+impl Citation {
+    /// Drops empty and repeated citations, keeping the first occurrence.
+    pub fn dedup(citations: impl IntoIterator<Item = Citation>) -> Vec<Citation> {
+        let mut unique: Vec<Citation> = Vec::new();
+        for citation in citations {
+            if (citation.url.is_some() || citation.title.is_some()) && !unique.contains(&citation) {
+                unique.push(citation);
+            }
+        }
+        unique
+    }
+}
+
+/// The provider's safety filters withheld the reply.
+#[derive(Debug, thiserror::Error)]
+#[error("{provider} blocked the response ({reason})")]
+pub struct ContentBlocked {
+    pub provider: &'static str,
+    /// The finish or block reason the API gave, e.g. `SAFETY`
+    pub reason: String,
+}
+
+/// The request was given up on through its `CancellationToken`.
+#[derive(Debug, thiserror::Error)]
+#[error("canceled")]
+pub struct Canceled;
+
+/// Runs `future` unless `cancel` fires first, which fails with `Canceled`.
+pub async fn until_canceled<F: Future>(cancel: &CancellationToken, future: F) -> Result<F::Output> {
+    cancel
+        .run_until_cancelled(future)
+        .await
+        .ok_or_else(|| Canceled.into())
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ChatRequestOptions {
+    pub temperature: Option<f32>,
+    pub max_output_tokens: Option<u32>,
+    /// Tools offered to the model; empty disables function calling
+    pub tools: Vec<ToolSpec>,
+    /// Keeps `tools` declared, since earlier turns may refer to them, but
+    /// asks the model to answer in text without calling any
+    pub forbid_tool_calls: bool,
+}
+
+/// The HTTP request a provider would send, as `--dry-run` prints it.
+/// Credentials are already masked.
+#[derive(Debug, Serialize)]
+pub struct RequestPreview {
+    pub method: &'static str,
+    pub url: String,
+    #[serde(serialize_with = "headers_as_map")]
+    pub headers: Vec<(String, String)>,
+    pub body: serde_json::Value,
+}
+
+impl RequestPreview {
+    pub fn post(url: String, body: impl Serialize) -> Result<Self> {
+        Ok(Self {
+            method: "POST",
+            url,
+            headers: vec![("content-type".to_string(), "application/json".to_string())],
+            body: serde_json::to_value(body)?,
+        })
+    }
+
+    pub fn header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.push((name.to_string(), value.into()));
+        self
+    }
+}
+
+fn headers_as_map<S: serde::Serializer>(
+    headers: &[(String, String)],
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_map(headers.iter().map(|(name, value)| (name, value)))
+}
+
+impl fmt::Display for RequestPreview {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} {}", self.method, self.url)?;
+        for (name, value) in &self.headers {
+            writeln!(f, "{name}: {value}")?;
+        }
+        writeln!(f)?;
+        let body = serde_json::to_string_pretty(&self.body).map_err(|_| fmt::Error)?;
+        write!(f, "{body}")
+    }
+}
+
+/// `chat` and `stream_chat` give up with `Canceled` soon after `cancel`
+/// fires, between retries or mid-stream, rather than waiting the request out.
+#[async_trait]
+pub trait Provider: Send + Sync {
+    async fn chat(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+        cancel: &CancellationToken,
+    ) -> Result<ChatResponse>;
+
+    /// The request `chat` would send for these arguments, without sending it.
+    fn preview_chat(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+    ) -> Result<RequestPreview>;
+
+    async fn stream_chat(
+        &self,
+        _model: &str,
+        _system: Option<&str>,
+        _messages: &[ChatMessage],
+        _options: &ChatRequestOptions,
+        _cancel: &CancellationToken,
+    ) -> Result<ChatStream> {
+        bail!("streaming not supported by this provider yet");
+    }
+
+    /// How many tokens the prompt takes as the provider counts it, for
+    /// providers with a counting endpoint; `None` for the rest.
+    async fn count_tokens(
+        &self,
+        _model: &str,
+        _system: Option<&str>,
+        _messages: &[ChatMessage],
+        _options: &ChatRequestOptions,
+    ) -> Result<Option<usize>> {
+        Ok(None)
+    }
+
+    /// One embedding vector per input, for searching documents by meaning.
+    /// An empty `model` means the provider's default embedding model.
+    async fn embed(&self, _model: &str, _inputs: &[String]) -> Result<Vec<Vec<f32>>> {
+        bail!("embeddings are not supported by this provider");
+    }
+}
+
+pub type DynProvider = std::sync::Arc<dyn Provider>;
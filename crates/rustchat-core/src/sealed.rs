@@ -0,0 +1,155 @@
+use std::fmt;
+
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose, Engine as _};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+
+use crate::secrets::{self, EncryptedSecret, Kdf, DEFAULT_MASTER_ENV};
+
+/// Keychain entry holding the generated key of a keychain-sealed config.
+const KEYCHAIN_USER: &str = "config-encryption-key";
+
+/// Where the key of an encrypted `config.toml` comes from.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SealKey {
+    /// The master passphrase (`RUSTCHAT_PASSPHRASE` or a prompt)
+    Passphrase,
+    /// A random key kept in the OS keychain
+    Keychain,
+}
+
+/// On-disk form of an encrypted config: only the key source and the
+/// ciphertext of the whole TOML document are readable.
+#[derive(Serialize, Deserialize)]
+struct SealedFile {
+    encrypted: SealedConfig,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SealedConfig {
+    key: SealKey,
+    #[serde(flatten)]
+    secret: EncryptedSecret,
+}
+
+/// Key material kept in memory so saving an encrypted config re-encrypts it.
+#[derive(Clone)]
+pub struct Seal {
+    pub key: SealKey,
+    passphrase: String,
+}
+
+impl fmt::Debug for Seal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Seal").field("key", &self.key).finish()
+    }
+}
+
+impl Seal {
+    pub fn with_passphrase(passphrase: String) -> Self {
+        Self {
+            key: SealKey::Passphrase,
+            passphrase,
+        }
+    }
+
+    /// Generates a random key and stores it in the OS keychain.
+    pub fn new_in_keychain() -> Result<Self> {
+        let mut bytes = [0u8; 32];
+        SystemRandom::new()
+            .fill(&mut bytes)
+            .map_err(|_| anyhow::anyhow!("failed to read random bytes for the config key"))?;
+        let passphrase = general_purpose::STANDARD.encode(bytes);
+        secrets::with_keychain_entry_blocking(KEYCHAIN_USER, |entry| {
+            entry.set_password(&passphrase)
+        })
+        .context("failed to store the config key in the keychain")?;
+        Ok(Self {
+            key: SealKey::Keychain,
+            passphrase,
+        })
+    }
+
+    /// Encrypts `plaintext` (the serialized config) into the on-disk form.
+    pub fn seal(&self, plaintext: &str) -> Result<String> {
+        let file = SealedFile {
+            encrypted: SealedConfig {
+                key: self.key,
+                secret: secrets::encrypt_secret(&self.passphrase, plaintext, Kdf::Argon2id)?,
+            },
+        };
+        Ok(format!(
+            "# Encrypted with `rustchat-cli config encrypt`; `config decrypt` restores plain TOML\n{}",
+            toml::to_string_pretty(&file)?
+        ))
+    }
+
+    /// Drops the keychain entry once the config is stored in plain text again.
+    pub fn forget(&self) -> Result<()> {
+        if self.key == SealKey::Keychain {
+            secrets::with_keychain_entry_blocking(KEYCHAIN_USER, |entry| entry.delete_credential())
+                .context("failed to remove the config key from the keychain")?;
+        }
+        Ok(())
+    }
+}
+
+pub fn is_sealed(data: &str) -> bool {
+    toml::from_str::<toml::Table>(data).is_ok_and(|table| table.contains_key("encrypted"))
+}
+
+/// Decrypts an encrypted config, returning its TOML and the seal to save it
+/// with again; plain configs give `None`.
+pub fn open(data: &str) -> Result<Option<(String, Seal)>> {
+    if !is_sealed(data) {
+        return Ok(None);
+    }
+    let file: SealedFile = toml::from_str(data).context("malformed encrypted config")?;
+    let passphrase = match file.encrypted.key {
+        SealKey::Passphrase => {
+            let passphrase = secrets::optional_passphrase_from_env(DEFAULT_MASTER_ENV, false)?;
+            secrets::prompt_for_passphrase(passphrase, true, true, DEFAULT_MASTER_ENV)?
+                .unwrap_or_default()
+        }
+        SealKey::Keychain => {
+            secrets::with_keychain_entry_blocking(KEYCHAIN_USER, |entry| entry.get_password())
+                .context("failed to read the config key from the keychain")?
+        }
+    };
+    let plaintext = match secrets::decrypt_secret(&passphrase, &file.encrypted.secret) {
+        Ok(plaintext) => plaintext,
+        Err(_) => bail!("cannot decrypt the config - wrong passphrase or keychain entry"),
+    };
+    Ok(Some((
+        plaintext,
+        Seal {
+            key: file.encrypted.key,
+            passphrase,
+        },
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sealed_config_hides_everything_but_the_key_source() {
+        let seal = Seal::with_passphrase("correct horse".into());
+        let plaintext = "default_provider = \"work-openai\"\n";
+        let sealed = seal.seal(plaintext).unwrap();
+        assert!(is_sealed(&sealed));
+        assert!(!is_sealed(plaintext));
+        assert!(!sealed.contains("work-openai"));
+
+        let file: SealedFile = toml::from_str(&sealed).unwrap();
+        assert_eq!(file.encrypted.key, SealKey::Passphrase);
+        assert_eq!(file.encrypted.secret.kdf, Kdf::Argon2id);
+        assert_eq!(
+            secrets::decrypt_secret("correct horse", &file.encrypted.secret).unwrap(),
+            plaintext
+        );
+    }
+}
@@ -0,0 +1,448 @@
+use std::env;
+use std::io::{self, IsTerminal};
+use std::num::NonZeroU32;
+use std::process::{Command, Stdio};
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose, Engine as _};
+use ring::{
+    aead::{self, Aad, LessSafeKey, Nonce, UnboundKey},
+    pbkdf2,
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_MASTER_ENV: &str = "RUSTCHAT_PASSPHRASE";
+const KEYCHAIN_SERVICE: &str = "rustchat-cli";
+const PBKDF2_ITERATIONS: u32 = 150_000;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptedSecret {
+    pub salt: String,
+    pub nonce: String,
+    pub ciphertext: String,
+    /// Key derivation used; secrets written before Argon2id have none and
+    /// use PBKDF2
+    #[serde(default = "legacy_kdf")]
+    pub kdf: Kdf,
+    /// KDF cost parameters; absent ones take the defaults for `kdf`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iterations: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_kib: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parallelism: Option<u32>,
+}
+
+/// How the AES key is derived from the passphrase.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Kdf {
+    /// PBKDF2-HMAC-SHA256; kept so existing configs still decrypt
+    Pbkdf2,
+    /// Memory-hard Argon2id, used for new secrets
+    Argon2id,
+}
+
+fn legacy_kdf() -> Kdf {
+    Kdf::Pbkdf2
+}
+
+pub fn maybe_encrypt_secret(
+    value: Option<String>,
+    encrypt: bool,
+    passphrase: Option<&str>,
+    env_label: &str,
+    kdf: Kdf,
+) -> Result<(Option<String>, Option<EncryptedSecret>)> {
+    if !encrypt {
+        return Ok((value, None));
+    }
+    let plaintext = match value {
+        Some(v) => v,
+        None => return Ok((None, None)),
+    };
+    let passphrase = passphrase.map(|s| s.to_string()).ok_or_else(|| {
+        anyhow!("passphrase required via {env_label} when --encrypt-secrets is used")
+    })?;
+    let encrypted = encrypt_secret(&passphrase, &plaintext, kdf)?;
+    Ok((None, Some(encrypted)))
+}
+
+pub fn resolve_secret(
+    plain: Option<&str>,
+    encrypted: Option<&EncryptedSecret>,
+    provided_passphrase: Option<&str>,
+    env_label: &str,
+) -> Result<Option<String>> {
+    if let Some(value) = plain {
+        return Ok(Some(value.to_string()));
+    }
+    if let Some(data) = encrypted {
+        let passphrase = match provided_passphrase {
+            Some(value) => value.to_string(),
+            None => require_passphrase_from_env(env_label)?,
+        };
+        let decrypted = decrypt_secret(&passphrase, data)?;
+        return Ok(Some(decrypted));
+    }
+    Ok(None)
+}
+
+pub fn require_secret(
+    plain: Option<&str>,
+    encrypted: Option<&EncryptedSecret>,
+    provided_passphrase: Option<&str>,
+    env_label: &str,
+    missing_context: &str,
+) -> Result<String> {
+    resolve_secret(plain, encrypted, provided_passphrase, env_label)?
+        .ok_or_else(|| anyhow!(missing_context.to_string()))
+}
+
+/// Reads an API key from the variable named by a provider's `api_key_env`.
+pub fn api_key_from_env(provider: &str, var: &str) -> Result<String> {
+    match env::var(var) {
+        Ok(value) if !value.trim().is_empty() => Ok(value),
+        _ => Err(anyhow!(
+            "provider '{provider}' reads its API key from {var}, which is not set"
+        )),
+    }
+}
+
+/// Runs a provider's `api_key_cmd` (e.g. `op read op://vault/openai/key`)
+/// through the platform shell and returns its trimmed stdout. The command
+/// keeps the terminal for stdin/stderr so password managers can prompt.
+pub async fn api_key_from_command(provider: &str, command: &str) -> Result<String> {
+    let owned = command.to_string();
+    let output = tokio::task::spawn_blocking(move || {
+        let mut shell = if cfg!(windows) {
+            let mut shell = Command::new("cmd");
+            shell.args(["/C", &owned]);
+            shell
+        } else {
+            let mut shell = Command::new("sh");
+            shell.args(["-c", &owned]);
+            shell
+        };
+        shell
+            .stdin(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .output()
+    })
+    .await
+    .context("api_key_cmd task panicked")?
+    .with_context(|| format!("failed to run api_key_cmd for '{provider}'"))?;
+    if !output.status.success() {
+        bail!("api_key_cmd for '{provider}' failed ({})", output.status);
+    }
+    let key = String::from_utf8(output.stdout)
+        .with_context(|| format!("api_key_cmd for '{provider}' printed invalid UTF-8"))?;
+    let key = key.trim();
+    if key.is_empty() {
+        bail!("api_key_cmd for '{provider}' printed nothing");
+    }
+    Ok(key.to_string())
+}
+
+/// Saves a provider's API key in the OS keychain (macOS Keychain, Windows
+/// Credential Manager, or Secret Service).
+pub async fn store_in_keychain(provider: &str, secret: &str) -> Result<()> {
+    let secret = secret.to_string();
+    with_keychain_entry(provider, move |entry| entry.set_password(&secret))
+        .await
+        .with_context(|| format!("failed to store the API key for '{provider}' in the keychain"))
+}
+
+pub async fn keychain_api_key(provider: &str) -> Result<String> {
+    with_keychain_entry(provider, |entry| entry.get_password())
+        .await
+        .with_context(|| format!("failed to read the API key for '{provider}' from the keychain"))
+}
+
+/// Deletes a provider's keychain entry; a missing entry is not an error.
+pub async fn delete_from_keychain(provider: &str) -> Result<()> {
+    match with_keychain_entry(provider, |entry| entry.delete_credential()).await {
+        Ok(()) => Ok(()),
+        Err(err) if matches!(err.downcast_ref(), Some(keyring::Error::NoEntry)) => Ok(()),
+        Err(err) => Err(err.context(format!(
+            "failed to remove the API key for '{provider}' from the keychain"
+        ))),
+    }
+}
+
+/// The platform keychain APIs block, so they run off the async runtime.
+async fn with_keychain_entry<T: Send + 'static>(
+    provider: &str,
+    op: impl FnOnce(&keyring::Entry) -> keyring::Result<T> + Send + 'static,
+) -> Result<T> {
+    let provider = provider.to_string();
+    let result = tokio::task::spawn_blocking(move || {
+        let entry = keyring::Entry::new(KEYCHAIN_SERVICE, &provider)?;
+        op(&entry)
+    })
+    .await
+    .context("keychain task panicked")?;
+    Ok(result?)
+}
+
+/// Synchronous keychain access for code that runs before the async parts of
+/// a command, such as loading an encrypted config. A plain thread keeps the
+/// blocking call out of the runtime.
+pub fn with_keychain_entry_blocking<T: Send>(
+    user: &str,
+    op: impl FnOnce(&keyring::Entry) -> keyring::Result<T> + Send,
+) -> Result<T> {
+    let result = std::thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                let entry = keyring::Entry::new(KEYCHAIN_SERVICE, user)?;
+                op(&entry)
+            })
+            .join()
+    })
+    .map_err(|_| anyhow!("keychain thread panicked"))?;
+    Ok(result?)
+}
+
+/// Encrypts a stored key (plain or encrypted) under `new_passphrase`, for
+/// moving a config to a machine with a different passphrase.
+pub fn reencrypt_secret(
+    plain: Option<&str>,
+    encrypted: Option<&EncryptedSecret>,
+    provided_passphrase: Option<&str>,
+    env_label: &str,
+    new_passphrase: &str,
+) -> Result<Option<EncryptedSecret>> {
+    match resolve_secret(plain, encrypted, provided_passphrase, env_label)? {
+        Some(secret) => encrypt_secret(new_passphrase, &secret, Kdf::Argon2id).map(Some),
+        None => Ok(None),
+    }
+}
+
+pub fn optional_passphrase_from_env(env_label: &str, strict: bool) -> Result<Option<String>> {
+    match env::var(env_label) {
+        Ok(value) => Ok(Some(value)),
+        Err(env::VarError::NotPresent) if !strict => Ok(None),
+        Err(_) => Err(anyhow!("environment variable {env_label} is not set")),
+    }
+}
+
+/// Asks for the passphrase on the terminal, without echo, when the provider
+/// has an encrypted key and `env_label` was not set.
+pub fn prompt_for_passphrase(
+    passphrase: Option<String>,
+    needed: bool,
+    allow_prompt: bool,
+    env_label: &str,
+) -> Result<Option<String>> {
+    if passphrase.is_some() || !needed {
+        return Ok(passphrase);
+    }
+    if !allow_prompt || !io::stdin().is_terminal() {
+        bail!("environment variable {env_label} must be set to use encrypted secrets");
+    }
+    let entered = rpassword::prompt_password(format!("Passphrase ({env_label} is not set): "))
+        .context("failed to read the passphrase")?;
+    Ok(Some(entered))
+}
+
+/// Asks for a new passphrase twice on the terminal and returns it once both
+/// entries match.
+pub fn prompt_new_passphrase() -> Result<String> {
+    if !io::stdin().is_terminal() {
+        bail!("no terminal to ask for the new passphrase - use --new-secret-env");
+    }
+    let first =
+        rpassword::prompt_password("New passphrase: ").context("failed to read the passphrase")?;
+    if first.is_empty() {
+        bail!("the new passphrase must not be empty");
+    }
+    let second = rpassword::prompt_password("Repeat new passphrase: ")
+        .context("failed to read the passphrase")?;
+    if first != second {
+        bail!("the passphrases do not match");
+    }
+    Ok(first)
+}
+
+pub fn require_passphrase_from_env(env_label: &str) -> Result<String> {
+    optional_passphrase_from_env(env_label, true)?.ok_or_else(|| {
+        anyhow!("environment variable {env_label} must be set to use encrypted secrets")
+    })
+}
+
+pub fn encrypt_secret(passphrase: &str, plaintext: &str, kdf: Kdf) -> Result<EncryptedSecret> {
+    let rng = SystemRandom::new();
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce_bytes = [0u8; NONCE_LEN];
+    rng.fill(&mut salt)
+        .map_err(|_| anyhow!("failed to read random bytes for salt"))?;
+    rng.fill(&mut nonce_bytes)
+        .map_err(|_| anyhow!("failed to read random bytes for nonce"))?;
+
+    let mut secret = EncryptedSecret {
+        salt: general_purpose::STANDARD.encode(salt),
+        nonce: general_purpose::STANDARD.encode(nonce_bytes),
+        ciphertext: String::new(),
+        kdf,
+        iterations: None,
+        memory_kib: None,
+        parallelism: None,
+    };
+    match kdf {
+        Kdf::Pbkdf2 => secret.iterations = Some(PBKDF2_ITERATIONS),
+        Kdf::Argon2id => {
+            secret.iterations = Some(argon2::Params::DEFAULT_T_COST);
+            secret.memory_kib = Some(argon2::Params::DEFAULT_M_COST);
+            secret.parallelism = Some(argon2::Params::DEFAULT_P_COST);
+        }
+    }
+    let key_bytes = derive_key(passphrase, &salt, &secret)?;
+    let unbound = UnboundKey::new(&aead::AES_256_GCM, &key_bytes)
+        .map_err(|_| anyhow!("failed to initialize AES-256-GCM"))?;
+    let sealing_key = LessSafeKey::new(unbound);
+    let nonce = Nonce::assume_unique_for_key(nonce_bytes);
+
+    let mut buffer = plaintext.as_bytes().to_vec();
+    sealing_key
+        .seal_in_place_append_tag(nonce, Aad::empty(), &mut buffer)
+        .map_err(|_| anyhow!("failed to encrypt secret"))?;
+
+    secret.ciphertext = general_purpose::STANDARD.encode(buffer);
+    Ok(secret)
+}
+
+pub fn decrypt_secret(passphrase: &str, data: &EncryptedSecret) -> Result<String> {
+    let salt = decode_field(&data.salt, "salt")?;
+    let nonce_bytes = decode_field(&data.nonce, "nonce")?;
+    let ciphertext = decode_field(&data.ciphertext, "ciphertext")?;
+
+    let key_bytes = derive_key(passphrase, &salt, data)?;
+    let unbound = UnboundKey::new(&aead::AES_256_GCM, &key_bytes)
+        .map_err(|_| anyhow!("failed to initialize AES-256-GCM"))?;
+    let opening_key = LessSafeKey::new(unbound);
+    let nonce = Nonce::try_assume_unique_for_key(&nonce_bytes)
+        .map_err(|_| anyhow!("invalid nonce length"))?;
+    let mut buffer = ciphertext;
+    let decrypted = opening_key
+        .open_in_place(nonce, Aad::empty(), &mut buffer)
+        .map_err(|_| anyhow!("failed to decrypt secret"))?;
+    let plaintext = String::from_utf8(decrypted.to_vec()).context("secret is not utf-8")?;
+    Ok(plaintext)
+}
+
+/// Derives the AES key with the KDF and parameters recorded in `secret`.
+fn derive_key(passphrase: &str, salt: &[u8], secret: &EncryptedSecret) -> Result<[u8; KEY_LEN]> {
+    let mut key = [0u8; KEY_LEN];
+    match secret.kdf {
+        Kdf::Pbkdf2 => {
+            let iterations = NonZeroU32::new(secret.iterations.unwrap_or(PBKDF2_ITERATIONS))
+                .ok_or_else(|| anyhow!("PBKDF2 iterations must be positive"))?;
+            pbkdf2::derive(
+                pbkdf2::PBKDF2_HMAC_SHA256,
+                iterations,
+                salt,
+                passphrase.as_bytes(),
+                &mut key,
+            );
+        }
+        Kdf::Argon2id => {
+            let params = argon2::Params::new(
+                secret.memory_kib.unwrap_or(argon2::Params::DEFAULT_M_COST),
+                secret.iterations.unwrap_or(argon2::Params::DEFAULT_T_COST),
+                secret.parallelism.unwrap_or(argon2::Params::DEFAULT_P_COST),
+                Some(KEY_LEN),
+            )
+            .map_err(|err| anyhow!("invalid Argon2id parameters: {err}"))?;
+            argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+                .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+                .map_err(|err| anyhow!("Argon2id key derivation failed: {err}"))?;
+        }
+    }
+    Ok(key)
+}
+
+fn decode_field(value: &str, field: &str) -> Result<Vec<u8>> {
+    general_purpose::STANDARD
+        .decode(value)
+        .with_context(|| format!("invalid base64 for {field}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_secret() {
+        let secret = "shh";
+        let passphrase = "topsecret";
+        for kdf in [Kdf::Pbkdf2, Kdf::Argon2id] {
+            let encrypted = encrypt_secret(passphrase, secret, kdf).expect("encrypt");
+            let decrypted = decrypt_secret(passphrase, &encrypted).expect("decrypt");
+            assert_eq!(secret, decrypted);
+        }
+    }
+
+    #[test]
+    fn secrets_without_kdf_use_pbkdf2() {
+        let legacy = encrypt_secret("topsecret", "shh", Kdf::Pbkdf2).expect("encrypt");
+        let toml = format!(
+            "salt = \"{}\"\nnonce = \"{}\"\nciphertext = \"{}\"\n",
+            legacy.salt, legacy.nonce, legacy.ciphertext
+        );
+        let parsed: EncryptedSecret = toml::from_str(&toml).unwrap();
+        assert_eq!(parsed.kdf, Kdf::Pbkdf2);
+        assert_eq!(decrypt_secret("topsecret", &parsed).unwrap(), "shh");
+
+        let modern = encrypt_secret("topsecret", "shh", Kdf::Argon2id).expect("encrypt");
+        let saved = toml::to_string(&modern).unwrap();
+        assert!(saved.contains("kdf = \"argon2id\""));
+        assert!(saved.contains("memory_kib = 19456"));
+    }
+
+    #[test]
+    fn no_prompt_fails_fast_for_encrypted_keys() {
+        assert_eq!(
+            prompt_for_passphrase(None, false, true, DEFAULT_MASTER_ENV).unwrap(),
+            None
+        );
+        assert_eq!(
+            prompt_for_passphrase(Some("pw".into()), true, false, DEFAULT_MASTER_ENV).unwrap(),
+            Some("pw".to_string())
+        );
+        let err = prompt_for_passphrase(None, true, false, DEFAULT_MASTER_ENV).unwrap_err();
+        assert!(err.to_string().contains("RUSTCHAT_PASSPHRASE must be set"));
+    }
+
+    #[tokio::test]
+    async fn api_key_cmd_output_becomes_the_key() {
+        assert_eq!(
+            api_key_from_command("openai", "echo sk-from-cmd")
+                .await
+                .unwrap(),
+            "sk-from-cmd"
+        );
+        let err = api_key_from_command("openai", "exit 3").await.unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("api_key_cmd for 'openai' failed"));
+    }
+
+    #[test]
+    fn api_key_env_must_be_set() {
+        env::set_var("RUSTCHAT_TEST_API_KEY_ENV", "sk-from-env");
+        assert_eq!(
+            api_key_from_env("openai", "RUSTCHAT_TEST_API_KEY_ENV").unwrap(),
+            "sk-from-env"
+        );
+        let err = api_key_from_env("work", "RUSTCHAT_TEST_API_KEY_UNSET").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "provider 'work' reads its API key from RUSTCHAT_TEST_API_KEY_UNSET, which is not set"
+        );
+    }
+}
@@ -0,0 +1,17 @@
+use std::pin::Pin;
+
+use anyhow::Result;
+use futures::Stream;
+
+use crate::provider::TokenUsage;
+
+/// A single item emitted by a provider stream.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StreamEvent {
+    Text(String),
+    Usage(TokenUsage),
+    /// Why the provider stopped, in its own words (`stop`, `max_tokens`, ...)
+    Finish(String),
+}
+
+pub type ChatStream = Pin<Box<dyn Stream<Item = Result<StreamEvent>> + Send>>;
@@ -0,0 +1,128 @@
+//! Token counts before anything is sent: exact for OpenAI models through
+//! their BPE vocabularies, estimated for Claude and Gemini, whose tokenizers
+//! are not published (their providers' `count_tokens` gives exact numbers).
+
+use tiktoken_rs::CoreBPE;
+
+use crate::provider::ChatMessage;
+
+/// Formatting tokens each chat message costs on top of its text.
+const MESSAGE_OVERHEAD: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tokenizer {
+    /// GPT-4o, GPT-4.1, GPT-5 and the o-series
+    O200k,
+    /// GPT-4 and GPT-3.5
+    Cl100k,
+    /// About 3.5 characters per token
+    Claude,
+    /// About 4 characters per token
+    Gemini,
+    /// About 4 characters per token, for models nothing is known about
+    Approx,
+}
+
+impl Tokenizer {
+    pub fn for_model(model: &str) -> Self {
+        let model = model.to_ascii_lowercase();
+        let model = model.rsplit('/').next().unwrap_or(&model);
+        if model.starts_with("gpt-4o")
+            || model.starts_with("gpt-4.1")
+            || model.starts_with("gpt-4.5")
+            || model.starts_with("gpt-5")
+            || model.starts_with("chatgpt-")
+            || ["o1", "o3", "o4"]
+                .iter()
+                .any(|family| model.starts_with(family))
+        {
+            Self::O200k
+        } else if model.starts_with("gpt-4") || model.starts_with("gpt-3.5") {
+            Self::Cl100k
+        } else if model.starts_with("claude") {
+            Self::Claude
+        } else if model.starts_with("gemini") {
+            Self::Gemini
+        } else {
+            Self::Approx
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::O200k => "o200k_base",
+            Self::Cl100k => "cl100k_base",
+            Self::Claude => "claude estimate",
+            Self::Gemini => "gemini estimate",
+            Self::Approx => "estimate",
+        }
+    }
+
+    /// True when counts match what the provider bills.
+    pub fn is_exact(self) -> bool {
+        matches!(self, Self::O200k | Self::Cl100k)
+    }
+
+    pub fn count(self, text: &str) -> usize {
+        match self {
+            Self::O200k => bpe_count(tiktoken_rs::o200k_base_singleton(), text),
+            Self::Cl100k => bpe_count(tiktoken_rs::cl100k_base_singleton(), text),
+            Self::Claude => (text.chars().count() * 2).div_ceil(7),
+            Self::Gemini | Self::Approx => crate::utils::approx_tokens(text),
+        }
+    }
+
+    pub fn count_message(self, message: &ChatMessage) -> usize {
+        let calls: usize = message
+            .tool_calls
+            .iter()
+            .map(|call| self.count(&call.name) + self.count(&call.arguments.to_string()))
+            .sum();
+        MESSAGE_OVERHEAD + self.count(&message.content) + calls
+    }
+
+    /// The prompt a chat request sends: the system prompt and every message.
+    pub fn count_chat(self, system: Option<&str>, messages: &[ChatMessage]) -> usize {
+        let system = system.map_or(0, |system| MESSAGE_OVERHEAD + self.count(system));
+        system
+            + messages
+                .iter()
+                .map(|message| self.count_message(message))
+                .sum::<usize>()
+    }
+}
+
+fn bpe_count(bpe: &CoreBPE, text: &str) -> usize {
+    bpe.encode_ordinary(text).len()
+}
+
+/// Tokens `text` takes for `model`.
+pub fn count(model: &str, text: &str) -> usize {
+    Tokenizer::for_model(model).count(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn models_pick_their_tokenizer() {
+        assert_eq!(Tokenizer::for_model("gpt-4o-mini"), Tokenizer::O200k);
+        assert_eq!(Tokenizer::for_model("openai/o3-mini"), Tokenizer::O200k);
+        assert_eq!(Tokenizer::for_model("gpt-4-turbo"), Tokenizer::Cl100k);
+        assert_eq!(
+            Tokenizer::for_model("claude-sonnet-4-20250514"),
+            Tokenizer::Claude
+        );
+        assert_eq!(Tokenizer::for_model("llama3"), Tokenizer::Approx);
+
+        assert_eq!(count("gpt-4o", "Hello, world!"), 4);
+        assert_eq!(count("gpt-4", "Hello, world!"), 4);
+        assert_eq!(count("claude-3-haiku", "Hello, world!"), 4);
+        let messages = [ChatMessage::user("Hello, world!")];
+        assert_eq!(
+            Tokenizer::O200k.count_chat(Some("Be brief."), &messages),
+            13
+        );
+    }
+}
@@ -0,0 +1,268 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Client, Request, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use tracing::{Instrument, Level};
+
+use crate::config;
+
+/// `-v` (debug level): method, URL, status, timing and retries of every
+/// request.
+pub const REQUESTS: u8 = 1;
+/// `-vv` (trace level): headers and full request/response bodies as well.
+pub const BODIES: u8 = 2;
+
+/// Headers whose whole value is a credential.
+const CREDENTIAL_HEADERS: [&str; 4] = ["authorization", "x-api-key", "x-goog-api-key", "cookie"];
+
+static SECRETS: Mutex<Vec<String>> = parking_lot::const_mutex(Vec::new());
+
+/// Whether `log` at `level` would be seen, so costly messages (bodies) are
+/// only built when they are.
+pub fn enabled(level: u8) -> bool {
+    match level {
+        REQUESTS => tracing::enabled!(Level::DEBUG),
+        _ => tracing::enabled!(Level::TRACE),
+    }
+}
+
+/// Remembers a resolved API key so it never appears in the log.
+pub fn register_secret(secret: &str) {
+    let mut secrets = SECRETS.lock();
+    if !secret.is_empty() && !secrets.iter().any(|known| known == secret) {
+        secrets.push(secret.to_string());
+    }
+}
+
+/// Emits `message`, redacted, as a `tracing` event: debug level for
+/// `REQUESTS`, trace level for `BODIES`.
+pub fn log(level: u8, message: &str) {
+    if !enabled(level) {
+        return;
+    }
+    let message = redact(message);
+    match level {
+        REQUESTS => tracing::debug!("{message}"),
+        _ => tracing::trace!("{message}"),
+    }
+}
+
+/// Sends `request`, logging it and the response status with the elapsed time,
+/// inside an `http` span.
+pub async fn send(request: RequestBuilder) -> reqwest::Result<Response> {
+    let (client, request) = request.build_split();
+    let request = request?;
+    let span = tracing::info_span!(
+        "http",
+        otel.name = %request.method(),
+        otel.kind = "client",
+        http.request.method = %request.method(),
+        url.full = %redact(request.url().as_str()),
+        http.response.status_code = tracing::field::Empty,
+        otel.status_code = tracing::field::Empty,
+        otel.status_message = tracing::field::Empty,
+    );
+    let result = execute(client, request).instrument(span.clone()).await;
+    match &result {
+        Ok(response) => {
+            span.record(
+                "http.response.status_code",
+                i64::from(response.status().as_u16()),
+            );
+            if response.status().is_client_error() || response.status().is_server_error() {
+                span.record("otel.status_code", "ERROR");
+            }
+        }
+        Err(err) => {
+            span.record("otel.status_code", "ERROR");
+            span.record("otel.status_message", redact(&err.to_string()));
+        }
+    }
+    result
+}
+
+async fn execute(client: Client, request: Request) -> reqwest::Result<Response> {
+    if !enabled(REQUESTS) {
+        return client.execute(request).await;
+    }
+    log(
+        REQUESTS,
+        &format!("http: {} {}", request.method(), request.url()),
+    );
+    if enabled(BODIES) {
+        for (name, value) in request.headers() {
+            let value = if CREDENTIAL_HEADERS.contains(&name.as_str()) {
+                "****"
+            } else {
+                value.to_str().unwrap_or("<binary>")
+            };
+            log(BODIES, &format!("http:   {name}: {value}"));
+        }
+        if let Some(body) = request.body().and_then(|body| body.as_bytes()) {
+            log(
+                BODIES,
+                &format!("http:   request body: {}", String::from_utf8_lossy(body)),
+            );
+        }
+    }
+    let started = Instant::now();
+    let result = client.execute(request).await;
+    let elapsed = started.elapsed().as_millis();
+    match &result {
+        Ok(response) => log(
+            REQUESTS,
+            &format!("http: {} after {elapsed} ms", response.status()),
+        ),
+        Err(err) => log(REQUESTS, &format!("http: failed after {elapsed} ms: {err}")),
+    }
+    result
+}
+
+/// A 429 whose `Retry-After` said how long to wait before trying again.
+#[derive(Debug, thiserror::Error)]
+#[error("asked to retry after {}s", .after.as_secs())]
+pub struct RetryAfter {
+    pub after: Duration,
+    #[source]
+    source: reqwest::Error,
+}
+
+pub trait ResponseExt: Sized {
+    /// `error_for_status`, keeping the `Retry-After` of a 429 as
+    /// `RetryAfter` so a retry waits as long as the server asked.
+    fn check_status(self) -> Result<Self>;
+}
+
+impl ResponseExt for Response {
+    fn check_status(self) -> Result<Self> {
+        match self.error_for_status_ref() {
+            Ok(_) => Ok(self),
+            Err(err) => Err(status_error(err, self.headers())),
+        }
+    }
+}
+
+/// The error for a failed status, as `RetryAfter` when the server said when
+/// to come back.
+pub(crate) fn status_error(err: reqwest::Error, headers: &HeaderMap) -> anyhow::Error {
+    match retry_after(err.status(), headers, Utc::now()) {
+        Some(after) => RetryAfter { after, source: err }.into(),
+        None => err.into(),
+    }
+}
+
+/// `Retry-After` on a 429, in seconds or as an HTTP date.
+fn retry_after(
+    status: Option<StatusCode>,
+    headers: &HeaderMap,
+    now: DateTime<Utc>,
+) -> Option<Duration> {
+    if status != Some(StatusCode::TOO_MANY_REQUESTS) {
+        return None;
+    }
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = DateTime::parse_from_rfc2822(value).ok()?;
+    Some((at.with_timezone(&Utc) - now).to_std().unwrap_or_default())
+}
+
+/// `response.json()`, logging the body first at `-vv`.
+pub async fn json<T: DeserializeOwned>(response: Response) -> Result<T> {
+    if !enabled(BODIES) {
+        return Ok(response.json().await?);
+    }
+    let text = response.text().await?;
+    log(BODIES, &format!("http:   response body: {text}"));
+    Ok(serde_json::from_str(&text)?)
+}
+
+/// Masks registered API keys, bearer tokens and `key=` query parameters.
+pub fn redact(text: &str) -> String {
+    let mut text = text.to_string();
+    for secret in SECRETS.lock().iter() {
+        text = text.replace(secret.as_str(), &config::mask(secret));
+    }
+    let text = scrub_after(&text, "Bearer ");
+    scrub_after(&text, "key=")
+}
+
+/// Replaces the token following each `marker` with `****`.
+fn scrub_after(text: &str, marker: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find(marker) {
+        let (head, tail) = rest.split_at(pos + marker.len());
+        out.push_str(head);
+        let end = tail
+            .find(|c: char| c.is_whitespace() || matches!(c, '&' | '"' | '\'' | ',' | ';'))
+            .unwrap_or(tail.len());
+        if end > 0 {
+            out.push_str("****");
+        }
+        rest = &tail[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_after_is_read_from_rate_limits_only() {
+        let now = DateTime::parse_from_rfc2822("Wed, 21 Oct 2026 07:28:00 GMT")
+            .unwrap()
+            .with_timezone(&Utc);
+        let limited = Some(StatusCode::TOO_MANY_REQUESTS);
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(limited, &headers, now), None);
+        headers.insert(RETRY_AFTER, "30".parse().unwrap());
+        assert_eq!(
+            retry_after(limited, &headers, now),
+            Some(Duration::from_secs(30))
+        );
+        let unavailable = Some(StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(retry_after(unavailable, &headers, now), None);
+        headers.insert(
+            RETRY_AFTER,
+            "Wed, 21 Oct 2026 07:29:30 GMT".parse().unwrap(),
+        );
+        assert_eq!(
+            retry_after(limited, &headers, now),
+            Some(Duration::from_secs(90))
+        );
+        // A date already past means now.
+        headers.insert(
+            RETRY_AFTER,
+            "Wed, 21 Oct 2026 07:00:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(retry_after(limited, &headers, now), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn redaction_masks_keys_and_tokens() {
+        register_secret("sk-test-0123456789abcdef");
+        assert_eq!(
+            redact(r#"{"error":"Incorrect API key provided: sk-test-0123456789abcdef"}"#),
+            r#"{"error":"Incorrect API key provided: ****cdef"}"#
+        );
+        assert_eq!(
+            redact(
+                "POST https://example.com/v1beta/models/x:generateContent?key=AIzaSecret&alt=sse"
+            ),
+            "POST https://example.com/v1beta/models/x:generateContent?key=****&alt=sse"
+        );
+        assert_eq!(
+            redact("authorization: Bearer ya29.token-value"),
+            "authorization: Bearer ****"
+        );
+        assert_eq!(redact("no secrets here"), "no secrets here");
+    }
+}
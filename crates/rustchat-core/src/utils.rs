@@ -0,0 +1,128 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+
+/// Rough token estimate (~4 characters per token) for display purposes.
+pub fn approx_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Writes `contents` to a temporary file beside `path` and renames it into
+/// place, so readers never see a half-written file.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    write_atomic_as(path, contents, None)
+}
+
+/// `write_atomic` for a file readable by its owner alone from the moment it
+/// exists.
+pub fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    write_atomic_as(path, contents, Some(0o600))
+}
+
+fn write_atomic_as(path: &Path, contents: &[u8], mode: Option<u32>) -> std::io::Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = path.with_file_name(format!(".{name}.{}.tmp", std::process::id()));
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, mode);
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    let result = options
+        .open(&tmp)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&tmp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// Parses an age such as `90d`, `12h`, `2w`, or `30m`.
+pub fn parse_age(text: &str) -> Result<chrono::Duration> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: i64 = number
+        .parse()
+        .map_err(|_| anyhow!("invalid age '{text}' (expected e.g. 90d)"))?;
+    Ok(match unit.trim() {
+        "s" => chrono::Duration::seconds(number),
+        "m" => chrono::Duration::minutes(number),
+        "h" => chrono::Duration::hours(number),
+        "d" | "" => chrono::Duration::days(number),
+        "w" => chrono::Duration::weeks(number),
+        other => bail!("unknown age unit '{other}' in '{text}' (use s, m, h, d, or w)"),
+    })
+}
+
+const SIZE_UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+
+/// Parses a size such as `200MB`, `1.5GB`, or `4096` (bytes); units are
+/// powers of 1024.
+pub fn parse_size(text: &str) -> Result<u64> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| anyhow!("invalid size '{text}' (expected e.g. 200MB)"))?;
+    let unit = unit.trim().to_ascii_uppercase();
+    let unit = unit
+        .strip_suffix("IB")
+        .map_or(unit.clone(), |prefix| format!("{prefix}B"));
+    let power = match unit.as_str() {
+        "" => 0,
+        "K" => 1,
+        "M" => 2,
+        "G" => 3,
+        _ => SIZE_UNITS
+            .iter()
+            .position(|known| *known == unit)
+            .ok_or_else(|| anyhow!("unknown size unit in '{text}' (use B, KB, MB, or GB)"))?,
+    };
+    Ok((number * 1024f64.powi(power as i32)) as u64)
+}
+
+/// Formats a byte count for display, e.g. `1.2 MB`.
+pub fn format_size(bytes: u64) -> String {
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < SIZE_UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", SIZE_UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ages_and_sizes_parse() {
+        assert_eq!(parse_age("90d").unwrap(), chrono::Duration::days(90));
+        assert_eq!(parse_age("2w").unwrap(), chrono::Duration::days(14));
+        assert!(parse_age("3 fortnights").is_err());
+        assert_eq!(parse_size("200MB").unwrap(), 200 * 1024 * 1024);
+        assert_eq!(parse_size("1.5 GiB").unwrap(), 3 * 512 * 1024 * 1024);
+        assert_eq!(parse_size("4096").unwrap(), 4096);
+        assert!(parse_size("12 parsecs").is_err());
+        assert_eq!(format_size(1536), "1.5 KB");
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Result};
+use clap::CommandFactory;
+
+use crate::cli::Cli;
+
+/// Global flags that take a value, so their value is not the subcommand.
+const GLOBAL_VALUE_FLAGS: [&str; 2] = ["--config", "--log-file"];
+
+/// Position of the subcommand in `args` (after the program name and any
+/// global flags), if there is one.
+fn command_index(args: &[OsString]) -> Option<usize> {
+    let mut index = 1;
+    while let Some(arg) = args.get(index) {
+        let arg = arg.to_str()?;
+        if GLOBAL_VALUE_FLAGS.contains(&arg) {
+            index += 2;
+        } else if arg.starts_with('-') {
+            index += 1;
+        } else {
+            return Some(index);
+        }
+    }
+    None
+}
+
+fn is_builtin(name: &str) -> bool {
+    name == "help" || Cli::command().find_subcommand(name).is_some()
+}
+
+/// The subcommand as given, e.g. `message`.
+pub fn subcommand(args: &[OsString]) -> Option<&str> {
+    args.get(command_index(args)?)?.to_str()
+}
+
+/// The subcommand when it is not a built-in one and so may be an alias.
+pub fn candidate(args: &[OsString]) -> Option<&str> {
+    subcommand(args).filter(|name| !is_builtin(name))
+}
+
+/// The subcommand and the arguments after it when it is not a built-in one,
+/// for handing off to a plugin.
+pub fn external(args: &[OsString]) -> Option<(&str, &[OsString])> {
+    let index = command_index(args)?;
+    let name = args[index].to_str()?;
+    (!is_builtin(name)).then(|| (name, &args[index + 1..]))
+}
+
+/// The `--config` path given before the subcommand, else `RUSTCHAT_CONFIG`,
+/// so aliases come from the config the command will use.
+pub fn config_arg(args: &[OsString]) -> Option<PathBuf> {
+    let end = command_index(args).unwrap_or(args.len());
+    let mut flags = args[..end].iter().skip(1);
+    while let Some(arg) = flags.next() {
+        if arg == "--config" {
+            return flags.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.to_str().and_then(|arg| arg.strip_prefix("--config=")) {
+            return Some(PathBuf::from(path));
+        }
+    }
+    std::env::var_os("RUSTCHAT_CONFIG").map(PathBuf::from)
+}
+
+/// Replaces an alias in the subcommand position with the words it stands
+/// for, repeatedly so aliases can build on each other. Built-in subcommands
+/// cannot be shadowed. Values are split like a shell would, and a leading
+/// `~/` is taken from the home directory.
+pub fn expand(
+    mut args: Vec<OsString>,
+    aliases: &BTreeMap<String, String>,
+) -> Result<Vec<OsString>> {
+    let mut seen = Vec::new();
+    while let Some(index) = command_index(&args) {
+        let Some(name) = args[index].to_str().filter(|name| !is_builtin(name)) else {
+            break;
+        };
+        let Some(value) = aliases.get(name) else {
+            break;
+        };
+        if seen.iter().any(|known| known == name) {
+            bail!("alias '{name}' expands to itself");
+        }
+        let words = shlex::split(value)
+            .filter(|words| !words.is_empty())
+            .ok_or_else(|| anyhow!("alias '{name}' is empty or has unbalanced quotes"))?;
+        seen.push(name.to_string());
+        let words = words.into_iter().map(|word| match word.strip_prefix("~/") {
+            Some(rest) => dirs::home_dir()
+                .map(|home| home.join(rest).into_os_string())
+                .unwrap_or_else(|| word.into()),
+            None => word.into(),
+        });
+        args.splice(index..=index, words);
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(words: &[&str]) -> Vec<OsString> {
+        words.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn aliases_expand_in_the_subcommand_position() {
+        let aliases = BTreeMap::from([
+            (
+                "explain".to_string(),
+                "message --profile teacher --system 'Explain it simply'".to_string(),
+            ),
+            ("ex".to_string(), "explain --stats".to_string()),
+            ("chat".to_string(), "message".to_string()),
+            ("loop".to_string(), "loop".to_string()),
+        ]);
+        let expanded = expand(
+            args(&[
+                "rustchat-cli",
+                "-v",
+                "--config",
+                "team.toml",
+                "ex",
+                "lifetimes",
+            ]),
+            &aliases,
+        )
+        .unwrap();
+        assert_eq!(
+            expanded,
+            args(&[
+                "rustchat-cli",
+                "-v",
+                "--config",
+                "team.toml",
+                "message",
+                "--profile",
+                "teacher",
+                "--system",
+                "Explain it simply",
+                "--stats",
+                "lifetimes",
+            ])
+        );
+        assert_eq!(
+            config_arg(&args(&["rustchat-cli", "--config=team.toml", "ex"])),
+            Some(PathBuf::from("team.toml"))
+        );
+
+        // Built-ins win, and unknown names are left for clap to report.
+        let builtin = args(&["rustchat-cli", "chat"]);
+        assert_eq!(expand(builtin.clone(), &aliases).unwrap(), builtin);
+        assert_eq!(candidate(&builtin), None);
+        let unknown = args(&["rustchat-cli", "nope"]);
+        assert_eq!(expand(unknown.clone(), &aliases).unwrap(), unknown);
+        assert!(expand(args(&["rustchat-cli", "loop"]), &aliases).is_err());
+    }
+}
@@ -0,0 +1,438 @@
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::config::{AuditConfig, APP_DIR};
+use crate::exit::{self, ExitStatus};
+use crate::provider::{ChatMessage, TokenUsage};
+
+const AUDIT_FILE: &str = "audit.jsonl";
+
+struct Auditor {
+    path: PathBuf,
+    /// Opened for appending when auditing is turned on
+    file: File,
+    include_prompts: bool,
+    /// The subcommand that is running, e.g. `message` or `batch`
+    command: String,
+    user: Option<String>,
+}
+
+static AUDITOR: OnceLock<Auditor> = OnceLock::new();
+
+/// One line of the audit log.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AuditRecord {
+    pub at: DateTime<Utc>,
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    pub provider: String,
+    pub model: String,
+    /// `chat`, `stream` or `embed`
+    pub operation: String,
+    /// SHA-256 of the system prompt and messages (or embedding inputs)
+    pub prompt_sha256: String,
+    /// The prompt itself, only with `include_prompts = true`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_tokens: Option<u32>,
+    pub duration_ms: u64,
+    /// `started` when the request is about to go out; then `ok`, or the
+    /// kind of failure: `error`, `auth`, `rate_limited`, `network`,
+    /// `content_blocked`, `over_budget` or `canceled`. A reply from the
+    /// `[cache]` is logged once, as `cached`.
+    pub outcome: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+pub fn log_path(cfg: &AuditConfig) -> Option<PathBuf> {
+    cfg.path.clone().or_else(|| {
+        let base = dirs::data_local_dir().or_else(dirs::config_dir)?;
+        Some(base.join(APP_DIR).join(AUDIT_FILE))
+    })
+}
+
+/// Turns auditing on for the rest of the process when `[audit] enabled`,
+/// opening the log up front. Having nowhere to write it is an error, not a
+/// reason to run unaudited.
+pub fn init(cfg: &AuditConfig, command: &str) -> Result<()> {
+    if !cfg.enabled {
+        return Ok(());
+    }
+    let Some(path) = log_path(cfg) else {
+        bail!("[audit] is enabled but there is no data directory for its log; set [audit] path");
+    };
+    let file =
+        open(&path).with_context(|| format!("failed to open the audit log {}", path.display()))?;
+    let user = ["USER", "USERNAME"]
+        .into_iter()
+        .find_map(|name| std::env::var(name).ok().filter(|user| !user.is_empty()));
+    let _ = AUDITOR.set(Auditor {
+        path,
+        file,
+        include_prompts: cfg.include_prompts,
+        command: command.to_string(),
+        user,
+    });
+    Ok(())
+}
+
+/// What a chat request asked: the system prompt and every message.
+pub fn chat_prompt(system: Option<&str>, messages: &[ChatMessage]) -> Value {
+    let messages: Vec<Value> = messages
+        .iter()
+        .map(|message| {
+            json!({
+                "role": message.role.to_string(),
+                "content": message.content,
+                "tool_calls": message.tool_calls,
+                "tool_call_id": message.tool_call_id,
+            })
+        })
+        .collect();
+    json!({"system": system, "messages": messages})
+}
+
+pub fn embed_prompt(inputs: &[String]) -> Value {
+    json!({"inputs": inputs})
+}
+
+/// Logs a request answered from the `[cache]`: nothing was sent or billed,
+/// so a single line records it.
+pub fn cached(
+    provider: &str,
+    model: &str,
+    operation: &'static str,
+    prompt: impl FnOnce() -> Value,
+) -> Result<()> {
+    let Some(auditor) = AUDITOR.get() else {
+        return Ok(());
+    };
+    let mut entry = auditor.entry(provider, model, operation, prompt());
+    entry.cached = true;
+    entry.usage = Some(TokenUsage::default());
+    entry.finish(Ok(()))
+}
+
+/// A request being audited. A `started` line is written before it goes out;
+/// its outcome is written by `finish`, or when it is dropped unfinished, so
+/// an abandoned stream is logged too.
+pub struct Entry {
+    auditor: &'static Auditor,
+    provider: String,
+    model: String,
+    operation: &'static str,
+    prompt: Value,
+    started: Instant,
+    pub usage: Option<TokenUsage>,
+    /// The kind of failure and its message
+    failure: Option<(&'static str, String)>,
+    /// Answered from the `[cache]`
+    cached: bool,
+    written: bool,
+}
+
+impl Entry {
+    /// `None` when auditing is off, so the prompt is not even put together.
+    /// A request whose `started` line cannot be written must not be sent.
+    pub fn start(
+        provider: &str,
+        model: &str,
+        operation: &'static str,
+        prompt: impl FnOnce() -> Value,
+    ) -> Result<Option<Self>> {
+        match AUDITOR.get() {
+            Some(auditor) => auditor
+                .start(provider, model, operation, prompt())
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Adds usage reported in pieces, as streams do.
+    pub fn add_usage(&mut self, usage: TokenUsage) {
+        self.usage
+            .get_or_insert_with(TokenUsage::default)
+            .absorb(usage);
+    }
+
+    pub fn fail(&mut self, err: &anyhow::Error) {
+        self.failure = Some((failure(err), format!("{err:#}")));
+    }
+
+    /// Writes the line and hands back the request's `result`. A request whose
+    /// line cannot be written fails with that instead; one that failed anyway
+    /// keeps its own error and the audit failure is reported beside it.
+    pub fn finish<T>(&mut self, result: Result<T>) -> Result<T> {
+        let written = self.write();
+        match (result, written) {
+            (result, Ok(())) => result,
+            (Ok(_), Err(err)) => Err(exit::tag(err, ExitStatus::Config)),
+            (Err(request), Err(err)) => {
+                tracing::warn!("{err:#}");
+                Err(request)
+            }
+        }
+    }
+
+    fn write(&mut self) -> Result<()> {
+        self.written = true;
+        let record = self.auditor.record(self, Utc::now());
+        self.auditor.append(&record)
+    }
+}
+
+impl Drop for Entry {
+    fn drop(&mut self) {
+        // Nothing is left to fail, so every failure is at least reported.
+        if !self.written {
+            if let Err(err) = self.write() {
+                tracing::warn!("{err:#}");
+            }
+        }
+    }
+}
+
+impl Auditor {
+    fn entry(
+        &'static self,
+        provider: &str,
+        model: &str,
+        operation: &'static str,
+        prompt: Value,
+    ) -> Entry {
+        Entry {
+            auditor: self,
+            provider: provider.to_string(),
+            model: model.to_string(),
+            operation,
+            prompt,
+            started: Instant::now(),
+            usage: None,
+            failure: None,
+            cached: false,
+            written: false,
+        }
+    }
+
+    fn start(
+        &'static self,
+        provider: &str,
+        model: &str,
+        operation: &'static str,
+        prompt: Value,
+    ) -> Result<Entry> {
+        let mut entry = self.entry(provider, model, operation, prompt);
+        let mut record = self.record(&entry, Utc::now());
+        record.outcome = "started".to_string();
+        record.prompt = None;
+        record.duration_ms = 0;
+        if let Err(err) = self.append(&record) {
+            // Nothing was sent, so there is no outcome to log either.
+            entry.written = true;
+            return Err(exit::tag(err, ExitStatus::Config));
+        }
+        Ok(entry)
+    }
+
+    /// Appends one line; the log is only ever added to. One write per line,
+    /// so concurrent processes do not interleave.
+    fn append(&self, record: &AuditRecord) -> Result<()> {
+        let line = format!("{}\n", serde_json::to_string(record)?);
+        (&self.file)
+            .write_all(line.as_bytes())
+            .with_context(|| format!("failed to write the audit log {}", self.path.display()))
+    }
+
+    fn record(&self, entry: &Entry, at: DateTime<Utc>) -> AuditRecord {
+        let hash = digest::digest(&digest::SHA256, entry.prompt.to_string().as_bytes());
+        let mut prompt_sha256 = String::new();
+        for byte in hash.as_ref() {
+            let _ = write!(prompt_sha256, "{byte:02x}");
+        }
+        AuditRecord {
+            at,
+            command: self.command.clone(),
+            user: self.user.clone(),
+            provider: entry.provider.clone(),
+            model: entry.model.clone(),
+            operation: entry.operation.to_string(),
+            prompt_sha256,
+            prompt: self.include_prompts.then(|| entry.prompt.clone()),
+            input_tokens: entry.usage.map(|usage| usage.input_tokens),
+            output_tokens: entry.usage.map(|usage| usage.output_tokens),
+            duration_ms: entry.started.elapsed().as_millis() as u64,
+            outcome: match &entry.failure {
+                Some((kind, _)) => kind,
+                None if entry.cached => "cached",
+                None => "ok",
+            }
+            .to_string(),
+            error: entry.failure.as_ref().map(|(_, message)| message.clone()),
+        }
+    }
+}
+
+fn failure(err: &anyhow::Error) -> &'static str {
+    match exit::status_for(err) {
+        ExitStatus::Failure => "error",
+        ExitStatus::Config => "config",
+        ExitStatus::Auth => "auth",
+        ExitStatus::RateLimited => "rate_limited",
+        ExitStatus::Network => "network",
+        ExitStatus::ContentBlocked => "content_blocked",
+        ExitStatus::OverBudget => "over_budget",
+        ExitStatus::Incomplete => "incomplete",
+        ExitStatus::Canceled => "canceled",
+    }
+}
+
+/// Opens the log for appending, readable by its owner alone since it may
+/// hold prompts.
+fn open(path: &Path) -> Result<File> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    Ok(options.open(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leak_auditor(path: PathBuf, file: File) -> &'static mut Auditor {
+        Box::leak(Box::new(Auditor {
+            path,
+            file,
+            include_prompts: false,
+            command: "message".to_string(),
+            user: None,
+        }))
+    }
+
+    #[test]
+    fn prompts_are_hashed_unless_included() {
+        let auditor = leak_auditor(PathBuf::new(), tempfile::tempfile().unwrap());
+        auditor.user = Some("ana".to_string());
+        let prompt = || {
+            chat_prompt(
+                Some("Be brief."),
+                &[ChatMessage::user("Capital of France?")],
+            )
+        };
+        // Auditing is off in tests, so entries are built by hand.
+        let entry = |operation| {
+            let mut entry = leak_auditor(PathBuf::new(), tempfile::tempfile().unwrap()).entry(
+                "openai",
+                "gpt-4o",
+                operation,
+                prompt(),
+            );
+            // Never written: these only build records.
+            entry.written = true;
+            entry
+        };
+        let mut ok = entry("chat");
+        ok.add_usage(TokenUsage {
+            input_tokens: 12,
+            output_tokens: 3,
+        });
+        let ok = auditor.record(&ok, Utc::now());
+        assert_eq!(ok.outcome, "ok");
+        assert_eq!(ok.prompt, None);
+        assert_eq!(ok.input_tokens, Some(12));
+        assert_eq!(ok.prompt_sha256.len(), 64);
+        let line = serde_json::to_string(&ok).unwrap();
+        assert!(!line.contains("Capital of France"));
+
+        // The same prompt hashes the same; with prompts allowed it is kept.
+        auditor.include_prompts = true;
+        let mut failed = entry("stream");
+        failed.fail(&exit::tag(
+            anyhow::anyhow!("429 Too Many Requests"),
+            ExitStatus::RateLimited,
+        ));
+        let failed = auditor.record(&failed, Utc::now());
+        assert_eq!(failed.prompt_sha256, ok.prompt_sha256);
+        assert_eq!(failed.prompt, Some(prompt()));
+        assert_eq!(failed.outcome, "rate_limited");
+        assert_eq!(failed.error.as_deref(), Some("429 Too Many Requests"));
+        assert_eq!(failed.input_tokens, None);
+    }
+
+    #[test]
+    fn requests_are_logged_before_they_go_out() {
+        let tmp = tempfile::tempdir().unwrap();
+        let log = tmp.path().join("audit.jsonl");
+        let auditor = leak_auditor(log.clone(), open(&log).unwrap());
+        let prompt = || embed_prompt(&["hi".to_string()]);
+        let mut entry = auditor.start("openai", "m", "embed", prompt()).unwrap();
+        let lines = || -> Vec<AuditRecord> {
+            std::fs::read_to_string(&log)
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        };
+        assert_eq!(lines()[0].outcome, "started");
+        assert_eq!(entry.finish(Ok(1)).unwrap(), 1);
+        assert_eq!(lines()[1].outcome, "ok");
+        assert_eq!(lines()[1].prompt_sha256, lines()[0].prompt_sha256);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&log).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // A directory cannot be opened as the log.
+        let err = open(tmp.path()).unwrap_err();
+        assert!(err.downcast_ref::<std::io::Error>().is_some());
+    }
+
+    #[test]
+    fn a_line_that_cannot_be_written_stops_the_request() {
+        let tmp = tempfile::tempdir().unwrap();
+        let log = tmp.path().join("audit.jsonl");
+        std::fs::write(&log, "").unwrap();
+        // Opened read-only, so every write fails.
+        let auditor = leak_auditor(log.clone(), File::open(&log).unwrap());
+        let err = auditor
+            .start("openai", "gpt-4o", "chat", embed_prompt(&[]))
+            .err()
+            .unwrap();
+        assert!(format!("{err:#}").contains("failed to write the audit log"));
+        assert_eq!(exit::status_for(&err), ExitStatus::Config);
+
+        let entry = || {
+            let mut entry = auditor.entry("openai", "gpt-4o", "chat", embed_prompt(&[]));
+            entry.written = true;
+            entry
+        };
+        let err = entry().finish(Ok(1)).unwrap_err();
+        assert_eq!(exit::status_for(&err), ExitStatus::Config);
+        let err = entry()
+            .finish::<()>(Err(anyhow::anyhow!("refused")))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "refused");
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "");
+    }
+}
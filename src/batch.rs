@@ -0,0 +1,330 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use futures::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::cli::BatchRunCommand;
+use crate::config::{self, AppConfig};
+use crate::exit::{self, ExitStatus, ResultExt};
+use crate::output::ReplyReport;
+use crate::provider::{build_providers, ChatMessage, ChatRequestOptions};
+use crate::retry::RetryPolicy;
+use crate::{input, shutdown, template};
+
+/// First retry delay; doubles on each further attempt.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// One line of the prompts file. Everything but `prompt` falls back to the
+/// command-line flags.
+#[derive(Debug, Deserialize)]
+struct BatchItem {
+    /// Defaults to the line number
+    #[serde(default)]
+    id: Option<serde_json::Value>,
+    prompt: String,
+    #[serde(default)]
+    provider: Option<String>,
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default)]
+    system: Option<String>,
+    #[serde(default)]
+    temperature: Option<f32>,
+    #[serde(default)]
+    max_tokens: Option<u32>,
+    /// `{{name}}` values for this prompt, on top of `--var`
+    #[serde(default)]
+    vars: BTreeMap<String, String>,
+}
+
+/// A prompt ready to send.
+struct Job {
+    id: String,
+    provider: String,
+    model: String,
+    system: Option<String>,
+    prompt: String,
+    options: ChatRequestOptions,
+}
+
+/// A line of the results file.
+#[derive(Serialize)]
+struct BatchResult<'a> {
+    id: &'a str,
+    attempts: u32,
+    #[serde(flatten)]
+    outcome: Outcome<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Outcome<'a> {
+    Reply(ReplyReport<'a>),
+    Failed { error: String, exit_code: u8 },
+}
+
+/// What `--out` already holds, for resuming.
+#[derive(Deserialize)]
+struct PreviousResult {
+    id: String,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+pub async fn run_batch(mut args: BatchRunCommand, cfg: &AppConfig) -> Result<()> {
+    config::apply_overrides(cfg, &mut args.common).exit_status(ExitStatus::Config)?;
+    let default_provider = cfg
+        .infer_default_provider(&args.common.provider)
+        .exit_status(ExitStatus::Config)?;
+    let files = input::attach_files(
+        &args.common.files,
+        args.common.file_budget,
+        args.common.model.as_deref().unwrap_or_default(),
+    )?;
+    let items = read_items(&args.input)?;
+    let done = answered_ids(&args.out)?;
+
+    let mut jobs = Vec::new();
+    let mut skipped = 0;
+    for (id, item) in items {
+        if done.contains(&id) {
+            skipped += 1;
+            continue;
+        }
+        let provider = item
+            .provider
+            .clone()
+            .unwrap_or_else(|| default_provider.clone());
+        let provider_cfg = cfg
+            .require_provider(&provider)
+            .with_context(|| format!("prompt '{id}'"))
+            .exit_status(ExitStatus::Config)?;
+        let mut vars = args.common.vars.clone();
+        vars.extend(
+            item.vars
+                .iter()
+                .map(|(key, value)| format!("{key}={value}")),
+        );
+        let vars = template::Vars::parse(&vars)?;
+        let system = item
+            .system
+            .or_else(|| args.common.system.clone())
+            .or_else(|| provider_cfg.default_system().map(str::to_string))
+            .map(|system| template::render(&system, &vars))
+            .transpose()
+            .with_context(|| format!("prompt '{id}'"))?;
+        let prompt =
+            template::render(&item.prompt, &vars).with_context(|| format!("prompt '{id}'"))?;
+        jobs.push(Job {
+            model: item
+                .model
+                .or_else(|| args.common.model.clone())
+                .or_else(|| provider_cfg.default_model().map(str::to_string))
+                .unwrap_or_else(|| "gemini-pro".to_string()),
+            provider,
+            system,
+            prompt: input::with_context(files.as_deref(), prompt),
+            options: ChatRequestOptions {
+                temperature: item.temperature.or(args.common.temperature),
+                max_output_tokens: item.max_tokens.or(args.common.max_output_tokens),
+                ..Default::default()
+            },
+            id,
+        });
+    }
+    if jobs.is_empty() {
+        progress(
+            args.common.quiet,
+            &format!(
+                "Nothing to do: every prompt already has a result in {}",
+                args.out.display()
+            ),
+        );
+        return Ok(());
+    }
+    let providers = build_providers(
+        jobs.iter().map(|job| job.provider.as_str()),
+        cfg,
+        args.common.secret_env.as_deref(),
+        !args.common.no_prompt,
+    )
+    .await?;
+
+    let mut out = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&args.out)
+        .with_context(|| format!("failed to open {}", args.out.display()))?;
+    let total = jobs.len();
+    let policy = RetryPolicy {
+        retries: args.retries,
+        backoff: RETRY_DELAY,
+    };
+    let mut results = stream::iter(jobs)
+        .map(|job| {
+            let provider = providers[&job.provider].clone();
+            async move {
+                let started = Instant::now();
+                let messages = [ChatMessage::user(job.prompt.clone())];
+                let (response, attempts) = policy
+                    .chat(
+                        &provider,
+                        &job.model,
+                        job.system.as_deref(),
+                        &messages,
+                        &job.options,
+                        &shutdown::token(),
+                    )
+                    .await;
+                (job, response, attempts, started.elapsed())
+            }
+        })
+        .buffer_unordered(usize::from(args.concurrency));
+
+    let (mut finished, mut failed) = (0, 0);
+    while let Some((job, response, attempts, latency)) = results.next().await {
+        finished += 1;
+        let outcome = match &response {
+            Ok(response) => {
+                progress(
+                    args.common.quiet,
+                    &format!(
+                        "[{finished}/{total}] ok {} ({:.1}s)",
+                        job.id,
+                        latency.as_secs_f64()
+                    ),
+                );
+                Outcome::Reply(ReplyReport::new(
+                    &job.provider,
+                    &job.model,
+                    response,
+                    latency,
+                ))
+            }
+            Err(err) => {
+                failed += 1;
+                progress(
+                    args.common.quiet,
+                    &format!("[{finished}/{total}] failed {}: {err:#}", job.id),
+                );
+                Outcome::Failed {
+                    error: format!("{err:#}"),
+                    exit_code: exit::status_for(err) as u8,
+                }
+            }
+        };
+        let line = serde_json::to_string(&BatchResult {
+            id: &job.id,
+            attempts,
+            outcome,
+        })?;
+        writeln!(out, "{line}")
+            .and_then(|()| out.flush())
+            .with_context(|| format!("failed to write {}", args.out.display()))?;
+    }
+
+    let skipped = if skipped == 0 {
+        String::new()
+    } else {
+        format!(", {skipped} already done")
+    };
+    progress(
+        args.common.quiet,
+        &format!("{} ok, {failed} failed{skipped}", total - failed),
+    );
+    if failed > 0 {
+        bail!("{failed} prompt(s) failed - run the same command again to retry them");
+    }
+    Ok(())
+}
+
+/// Parses the prompts file, numbering lines without an `id`.
+fn read_items(path: &Path) -> Result<Vec<(String, BatchItem)>> {
+    let file =
+        std::fs::File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let mut items = Vec::new();
+    let mut seen = HashSet::new();
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let item: BatchItem = serde_json::from_str(&line)
+            .with_context(|| format!("{}:{}: invalid prompt line", path.display(), index + 1))?;
+        let id = match &item.id {
+            Some(serde_json::Value::String(id)) => id.clone(),
+            Some(id) => id.to_string(),
+            None => (index + 1).to_string(),
+        };
+        if !seen.insert(id.clone()) {
+            bail!("{}:{}: duplicate id '{id}'", path.display(), index + 1);
+        }
+        items.push((id, item));
+    }
+    Ok(items)
+}
+
+/// Ids that already have a successful result in `path`. Failed results are
+/// retried, and a half-written last line from an interrupted run is ignored.
+fn answered_ids(path: &Path) -> Result<HashSet<String>> {
+    let data = match std::fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(HashSet::new()),
+        Err(err) => return Err(err).with_context(|| format!("failed to read {}", path.display())),
+    };
+    Ok(data
+        .lines()
+        .filter_map(|line| serde_json::from_str::<PreviousResult>(line).ok())
+        .filter(|result| result.error.is_none())
+        .map(|result| result.id)
+        .collect())
+}
+
+fn progress(quiet: bool, line: &str) {
+    if !quiet {
+        eprintln!("{line}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resuming_skips_only_answered_prompts() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        let prompts = dir.join("prompts.jsonl");
+        std::fs::write(
+            &prompts,
+            "{\"prompt\": \"one\"}\n\n{\"id\": \"b\", \"prompt\": \"two\", \"model\": \"gpt-4o\"}\n{\"id\": 7, \"prompt\": \"{{x}}\", \"vars\": {\"x\": \"three\"}}\n",
+        )
+        .unwrap();
+        let items = read_items(&prompts).unwrap();
+        let ids: Vec<&str> = items.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(ids, ["1", "b", "7"]);
+        assert_eq!(items[1].1.model.as_deref(), Some("gpt-4o"));
+
+        let results = dir.join("results.jsonl");
+        std::fs::write(
+            &results,
+            "{\"id\": \"1\", \"attempts\": 1, \"text\": \"hi\"}\n{\"id\": \"b\", \"attempts\": 3, \"error\": \"rate limited\", \"exit_code\": 5}\n{\"id\": \"7\", \"te",
+        )
+        .unwrap();
+        let done = answered_ids(&results).unwrap();
+        assert_eq!(done, HashSet::from(["1".to_string()]));
+        assert!(answered_ids(&dir.join("missing.jsonl")).unwrap().is_empty());
+
+        std::fs::write(
+            &prompts,
+            "{\"id\": 2, \"prompt\": \"a\"}\n{\"prompt\": \"b\"}\n",
+        )
+        .unwrap();
+        assert!(read_items(&prompts).is_err());
+    }
+}
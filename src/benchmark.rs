@@ -0,0 +1,343 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use futures::{stream, StreamExt};
+use serde::Serialize;
+
+use crate::cancel;
+use crate::cli::{BenchmarkCommand, OutputArg};
+use crate::compare::Target;
+use crate::config::AppConfig;
+use crate::exit::{ExitStatus, ResultExt};
+use crate::provider::{
+    build_providers, Canceled, CancellationToken, ChatMessage, ChatRequestOptions, DynProvider,
+};
+use crate::streaming::StreamEvent;
+use crate::utils;
+
+const DEFAULT_PROMPT: &str = "Write a short paragraph about the history of the printing press.";
+
+/// How one request went.
+#[derive(Debug, Default)]
+struct Sample {
+    latency: Duration,
+    /// Time to the first text chunk; streaming only
+    ttft: Option<Duration>,
+    output_tokens: usize,
+    failed: bool,
+}
+
+impl Sample {
+    /// Output tokens per second of generation, i.e. after the first token
+    /// when streaming.
+    fn tokens_per_sec(&self) -> Option<f64> {
+        let generating = self.latency.saturating_sub(self.ttft.unwrap_or_default());
+        (self.output_tokens > 0 && !generating.is_zero())
+            .then(|| self.output_tokens as f64 / generating.as_secs_f64())
+    }
+}
+
+/// The numbers reported for one target.
+#[derive(Debug, Serialize, PartialEq)]
+struct BenchmarkReport {
+    provider: String,
+    model: String,
+    requests: usize,
+    errors: usize,
+    error_rate: f64,
+    p50_ms: Option<u128>,
+    p95_ms: Option<u128>,
+    ttft_p50_ms: Option<u128>,
+    ttft_p95_ms: Option<u128>,
+    tokens_per_sec: Option<f64>,
+    /// First error message, to tell what went wrong without `-v`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    first_error: Option<String>,
+}
+
+pub async fn run_benchmark(args: BenchmarkCommand, cfg: &AppConfig) -> Result<()> {
+    let targets = args
+        .targets
+        .iter()
+        .map(|spec| Target::parse(spec, cfg))
+        .collect::<Result<Vec<_>>>()
+        .exit_status(ExitStatus::Config)?;
+    let providers = build_providers(
+        targets.iter().map(|target| target.provider.as_str()),
+        cfg,
+        args.secret_env.as_deref(),
+        !args.no_prompt,
+    )
+    .await?;
+    let prompt = if args.prompt.is_empty() {
+        DEFAULT_PROMPT.to_string()
+    } else {
+        args.prompt.join(" ")
+    };
+    let messages = [ChatMessage::user(prompt)];
+    let options = ChatRequestOptions {
+        max_output_tokens: Some(args.max_output_tokens),
+        ..Default::default()
+    };
+
+    let mut reports = Vec::new();
+    let cancel = cancel::on_ctrl_c();
+    // One target at a time, so targets do not slow each other down.
+    for target in &targets {
+        if !args.quiet {
+            eprintln!(
+                "benchmarking {}:{} - {} request(s), {} at a time{}",
+                target.provider,
+                target.model,
+                args.requests,
+                args.concurrency,
+                if args.stream { ", streaming" } else { "" }
+            );
+        }
+        let provider = &providers[&target.provider];
+        let run = stream::iter(0..args.requests)
+            .map(|_| {
+                sample(
+                    provider,
+                    &target.model,
+                    &messages,
+                    &options,
+                    args.stream,
+                    &cancel,
+                )
+            })
+            .buffer_unordered(usize::from(args.concurrency))
+            .collect::<Vec<_>>();
+        let samples = run.await;
+        if cancel.is_cancelled() {
+            return Err(Canceled.into());
+        }
+        let first_error = samples.iter().find_map(|(_, err)| err.clone());
+        let samples: Vec<Sample> = samples.into_iter().map(|(sample, _)| sample).collect();
+        let mut report = summarize(target, &samples);
+        report.first_error = first_error;
+        reports.push(report);
+    }
+
+    match args.output {
+        OutputArg::Json => println!("{}", serde_json::to_string_pretty(&reports)?),
+        OutputArg::Text => print!("{}", table(&reports)),
+    }
+    if reports
+        .iter()
+        .all(|report| report.errors == report.requests)
+    {
+        bail!("every request failed");
+    }
+    Ok(())
+}
+
+/// Sends one request and times it; the error message comes back beside the
+/// sample so the report can show one.
+async fn sample(
+    provider: &DynProvider,
+    model: &str,
+    messages: &[ChatMessage],
+    options: &ChatRequestOptions,
+    streaming: bool,
+    cancel: &CancellationToken,
+) -> (Sample, Option<String>) {
+    let started = Instant::now();
+    let mut sample = Sample::default();
+    let outcome = if streaming {
+        stream_sample(
+            provider,
+            model,
+            messages,
+            options,
+            cancel,
+            started,
+            &mut sample,
+        )
+        .await
+    } else {
+        provider
+            .chat(model, None, messages, options, cancel)
+            .await
+            .map(|response| {
+                sample.output_tokens = match response.usage {
+                    Some(usage) if usage.output_tokens > 0 => usage.output_tokens as usize,
+                    _ => utils::approx_tokens(&response.text),
+                };
+            })
+    };
+    sample.latency = started.elapsed();
+    match outcome {
+        Ok(()) => (sample, None),
+        Err(err) => {
+            sample.failed = true;
+            (sample, Some(format!("{err:#}")))
+        }
+    }
+}
+
+async fn stream_sample(
+    provider: &DynProvider,
+    model: &str,
+    messages: &[ChatMessage],
+    options: &ChatRequestOptions,
+    cancel: &CancellationToken,
+    started: Instant,
+    sample: &mut Sample,
+) -> Result<()> {
+    let mut events = provider
+        .stream_chat(model, None, messages, options, cancel)
+        .await?;
+    let mut text = String::new();
+    let mut reported = None;
+    while let Some(event) = events.next().await {
+        match event? {
+            StreamEvent::Text(chunk) => {
+                if sample.ttft.is_none() && !chunk.is_empty() {
+                    sample.ttft = Some(started.elapsed());
+                }
+                text.push_str(&chunk);
+            }
+            StreamEvent::Usage(usage) if usage.output_tokens > 0 => {
+                reported = Some(usage.output_tokens as usize);
+            }
+            StreamEvent::Usage(_) | StreamEvent::Finish(_) => {}
+        }
+    }
+    sample.output_tokens = reported.unwrap_or_else(|| utils::approx_tokens(&text));
+    Ok(())
+}
+
+fn summarize(target: &Target, samples: &[Sample]) -> BenchmarkReport {
+    let ok: Vec<&Sample> = samples.iter().filter(|sample| !sample.failed).collect();
+    let mut latencies: Vec<u128> = ok.iter().map(|s| s.latency.as_millis()).collect();
+    let mut ttfts: Vec<u128> = ok
+        .iter()
+        .filter_map(|s| s.ttft.map(|ttft| ttft.as_millis()))
+        .collect();
+    latencies.sort_unstable();
+    ttfts.sort_unstable();
+    let rates: Vec<f64> = ok.iter().filter_map(|s| s.tokens_per_sec()).collect();
+    let errors = samples.len() - ok.len();
+    BenchmarkReport {
+        provider: target.provider.clone(),
+        model: target.model.clone(),
+        requests: samples.len(),
+        errors,
+        error_rate: if samples.is_empty() {
+            0.0
+        } else {
+            errors as f64 / samples.len() as f64
+        },
+        p50_ms: percentile(&latencies, 50),
+        p95_ms: percentile(&latencies, 95),
+        ttft_p50_ms: percentile(&ttfts, 50),
+        ttft_p95_ms: percentile(&ttfts, 95),
+        tokens_per_sec: (!rates.is_empty()).then(|| rates.iter().sum::<f64>() / rates.len() as f64),
+        first_error: None,
+    }
+}
+
+/// Nearest-rank percentile of an ascending list.
+fn percentile(sorted: &[u128], pct: usize) -> Option<u128> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (pct * sorted.len()).div_ceil(100).max(1);
+    Some(sorted[rank - 1])
+}
+
+fn table(reports: &[BenchmarkReport]) -> String {
+    let ms = |value: Option<u128>| value.map_or("-".to_string(), |ms| format!("{ms}ms"));
+    let rows: Vec<[String; 7]> = reports
+        .iter()
+        .map(|report| {
+            [
+                format!("{}:{}", report.provider, report.model),
+                format!("{}/{}", report.requests - report.errors, report.requests),
+                ms(report.p50_ms),
+                ms(report.p95_ms),
+                ms(report.ttft_p50_ms),
+                ms(report.ttft_p95_ms),
+                report
+                    .tokens_per_sec
+                    .map_or("-".to_string(), |rate| format!("{rate:.1}")),
+            ]
+        })
+        .collect();
+    let header = [
+        "target", "ok", "p50", "p95", "ttft p50", "ttft p95", "tok/s",
+    ]
+    .map(String::from);
+    let widths: Vec<usize> = (0..header.len())
+        .map(|column| {
+            std::iter::once(&header)
+                .chain(&rows)
+                .map(|row| row[column].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let mut out = String::new();
+    for row in std::iter::once(&header).chain(&rows) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect();
+        out.push_str(cells.join("  ").trim_end());
+        out.push('\n');
+    }
+    for report in reports {
+        if let Some(err) = &report.first_error {
+            out.push_str(&format!(
+                "{}:{}: {} error(s), first: {err}\n",
+                report.provider, report.model, report.errors
+            ));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_summarize_to_percentiles_and_rates() {
+        let target = Target {
+            provider: "p".to_string(),
+            model: "m".to_string(),
+        };
+        let mut samples: Vec<Sample> = (1..=20)
+            .map(|n| Sample {
+                latency: Duration::from_millis(n * 100),
+                ttft: Some(Duration::from_millis(50)),
+                output_tokens: 100,
+                failed: false,
+            })
+            .collect();
+        samples.push(Sample {
+            failed: true,
+            ..Default::default()
+        });
+        let report = summarize(&target, &samples);
+        assert_eq!(report.requests, 21);
+        assert_eq!(report.errors, 1);
+        assert_eq!(report.p50_ms, Some(1000));
+        assert_eq!(report.p95_ms, Some(1900));
+        assert_eq!(report.ttft_p50_ms, Some(50));
+        assert!(report.tokens_per_sec.unwrap() > 0.0);
+
+        assert_eq!(percentile(&[7], 95), Some(7));
+        assert_eq!(percentile(&[], 50), None);
+        let sample = Sample {
+            latency: Duration::from_secs(3),
+            ttft: Some(Duration::from_secs(1)),
+            output_tokens: 100,
+            failed: false,
+        };
+        assert_eq!(sample.tokens_per_sec(), Some(50.0));
+        assert!(table(&[report]).starts_with("target  ok     p50"));
+    }
+}
@@ -0,0 +1,230 @@
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::config::{default_cache_dir, CacheConfig};
+use crate::utils;
+use crate::vcr::Reply;
+
+const RESPONSES_DIR: &str = "responses";
+
+struct Cache {
+    dir: PathBuf,
+    ttl: chrono::Duration,
+}
+
+static CACHE: OnceLock<Cache> = OnceLock::new();
+/// Only the first failed write is reported.
+static WARNED: AtomicBool = AtomicBool::new(false);
+
+/// One cached reply, saved as `<dir>/<SHA-256 of request>.json`.
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    saved_at: DateTime<Utc>,
+    request: Value,
+    #[serde(flatten)]
+    reply: Reply,
+}
+
+pub fn cache_dir(cfg: &CacheConfig) -> Option<PathBuf> {
+    cfg.dir
+        .clone()
+        .or_else(|| default_cache_dir().map(|dir| dir.join(RESPONSES_DIR)))
+}
+
+/// Turns the reply cache on for the rest of the process when `--cache-ttl`
+/// or `[cache] ttl` is set and `--no-cache` is not.
+pub fn init(cfg: &CacheConfig, no_cache: bool, ttl: Option<chrono::Duration>) -> Result<()> {
+    if no_cache {
+        return Ok(());
+    }
+    let ttl = match (ttl, &cfg.ttl) {
+        (Some(ttl), _) => ttl,
+        (None, Some(ttl)) => utils::parse_age(ttl).context("invalid [cache] ttl")?,
+        (None, None) => return Ok(()),
+    };
+    let Some(dir) = cache_dir(cfg) else {
+        tracing::warn!("[cache] no cache directory for replies; set [cache] dir");
+        return Ok(());
+    };
+    let cache = Cache { dir, ttl };
+    cache.prune(SystemTime::now());
+    let _ = CACHE.set(cache);
+    Ok(())
+}
+
+pub fn enabled() -> bool {
+    CACHE.get().is_some()
+}
+
+/// The cached reply to `request`, if one was saved within the TTL.
+pub fn load(request: &Value) -> Option<Reply> {
+    let cache = CACHE.get()?;
+    cache.load(request, Utc::now())
+}
+
+/// Keeps `reply` for later; failures are reported once and otherwise ignored,
+/// since the request itself succeeded.
+pub fn save(request: Value, reply: Reply) {
+    let Some(cache) = CACHE.get() else {
+        return;
+    };
+    if let Err(err) = cache.save(request, reply, Utc::now()) {
+        if !WARNED.swap(true, Ordering::Relaxed) {
+            tracing::warn!("failed to write the reply cache: {err:#}");
+        }
+    }
+}
+
+impl Cache {
+    fn load(&self, request: &Value, now: DateTime<Utc>) -> Option<Reply> {
+        let path = entry_path(&self.dir, request);
+        let data = std::fs::read(&path).ok()?;
+        let entry: Entry = match serde_json::from_slice(&data) {
+            Ok(entry) => entry,
+            Err(err) => {
+                tracing::debug!("ignoring unreadable cache entry {}: {err}", path.display());
+                return None;
+            }
+        };
+        if now - entry.saved_at > self.ttl {
+            let _ = std::fs::remove_file(&path);
+            return None;
+        }
+        // The file name is only a hash; the request itself must match too.
+        (entry.request == *request).then_some(entry.reply)
+    }
+
+    /// Removes the entries that have outlived the TTL, by the time they were
+    /// written, so replies nobody asks for again do not pile up.
+    fn prune(&self, now: SystemTime) {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return;
+        };
+        let cutoff = now - self.ttl.to_std().unwrap_or_default();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let expired = entry
+                .metadata()
+                .and_then(|meta| meta.modified())
+                .is_ok_and(|modified| modified < cutoff);
+            if expired && path.extension().is_some_and(|ext| ext == "json") {
+                let _ = std::fs::remove_file(&path);
+            }
+        }
+    }
+
+    /// Entries may hold prompts, so the directory and its files are readable
+    /// by their owner alone.
+    fn save(&self, request: Value, reply: Reply, now: DateTime<Utc>) -> Result<()> {
+        let mut dir = std::fs::DirBuilder::new();
+        dir.recursive(true);
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut dir, 0o700);
+        dir.create(&self.dir)
+            .with_context(|| format!("failed to create {}", self.dir.display()))?;
+        let path = entry_path(&self.dir, &request);
+        let entry = Entry {
+            saved_at: now,
+            request,
+            reply,
+        };
+        utils::write_private(&path, &serde_json::to_vec(&entry)?)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+fn entry_path(dir: &Path, request: &Value) -> PathBuf {
+    let hash = digest::digest(&digest::SHA256, request.to_string().as_bytes());
+    let mut name = String::new();
+    for byte in hash.as_ref() {
+        let _ = write!(name, "{byte:02x}");
+    }
+    dir.join(format!("{name}.json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::{ChatMessage, ChatRequestOptions};
+    use crate::vcr::{self, RecordedResponse};
+
+    #[test]
+    fn replies_are_reused_until_they_expire() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join(RESPONSES_DIR);
+        let cache = Cache {
+            dir: dir.clone(),
+            ttl: chrono::Duration::hours(1),
+        };
+        let messages = [ChatMessage::user("Capital of France?")];
+        let options = ChatRequestOptions::default();
+        let openai = serde_json::json!({"kind": "openai", "base_url": null});
+        let request = vcr::chat_request("openai", &openai, "gpt-4o", None, &messages, &options);
+        let reply = Reply::Response(RecordedResponse {
+            text: "Paris.".to_string(),
+            usage: None,
+            finish_reason: None,
+            tool_calls: Vec::new(),
+            citations: Vec::new(),
+        });
+        let saved_at = Utc::now();
+        cache.save(request.clone(), reply, saved_at).unwrap();
+
+        let later = saved_at + chrono::Duration::minutes(30);
+        let response = cache
+            .load(&request, later)
+            .unwrap()
+            .into_response()
+            .unwrap();
+        assert_eq!(response.text, "Paris.");
+        let warmer = ChatRequestOptions {
+            temperature: Some(1.0),
+            ..ChatRequestOptions::default()
+        };
+        let other = vcr::chat_request("openai", &openai, "gpt-4o", None, &messages, &warmer);
+        assert!(cache.load(&other, later).is_none());
+        // The same name in another config may point somewhere else entirely.
+        let local = serde_json::json!({"kind": "openai", "base_url": "http://localhost:11434/v1"});
+        let elsewhere = vcr::chat_request("openai", &local, "gpt-4o", None, &messages, &options);
+        assert!(cache.load(&elsewhere, later).is_none());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+            assert_eq!(mode(&dir), 0o700);
+            assert_eq!(mode(&entry_path(&dir, &request)), 0o600);
+        }
+
+        // Past the TTL the entry is dropped and the request goes out again.
+        let expired = saved_at + chrono::Duration::hours(2);
+        assert!(cache.load(&request, expired).is_none());
+        assert!(cache.load(&request, later).is_none());
+    }
+
+    #[test]
+    fn expired_entries_are_pruned() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cache = Cache {
+            dir: tmp.path().to_path_buf(),
+            ttl: chrono::Duration::hours(1),
+        };
+        let request = vcr::embed_request("openai", &Value::Null, "m", &["hi".to_string()]);
+        let reply = Reply::Embeddings(vec![vec![0.5]]);
+        cache.save(request.clone(), reply, Utc::now()).unwrap();
+        let path = entry_path(&cache.dir, &request);
+
+        cache.prune(SystemTime::now());
+        assert!(path.exists());
+        cache.prune(SystemTime::now() + std::time::Duration::from_secs(2 * 3600));
+        assert!(!path.exists());
+    }
+}
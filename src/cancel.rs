@@ -0,0 +1,22 @@
+use crate::exit::ExitStatus;
+use crate::provider::CancellationToken;
+use crate::shutdown;
+
+/// A token that fires on the first Ctrl-C or at shutdown, for requests that
+/// should give up with `Canceled` rather than take the process down mid-write.
+/// What comes after, such as saving or posting the result, cannot be
+/// canceled, so a second Ctrl-C exits instead.
+pub fn on_ctrl_c() -> CancellationToken {
+    let cancel = shutdown::token();
+    let fired = cancel.clone();
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => fired.cancel(),
+            _ = fired.cancelled() => return,
+        }
+        let _ = tokio::signal::ctrl_c().await;
+        eprintln!();
+        shutdown::exit(ExitStatus::Canceled as u8).await;
+    });
+    cancel
+}
@@ -1,6 +1,12 @@
 use std::path::PathBuf;
 
-use clap::{Args, Parser, Subcommand, ValueEnum};
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+use clap_complete::ArgValueCandidates;
+
+use crate::completions::{model_candidates, provider_candidates, webhook_candidates};
+use crate::config::{ApprovalPolicy, ProviderKind};
+use crate::logger::{Compression, HistoryFormat};
+use crate::secrets::Kdf;
 
 #[derive(Parser, Debug)]
 #[command(
@@ -10,6 +16,44 @@ use clap::{Args, Parser, Subcommand, ValueEnum};
     about = "Multi-provider AI chat CLI"
 )]
 pub struct Cli {
+    /// Use this config file instead of the platform default
+    #[arg(long = "config", global = true, env = "RUSTCHAT_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Log HTTP requests to stderr: -v for URLs, status codes, timing and
+    /// retries, -vv for headers and bodies too (API keys masked)
+    #[arg(short = 'v', long = "verbose", action = ArgAction::Count, global = true)]
+    pub verbose: u8,
+    /// Lowest level logged, in place of -v (default: warn)
+    #[arg(long = "log-level", value_enum, global = true, env = "RUSTCHAT_LOG")]
+    pub log_level: Option<LogLevel>,
+    /// Log lines as text, or as one JSON object each
+    #[arg(
+        long = "log-format",
+        value_enum,
+        default_value_t = LogFormat::Text,
+        global = true,
+        env = "RUSTCHAT_LOG_FORMAT"
+    )]
+    pub log_format: LogFormat,
+    /// Append the log to this file instead of stderr; warnings still show
+    #[arg(long = "log-file", global = true, value_name = "PATH")]
+    pub log_file: Option<PathBuf>,
+    /// Save every provider request and reply (streams included) to this
+    /// directory, one JSON file each
+    #[arg(long, global = true, value_name = "DIR", conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+    /// Answer requests from a --record directory instead of the network;
+    /// a request that was never recorded fails
+    #[arg(long, global = true, value_name = "DIR")]
+    pub replay: Option<PathBuf>,
+    /// Send every request even when `[cache]` holds a fresh reply, and cache
+    /// nothing new
+    #[arg(long = "no-cache", global = true, conflicts_with = "cache_ttl")]
+    pub no_cache: bool,
+    /// Answer identical requests from the reply cache for this long, e.g. 1h
+    /// or 7d; overrides `[cache] ttl`
+    #[arg(long = "cache-ttl", global = true, value_name = "AGE", value_parser = parse_age_arg)]
+    pub cache_ttl: Option<chrono::Duration>,
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -25,19 +69,759 @@ pub enum Commands {
     Chat(ChatCommand),
     /// Send a single message and print the response
     Message(MessageCommand),
+    /// Full-screen terminal UI with a session sidebar
+    Tui(ChatCommand),
+    /// Browse and manage saved conversations
+    Sessions(SessionsArgs),
+    /// Save named prompt templates and run them
+    Prompts {
+        #[command(subcommand)]
+        command: PromptsCommand,
+    },
+    /// Send one prompt to several models at once and show the answers side by side
+    Compare(CompareCommand),
+    /// Score models on a prompt set, with another model as the judge
+    Eval(EvalCommand),
+    /// Ask for a shell command that does a task, then run it only once confirmed
+    Cmd(CmdCommand),
+    /// Write a Conventional Commits message for the staged changes
+    CommitMsg(CommitMsgCommand),
+    /// Measure latency, time to first token and throughput of models
+    Benchmark(BenchmarkCommand),
+    /// Count the tokens in text, files or piped stdin with a model's tokenizer
+    Tokens(TokensCommand),
+    /// Run many prompts at once
+    Batch {
+        #[command(subcommand)]
+        command: BatchCommand,
+    },
+    /// Report tokens and spend recorded for past requests
+    Usage {
+        #[command(subcommand)]
+        command: UsageCommand,
+    },
+    /// Index local documents for `chat --knowledge`
+    Index {
+        #[command(subcommand)]
+        command: IndexCommand,
+    },
+    /// Serve an OpenAI-compatible API (`/v1/chat/completions`, `/v1/models`)
+    /// backed by the configured providers and profiles
+    Serve(ServeCommand),
+    /// Keep providers set up and secrets decrypted in a background process
+    /// that `message` hands its requests to over a Unix socket
+    Daemon {
+        #[command(subcommand)]
+        command: DaemonCommand,
+    },
+    /// List plugins: executables named `rustchat-<name>` on PATH, which run as
+    /// `rustchat-cli <name>` and may offer tools to chat sessions
+    Plugins {
+        #[command(subcommand)]
+        command: PluginsCommand,
+    },
+    /// Print a shell completion script (e.g. `source <(rustchat-cli completions bash)`)
+    Completions {
+        #[arg(value_enum)]
+        shell: CompletionShell,
+        /// Emit a self-contained script that does not call back into
+        /// rustchat-cli, so provider and model names are not completed
+        #[arg(long = "static")]
+        static_script: bool,
+        /// Command name to complete, e.g. `rustchat` for the npm wrapper
+        #[arg(long, default_value = "rustchat-cli")]
+        bin: String,
+    },
+    /// Print the roff man page, or write one per subcommand with --out-dir
+    Man {
+        /// Write `rustchat-cli.1` and a page for every subcommand here
+        #[arg(long = "out-dir", value_name = "DIR")]
+        out_dir: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq, Eq)]
+pub enum CompletionShell {
+    Bash,
+    Zsh,
+    Fish,
+    Powershell,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum PromptsCommand {
+    /// Save a prompt template under a name
+    Add {
+        /// Name to run it by (letters, digits, '_' and '-')
+        name: String,
+        /// Prompt text; {{var}} placeholders are filled from --var, piped
+        /// stdin ({{stdin}}) or the environment when it runs
+        template: String,
+        /// Provider to run it with unless --provider says otherwise
+        #[arg(long)]
+        provider: Option<String>,
+        /// Model to run it with unless --model says otherwise
+        #[arg(long)]
+        model: Option<String>,
+        /// System prompt to run it with unless --system says otherwise
+        #[arg(long)]
+        system: Option<String>,
+        /// Temperature to run it with unless --temperature says otherwise
+        #[arg(long)]
+        temperature: Option<f32>,
+        /// Replace a prompt that already has this name
+        #[arg(long)]
+        force: bool,
+    },
+    /// List saved prompts
+    List,
+    /// Print a saved prompt and its settings
+    Show {
+        /// Saved prompt name
+        name: String,
+    },
+    /// Delete a saved prompt
+    Remove {
+        /// Saved prompt name
+        name: String,
+    },
+    /// Send a saved prompt, like `message`
+    Run(Box<PromptRunCommand>),
+}
+
+#[derive(Args, Debug)]
+pub struct PromptRunCommand {
+    /// Saved prompt name
+    pub name: String,
+    #[command(flatten)]
+    pub common: CommonChatArgs,
+    /// Print the reply as rendered text, or as one JSON object
+    #[arg(long, value_enum, default_value_t = OutputArg::Text)]
+    pub output: OutputArg,
+    /// Print the request instead of sending it
+    #[arg(long = "dry-run")]
+    pub dry_run: bool,
+    #[command(flatten)]
+    pub retry: RetryArgs,
+    #[command(flatten)]
+    pub cost: CostArgs,
+    #[command(flatten)]
+    pub stdin_filters: StdinFilterArgs,
+}
+
+/// Cleanup for piped stdin, applied before it joins the prompt.
+#[derive(Args, Debug, Clone, Copy)]
+pub struct StdinFilterArgs {
+    /// Remove ANSI color and cursor escape codes from piped stdin
+    #[arg(long = "strip-ansi")]
+    pub strip_ansi: bool,
+    /// Keep only the last N lines of piped stdin
+    #[arg(long = "tail-lines", value_name = "N")]
+    pub tail_lines: Option<usize>,
+    /// Keep only the last SIZE of piped stdin, e.g. 64KB
+    #[arg(long = "max-bytes", value_name = "SIZE", value_parser = parse_size_arg)]
+    pub max_bytes: Option<u64>,
+}
+
+fn parse_size_arg(text: &str) -> Result<u64, String> {
+    crate::utils::parse_size(text).map_err(|err| err.to_string())
+}
+
+fn parse_age_arg(text: &str) -> Result<chrono::Duration, String> {
+    crate::utils::parse_age(text).map_err(|err| err.to_string())
+}
+
+/// One `--tool-approval` value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolApprovalArg {
+    /// The tool it applies to; every tool when absent
+    pub tool: Option<String>,
+    pub policy: ApprovalPolicy,
+}
+
+fn parse_tool_approval(text: &str) -> Result<ToolApprovalArg, String> {
+    let (tool, policy) = match text.split_once('=') {
+        Some((tool, policy)) if !tool.trim().is_empty() => (Some(tool.trim().to_string()), policy),
+        Some(_) => return Err("expected TOOL=POLICY".to_string()),
+        None => (None, text),
+    };
+    let policy = ApprovalPolicy::parse(policy)
+        .ok_or_else(|| format!("unknown policy '{policy}' (expected ask, read-only or deny)"))?;
+    Ok(ToolApprovalArg { tool, policy })
+}
+
+#[derive(Args, Debug, Clone, Copy)]
+pub struct CostArgs {
+    /// Print the estimated price before sending and the actual price after,
+    /// from the bundled price table
+    #[arg(long = "estimate-cost")]
+    pub estimate_cost: bool,
+    /// Refuse to send when the estimate exceeds this many USD (the reply
+    /// counts only when --max-tokens caps it)
+    #[arg(long = "max-cost", value_name = "USD")]
+    pub max_cost: Option<f64>,
+}
+
+#[derive(Args, Debug, Clone, Copy)]
+pub struct RetryArgs {
+    /// Resend the request up to this many times after a rate limit, network
+    /// or server error
+    #[arg(long, default_value_t = 0)]
+    pub retries: u32,
+    /// Milliseconds to wait before the first retry; doubles on each further one
+    #[arg(long = "retry-backoff-ms", value_name = "MS", default_value_t = 1000)]
+    pub retry_backoff_ms: u64,
+}
+
+#[derive(Args, Debug)]
+pub struct CompareCommand {
+    /// Prompt text; `-` or piped stdin works as in `message`
+    pub prompt: Vec<String>,
+    /// Target as PROVIDER[:MODEL], repeated; PROVIDER may also be a kind
+    /// such as `anthropic` when only one provider of that kind is configured
+    #[arg(
+        short = 'm',
+        long = "model",
+        value_name = "PROVIDER[:MODEL]",
+        required = true
+    )]
+    pub targets: Vec<String>,
+    /// System prompt sent to every target
+    #[arg(long)]
+    pub system: Option<String>,
+    /// Optional temperature override
+    #[arg(long)]
+    pub temperature: Option<f32>,
+    /// Optional max output tokens
+    #[arg(long = "max-tokens")]
+    pub max_output_tokens: Option<u32>,
+    /// Print the answers as columns, or as a JSON array
+    #[arg(long, value_enum, default_value_t = OutputArg::Text)]
+    pub output: OutputArg,
+    /// Environment variable that stores the master passphrase (defaults to RUSTCHAT_PASSPHRASE)
+    #[arg(long = "secret-env")]
+    pub secret_env: Option<String>,
+    /// Fail instead of asking for the passphrase when its variable is unset
+    #[arg(long = "no-prompt")]
+    pub no_prompt: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct EvalCommand {
+    /// JSONL eval set with one `{"prompt": ...}` object per line; `id`,
+    /// `reference` (a known-good answer) and `rubric` are optional
+    pub input: PathBuf,
+    /// Candidate as PROVIDER[:MODEL], repeated
+    #[arg(
+        short = 'm',
+        long = "model",
+        value_name = "PROVIDER[:MODEL]",
+        required = true
+    )]
+    pub targets: Vec<String>,
+    /// Model that scores the answers (defaults to the default provider)
+    #[arg(long, value_name = "PROVIDER[:MODEL]")]
+    pub judge: Option<String>,
+    /// What the judge should score, unless a case sets its own
+    #[arg(long, conflicts_with = "rubric_file")]
+    pub rubric: Option<String>,
+    /// Read the rubric from a file
+    #[arg(long = "rubric-file", value_name = "PATH")]
+    pub rubric_file: Option<PathBuf>,
+    /// Report format
+    #[arg(long, value_enum, default_value_t = ReportFormatArg::Json)]
+    pub format: ReportFormatArg,
+    /// Write the report here instead of stdout
+    #[arg(long, value_name = "PATH")]
+    pub out: Option<PathBuf>,
+    /// How many cases to run at once
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u16).range(1..=64))]
+    pub concurrency: u16,
+    /// System prompt sent to every candidate
+    #[arg(long)]
+    pub system: Option<String>,
+    /// Optional temperature override for the candidates
+    #[arg(long)]
+    pub temperature: Option<f32>,
+    /// Optional max output tokens for the candidates
+    #[arg(long = "max-tokens")]
+    pub max_output_tokens: Option<u32>,
+    /// Hide per-case progress and the summary on stderr
+    #[arg(short, long)]
+    pub quiet: bool,
+    /// Environment variable that stores the master passphrase (defaults to RUSTCHAT_PASSPHRASE)
+    #[arg(long = "secret-env")]
+    pub secret_env: Option<String>,
+    /// Fail instead of asking for the passphrase when its variable is unset
+    #[arg(long = "no-prompt")]
+    pub no_prompt: bool,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq, Eq)]
+pub enum ReportFormatArg {
+    Json,
+    Csv,
+}
+
+#[derive(Args, Debug)]
+pub struct CmdCommand {
+    /// What the command should do, e.g. "largest files under src"
+    #[arg(required = true)]
+    pub task: Vec<String>,
+    /// Provider to ask (defaults to the configured default)
+    #[arg(long)]
+    pub provider: Option<String>,
+    /// Model to use (defaults to the provider's default model)
+    #[arg(long)]
+    pub model: Option<String>,
+    /// Environment variable that stores the master passphrase (defaults to RUSTCHAT_PASSPHRASE)
+    #[arg(long = "secret-env")]
+    pub secret_env: Option<String>,
+    /// Fail instead of asking for the passphrase when its variable is unset
+    #[arg(long = "no-prompt")]
+    pub no_prompt: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct TokensCommand {
+    /// Text to count; piped stdin is counted too
+    pub text: Vec<String>,
+    /// Count a file (path or glob) as well; repeatable
+    #[arg(long = "file", short = 'f', value_name = "PATH")]
+    pub files: Vec<String>,
+    /// Provider whose default model is used, and that --exact asks
+    #[arg(long)]
+    pub provider: Option<String>,
+    /// Model whose tokenizer counts (defaults to the provider's default model)
+    #[arg(long)]
+    pub model: Option<String>,
+    /// Ask the provider to count instead of estimating (Claude and Gemini;
+    /// OpenAI counts are exact already)
+    #[arg(long)]
+    pub exact: bool,
+    /// Environment variable that stores the master passphrase (defaults to RUSTCHAT_PASSPHRASE)
+    #[arg(long = "secret-env")]
+    pub secret_env: Option<String>,
+    /// Fail instead of asking for the passphrase when its variable is unset
+    #[arg(long = "no-prompt")]
+    pub no_prompt: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct CommitMsgCommand {
+    /// Extra context for the model, such as why the change was made
+    pub notes: Vec<String>,
+    /// Provider to ask (defaults to the configured default)
+    #[arg(long)]
+    pub provider: Option<String>,
+    /// Model to use (defaults to the provider's default model)
+    #[arg(long)]
+    pub model: Option<String>,
+    /// Also save the message to .git/COMMIT_EDITMSG
+    #[arg(long, conflicts_with = "commit")]
+    pub write: bool,
+    /// Run `git commit -e -m <message>` so you can review it in your editor
+    #[arg(long)]
+    pub commit: bool,
+    /// Send at most this many characters of the diff
+    #[arg(long, default_value_t = 40_000)]
+    pub max_diff_chars: usize,
+    /// Environment variable that stores the master passphrase (defaults to RUSTCHAT_PASSPHRASE)
+    #[arg(long = "secret-env")]
+    pub secret_env: Option<String>,
+    /// Fail instead of asking for the passphrase when its variable is unset
+    #[arg(long = "no-prompt")]
+    pub no_prompt: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct BenchmarkCommand {
+    /// Prompt sent with every request (a short essay request by default)
+    pub prompt: Vec<String>,
+    /// Target as PROVIDER[:MODEL], repeated; targets run one after another
+    #[arg(
+        short = 'm',
+        long = "model",
+        value_name = "PROVIDER[:MODEL]",
+        required = true
+    )]
+    pub targets: Vec<String>,
+    /// Requests per target
+    #[arg(short = 'n', long, default_value_t = 10)]
+    pub requests: usize,
+    /// How many requests to keep in flight
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..=64))]
+    pub concurrency: u16,
+    /// Stream the replies, which also measures time to first token
+    #[arg(long)]
+    pub stream: bool,
+    /// Max output tokens per request
+    #[arg(long = "max-tokens", default_value_t = 256)]
+    pub max_output_tokens: u32,
+    /// Print a table, or the numbers as JSON
+    #[arg(long, value_enum, default_value_t = OutputArg::Text)]
+    pub output: OutputArg,
+    /// Hide progress lines on stderr
+    #[arg(short, long)]
+    pub quiet: bool,
+    /// Environment variable that stores the master passphrase (defaults to RUSTCHAT_PASSPHRASE)
+    #[arg(long = "secret-env")]
+    pub secret_env: Option<String>,
+    /// Fail instead of asking for the passphrase when its variable is unset
+    #[arg(long = "no-prompt")]
+    pub no_prompt: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum BatchCommand {
+    /// Send every prompt in a JSONL file and append the replies to --out
+    Run(BatchRunCommand),
+}
+
+#[derive(Args, Debug)]
+pub struct BatchRunCommand {
+    /// JSONL file with one `{"prompt": ...}` object per line; `id`,
+    /// `provider`, `model`, `system`, `temperature`, `max_tokens` and `vars`
+    /// are optional
+    pub input: PathBuf,
+    /// JSONL results file; prompts that already have a reply in it are
+    /// skipped, so an interrupted run resumes where it stopped
+    #[arg(long, value_name = "PATH")]
+    pub out: PathBuf,
+    /// How many requests to keep in flight
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u16).range(1..=64))]
+    pub concurrency: u16,
+    /// Extra attempts after a rate limit, network or server error
+    #[arg(long, default_value_t = 2)]
+    pub retries: u32,
+    #[command(flatten)]
+    pub common: CommonChatArgs,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum IndexCommand {
+    /// Chunk and embed files (directories are walked) into an index;
+    /// unchanged files are skipped
+    Add(IndexAddArgs),
+    /// Show each index with its size and embedding model
+    List,
+}
+
+#[derive(Args, Debug)]
+pub struct IndexAddArgs {
+    /// Files, directories or quoted globs to index
+    #[arg(required = true)]
+    pub paths: Vec<String>,
+    /// Index to add to; created on first use
+    #[arg(long, default_value = "default")]
+    pub index: String,
+    /// Provider whose embeddings API is used (an existing index keeps its own)
+    #[arg(long, add = ArgValueCandidates::new(provider_candidates))]
+    pub provider: Option<String>,
+    /// Embedding model (default: text-embedding-3-small for OpenAI,
+    /// text-embedding-004 for Gemini)
+    #[arg(long = "embedding-model")]
+    pub embedding_model: Option<String>,
+    /// Approximate characters per chunk
+    #[arg(long = "chunk-size", default_value_t = 1500)]
+    pub chunk_size: usize,
+    /// Characters repeated from the end of each chunk at the start of the next
+    #[arg(long = "chunk-overlap", default_value_t = 200)]
+    pub chunk_overlap: usize,
+    /// Env var holding the passphrase for encrypted keys
+    #[arg(long = "secret-env")]
+    pub secret_env: Option<String>,
+    /// Fail instead of prompting for a passphrase
+    #[arg(long = "no-prompt")]
+    pub no_prompt: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ServeCommand {
+    /// Address to listen on; use 0.0.0.0 only together with --api-key-env
+    #[arg(long, default_value = "127.0.0.1")]
+    pub host: String,
+    #[arg(long, default_value_t = 8080)]
+    pub port: u16,
+    /// Env var holding a key clients must send as `Authorization: Bearer <key>`
+    #[arg(long = "api-key-env")]
+    pub api_key_env: Option<String>,
+    /// Env var holding the passphrase for encrypted keys
+    #[arg(long = "secret-env")]
+    pub secret_env: Option<String>,
+    /// Fail instead of prompting for a passphrase
+    #[arg(long = "no-prompt")]
+    pub no_prompt: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DaemonCommand {
+    /// Run the daemon in the foreground until Ctrl-C or `daemon stop`
+    Start(DaemonStartArgs),
+    /// Ask a running daemon to exit
+    Stop,
+    /// Show whether a daemon is running and what it serves
+    Status,
+}
+
+#[derive(Args, Debug)]
+pub struct DaemonStartArgs {
+    /// Env var holding the passphrase for encrypted keys
+    #[arg(long = "secret-env")]
+    pub secret_env: Option<String>,
+    /// Fail instead of prompting for a passphrase
+    #[arg(long = "no-prompt")]
+    pub no_prompt: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum PluginsCommand {
+    /// Show each plugin found on PATH with its description and tools
+    List,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum UsageCommand {
+    /// Totals per model, provider or day from the usage log
+    Report(UsageReportArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct UsageReportArgs {
+    /// Only count requests newer than this age, e.g. 30d or 12h
+    #[arg(long, value_name = "AGE")]
+    pub since: Option<String>,
+    /// What each row totals
+    #[arg(long, value_enum, default_value_t = UsageGroupArg::Model)]
+    pub by: UsageGroupArg,
+    /// Print an aligned table, or CSV for spreadsheets
+    #[arg(long, value_enum, default_value_t = UsageReportFormat::Table)]
+    pub format: UsageReportFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum UsageGroupArg {
+    Model,
+    Provider,
+    Day,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum UsageReportFormat {
+    Table,
+    Csv,
+}
+
+#[derive(Args, Debug)]
+pub struct SessionsArgs {
+    /// Directory holding saved sessions (defaults to the auto-save directory)
+    #[arg(long = "history-dir", global = true)]
+    pub history_dir: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: SessionsCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SessionsCommand {
+    /// List saved sessions, newest first
+    List {
+        /// Only show sessions carrying this tag; repeat to require several
+        #[arg(long = "tag", value_name = "TAG")]
+        tags: Vec<String>,
+    },
+    /// Print a saved session through the pager
+    Show {
+        /// Session id (file name without extension), unique prefix, or path
+        id: String,
+        /// Do not shorten long messages
+        #[arg(long)]
+        full: bool,
+    },
+    /// Summarize saved history: totals, per provider/model, busiest days
+    Stats,
+    /// Find saved messages containing some text (case-insensitive)
+    Search {
+        /// Text to look for
+        query: String,
+    },
+    /// Delete a saved session
+    Delete {
+        /// Session id (file name without extension), unique prefix, or path
+        id: String,
+        /// Skip the confirmation prompt
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Delete old sessions, or the oldest ones beyond a total size
+    Prune {
+        /// Delete sessions started longer ago than this (e.g. 90d, 12h, 2w)
+        #[arg(long, value_name = "AGE")]
+        older_than: Option<String>,
+        /// Then delete the oldest sessions until the rest fit (e.g. 200MB)
+        #[arg(long, value_name = "SIZE")]
+        max_size: Option<String>,
+        /// Only list what would be deleted
+        #[arg(long)]
+        dry_run: bool,
+        /// Skip the confirmation prompt
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Remove saved sessions that are earlier snapshots or copies of another
+    /// one, folding their title and tags into the session that is kept
+    Compact {
+        /// Only list what would be removed
+        #[arg(long)]
+        dry_run: bool,
+        /// Skip the confirmation prompt
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Copy conversations exported from another tool into the session store
+    Import {
+        /// Where the export came from
+        #[arg(value_enum)]
+        source: ImportSourceArg,
+        /// Export file (`conversations.json` for ChatGPT)
+        path: PathBuf,
+    },
+    /// Convert a saved session to another format
+    Export {
+        /// Session id (file name without extension), unique prefix, or path
+        id: String,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = SaveFormatArg::Markdown)]
+        format: SaveFormatArg,
+        /// Write to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum CompressArg {
+    Gzip,
+    Zstd,
+}
+
+impl From<CompressArg> for Compression {
+    fn from(value: CompressArg) -> Self {
+        match value {
+            CompressArg::Gzip => Compression::Gzip,
+            CompressArg::Zstd => Compression::Zstd,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SaveModeArg {
+    /// Write this conversation to the file (needs --force if it exists)
+    Overwrite,
+    /// Keep the saved transcript and add this conversation after it
+    Append,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ImportSourceArg {
+    /// OpenAI ChatGPT data export
+    Chatgpt,
 }
 
 #[derive(Subcommand, Debug)]
 pub enum ConfigCommand {
     /// Persist provider credentials and defaults
-    Set(ConfigSetArgs),
-    /// Print the active configuration
-    Show,
+    Set(Box<ConfigSetArgs>),
+    /// Print the active configuration with secrets masked
+    Show {
+        /// Print API keys and credentials in full
+        #[arg(long)]
+        reveal: bool,
+    },
     /// Remove a provider entry
     Remove {
         /// Provider name to remove
+        #[arg(add = ArgValueCandidates::new(provider_candidates))]
         provider: String,
     },
+    /// Write the configuration to move it to another machine or a dotfiles repo
+    Export(ConfigExportArgs),
+    /// Merge providers and profiles from an exported configuration
+    Import {
+        /// TOML file written by `config export`
+        path: PathBuf,
+        /// Replace the whole configuration instead of merging into it
+        #[arg(long)]
+        replace: bool,
+    },
+    /// Re-encrypt every stored API key under a new passphrase
+    RotatePassphrase {
+        /// Environment variable holding the current passphrase (defaults to RUSTCHAT_PASSPHRASE)
+        #[arg(long = "secret-env")]
+        secret_env: Option<String>,
+        /// Environment variable holding the new passphrase; prompted for when omitted
+        #[arg(long = "new-secret-env")]
+        new_secret_env: Option<String>,
+    },
+    /// Send a one-token request to each provider to check keys and latency
+    Test {
+        /// Only check this provider
+        #[arg(add = ArgValueCandidates::new(provider_candidates))]
+        provider: Option<String>,
+        /// Environment variable that stores the master passphrase (defaults to RUSTCHAT_PASSPHRASE)
+        #[arg(long = "secret-env")]
+        secret_env: Option<String>,
+    },
+    /// Encrypt the whole config file, not just the API keys in it
+    Encrypt {
+        /// Use a random key kept in the OS keychain instead of the master passphrase
+        #[arg(long)]
+        keychain: bool,
+    },
+    /// Store an encrypted config file as plain TOML again
+    Decrypt,
+    /// Print the resolved config file path
+    Path {
+        /// Print JSON instead of a bare path
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print where config, history, and cache files live on this machine
+    Dirs {
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Args, Debug)]
+pub struct ConfigExportArgs {
+    /// Write to this file instead of stdout
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// Leave API keys and remote upload credentials out of the export
+    #[arg(long = "redact-secrets")]
+    pub redact_secrets: bool,
+    /// Re-encrypt every API key with the passphrase in this environment variable
+    #[arg(long = "reencrypt-env", conflicts_with = "redact_secrets")]
+    pub reencrypt_env: Option<String>,
+    /// Environment variable holding the current passphrase (defaults to RUSTCHAT_PASSPHRASE)
+    #[arg(long = "secret-env")]
+    pub secret_env: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum KdfArg {
+    Argon2id,
+    Pbkdf2,
+}
+
+impl From<KdfArg> for Kdf {
+    fn from(value: KdfArg) -> Self {
+        match value {
+            KdfArg::Argon2id => Kdf::Argon2id,
+            KdfArg::Pbkdf2 => Kdf::Pbkdf2,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, ValueEnum)]
@@ -47,19 +831,49 @@ pub enum ProviderKindArg {
     Openai,
 }
 
+impl From<ProviderKindArg> for ProviderKind {
+    fn from(value: ProviderKindArg) -> Self {
+        match value {
+            ProviderKindArg::Google => ProviderKind::Google,
+            ProviderKindArg::Anthropic => ProviderKind::Anthropic,
+            ProviderKindArg::Openai => ProviderKind::Openai,
+        }
+    }
+}
+
+/// Tools the model may call during a chat session
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq, Eq)]
+pub enum ToolArg {
+    /// Run shell commands (each one needs approval)
+    Shell,
+    /// Fetch web pages as Markdown (each request needs approval)
+    Web,
+    /// Read and write files (each access needs approval)
+    Fs,
+    /// Tools offered by plugins on PATH (each call needs approval)
+    Plugins,
+}
+
 #[derive(Debug, Clone, Copy, ValueEnum, PartialEq, Eq)]
 pub enum SaveFormatArg {
     Json,
     Markdown,
+    /// OpenAI fine-tuning records (`{"messages": [...]}` per line)
+    Jsonl,
+    /// Same structure as json, in YAML for hand editing
+    Yaml,
+    /// ShareGPT dataset (`from`/`value` turns) for dataset/sharing tools
+    Sharegpt,
 }
 
-impl ProviderKindArg {
-    pub fn infer_from_name(name: &str) -> Option<Self> {
-        match name.to_ascii_lowercase().as_str() {
-            "google" => Some(Self::Google),
-            "anthropic" => Some(Self::Anthropic),
-            "openai" => Some(Self::Openai),
-            _ => None,
+impl From<SaveFormatArg> for HistoryFormat {
+    fn from(value: SaveFormatArg) -> Self {
+        match value {
+            SaveFormatArg::Json => HistoryFormat::Json,
+            SaveFormatArg::Markdown => HistoryFormat::Markdown,
+            SaveFormatArg::Jsonl => HistoryFormat::Jsonl,
+            SaveFormatArg::Yaml => HistoryFormat::Yaml,
+            SaveFormatArg::Sharegpt => HistoryFormat::ShareGpt,
         }
     }
 }
@@ -67,6 +881,7 @@ impl ProviderKindArg {
 #[derive(Args, Debug)]
 pub struct ConfigSetArgs {
     /// Unique provider label (e.g. google, work-google, openai)
+    #[arg(add = ArgValueCandidates::new(provider_candidates))]
     pub provider: String,
     /// Provider kind, defaults to name-based inference
     #[arg(long = "kind", value_enum)]
@@ -74,9 +889,22 @@ pub struct ConfigSetArgs {
     /// Mark this provider as the default for chat/message commands
     #[arg(long)]
     pub default: bool,
+    /// System prompt used with this provider when `--system` is omitted
+    #[arg(long = "default-system")]
+    pub default_system: Option<String>,
+    /// Redefine the provider from scratch instead of updating only the given fields
+    #[arg(long)]
+    pub replace: bool,
     /// Encrypt any provided API keys with the passphrase below
     #[arg(long = "encrypt-secrets")]
     pub encrypt_secrets: bool,
+    /// Key derivation for --encrypt-secrets; pbkdf2 only for configs shared
+    /// with older rustchat versions
+    #[arg(long, value_enum, default_value_t = KdfArg::Argon2id)]
+    pub kdf: KdfArg,
+    /// Store the API key in the OS keychain instead of the config file
+    #[arg(long = "use-keychain", conflicts_with_all = ["encrypt_secrets", "api_key_env", "api_key_cmd"])]
+    pub use_keychain: bool,
     /// Environment variable that stores the master passphrase (defaults to RUSTCHAT_PASSPHRASE)
     #[arg(long = "secret-env")]
     pub secret_env: Option<String>,
@@ -107,6 +935,13 @@ pub struct ApiKeySetArgs {
     /// API key / bearer token used by providers that require it
     #[arg(long = "api-key")]
     pub api_key: Option<String>,
+    /// Environment variable to read the API key from at runtime instead of
+    /// storing it in the config
+    #[arg(long = "api-key-env", conflicts_with = "api_key")]
+    pub api_key_env: Option<String>,
+    /// Shell command that prints the API key, run each time the provider starts
+    #[arg(long = "api-key-cmd", conflicts_with_all = ["api_key", "api_key_env"])]
+    pub api_key_cmd: Option<String>,
     /// Custom base URL (Anthropic / OpenAI enterprise deployments)
     #[arg(long = "base-url")]
     pub base_url: Option<String>,
@@ -115,13 +950,29 @@ pub struct ApiKeySetArgs {
     pub shared_default_model: Option<String>,
 }
 
+impl ApiKeySetArgs {
+    /// Whether the key comes from outside the config (env var or command).
+    pub fn has_external_key(&self) -> bool {
+        self.api_key_env.is_some() || self.api_key_cmd.is_some()
+    }
+}
+
 #[derive(Args, Debug, Clone)]
 pub struct CommonChatArgs {
+    /// Preset from `[profiles.<name>]` supplying provider, model, system prompt
+    /// and temperature; explicit flags override it
+    #[arg(long, env = "RUSTCHAT_PROFILE")]
+    pub profile: Option<String>,
+    /// Persona from `roles/<name>.md` or `.toml` beside the config (or a path
+    /// to one) supplying a system prompt, temperature and preferred model;
+    /// explicit flags override it, and it overrides --profile
+    #[arg(long, value_name = "NAME|PATH")]
+    pub role: Option<String>,
     /// Provider to use. Falls back to config default when omitted
-    #[arg(short, long)]
+    #[arg(short, long, add = ArgValueCandidates::new(provider_candidates))]
     pub provider: Option<String>,
     /// Model identifier (e.g. gemini-pro)
-    #[arg(short, long)]
+    #[arg(short, long, add = ArgValueCandidates::new(model_candidates))]
     pub model: Option<String>,
     /// Optional system prompt / persona
     #[arg(long)]
@@ -138,18 +989,83 @@ pub struct CommonChatArgs {
     /// File format to use for history exports
     #[arg(long = "save-format", value_enum, default_value_t = SaveFormatArg::Json)]
     pub save_format: SaveFormatArg,
+    /// What --save does when the file already exists
+    #[arg(long = "save-mode", value_enum, default_value_t = SaveModeArg::Overwrite)]
+    pub save_mode: SaveModeArg,
+    /// Compress saved transcripts (adds a .gz/.zst suffix)
+    #[arg(long, value_enum, value_name = "ALGORITHM")]
+    pub compress: Option<CompressArg>,
+    /// Let --save replace a file that already exists
+    #[arg(long)]
+    pub force: bool,
     /// Optional webhook URL to receive the chat transcript at the end of the session
     #[arg(long = "webhook-url")]
     pub webhook_url: Option<String>,
+    /// Webhook target from `[webhooks.<name>]` to post the transcript to
+    #[arg(
+        long,
+        value_name = "NAME",
+        conflicts_with = "webhook_url",
+        add = ArgValueCandidates::new(webhook_candidates)
+    )]
+    pub webhook: Option<String>,
+    /// Also post each completed exchange of a chat session as it happens
+    #[arg(long = "webhook-per-turn")]
+    pub webhook_per_turn: bool,
+    /// minijinja template for the webhook body (overrides [webhook] template)
+    #[arg(long = "webhook-template", value_name = "PATH")]
+    pub webhook_template: Option<PathBuf>,
+    /// Copy each reply to this file as it streams (the file is replaced)
+    #[arg(long, value_name = "PATH")]
+    pub tee: Option<PathBuf>,
+    /// Write the prompts to the --tee file too, quoted
+    #[arg(long = "tee-prompt", requires = "tee")]
+    pub tee_prompt: bool,
     /// Environment variable that stores the master passphrase (defaults to RUSTCHAT_PASSPHRASE)
     #[arg(long = "secret-env")]
     pub secret_env: Option<String>,
+    /// Fail instead of asking for the passphrase when its variable is unset
+    #[arg(long = "no-prompt")]
+    pub no_prompt: bool,
     /// Optional temperature override
     #[arg(long)]
     pub temperature: Option<f32>,
     /// Optional max output tokens
     #[arg(long = "max-tokens")]
     pub max_output_tokens: Option<u32>,
+    /// Print replies as raw text instead of rendering markdown
+    #[arg(long)]
+    pub plain: bool,
+    /// Hide status lines such as `[saved chat history to ...]`
+    #[arg(short, long)]
+    pub quiet: bool,
+    /// Print only the reply: no markdown rendering, `bot>` label or status
+    /// lines (replies are already undecorated when stdout is not a terminal)
+    #[arg(long)]
+    pub raw: bool,
+    /// Print model, latency, token usage and estimated cost after each reply
+    #[arg(long)]
+    pub stats: bool,
+    /// Send a desktop notification / bell when a reply takes longer than this (seconds)
+    #[arg(long = "notify-after", value_name = "SECS")]
+    pub notify_after: Option<u64>,
+    /// Name for the saved session (shown by `sessions list`)
+    #[arg(long)]
+    pub title: Option<String>,
+    /// Label for the saved session; repeat for several
+    #[arg(long = "tag", value_name = "TAG")]
+    pub tags: Vec<String>,
+    /// Attach a file's contents (or every file a glob matches) to the first
+    /// message; repeat for several
+    #[arg(long = "file", value_name = "PATH|GLOB")]
+    pub files: Vec<String>,
+    /// Value for a {{KEY}} placeholder in the prompt or system prompt; repeat
+    /// for several (unset placeholders fall back to environment variables)
+    #[arg(long = "var", value_name = "KEY=VALUE")]
+    pub vars: Vec<String>,
+    /// Refuse --file attachments estimated above this many tokens
+    #[arg(long = "file-budget", value_name = "TOKENS", default_value_t = 32_000)]
+    pub file_budget: usize,
 }
 
 #[derive(Args, Debug)]
@@ -159,13 +1075,86 @@ pub struct ChatCommand {
     /// Enable streaming output (MVP+ placeholder)
     #[arg(long)]
     pub stream: bool,
+    /// Comma-separated tools the model may call (e.g. --tools web,fs)
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub tools: Vec<ToolArg>,
+    /// Approval for tool calls this session, overriding `[tools]`: ask,
+    /// read-only or deny for every tool, or TOOL=POLICY for one (repeatable)
+    #[arg(long = "tool-approval", value_name = "[TOOL=]POLICY", value_parser = parse_tool_approval)]
+    pub tool_approval: Vec<ToolApprovalArg>,
+    /// Search this `index add` index with every message and send the best
+    /// passages along with it
+    #[arg(long, value_name = "INDEX")]
+    pub knowledge: Option<String>,
+    /// Passages sent per message with --knowledge
+    #[arg(long = "top-k", default_value_t = 4, requires = "knowledge")]
+    pub top_k: usize,
+    /// Reopen the most recent saved session for the provider
+    #[arg(long = "continue", conflicts_with = "resume")]
+    pub continue_session: bool,
+    /// Reopen a saved session by id, unique id prefix, or transcript path
+    #[arg(long, value_name = "ID|PATH")]
+    pub resume: Option<String>,
+    /// Start from the messages in any transcript file (JSON, JSONL, Markdown,
+    /// YAML); unlike --resume the file is never written back
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["resume", "continue_session"])]
+    pub load: Option<PathBuf>,
 }
 
 #[derive(Args, Debug)]
 pub struct MessageCommand {
     #[command(flatten)]
     pub common: CommonChatArgs,
-    /// Prompt text to send
-    #[arg(required = true)]
+    /// Prompt text to send; `-` (or nothing) reads it from stdin, and piped
+    /// stdin is otherwise appended as context
     pub prompt: Vec<String>,
+    /// Print the reply as rendered text, or as one JSON object with the
+    /// model, usage, latency, finish reason and citations
+    #[arg(long, value_enum, default_value_t = OutputArg::Text)]
+    pub output: OutputArg,
+    /// Print the request (URL, headers and JSON body, with keys masked)
+    /// instead of sending it
+    #[arg(long = "dry-run")]
+    pub dry_run: bool,
+    /// Print the reply as it is generated (ignored with `--output json`)
+    #[arg(long)]
+    pub stream: bool,
+    /// Write the reply here instead of stdout (`--stream` still shows it);
+    /// the file is only written once the reply has completed
+    #[arg(long, value_name = "PATH")]
+    pub out: Option<PathBuf>,
+    /// Add the reply to the end of the --out file instead of replacing it
+    #[arg(long, requires = "out")]
+    pub append: bool,
+    #[command(flatten)]
+    pub retry: RetryArgs,
+    #[command(flatten)]
+    pub cost: CostArgs,
+    #[command(flatten)]
+    pub stdin_filters: StdinFilterArgs,
+    /// Continue the conversation in this transcript: its messages are sent
+    /// before the prompt and the reply is appended to it (created if missing)
+    #[arg(long, value_name = "PATH", conflicts_with = "save_path")]
+    pub history: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq, Eq)]
+pub enum OutputArg {
+    Text,
+    Json,
 }
@@ -0,0 +1,167 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use anyhow::{bail, Context, Result};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+use crate::cancel;
+use crate::cli::CmdCommand;
+use crate::config::AppConfig;
+use crate::exit::{ExitStatus, ResultExt};
+use crate::provider::{build_providers, ChatMessage, ChatRequestOptions};
+use crate::shell;
+
+/// What to do with the suggested command.
+#[derive(Debug, PartialEq, Eq)]
+enum Choice {
+    Run,
+    Edit,
+    Abort,
+}
+
+pub async fn run_cmd(args: CmdCommand, cfg: &AppConfig) -> Result<()> {
+    let task = args.task.join(" ");
+    let provider_name = cfg
+        .infer_default_provider(&args.provider)
+        .exit_status(ExitStatus::Config)?;
+    let providers = build_providers(
+        [provider_name.as_str()],
+        cfg,
+        args.secret_env.as_deref(),
+        !args.no_prompt,
+    )
+    .await?;
+    let model = args
+        .model
+        .clone()
+        .or_else(|| {
+            cfg.providers[&provider_name]
+                .default_model()
+                .map(str::to_string)
+        })
+        .unwrap_or_else(|| "gemini-pro".to_string());
+
+    let system = system_prompt();
+    let messages = [ChatMessage::user(task)];
+    let options = ChatRequestOptions::default();
+    let response = providers[&provider_name]
+        .chat(
+            &model,
+            Some(&system),
+            &messages,
+            &options,
+            &cancel::on_ctrl_c(),
+        )
+        .await?;
+    let mut command = extract_command(&response.text);
+    if command.is_empty() {
+        bail!("{provider_name} did not suggest a command");
+    }
+
+    // Without a terminal there is nobody to confirm, so never run anything.
+    if !io::stdin().is_terminal() {
+        println!("{command}");
+        return Ok(());
+    }
+    loop {
+        println!("\n  {}\n", command.replace('\n', "\n  "));
+        match ask()? {
+            Choice::Run => break,
+            Choice::Edit => match edit(&command)? {
+                Some(edited) if !edited.trim().is_empty() => command = edited.trim().to_string(),
+                _ => {}
+            },
+            Choice::Abort => {
+                eprintln!("Aborted.");
+                return Ok(());
+            }
+        }
+    }
+    let status = shell::run_attached(&command)?;
+    if !status.success() {
+        bail!("`{command}` exited with {status}");
+    }
+    Ok(())
+}
+
+/// Tells the model which shell will run the command.
+fn system_prompt() -> String {
+    let shell = if cfg!(windows) { "cmd.exe" } else { "POSIX sh" };
+    let cwd = std::env::current_dir()
+        .map(|dir| dir.display().to_string())
+        .unwrap_or_default();
+    format!(
+        "You turn a task into one shell command for {shell} on {os}, run from {cwd}. Reply with \
+the command only: no explanation, no code fences, no leading prompt character. Prefer a single \
+line; chain steps with && or pipes when needed. Never suggest anything destructive that the task \
+did not ask for.",
+        os = std::env::consts::OS
+    )
+}
+
+/// The command inside the reply, without fences or a `$ ` prompt.
+fn extract_command(reply: &str) -> String {
+    let lines: Vec<&str> = reply
+        .trim()
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("```"))
+        .collect();
+    let command = lines.join("\n");
+    let command = command.trim();
+    command
+        .strip_prefix("$ ")
+        .unwrap_or(command)
+        .trim_matches('`')
+        .trim()
+        .to_string()
+}
+
+fn ask() -> Result<Choice> {
+    loop {
+        print!("[r]un / [e]dit / [a]bort? ");
+        io::stdout().flush().ok();
+        let mut answer = String::new();
+        if io::stdin().lock().read_line(&mut answer)? == 0 {
+            return Ok(Choice::Abort);
+        }
+        if let Some(choice) = parse_choice(&answer) {
+            return Ok(choice);
+        }
+    }
+}
+
+fn parse_choice(answer: &str) -> Option<Choice> {
+    match answer.trim().to_ascii_lowercase().as_str() {
+        "r" | "run" => Some(Choice::Run),
+        "e" | "edit" => Some(Choice::Edit),
+        "a" | "abort" | "q" | "n" => Some(Choice::Abort),
+        _ => None,
+    }
+}
+
+/// Lets the user change the command in place; `None` on Ctrl-C or Ctrl-D.
+fn edit(command: &str) -> Result<Option<String>> {
+    let mut editor = DefaultEditor::new().context("failed to start line editor")?;
+    match editor.readline_with_initial("> ", (command, "")) {
+        Ok(line) => Ok(Some(line)),
+        Err(ReadlineError::Interrupted | ReadlineError::Eof) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replies_reduce_to_the_bare_command() {
+        assert_eq!(
+            extract_command("```sh\n$ find . -name '*.rs' | wc -l\n```"),
+            "find . -name '*.rs' | wc -l"
+        );
+        assert_eq!(extract_command("`ls -la`\n"), "ls -la");
+        assert_eq!(parse_choice(" R\n"), Some(Choice::Run));
+        assert_eq!(parse_choice("edit"), Some(Choice::Edit));
+        assert_eq!(parse_choice(""), None);
+    }
+}
@@ -0,0 +1,147 @@
+use std::path::PathBuf;
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+
+use crate::cancel;
+use crate::cli::CommitMsgCommand;
+use crate::config::AppConfig;
+use crate::exit::{ExitStatus, ResultExt};
+use crate::output;
+use crate::provider::{build_providers, ChatMessage, ChatRequestOptions};
+
+const SYSTEM_PROMPT: &str = "You write git commit messages in the Conventional Commits format. \
+Reply with the commit message only: a subject line of the form `type(optional scope): summary` \
+(types: feat, fix, docs, style, refactor, perf, test, build, ci, chore, revert), at most 72 \
+characters, imperative mood, no trailing period; then, only if the change needs explaining, a \
+blank line and a body wrapped at 72 characters that says what changed and why. Use `!` after the \
+type and a `BREAKING CHANGE:` footer for breaking changes. No code fences, no commentary.";
+
+pub async fn run_commit_msg(args: CommitMsgCommand, cfg: &AppConfig) -> Result<()> {
+    // Outside a repository `git diff` would compare files instead.
+    git(&["rev-parse", "--git-dir"])?;
+    let diff = git(&["diff", "--cached", "--no-color"])?;
+    if diff.trim().is_empty() {
+        bail!("nothing is staged - `git add` the changes to describe first");
+    }
+    let stat = git(&["diff", "--cached", "--no-color", "--stat"])?;
+
+    let provider_name = cfg
+        .infer_default_provider(&args.provider)
+        .exit_status(ExitStatus::Config)?;
+    let providers = build_providers(
+        [provider_name.as_str()],
+        cfg,
+        args.secret_env.as_deref(),
+        !args.no_prompt,
+    )
+    .await?;
+    let model = args
+        .model
+        .clone()
+        .or_else(|| {
+            cfg.providers[&provider_name]
+                .default_model()
+                .map(str::to_string)
+        })
+        .unwrap_or_else(|| "gemini-pro".to_string());
+
+    let prompt = build_prompt(&stat, &diff, &args.notes.join(" "), args.max_diff_chars);
+    let messages = [ChatMessage::user(prompt)];
+    let options = ChatRequestOptions::default();
+    let response = providers[&provider_name]
+        .chat(
+            &model,
+            Some(SYSTEM_PROMPT),
+            &messages,
+            &options,
+            &cancel::on_ctrl_c(),
+        )
+        .await?;
+    let message = clean_message(&response.text);
+    if message.is_empty() {
+        bail!("{provider_name} returned an empty commit message");
+    }
+
+    if args.commit {
+        let status = Command::new("git")
+            .args(["commit", "-e", "-m", &message])
+            .status()
+            .context("failed to run git commit")?;
+        if !status.success() {
+            bail!("git commit exited with {status}");
+        }
+        return Ok(());
+    }
+    println!("{message}");
+    if args.write {
+        let path = PathBuf::from(git(&["rev-parse", "--git-path", "COMMIT_EDITMSG"])?.trim());
+        std::fs::write(&path, format!("{message}\n"))
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        output::status(&format!("[wrote {}]", path.display()));
+    }
+    Ok(())
+}
+
+/// Runs git and returns its stdout, failing with git's own complaint.
+fn git(args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .output()
+        .context("failed to run git - is it installed?")?;
+    if !output.status.success() {
+        bail!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The staged changes for the model, with the diff cut at `max_diff_chars`
+/// (the `--stat` summary still covers every file).
+fn build_prompt(stat: &str, diff: &str, notes: &str, max_diff_chars: usize) -> String {
+    let mut prompt = String::from("Write the commit message for these staged changes.\n\n");
+    if !notes.trim().is_empty() {
+        prompt.push_str(&format!("Notes from the author: {}\n\n", notes.trim()));
+    }
+    prompt.push_str(&format!("Files changed:\n{}\n\nDiff:\n", stat.trim_end()));
+    match diff.char_indices().nth(max_diff_chars) {
+        Some((cut, _)) => {
+            prompt.push_str(&diff[..cut]);
+            prompt.push_str("\n[diff truncated]\n");
+        }
+        None => prompt.push_str(diff),
+    }
+    prompt
+}
+
+/// Drops code fences and surrounding blank lines that models add anyway.
+fn clean_message(reply: &str) -> String {
+    let lines: Vec<&str> = reply
+        .trim()
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("```"))
+        .collect();
+    lines.join("\n").trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prompts_truncate_long_diffs_and_replies_lose_fences() {
+        let prompt = build_prompt(" a.rs | 2 +-\n", "+héllo world\n", "fixes #12", 4);
+        assert!(prompt.contains("Notes from the author: fixes #12"));
+        assert!(prompt.contains(" a.rs | 2 +-\n\nDiff:\n+hél\n[diff truncated]\n"));
+        assert!(build_prompt("", "+x\n", " ", 100).ends_with("Diff:\n+x\n"));
+
+        assert_eq!(
+            clean_message("```\nfix(cli): handle empty input\n\nBody.\n```\n"),
+            "fix(cli): handle empty input\n\nBody."
+        );
+        assert_eq!(clean_message("feat: add x"), "feat: add x");
+    }
+}
@@ -0,0 +1,341 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Result};
+use serde::Serialize;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::cancel;
+use crate::cli::{CompareCommand, OutputArg};
+use crate::config::{AppConfig, ProviderKind};
+use crate::exit::{self, ExitStatus, ResultExt};
+use crate::output::ReplyReport;
+use crate::provider::{build_providers, Canceled, ChatMessage, ChatRequestOptions, ChatResponse};
+use crate::stats::TurnStats;
+use crate::{input, render};
+
+/// Columns narrower than this are printed one after another instead.
+const MIN_COLUMN_WIDTH: usize = 24;
+const COLUMN_GAP: &str = " │ ";
+
+/// A `-m provider[:model]` argument, resolved against the config.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub provider: String,
+    pub model: String,
+}
+
+impl Target {
+    /// `provider` may be a configured provider name or, when exactly one
+    /// provider of that kind is configured, a kind such as `anthropic`. The
+    /// model defaults to the provider's `default_model`.
+    pub fn parse(spec: &str, cfg: &AppConfig) -> Result<Self> {
+        let (name, model) = match spec.split_once(':') {
+            Some((name, model)) => (name, Some(model)),
+            None => (spec, None),
+        };
+        let provider = if cfg.providers.contains_key(name) {
+            name.to_string()
+        } else {
+            let kind = ProviderKind::infer(name)
+                .ok_or_else(|| anyhow!("provider '{name}' not found in config"))?;
+            let mut matches = cfg
+                .providers
+                .iter()
+                .filter(|(_, provider)| provider.kind() == kind);
+            match (matches.next(), matches.next()) {
+                (Some((only, _)), None) => only.clone(),
+                (Some(_), Some(_)) => {
+                    bail!("several providers are {name} - name one of them in '{spec}'")
+                }
+                (None, _) => bail!("no {name} provider is configured"),
+            }
+        };
+        let model = match model.filter(|model| !model.is_empty()) {
+            Some(model) => model.to_string(),
+            None => cfg.providers[&provider]
+                .default_model()
+                .map(str::to_string)
+                .ok_or_else(|| {
+                    anyhow!("'{spec}' names no model and provider '{provider}' has no default")
+                })?,
+        };
+        Ok(Self { provider, model })
+    }
+}
+
+/// One target's answer, or why it has none.
+struct Answer {
+    target: Target,
+    response: Result<ChatResponse>,
+    latency: Duration,
+}
+
+/// A `--output json` entry: the reply as `message --output json` prints it,
+/// or the error.
+#[derive(Serialize)]
+#[serde(untagged)]
+enum JsonAnswer<'a> {
+    Reply(ReplyReport<'a>),
+    Failed {
+        provider: &'a str,
+        model: &'a str,
+        error: String,
+        exit_code: u8,
+        latency_ms: u128,
+    },
+}
+
+pub async fn run_compare(args: CompareCommand, cfg: &AppConfig) -> Result<()> {
+    let targets = args
+        .targets
+        .iter()
+        .map(|spec| Target::parse(spec, cfg))
+        .collect::<Result<Vec<_>>>()
+        .exit_status(ExitStatus::Config)?;
+    let providers = build_providers(
+        targets.iter().map(|target| target.provider.as_str()),
+        cfg,
+        args.secret_env.as_deref(),
+        !args.no_prompt,
+    )
+    .await?;
+    let prompt = input::compose_prompt(&args.prompt, input::read_piped_stdin()?)?;
+    let messages = [ChatMessage::user(prompt)];
+    let options = ChatRequestOptions {
+        temperature: args.temperature,
+        max_output_tokens: args.max_output_tokens,
+        ..Default::default()
+    };
+
+    let cancel = cancel::on_ctrl_c();
+    let requests = targets.into_iter().map(|target| {
+        let provider = providers[&target.provider].clone();
+        let (messages, options, cancel) = (&messages, &options, &cancel);
+        let system = args.system.as_deref();
+        async move {
+            let started = Instant::now();
+            let response = provider
+                .chat(&target.model, system, messages, options, cancel)
+                .await;
+            Answer {
+                target,
+                response,
+                latency: started.elapsed(),
+            }
+        }
+    });
+    let answers = futures::future::join_all(requests).await;
+    if cancel.is_cancelled() {
+        return Err(Canceled.into());
+    }
+
+    match args.output {
+        OutputArg::Json => println!("{}", serde_json::to_string_pretty(&json_report(&answers))?),
+        OutputArg::Text => print!("{}", side_by_side(&answers, render::terminal_width())),
+    }
+    let failed = answers
+        .iter()
+        .filter(|answer| answer.response.is_err())
+        .count();
+    if failed > 0 {
+        bail!("{failed} of {} targets failed", answers.len());
+    }
+    Ok(())
+}
+
+fn json_report(answers: &[Answer]) -> Vec<JsonAnswer<'_>> {
+    answers
+        .iter()
+        .map(|answer| {
+            let (provider, model) = (&answer.target.provider, &answer.target.model);
+            match &answer.response {
+                Ok(response) => {
+                    JsonAnswer::Reply(ReplyReport::new(provider, model, response, answer.latency))
+                }
+                Err(err) => JsonAnswer::Failed {
+                    provider,
+                    model,
+                    error: format!("{err:#}"),
+                    exit_code: exit::status_for(err) as u8,
+                    latency_ms: answer.latency.as_millis(),
+                },
+            }
+        })
+        .collect()
+}
+
+/// Each answer as a column headed by its target and stats; stacked when the
+/// terminal is too narrow for that many columns.
+fn side_by_side(answers: &[Answer], width: usize) -> String {
+    let columns = answers.len().max(1);
+    let gaps = COLUMN_GAP.width() * (columns - 1);
+    let column_width = width.saturating_sub(gaps) / columns;
+    let stacked = column_width < MIN_COLUMN_WIDTH;
+    let column_width = if stacked { width } else { column_width };
+
+    let sections: Vec<(Vec<String>, Vec<String>)> = answers
+        .iter()
+        .map(|answer| {
+            let stats = match &answer.response {
+                Ok(response) => TurnStats {
+                    model: &answer.target.model,
+                    elapsed: answer.latency,
+                    usage: response.usage,
+                }
+                .line(),
+                Err(_) => format!(
+                    "{} · failed after {:.1}s",
+                    answer.target.model,
+                    answer.latency.as_secs_f64()
+                ),
+            };
+            let header = format!("{} · {stats}", answer.target.provider);
+            let body = match &answer.response {
+                Ok(response) => response.text.clone(),
+                Err(err) => format!("error: {err:#}"),
+            };
+            (wrap(&header, column_width), wrap(&body, column_width))
+        })
+        .collect();
+    // Line the rules up under headers that wrapped to different heights.
+    let header_rows = sections.iter().map(|(header, _)| header.len()).max();
+    let blocks: Vec<Vec<String>> = sections
+        .into_iter()
+        .map(|(mut lines, body)| {
+            if !stacked {
+                lines.resize(header_rows.unwrap_or(0), String::new());
+            }
+            lines.push("─".repeat(column_width));
+            lines.extend(body);
+            lines
+        })
+        .collect();
+
+    let mut out = String::new();
+    if stacked {
+        for block in &blocks {
+            for line in block {
+                out.push_str(line.trim_end());
+                out.push('\n');
+            }
+            out.push('\n');
+        }
+        return out;
+    }
+    let rows = blocks.iter().map(Vec::len).max().unwrap_or(0);
+    for row in 0..rows {
+        let cells: Vec<String> = blocks
+            .iter()
+            .map(|block| {
+                let cell = block.get(row).map(String::as_str).unwrap_or("");
+                let pad = column_width.saturating_sub(cell.width());
+                format!("{cell}{}", " ".repeat(pad))
+            })
+            .collect();
+        out.push_str(cells.join(COLUMN_GAP).trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+/// Word-wraps `text` to `width` display columns, breaking words that do not
+/// fit on a line of their own.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            if !line.is_empty() && line.width() + 1 + word.width() > width {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            for ch in word.chars() {
+                if line.width() + ch.width().unwrap_or(0) > width {
+                    lines.push(std::mem::take(&mut line));
+                }
+                line.push(ch);
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::TokenUsage;
+
+    #[test]
+    fn targets_resolve_names_kinds_and_default_models() {
+        let cfg: AppConfig = toml::from_str(
+            r#"
+            [providers.work]
+            type = "openai"
+            api_key = "sk-test"
+            default_model = "gpt-4o"
+
+            [providers.claude]
+            type = "anthropic"
+            api_key = "sk-ant"
+            "#,
+        )
+        .unwrap();
+        let target = Target::parse("work", &cfg).unwrap();
+        assert_eq!(
+            (target.provider.as_str(), target.model.as_str()),
+            ("work", "gpt-4o")
+        );
+        let target = Target::parse("openai:gpt-4o-mini", &cfg).unwrap();
+        assert_eq!(
+            (target.provider.as_str(), target.model.as_str()),
+            ("work", "gpt-4o-mini")
+        );
+        let target = Target::parse("anthropic:claude-sonnet-4", &cfg).unwrap();
+        assert_eq!(target.provider, "claude");
+        assert!(Target::parse("claude", &cfg).is_err());
+        assert!(Target::parse("gemini:gemini-pro", &cfg).is_err());
+    }
+
+    #[test]
+    fn answers_render_as_columns_or_stacked() {
+        let target = |model: &str| Target {
+            provider: "p".to_string(),
+            model: model.to_string(),
+        };
+        let answers = [
+            Answer {
+                target: target("a"),
+                response: Ok(ChatResponse {
+                    text: "short answer".to_string(),
+                    usage: Some(TokenUsage {
+                        input_tokens: 5,
+                        output_tokens: 2,
+                    }),
+                    ..Default::default()
+                }),
+                latency: Duration::from_millis(1200),
+            },
+            Answer {
+                target: target("b"),
+                response: Err(anyhow!("boom")),
+                latency: Duration::from_millis(300),
+            },
+        ];
+        let columns = side_by_side(&answers, 80);
+        let first = columns.lines().next().unwrap();
+        assert!(first.starts_with("p · a · 1.2s · 5 in / 2 out tokens "));
+        assert!(first.contains(" │ p · b · failed after 0.3s"));
+        assert!(columns.contains("error: boom"));
+        assert!(columns.lines().all(|line| line.width() <= 80));
+
+        let stacked = side_by_side(&answers, 40);
+        assert!(stacked.starts_with("p · a · 1.2s"));
+        assert!(!stacked.contains('│'));
+
+        assert_eq!(wrap("aaaa bb cccccc", 4), ["aaaa", "bb", "cccc", "cc"]);
+    }
+}
@@ -0,0 +1,167 @@
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use clap::CommandFactory;
+use clap_complete::env::{Bash, EnvCompleter, Fish, Powershell, Zsh};
+use clap_complete::{CompleteEnv, CompletionCandidate, Shell};
+
+use crate::cli::{Cli, CompletionShell};
+use crate::config::{self, AppConfig, ProviderKind};
+use crate::{pricing, sealed};
+
+/// Variable the generated scripts set when they call back for candidates.
+const COMPLETE_VAR: &str = "COMPLETE";
+
+/// Answers a completion request from a script printed by `completions` and
+/// exits; a no-op on ordinary runs.
+pub fn complete_from_env() {
+    CompleteEnv::with_factory(Cli::command)
+        .var(COMPLETE_VAR)
+        .complete();
+}
+
+/// Prints the completion script for `shell`, binding it to the command `bin`.
+/// The default script asks rustchat-cli for candidates as you type, so
+/// configured provider names and models complete too; `static_script` emits a
+/// self-contained one instead.
+pub fn print_script(shell: CompletionShell, static_script: bool, bin: &str) -> Result<()> {
+    let mut stdout = io::stdout();
+    if static_script {
+        clap_complete::generate(Shell::from(shell), &mut Cli::command(), bin, &mut stdout);
+        return Ok(());
+    }
+    let completer: &dyn EnvCompleter = match shell {
+        CompletionShell::Bash => &Bash,
+        CompletionShell::Zsh => &Zsh,
+        CompletionShell::Fish => &Fish,
+        CompletionShell::Powershell => &Powershell,
+    };
+    let exe = callback_path()?;
+    completer.write_registration(COMPLETE_VAR, bin, bin, &exe, &mut stdout)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+impl From<CompletionShell> for Shell {
+    fn from(value: CompletionShell) -> Self {
+        match value {
+            CompletionShell::Bash => Shell::Bash,
+            CompletionShell::Zsh => Shell::Zsh,
+            CompletionShell::Fish => Shell::Fish,
+            CompletionShell::Powershell => Shell::PowerShell,
+        }
+    }
+}
+
+/// How the script should invoke us: by name when found on `PATH`, otherwise
+/// by the absolute path of the running binary.
+fn callback_path() -> Result<String> {
+    let invoked = std::env::args_os().next().map(PathBuf::from);
+    match invoked {
+        Some(path) if path.components().count() == 1 => Ok(path.to_string_lossy().into_owned()),
+        _ => Ok(std::env::current_exe()
+            .map_err(|err| anyhow!("cannot locate the rustchat-cli binary: {err}"))?
+            .to_string_lossy()
+            .into_owned()),
+    }
+}
+
+/// Configured provider names, for `--provider` and `config` arguments.
+pub fn provider_candidates() -> Vec<CompletionCandidate> {
+    let Some(cfg) = completion_config() else {
+        return Vec::new();
+    };
+    cfg.providers
+        .iter()
+        .map(|(name, provider)| {
+            let kind = match provider.kind() {
+                ProviderKind::Google => "google",
+                ProviderKind::Anthropic => "anthropic",
+                ProviderKind::Openai => "openai",
+            };
+            CompletionCandidate::new(name).help(Some(kind.into()))
+        })
+        .collect()
+}
+
+/// Models named in the config, then the model aliases the price table knows.
+pub fn model_candidates() -> Vec<CompletionCandidate> {
+    let mut models: Vec<(String, String)> = Vec::new();
+    if let Some(cfg) = completion_config() {
+        for (name, provider) in &cfg.providers {
+            if let Some(model) = provider.default_model() {
+                models.push((model.to_string(), format!("default for {name}")));
+            }
+        }
+        for (name, profile) in &cfg.profiles {
+            if let Some(model) = &profile.model {
+                models.push((model.clone(), format!("profile {name}")));
+            }
+        }
+    }
+    models.extend(
+        pricing::model_families().map(|model| (model.to_string(), "known model".to_string())),
+    );
+    let mut candidates: Vec<CompletionCandidate> = Vec::new();
+    for (model, help) in models {
+        if !candidates
+            .iter()
+            .any(|candidate| candidate.get_value() == model.as_str())
+        {
+            candidates.push(CompletionCandidate::new(model).help(Some(help.into())));
+        }
+    }
+    candidates
+}
+
+/// `[webhooks]` target names, for `--webhook`.
+pub fn webhook_candidates() -> Vec<CompletionCandidate> {
+    let Some(cfg) = completion_config() else {
+        return Vec::new();
+    };
+    cfg.webhooks
+        .iter()
+        .map(|(name, target)| CompletionCandidate::new(name).help(Some(target.url.clone().into())))
+        .collect()
+}
+
+/// The config, read without ever prompting: completion runs mid-keystroke,
+/// so an encrypted config simply offers no candidates.
+fn completion_config() -> Option<AppConfig> {
+    let path = match std::env::var_os("RUSTCHAT_CONFIG") {
+        Some(path) => PathBuf::from(path),
+        None => config::config_path().ok()?,
+    };
+    let data = std::fs::read_to_string(path).ok()?;
+    if sealed::is_sealed(&data) {
+        return None;
+    }
+    toml::from_str(&data).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scripts_are_generated_for_every_shell() {
+        for shell in [
+            CompletionShell::Bash,
+            CompletionShell::Zsh,
+            CompletionShell::Fish,
+            CompletionShell::Powershell,
+        ] {
+            let mut script = Vec::new();
+            clap_complete::generate(
+                Shell::from(shell),
+                &mut Cli::command(),
+                "rustchat-cli",
+                &mut script,
+            );
+            assert!(String::from_utf8(script).unwrap().contains("rustchat-cli"));
+        }
+        let models = model_candidates();
+        assert!(models.iter().any(|model| model.get_value() == "gpt-4o"));
+    }
+}
@@ -1,234 +1,468 @@
-use std::collections::BTreeMap;
-use std::fs;
 use std::path::{Path, PathBuf};
 
-use anyhow::{anyhow, Context, Result};
-use serde::{Deserialize, Serialize};
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Serialize;
 
-use crate::cli::ProviderKindArg;
-use crate::secrets::{self, EncryptedSecret, DEFAULT_MASTER_ENV};
+// The schema lives in the core crate; this module adds what only the
+// command line needs on top of it.
+pub use rustchat_core::config::*;
 
-pub const APP_DIR: &str = "rustchat-cli";
-const CONFIG_FILE: &str = "config.toml";
+use crate::cli::{CommonChatArgs, ConfigSetArgs};
+use crate::secrets::{self, Kdf, DEFAULT_MASTER_ENV};
+use crate::{audit, cache, daemon, logger, prompts, roles, store, usage, vector_store};
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct AppConfig {
-    pub default_provider: Option<String>,
-    #[serde(default)]
-    pub providers: BTreeMap<String, ProviderConfig>,
-}
-
-impl AppConfig {
-    pub fn load() -> Result<Self> {
-        let path = config_path()?;
-        if !path.exists() {
-            return Ok(Self::default());
-        }
-        let data = fs::read_to_string(&path)
-            .with_context(|| format!("failed to read config at {}", path.display()))?;
-        let cfg: AppConfig =
-            toml::from_str(&data).with_context(|| "failed to parse config file (toml)")?;
-        Ok(cfg)
-    }
-
-    pub fn save(&self) -> Result<()> {
-        let path = config_path()?;
-        if let Some(parent) = path.parent() {
-            fs::create_dir_all(parent)
-                .with_context(|| format!("failed to create config dir {}", parent.display()))?;
-        }
-        let data = toml::to_string_pretty(self)?;
-        fs::write(&path, data)
-            .with_context(|| format!("failed to write config at {}", path.display()))?;
-        Ok(())
+/// Fills in what the flags left unset: first from `--role`, then
+/// `--profile`, then the `RUSTCHAT_*` environment variables, then the
+/// project's `.rustchat.toml`.
+pub fn apply_overrides(cfg: &AppConfig, args: &mut CommonChatArgs) -> Result<()> {
+    if let Some(name) = &args.role {
+        apply_profile(&roles::load_role(name)?, args);
     }
-
-    pub fn upsert_provider(&mut self, name: String, cfg: ProviderConfig) {
-        self.providers.insert(name, cfg);
+    if let Some(name) = args.profile.clone() {
+        let profile = cfg
+            .profiles
+            .get(&name)
+            .ok_or_else(|| anyhow!("profile '{name}' not found in config"))?;
+        apply_profile(profile, args);
     }
-
-    pub fn remove_provider(&mut self, name: &str) -> bool {
-        self.providers.remove(name).is_some()
-    }
-
-    pub fn require_provider(&self, provider: &str) -> Result<&ProviderConfig> {
-        self.providers
-            .get(provider)
-            .ok_or_else(|| anyhow!("provider '{provider}' not found in config"))
-    }
-
-    pub fn infer_default_provider(&self, explicit: &Option<String>) -> Result<String> {
-        if let Some(name) = explicit {
-            return Ok(name.clone());
-        }
-        self.default_provider
-            .clone()
-            .ok_or_else(|| anyhow!("no provider selected and no default configured"))
+    apply_env_overrides(args, |name| std::env::var(name).ok())?;
+    if let Some(project) = &cfg.project {
+        apply_project(project, args);
     }
+    Ok(())
 }
 
-pub fn config_path() -> Result<PathBuf> {
-    let base = dirs::config_dir().ok_or_else(|| anyhow!("unable to locate platform config dir"))?;
-    Ok(base.join(APP_DIR).join(CONFIG_FILE))
+/// Fills in whatever `args` left unset from a profile, role or saved prompt.
+pub fn apply_profile(profile: &ProfileConfig, args: &mut CommonChatArgs) {
+    args.provider = args.provider.take().or_else(|| profile.provider.clone());
+    args.model = args.model.take().or_else(|| profile.model.clone());
+    args.system = args.system.take().or_else(|| profile.system.clone());
+    args.temperature = args.temperature.or(profile.temperature);
+    if args.webhook_url.is_none() {
+        args.webhook = args.webhook.take().or_else(|| profile.webhook.clone());
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(tag = "type", rename_all = "lowercase")]
-pub enum ProviderConfig {
-    Google(GoogleProviderConfig),
-    Anthropic(ApiKeyProviderConfig),
-    Openai(ApiKeyProviderConfig),
+/// Fills in whatever `args` (after any profile) left unset from `.rustchat.toml`.
+fn apply_project(project: &ProjectConfig, args: &mut CommonChatArgs) {
+    args.model = args.model.take().or_else(|| project.model.clone());
+    args.system = args.system.take().or_else(|| project.system.clone());
+    args.history_dir = args
+        .history_dir
+        .take()
+        .or_else(|| project.history_dir.clone());
 }
 
-impl ProviderConfig {
-    pub fn kind(&self) -> ProviderKind {
-        match self {
-            ProviderConfig::Google(_) => ProviderKind::Google,
-            ProviderConfig::Anthropic(_) => ProviderKind::Anthropic,
-            ProviderConfig::Openai(_) => ProviderKind::Openai,
+/// `RUSTCHAT_PROVIDER`, `RUSTCHAT_MODEL`, `RUSTCHAT_SYSTEM`,
+/// `RUSTCHAT_HISTORY_DIR` and `RUSTCHAT_TEMPERATURE` for settings `args` left
+/// unset; empty variables are ignored.
+fn apply_env_overrides(
+    args: &mut CommonChatArgs,
+    var: impl Fn(&str) -> Option<String>,
+) -> Result<()> {
+    let var = |name: &str| var(name).filter(|value| !value.is_empty());
+    args.provider = args.provider.take().or_else(|| var("RUSTCHAT_PROVIDER"));
+    args.model = args.model.take().or_else(|| var("RUSTCHAT_MODEL"));
+    args.system = args.system.take().or_else(|| var("RUSTCHAT_SYSTEM"));
+    args.history_dir = args
+        .history_dir
+        .take()
+        .or_else(|| var("RUSTCHAT_HISTORY_DIR").map(PathBuf::from));
+    if args.temperature.is_none() {
+        if let Some(value) = var("RUSTCHAT_TEMPERATURE") {
+            let temperature = value
+                .parse()
+                .with_context(|| format!("RUSTCHAT_TEMPERATURE={value} is not a number"))?;
+            args.temperature = Some(temperature);
         }
     }
-
-    pub fn default_model(&self) -> Option<&str> {
-        match self {
-            ProviderConfig::Google(cfg) => cfg.default_model.as_deref(),
-            ProviderConfig::Anthropic(cfg) | ProviderConfig::Openai(cfg) => {
-                cfg.default_model.as_deref()
-            }
-        }
-    }
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct GoogleProviderConfig {
-    pub service_account_file: Option<PathBuf>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub api_key: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub encrypted_api_key: Option<EncryptedSecret>,
-    pub project_id: Option<String>,
-    pub location: Option<String>,
-    pub default_model: Option<String>,
+    Ok(())
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct ApiKeyProviderConfig {
+/// Where rustchat keeps its state, as reported by `config dirs`.
+#[derive(Debug, Serialize)]
+pub struct StatePaths {
+    pub config: Option<PathBuf>,
+    /// Saved `prompts` templates
+    pub prompts: Option<PathBuf>,
+    /// `--role` persona files
+    pub roles: Option<PathBuf>,
+    /// The `.rustchat.toml` overlaying the config, if any
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub api_key: Option<String>,
+    pub project: Option<PathBuf>,
+    pub history: Option<PathBuf>,
+    /// Only set when sessions live in SQLite
+    pub database: Option<PathBuf>,
+    /// `usage report` log
+    pub usage: Option<PathBuf>,
+    /// `index add` document index database
+    pub indexes: Option<PathBuf>,
+    /// Where `daemon start` listens
+    pub daemon: Option<PathBuf>,
+    /// Only set when `[audit]` is enabled
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub encrypted_api_key: Option<EncryptedSecret>,
-    pub base_url: Option<String>,
-    pub default_model: Option<String>,
-}
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum ProviderKind {
-    Google,
-    Anthropic,
-    Openai,
+    pub audit: Option<PathBuf>,
+    pub cache: Option<PathBuf>,
 }
 
-impl From<ProviderKindArg> for ProviderKind {
-    fn from(value: ProviderKindArg) -> Self {
-        match value {
-            ProviderKindArg::Google => ProviderKind::Google,
-            ProviderKindArg::Anthropic => ProviderKind::Anthropic,
-            ProviderKindArg::Openai => ProviderKind::Openai,
+impl StatePaths {
+    pub fn resolve(cfg: &AppConfig) -> Self {
+        let database = match cfg.sessions.store {
+            StoreKind::Files => None,
+            StoreKind::Sqlite => cfg
+                .sessions
+                .database
+                .clone()
+                .or_else(store::default_database_path),
+        };
+        Self {
+            config: config_path().ok(),
+            prompts: prompts::prompts_path().ok(),
+            roles: roles::roles_dir().ok(),
+            project: cfg.project.as_ref().map(|project| project.path.clone()),
+            history: cfg
+                .project
+                .as_ref()
+                .and_then(|project| project.history_dir.clone())
+                .or_else(logger::default_history_dir),
+            database,
+            usage: usage::log_path(&cfg.usage),
+            indexes: vector_store::database_path(&cfg.knowledge).ok(),
+            daemon: daemon::socket_path(),
+            audit: cfg
+                .audit
+                .enabled
+                .then(|| audit::log_path(&cfg.audit))
+                .flatten(),
+            cache: cache::cache_dir(&cfg.cache),
         }
     }
-}
 
-impl ProviderKind {
-    pub fn infer(name: &str) -> Option<Self> {
-        ProviderKindArg::infer_from_name(name).map(ProviderKind::from)
+    pub fn rows(&self) -> Vec<(&'static str, Option<&Path>)> {
+        let mut rows = vec![
+            ("config", self.config.as_deref()),
+            ("prompts", self.prompts.as_deref()),
+            ("roles", self.roles.as_deref()),
+        ];
+        if self.project.is_some() {
+            rows.push(("project", self.project.as_deref()));
+        }
+        rows.push(("history", self.history.as_deref()));
+        if self.database.is_some() {
+            rows.push(("database", self.database.as_deref()));
+        }
+        rows.push(("usage", self.usage.as_deref()));
+        rows.push(("indexes", self.indexes.as_deref()));
+        rows.push(("daemon", self.daemon.as_deref()));
+        if self.audit.is_some() {
+            rows.push(("audit", self.audit.as_deref()));
+        }
+        rows.push(("cache", self.cache.as_deref()));
+        rows
     }
 }
 
-pub fn build_provider_config(
+/// Builds the provider described by `config set`. With an `existing` entry
+/// only the fields that were passed change; the rest, including a stored key,
+/// are kept.
+pub async fn build_provider_config(
     kind: ProviderKind,
-    set: &crate::cli::ConfigSetArgs,
+    set: &ConfigSetArgs,
+    existing: Option<&ProviderConfig>,
 ) -> Result<ProviderConfig> {
+    if let Some(existing) = existing {
+        if existing.kind() != kind {
+            bail!(
+                "provider '{}' is configured with a different kind - pass --replace to redefine it",
+                set.provider
+            );
+        }
+    }
     let env_label = set.secret_env.as_deref().unwrap_or(DEFAULT_MASTER_ENV);
     let passphrase = if set.encrypt_secrets {
         Some(secrets::require_passphrase_from_env(env_label)?)
     } else {
         None
     };
-    Ok(match kind {
+    let kdf = Kdf::from(set.kdf);
+    let keychain = set.use_keychain;
+    let provided = match &set.shared_api.api_key {
+        Some(key) if keychain => {
+            secrets::store_in_keychain(&set.provider, key).await?;
+            None
+        }
+        Some(key) => Some(key.clone()),
+        None if keychain => bail!("--use-keychain needs the key passed with --api-key"),
+        None => None,
+    };
+    let default_model = set
+        .google
+        .default_model
+        .clone()
+        .or_else(|| set.shared_api.shared_default_model.clone());
+    let fresh = match kind {
         ProviderKind::Google => {
             let (api_key, encrypted_api_key) = secrets::maybe_encrypt_secret(
-                set.shared_api.api_key.clone(),
+                provided,
                 set.encrypt_secrets,
                 passphrase.as_deref(),
                 env_label,
+                kdf,
             )?;
             ProviderConfig::Google(GoogleProviderConfig {
                 service_account_file: set.google.service_account.clone(),
                 api_key,
                 encrypted_api_key,
+                api_key_env: set.shared_api.api_key_env.clone(),
+                api_key_cmd: set.shared_api.api_key_cmd.clone(),
+                keychain,
                 project_id: set.google.project_id.clone(),
                 location: set.google.location.clone(),
-                default_model: set
-                    .google
-                    .default_model
-                    .clone()
-                    .or_else(|| set.shared_api.shared_default_model.clone()),
+                default_model,
+                system: set.default_system.clone(),
             })
         }
         ProviderKind::Anthropic => {
-            let provided = set
-                .shared_api
-                .api_key
-                .clone()
-                .ok_or_else(|| anyhow!("--api-key is required for anthropic"))?;
+            if existing.is_none()
+                && provided.is_none()
+                && !set.shared_api.has_external_key()
+                && !keychain
+            {
+                bail!("--api-key, --api-key-env or --api-key-cmd is required for anthropic");
+            }
             let (api_key, encrypted_api_key) = secrets::maybe_encrypt_secret(
-                Some(provided),
+                provided,
                 set.encrypt_secrets,
                 passphrase.as_deref(),
                 env_label,
+                kdf,
             )?;
             ProviderConfig::Anthropic(ApiKeyProviderConfig {
                 api_key,
                 encrypted_api_key,
+                api_key_env: set.shared_api.api_key_env.clone(),
+                api_key_cmd: set.shared_api.api_key_cmd.clone(),
+                keychain,
                 base_url: set.shared_api.base_url.clone(),
-                default_model: set.shared_api.shared_default_model.clone(),
+                default_model: default_model.clone(),
+                system: set.default_system.clone(),
             })
         }
         ProviderKind::Openai => {
-            let provided = set
-                .shared_api
-                .api_key
-                .clone()
-                .ok_or_else(|| anyhow!("--api-key is required for openai"))?;
+            if existing.is_none()
+                && provided.is_none()
+                && !set.shared_api.has_external_key()
+                && !keychain
+            {
+                bail!("--api-key, --api-key-env or --api-key-cmd is required for openai");
+            }
             let (api_key, encrypted_api_key) = secrets::maybe_encrypt_secret(
-                Some(provided),
+                provided,
                 set.encrypt_secrets,
                 passphrase.as_deref(),
                 env_label,
+                kdf,
             )?;
             ProviderConfig::Openai(ApiKeyProviderConfig {
                 api_key,
                 encrypted_api_key,
+                api_key_env: set.shared_api.api_key_env.clone(),
+                api_key_cmd: set.shared_api.api_key_cmd.clone(),
+                keychain,
                 base_url: set.shared_api.base_url.clone(),
-                default_model: set.shared_api.shared_default_model.clone(),
+                default_model,
+                system: set.default_system.clone(),
             })
         }
+    };
+    let new_key = set.shared_api.api_key.is_some() || set.shared_api.has_external_key();
+    Ok(match existing {
+        Some(existing) => existing.updated_with(fresh, new_key),
+        None => fresh,
     })
 }
 
-pub fn ensure_permissions(path: &Path) -> Result<()> {
-    #[cfg(not(unix))]
-    let _ = path;
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        const PERM: u32 = 0o600;
-        let metadata = fs::metadata(path)?;
-        if metadata.permissions().mode() & 0o777 != PERM {
-            let mut perm = metadata.permissions();
-            perm.set_mode(PERM);
-            fs::set_permissions(path, perm)?;
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{Cli, Commands, ConfigCommand};
+    use clap::Parser;
+
+    #[test]
+    fn profile_fills_in_unset_flags() {
+        let cfg: AppConfig = toml::from_str(
+            r#"
+            [profiles.writing]
+            provider = "claude"
+            model = "claude-3-haiku"
+            system = "Be terse"
+            temperature = 0.2
+            "#,
+        )
+        .unwrap();
+        let cli = Cli::try_parse_from([
+            "rustchat-cli",
+            "message",
+            "--profile",
+            "writing",
+            "--model",
+            "claude-3-opus",
+            "hi",
+        ])
+        .unwrap();
+        let Commands::Message(mut args) = cli.command else {
+            panic!("expected message command");
+        };
+        apply_overrides(&cfg, &mut args.common).unwrap();
+        assert_eq!(args.common.provider.as_deref(), Some("claude"));
+        assert_eq!(args.common.model.as_deref(), Some("claude-3-opus"));
+        assert_eq!(args.common.system.as_deref(), Some("Be terse"));
+        assert_eq!(args.common.temperature, Some(0.2));
+
+        args.common.profile = Some("missing".to_string());
+        assert!(apply_overrides(&cfg, &mut args.common).is_err());
+    }
+
+    #[test]
+    fn state_paths_include_the_database_only_for_sqlite() {
+        let mut cfg = AppConfig::default();
+        assert!(StatePaths::resolve(&cfg).database.is_none());
+        cfg.sessions.store = StoreKind::Sqlite;
+        cfg.sessions.database = Some(PathBuf::from("/srv/chats.db"));
+        let paths = StatePaths::resolve(&cfg);
+        assert_eq!(paths.database.as_deref(), Some(Path::new("/srv/chats.db")));
+        let labels: Vec<_> = paths.rows().into_iter().map(|(label, _)| label).collect();
+        assert_eq!(
+            labels,
+            [
+                "config", "prompts", "roles", "history", "database", "usage", "indexes", "daemon",
+                "cache"
+            ]
+        );
+    }
+
+    #[test]
+    fn environment_fills_settings_flags_left_unset() {
+        let cli =
+            Cli::try_parse_from(["rustchat-cli", "message", "--model", "gpt-4o", "x"]).unwrap();
+        let Commands::Message(mut args) = cli.command else {
+            panic!("expected message command");
+        };
+        let env = |name: &str| match name {
+            "RUSTCHAT_PROVIDER" => Some("openai".to_string()),
+            "RUSTCHAT_MODEL" => Some("gpt-4o-mini".to_string()),
+            "RUSTCHAT_SYSTEM" => Some(String::new()),
+            "RUSTCHAT_TEMPERATURE" => Some("0.5".to_string()),
+            _ => None,
+        };
+        apply_env_overrides(&mut args.common, env).unwrap();
+        assert_eq!(args.common.provider.as_deref(), Some("openai"));
+        assert_eq!(args.common.model.as_deref(), Some("gpt-4o"));
+        assert_eq!(args.common.system, None);
+        assert_eq!(args.common.temperature, Some(0.5));
+
+        args.common.temperature = None;
+        let bad = |name: &str| (name == "RUSTCHAT_TEMPERATURE").then(|| "warm".to_string());
+        assert!(apply_env_overrides(&mut args.common, bad).is_err());
+    }
+
+    #[test]
+    fn project_settings_come_after_flags() {
+        let cfg = AppConfig {
+            project: Some(ProjectConfig {
+                model: Some("claude-3-haiku".into()),
+                system: Some("Review Rust".into()),
+                history_dir: Some(PathBuf::from("/work/chats")),
+                ..ProjectConfig::default()
+            }),
+            ..AppConfig::default()
+        };
+        let cli = Cli::try_parse_from(["rustchat-cli", "message", "--system", "Hi", "x"]).unwrap();
+        let Commands::Message(mut args) = cli.command else {
+            panic!("expected message command");
+        };
+        apply_overrides(&cfg, &mut args.common).unwrap();
+        assert_eq!(args.common.model.as_deref(), Some("claude-3-haiku"));
+        assert_eq!(args.common.system.as_deref(), Some("Hi"));
+        assert_eq!(args.common.history_dir, Some(PathBuf::from("/work/chats")));
+
+        let cli = Cli::try_parse_from(["rustchat-cli", "config", "show", "--config", "work.toml"])
+            .unwrap();
+        assert_eq!(cli.config, Some(PathBuf::from("work.toml")));
+    }
+
+    fn config_set(args: &[&str]) -> ConfigSetArgs {
+        let cli =
+            Cli::try_parse_from(["rustchat-cli", "config", "set"].iter().chain(args)).unwrap();
+        let Commands::Config {
+            command: ConfigCommand::Set(set),
+        } = cli.command
+        else {
+            panic!("expected config set");
+        };
+        *set
+    }
+
+    #[tokio::test]
+    async fn config_set_updates_only_given_fields() {
+        let existing = ProviderConfig::Openai(ApiKeyProviderConfig {
+            api_key: Some("sk-old".into()),
+            base_url: Some("https://proxy.example.com".into()),
+            ..ApiKeyProviderConfig::default()
+        });
+        let set = config_set(&[
+            "openai",
+            "--default-model",
+            "gpt-4o",
+            "--default-system",
+            "Follow the house style guide",
+        ]);
+        let updated = build_provider_config(ProviderKind::Openai, &set, Some(&existing))
+            .await
+            .unwrap();
+        let ProviderConfig::Openai(updated) = updated else {
+            panic!("expected openai provider");
+        };
+        assert_eq!(updated.api_key.as_deref(), Some("sk-old"));
+        assert_eq!(
+            updated.base_url.as_deref(),
+            Some("https://proxy.example.com")
+        );
+        assert_eq!(updated.default_model.as_deref(), Some("gpt-4o"));
+        assert_eq!(
+            ProviderConfig::Openai(updated.clone()).default_system(),
+            Some("Follow the house style guide")
+        );
+
+        let set = config_set(&["openai", "--api-key-env", "OPENAI_API_KEY"]);
+        let ProviderConfig::Openai(updated) =
+            build_provider_config(ProviderKind::Openai, &set, Some(&existing))
+                .await
+                .unwrap()
+        else {
+            panic!("expected openai provider");
+        };
+        assert_eq!(updated.api_key, None);
+        assert_eq!(updated.api_key_env.as_deref(), Some("OPENAI_API_KEY"));
+
+        let set = config_set(&["openai", "--kind", "anthropic"]);
+        assert!(
+            build_provider_config(ProviderKind::Anthropic, &set, Some(&existing))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn keychain_keys_stay_out_of_the_config() {
+        keyring::set_default_credential_builder(keyring::mock::default_credential_builder());
+        let set = config_set(&["openai", "--api-key", "sk-secret", "--use-keychain"]);
+        let provider = build_provider_config(ProviderKind::Openai, &set, None)
+            .await
+            .unwrap();
+        assert!(provider.uses_keychain());
+        let saved = toml::to_string(&provider).unwrap();
+        assert!(!saved.contains("sk-secret"));
+        assert!(saved.contains("keychain = true"));
     }
-    Ok(())
 }
@@ -0,0 +1,194 @@
+use crate::config::{ContextConfig, ContextStrategy};
+use crate::provider::{ChatMessage, MessageRole};
+use crate::tokens::Tokenizer;
+
+/// Messages kept by `sliding-window` and `keep-system-last-n` when
+/// `keep_last` is unset.
+pub const DEFAULT_KEEP_LAST: usize = 20;
+/// Tokens left free for the reply when `--max-tokens` does not say.
+const REPLY_RESERVE: usize = 4_096;
+
+/// Context windows in tokens, matched by longest model-name prefix like the
+/// price table.
+const CONTEXT_WINDOWS: &[(&str, usize)] = &[
+    ("gpt-3.5-turbo", 16_385),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4o", 128_000),
+    ("gpt-4.1", 1_047_576),
+    ("gpt-5", 400_000),
+    ("o1-mini", 128_000),
+    ("o1", 200_000),
+    ("o3", 200_000),
+    ("o4-mini", 200_000),
+    ("claude-3", 200_000),
+    ("claude-haiku-4", 200_000),
+    ("claude-sonnet-4", 200_000),
+    ("claude-opus-4", 200_000),
+    ("gemini-1.5-flash", 1_048_576),
+    ("gemini-1.5-pro", 2_097_152),
+    ("gemini-2", 1_048_576),
+    ("gemini-pro", 32_760),
+];
+
+pub fn context_window(model: &str) -> Option<usize> {
+    let model = model.to_ascii_lowercase();
+    let model = model.rsplit('/').next().unwrap_or(&model);
+    CONTEXT_WINDOWS
+        .iter()
+        .filter(|(prefix, _)| model.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|&(_, tokens)| tokens)
+}
+
+/// The window to fit in: `[context] max_tokens` when set, otherwise the
+/// model's own.
+pub fn limit(cfg: &ContextConfig, model: &str) -> Option<usize> {
+    cfg.max_tokens.or_else(|| context_window(model))
+}
+
+/// Tokens the history takes with `model`'s tokenizer.
+pub fn history_tokens(model: &str, system: Option<&str>, messages: &[ChatMessage]) -> usize {
+    Tokenizer::for_model(model).count_chat(system, messages)
+}
+
+/// What is actually sent: the tail of the history, from a user message on.
+#[derive(Debug, Clone, Copy)]
+pub struct Window<'a> {
+    pub system: Option<&'a str>,
+    pub messages: &'a [ChatMessage],
+    /// Older messages left out
+    pub dropped: usize,
+    /// The system prompt was left out
+    pub dropped_system: bool,
+}
+
+/// Cuts `messages` down by the configured strategy. A cut only ever falls
+/// before a user message, so tool results never lose the call they answer,
+/// and the newest turn is always sent even when it alone is too long.
+pub fn apply<'a>(
+    cfg: &ContextConfig,
+    model: &str,
+    system: Option<&'a str>,
+    messages: &'a [ChatMessage],
+    max_output_tokens: Option<u32>,
+) -> Window<'a> {
+    let keep_last = cfg.keep_last.unwrap_or(DEFAULT_KEEP_LAST).max(1);
+    let had_system = system.is_some();
+    let (start, system) = match cfg.strategy {
+        ContextStrategy::None => (0, system),
+        ContextStrategy::SlidingWindow => {
+            let items = messages.len() + usize::from(system.is_some());
+            let system = system.filter(|_| items <= keep_last);
+            (messages.len().saturating_sub(keep_last), system)
+        }
+        ContextStrategy::KeepSystemLastN => (messages.len().saturating_sub(keep_last), system),
+        ContextStrategy::TokenBudget => match cfg.max_tokens {
+            Some(budget) => (fit(model, system, messages, budget), system),
+            None => (0, system),
+        },
+        ContextStrategy::DropOldest => {
+            let reserve = max_output_tokens.map_or(REPLY_RESERVE, |tokens| tokens as usize);
+            match limit(cfg, model) {
+                Some(window) => (
+                    fit(model, system, messages, window.saturating_sub(reserve)),
+                    system,
+                ),
+                None => (0, system),
+            }
+        }
+    };
+    let start = turn_start(messages, start);
+    Window {
+        system,
+        messages: &messages[start..],
+        dropped: start,
+        dropped_system: had_system && system.is_none(),
+    }
+}
+
+/// The first message that can be kept so the rest fits in `budget`.
+fn fit(model: &str, system: Option<&str>, messages: &[ChatMessage], budget: usize) -> usize {
+    let tokenizer = Tokenizer::for_model(model);
+    let mut total = tokenizer.count_chat(system, &[]);
+    for (index, message) in messages.iter().enumerate().rev() {
+        total += tokenizer.count_message(message);
+        if total > budget {
+            return index + 1;
+        }
+    }
+    0
+}
+
+/// Moves a cut forward to the next user message, or back to the last one
+/// when none follows.
+pub fn turn_start(messages: &[ChatMessage], start: usize) -> usize {
+    if start == 0 {
+        return 0;
+    }
+    let is_user = |message: &ChatMessage| message.role == MessageRole::User;
+    messages[start..]
+        .iter()
+        .position(is_user)
+        .map(|offset| start + offset)
+        .or_else(|| messages.iter().rposition(is_user))
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::ToolCall;
+
+    fn conversation() -> Vec<ChatMessage> {
+        vec![
+            ChatMessage::user("a".repeat(400)),
+            ChatMessage::assistant("b".repeat(400)),
+            ChatMessage::user("What is in notes.txt?"),
+            ChatMessage::assistant_with_tools(
+                "",
+                vec![ToolCall {
+                    id: "1".to_string(),
+                    name: "read_file".to_string(),
+                    arguments: serde_json::json!({"path": "notes.txt"}),
+                }],
+            ),
+            ChatMessage::tool_result("1", "c".repeat(40)),
+            ChatMessage::assistant("It lists groceries."),
+            ChatMessage::user("Thanks"),
+        ]
+    }
+
+    #[test]
+    fn cuts_fall_before_user_messages() {
+        let messages = conversation();
+        let mut cfg = ContextConfig {
+            strategy: ContextStrategy::KeepSystemLastN,
+            keep_last: Some(3),
+            ..ContextConfig::default()
+        };
+        // The last three messages start inside a tool round, so the cut moves
+        // on to the next user message.
+        let window = apply(&cfg, "gpt-4o", Some("Be brief."), &messages, None);
+        assert_eq!(window.dropped, 6);
+        assert_eq!(window.system, Some("Be brief."));
+
+        cfg.strategy = ContextStrategy::SlidingWindow;
+        cfg.keep_last = Some(5);
+        let window = apply(&cfg, "gpt-4o", Some("Be brief."), &messages, None);
+        assert_eq!(window.dropped, 2);
+        assert!(window.dropped_system);
+
+        cfg.strategy = ContextStrategy::TokenBudget;
+        cfg.max_tokens = Some(100);
+        let window = apply(&cfg, "gpt-4o", None, &messages, None);
+        assert_eq!(window.dropped, 2);
+        assert_eq!(window.messages[0].content, "What is in notes.txt?");
+
+        // Under the model's window nothing is dropped.
+        cfg.strategy = ContextStrategy::DropOldest;
+        cfg.max_tokens = None;
+        let window = apply(&cfg, "gpt-4o", None, &messages, None);
+        assert_eq!(window.dropped, 0);
+        assert_eq!(context_window("openai/gpt-4o-mini"), Some(128_000));
+    }
+}
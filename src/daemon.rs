@@ -0,0 +1,891 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+
+use crate::cli::DaemonCommand;
+use crate::config::{AppConfig, APP_DIR};
+use crate::provider::DynProvider;
+
+const SOCKET_FILE: &str = "daemon.sock";
+/// Overrides where the daemon listens and where `message` looks for it
+pub const SOCKET_ENV: &str = "RUSTCHAT_DAEMON_SOCKET";
+
+/// `$RUSTCHAT_DAEMON_SOCKET`, else `daemon.sock` in the runtime directory
+/// (the cache directory on systems without one).
+pub fn socket_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os(SOCKET_ENV) {
+        return Some(PathBuf::from(path));
+    }
+    let base = dirs::runtime_dir().or_else(dirs::cache_dir)?;
+    Some(base.join(APP_DIR).join(SOCKET_FILE))
+}
+
+#[cfg(unix)]
+pub use unix::{connect, provider, run};
+
+#[cfg(not(unix))]
+pub async fn connect() -> Option<AppConfig> {
+    None
+}
+
+#[cfg(not(unix))]
+pub fn provider(_name: &str) -> Option<DynProvider> {
+    None
+}
+
+#[cfg(not(unix))]
+pub async fn run(_command: DaemonCommand, _cfg: &AppConfig) -> Result<()> {
+    anyhow::bail!("the daemon needs Unix domain sockets, which this system lacks")
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::collections::HashMap;
+    use std::fs::{DirBuilder, Permissions};
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+    use std::path::Path;
+    use std::sync::{Arc, OnceLock};
+    use std::time::SystemTime;
+
+    use anyhow::{anyhow, bail, Context};
+    use async_stream::try_stream;
+    use async_trait::async_trait;
+    use chrono::{DateTime, Utc};
+    use futures::StreamExt;
+    use serde::de::DeserializeOwned;
+    use serde::{Deserialize, Serialize};
+    use serde_json::Value;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+    use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+    use tokio::net::{UnixListener, UnixStream};
+    use tokio::sync::Notify;
+
+    use super::*;
+    use crate::cli::DaemonStartArgs;
+    use crate::config;
+    use crate::exit::{self, ExitStatus, ResultExt};
+    use crate::provider::{
+        self, until_canceled, CancellationToken, ChatMessage, ChatRequestOptions, ChatResponse,
+        MessageRole, Provider, RequestPreview, ToolCall, ToolSpec,
+    };
+    use crate::shutdown;
+    use crate::streaming::{ChatStream, StreamEvent};
+    use crate::vcr::{self, RecordedEvent, RecordedResponse};
+
+    /// What a client sends: one request per connection, as a line of JSON.
+    #[derive(Debug, Serialize, Deserialize)]
+    #[serde(tag = "op", rename_all = "snake_case")]
+    enum Request {
+        Hello,
+        Chat {
+            provider: String,
+            stream: bool,
+            call: ChatCall,
+        },
+        Stop,
+    }
+
+    /// What the daemon answers with, a line of JSON each.
+    #[derive(Debug, Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    enum Frame {
+        Hello(Hello),
+        Response(RecordedResponse),
+        Event(RecordedEvent),
+        /// The end of a streamed reply
+        End,
+        Error {
+            message: String,
+            exit_code: u8,
+        },
+        Stopping,
+    }
+
+    impl Frame {
+        fn error(err: &anyhow::Error) -> Self {
+            Frame::Error {
+                message: format!("{err:#}"),
+                exit_code: exit::status_for(err) as u8,
+            }
+        }
+    }
+
+    /// The daemon's answer to `hello`, which a client checks before using it.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    struct Hello {
+        pid: u32,
+        started: DateTime<Utc>,
+        config_path: PathBuf,
+        config_modified: Option<SystemTime>,
+        /// The config as plain TOML with every secret taken out, so the client
+        /// neither reads nor decrypts it; its providers are reached through
+        /// the daemon, which holds their keys
+        config: String,
+        /// The config holds webhook, session-store or collector
+        /// credentials, withheld with the rest, so the client reads the file itself
+        credentials_withheld: bool,
+        /// Providers that could be set up
+        providers: Vec<String>,
+    }
+
+    /// A chat request's arguments as they cross the socket.
+    #[derive(Debug, Serialize, Deserialize)]
+    struct ChatCall {
+        model: String,
+        system: Option<String>,
+        messages: Vec<WireMessage>,
+        temperature: Option<f32>,
+        max_output_tokens: Option<u32>,
+        tools: Vec<WireTool>,
+        forbid_tool_calls: bool,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct WireMessage {
+        role: String,
+        content: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        tool_calls: Vec<ToolCall>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tool_call_id: Option<String>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct WireTool {
+        name: String,
+        description: String,
+        parameters: Value,
+    }
+
+    impl ChatCall {
+        fn new(
+            model: &str,
+            system: Option<&str>,
+            messages: &[ChatMessage],
+            options: &ChatRequestOptions,
+        ) -> Self {
+            Self {
+                model: model.to_string(),
+                system: system.map(str::to_string),
+                messages: messages
+                    .iter()
+                    .map(|message| WireMessage {
+                        role: message.role.to_string(),
+                        content: message.content.clone(),
+                        tool_calls: message.tool_calls.clone(),
+                        tool_call_id: message.tool_call_id.clone(),
+                    })
+                    .collect(),
+                temperature: options.temperature,
+                max_output_tokens: options.max_output_tokens,
+                tools: options
+                    .tools
+                    .iter()
+                    .map(|tool| WireTool {
+                        name: tool.name.clone(),
+                        description: tool.description.clone(),
+                        parameters: tool.parameters.clone(),
+                    })
+                    .collect(),
+                forbid_tool_calls: options.forbid_tool_calls,
+            }
+        }
+
+        fn messages(&self) -> Result<Vec<ChatMessage>> {
+            self.messages
+                .iter()
+                .map(|message| {
+                    let role = MessageRole::parse(&message.role)
+                        .ok_or_else(|| anyhow!("unknown message role '{}'", message.role))?;
+                    Ok(ChatMessage {
+                        role,
+                        content: message.content.clone(),
+                        tool_calls: message.tool_calls.clone(),
+                        tool_call_id: message.tool_call_id.clone(),
+                        timestamp: None,
+                        reply: None,
+                    })
+                })
+                .collect()
+        }
+
+        fn options(&self) -> ChatRequestOptions {
+            ChatRequestOptions {
+                temperature: self.temperature,
+                max_output_tokens: self.max_output_tokens,
+                tools: self
+                    .tools
+                    .iter()
+                    .map(|tool| ToolSpec {
+                        name: tool.name.clone(),
+                        description: tool.description.clone(),
+                        parameters: tool.parameters.clone(),
+                    })
+                    .collect(),
+                forbid_tool_calls: self.forbid_tool_calls,
+            }
+        }
+    }
+
+    fn remote_error(message: String, exit_code: u8) -> anyhow::Error {
+        exit::tag(anyhow!(message), ExitStatus::from_code(exit_code))
+    }
+
+    /// Modification time of the config file, which tells a client whether the
+    /// daemon's copy is stale.
+    fn modified(path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path)
+            .and_then(|meta| meta.modified())
+            .ok()
+    }
+
+    fn resolved_config_path() -> Result<PathBuf> {
+        let path = config::config_path()?;
+        Ok(std::fs::canonicalize(&path).unwrap_or(path))
+    }
+
+    /// The daemon `message` hands its requests to, found by `connect`.
+    struct Session {
+        socket: PathBuf,
+        providers: Vec<String>,
+    }
+
+    static SESSION: OnceLock<Session> = OnceLock::new();
+
+    /// A stand-in for `name` that sends its requests to the daemon, once
+    /// `connect` found one that set it up.
+    pub fn provider(name: &str) -> Option<DynProvider> {
+        let session = SESSION.get()?;
+        if !session.providers.iter().any(|known| known == name) {
+            return None;
+        }
+        Some(Arc::new(DaemonProvider {
+            socket: session.socket.clone(),
+            name: name.to_string(),
+        }))
+    }
+
+    /// One end of a connection, exchanging lines of JSON.
+    struct Connection {
+        lines: Lines<BufReader<OwnedReadHalf>>,
+        writer: Writer,
+    }
+
+    /// The sending half of a connection.
+    struct Writer(OwnedWriteHalf);
+
+    impl Writer {
+        async fn send<T: Serialize>(&mut self, message: &T) -> Result<()> {
+            let mut line = serde_json::to_string(message)?;
+            line.push('\n');
+            self.0.write_all(line.as_bytes()).await?;
+            Ok(())
+        }
+    }
+
+    impl Connection {
+        fn new(stream: UnixStream) -> Self {
+            let (reader, writer) = stream.into_split();
+            Self {
+                lines: BufReader::new(reader).lines(),
+                writer: Writer(writer),
+            }
+        }
+
+        async fn open(socket: &Path) -> Result<Self> {
+            let stream = UnixStream::connect(socket)
+                .await
+                .with_context(|| format!("no daemon is listening on {}", socket.display()))?;
+            Ok(Self::new(stream))
+        }
+
+        async fn send<T: Serialize>(&mut self, message: &T) -> Result<()> {
+            self.writer.send(message).await
+        }
+
+        async fn receive<T: DeserializeOwned>(&mut self) -> Result<Option<T>> {
+            match self.lines.next_line().await? {
+                Some(line) => Ok(Some(serde_json::from_str(&line)?)),
+                None => Ok(None),
+            }
+        }
+    }
+
+    async fn hello(socket: &Path) -> Result<Hello> {
+        let mut connection = Connection::open(socket).await?;
+        connection.send(&Request::Hello).await?;
+        match connection.receive().await? {
+            Some(Frame::Hello(hello)) => Ok(hello),
+            _ => bail!("unexpected reply from the daemon on {}", socket.display()),
+        }
+    }
+
+    /// The running daemon's config, when one is listening and was started
+    /// with the config file this process would read, unchanged since; its
+    /// providers are then reachable through `provider`. A config holding
+    /// credentials the daemon does not hand out is left for the caller to
+    /// load, though the providers still go through the daemon. Never used
+    /// under `--record` or `--replay`.
+    pub async fn connect() -> Option<AppConfig> {
+        if vcr::mode().is_some() {
+            return None;
+        }
+        let socket = socket_path().filter(|socket| socket.exists())?;
+        let hello = match hello(&socket).await {
+            Ok(hello) => hello,
+            Err(err) => {
+                tracing::debug!("not using the daemon: {err:#}");
+                return None;
+            }
+        };
+        let path = resolved_config_path().ok()?;
+        if hello.config_path != path {
+            return None;
+        }
+        if hello.config_modified != modified(&path) {
+            tracing::warn!(
+                "the config changed since the daemon started; not using it until it is restarted"
+            );
+            return None;
+        }
+        let cfg = match AppConfig::from_toml(&hello.config) {
+            Ok(cfg) => cfg,
+            Err(err) => {
+                tracing::warn!("not using the daemon: {err:#}");
+                return None;
+            }
+        };
+        let _ = SESSION.set(Session {
+            socket,
+            providers: hello.providers,
+        });
+        (!hello.credentials_withheld).then_some(cfg)
+    }
+
+    pub async fn run(command: DaemonCommand, cfg: &AppConfig) -> Result<()> {
+        let socket = socket_path()
+            .ok_or_else(|| anyhow!("unable to locate a directory for the daemon socket"))
+            .exit_status(ExitStatus::Config)?;
+        match command {
+            DaemonCommand::Start(args) => start(args, cfg, &socket).await,
+            DaemonCommand::Stop => {
+                let mut connection = Connection::open(&socket).await?;
+                connection.send(&Request::Stop).await?;
+                match connection.receive().await? {
+                    Some(Frame::Stopping) => {
+                        println!("Stopped the daemon on {}", socket.display());
+                        Ok(())
+                    }
+                    _ => bail!("unexpected reply from the daemon on {}", socket.display()),
+                }
+            }
+            DaemonCommand::Status => {
+                let hello = hello(&socket).await?;
+                println!("socket:    {}", socket.display());
+                println!("pid:       {}", hello.pid);
+                println!(
+                    "started:   {}",
+                    hello.started.format("%Y-%m-%d %H:%M:%S UTC")
+                );
+                println!("config:    {}", hello.config_path.display());
+                if hello.config_modified != modified(&hello.config_path) {
+                    println!("           (changed since; `message` will not use the daemon)");
+                }
+                println!("providers: {}", hello.providers.join(", "));
+                Ok(())
+            }
+        }
+    }
+
+    async fn start(args: DaemonStartArgs, cfg: &AppConfig, socket: &Path) -> Result<()> {
+        if socket.exists() {
+            if UnixStream::connect(socket).await.is_ok() {
+                bail!("a daemon is already listening on {}", socket.display());
+            }
+            // Left behind by a daemon that did not shut down cleanly.
+            std::fs::remove_file(socket)
+                .with_context(|| format!("failed to remove stale socket {}", socket.display()))?;
+        }
+        if let Some(parent) = socket.parent() {
+            private_dir(parent).exit_status(ExitStatus::Config)?;
+        }
+        let providers = provider::build_all(cfg, args.secret_env.as_deref(), !args.no_prompt)
+            .await
+            .exit_status(ExitStatus::Config)?;
+        let config_path = resolved_config_path()?;
+        let mut names: Vec<String> = providers.keys().cloned().collect();
+        names.sort();
+        let hello = Hello {
+            pid: std::process::id(),
+            started: Utc::now(),
+            config_modified: modified(&config_path),
+            config_path,
+            config: toml::to_string(&cfg.export(&config::SecretExport::Redact)?)?,
+            credentials_withheld: cfg.stores_service_credentials(),
+            providers: names,
+        };
+        let listener = UnixListener::bind(socket)
+            .with_context(|| format!("failed to listen on {}", socket.display()))?;
+        std::fs::set_permissions(socket, Permissions::from_mode(0o600))?;
+        eprintln!(
+            "Daemon listening on {} with {} (Ctrl-C or `daemon stop` to stop)",
+            socket.display(),
+            hello.providers.join(", ")
+        );
+        let daemon = Arc::new(Daemon {
+            hello,
+            providers,
+            stop: Notify::new(),
+        });
+        let stop = shutdown::token();
+        let outcome = tokio::select! {
+            outcome = listen(listener, daemon.clone()) => outcome,
+            _ = daemon.stop.notified() => Ok(()),
+            _ = tokio::signal::ctrl_c() => Ok(()),
+            _ = stop.cancelled() => Ok(()),
+        };
+        std::fs::remove_file(socket).ok();
+        outcome
+    }
+
+    /// Creates `dir` for the socket, or checks one that already exists: it
+    /// must be ours and closed to everyone else, since the socket is
+    /// reachable from the moment it is bound and answers with decrypted keys.
+    fn private_dir(dir: &Path) -> Result<()> {
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+        let meta = std::fs::metadata(dir)
+            .with_context(|| format!("failed to inspect {}", dir.display()))?;
+        // SAFETY: geteuid has no preconditions and cannot fail.
+        let uid = unsafe { libc::geteuid() };
+        if meta.uid() != uid || meta.permissions().mode() & 0o077 != 0 {
+            bail!(
+                "{} must be owned by you with mode 0700 to hold the daemon socket; \
+                 fix it or point {SOCKET_ENV} into a private directory",
+                dir.display()
+            );
+        }
+        Ok(())
+    }
+
+    struct Daemon {
+        hello: Hello,
+        providers: HashMap<String, DynProvider>,
+        stop: Notify,
+    }
+
+    async fn listen(listener: UnixListener, daemon: Arc<Daemon>) -> Result<()> {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let daemon = daemon.clone();
+            tokio::spawn(async move {
+                if let Err(err) = daemon.handle(Connection::new(stream)).await {
+                    tracing::debug!("daemon connection: {err:#}");
+                }
+            });
+        }
+    }
+
+    impl Daemon {
+        async fn handle(&self, mut connection: Connection) -> Result<()> {
+            let Some(request) = connection.receive::<Request>().await? else {
+                return Ok(());
+            };
+            let (name, stream, call) = match request {
+                Request::Hello => return connection.send(&Frame::Hello(self.hello.clone())).await,
+                Request::Stop => {
+                    connection.send(&Frame::Stopping).await?;
+                    self.stop.notify_one();
+                    return Ok(());
+                }
+                Request::Chat {
+                    provider,
+                    stream,
+                    call,
+                } => (provider, stream, call),
+            };
+            // The client sends nothing after its request, so the read half
+            // reaching its end means it went away: the request is canceled
+            // rather than run to completion for nobody.
+            let Connection {
+                mut lines,
+                mut writer,
+            } = connection;
+            let cancel = shutdown::token().child_token();
+            let hang_up = tokio::spawn({
+                let cancel = cancel.clone();
+                async move {
+                    while let Ok(Some(_)) = lines.next_line().await {}
+                    cancel.cancel();
+                }
+            });
+            let outcome = self.chat(&name, stream, call, &mut writer, &cancel).await;
+            hang_up.abort();
+            outcome
+        }
+
+        async fn chat(
+            &self,
+            name: &str,
+            stream: bool,
+            call: ChatCall,
+            writer: &mut Writer,
+            cancel: &CancellationToken,
+        ) -> Result<()> {
+            let Some(provider) = self.providers.get(name) else {
+                let err = exit::tag(
+                    anyhow!("the daemon has no provider '{name}'"),
+                    ExitStatus::Config,
+                );
+                return writer.send(&Frame::error(&err)).await;
+            };
+            let messages = match call.messages() {
+                Ok(messages) => messages,
+                Err(err) => return writer.send(&Frame::error(&err)).await,
+            };
+            let (model, system, options) = (&call.model, call.system.as_deref(), call.options());
+            if !stream {
+                let frame = match provider
+                    .chat(model, system, &messages, &options, cancel)
+                    .await
+                {
+                    Ok(response) => Frame::Response(RecordedResponse::from(&response)),
+                    Err(err) => Frame::error(&err),
+                };
+                return writer.send(&frame).await;
+            }
+            let mut events = match provider
+                .stream_chat(model, system, &messages, &options, cancel)
+                .await
+            {
+                Ok(events) => events,
+                Err(err) => return writer.send(&Frame::error(&err)).await,
+            };
+            while let Some(event) = until_canceled(cancel, events.next()).await? {
+                match event {
+                    Ok(event) => {
+                        writer
+                            .send(&Frame::Event(RecordedEvent::from(&event)))
+                            .await?
+                    }
+                    Err(err) => return writer.send(&Frame::error(&err)).await,
+                }
+            }
+            writer.send(&Frame::End).await
+        }
+    }
+
+    /// A provider set up in the daemon, reached through its socket. Usage and
+    /// `[hooks]` are handled on the daemon's side.
+    struct DaemonProvider {
+        socket: PathBuf,
+        name: String,
+    }
+
+    impl DaemonProvider {
+        async fn request(&self, stream: bool, call: ChatCall) -> Result<Connection> {
+            tracing::debug!("sending the '{}' request to the daemon", self.name);
+            let mut connection = Connection::open(&self.socket)
+                .await
+                .exit_status(ExitStatus::Network)?;
+            connection
+                .send(&Request::Chat {
+                    provider: self.name.clone(),
+                    stream,
+                    call,
+                })
+                .await?;
+            Ok(connection)
+        }
+    }
+
+    #[async_trait]
+    impl Provider for DaemonProvider {
+        async fn chat(
+            &self,
+            model: &str,
+            system: Option<&str>,
+            messages: &[ChatMessage],
+            options: &ChatRequestOptions,
+            cancel: &CancellationToken,
+        ) -> Result<ChatResponse> {
+            let call = ChatCall::new(model, system, messages, options);
+            let mut connection = until_canceled(cancel, self.request(false, call)).await??;
+            match until_canceled(cancel, connection.receive()).await?? {
+                Some(Frame::Response(response)) => Ok(response.into()),
+                Some(Frame::Error { message, exit_code }) => Err(remote_error(message, exit_code)),
+                _ => bail!("the daemon ended the reply early"),
+            }
+        }
+
+        fn preview_chat(
+            &self,
+            _model: &str,
+            _system: Option<&str>,
+            _messages: &[ChatMessage],
+            _options: &ChatRequestOptions,
+        ) -> Result<RequestPreview> {
+            bail!("the daemon does not preview requests; --dry-run builds them locally")
+        }
+
+        async fn stream_chat(
+            &self,
+            model: &str,
+            system: Option<&str>,
+            messages: &[ChatMessage],
+            options: &ChatRequestOptions,
+            cancel: &CancellationToken,
+        ) -> Result<ChatStream> {
+            let call = ChatCall::new(model, system, messages, options);
+            let mut connection = until_canceled(cancel, self.request(true, call)).await??;
+            // A request that fails before any output fails here, as it would
+            // without the daemon, so it can be retried.
+            let mut next = until_canceled(cancel, connection.receive::<Frame>()).await??;
+            if let Some(Frame::Error { message, exit_code }) = next {
+                return Err(remote_error(message, exit_code));
+            }
+            let cancel = cancel.clone();
+            let events = try_stream! {
+                loop {
+                    match next {
+                        Some(Frame::Event(event)) => yield StreamEvent::from(event),
+                        Some(Frame::End) => break,
+                        Some(Frame::Error { message, exit_code }) => {
+                            Err(remote_error(message, exit_code))?
+                        }
+                        _ => Err(anyhow!("the daemon ended the reply early"))?,
+                    }
+                    next = until_canceled(&cancel, connection.receive()).await??;
+                }
+            };
+            Ok(events.boxed())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::provider::TokenUsage;
+
+        /// Answers with the last message reversed, in two chunks.
+        struct Reverse;
+
+        #[async_trait]
+        impl Provider for Reverse {
+            async fn chat(
+                &self,
+                model: &str,
+                _system: Option<&str>,
+                messages: &[ChatMessage],
+                options: &ChatRequestOptions,
+                _cancel: &CancellationToken,
+            ) -> Result<ChatResponse> {
+                if model == "broken" {
+                    return Err(exit::tag(anyhow!("slow down"), ExitStatus::RateLimited));
+                }
+                let last = messages.last().map(|m| m.content.as_str()).unwrap_or("");
+                Ok(ChatResponse {
+                    text: last.chars().rev().collect(),
+                    usage: Some(TokenUsage {
+                        input_tokens: options.tools.len() as u32,
+                        output_tokens: 1,
+                    }),
+                    ..ChatResponse::default()
+                })
+            }
+
+            fn preview_chat(
+                &self,
+                _model: &str,
+                _system: Option<&str>,
+                _messages: &[ChatMessage],
+                _options: &ChatRequestOptions,
+            ) -> Result<RequestPreview> {
+                bail!("not used")
+            }
+
+            async fn stream_chat(
+                &self,
+                model: &str,
+                system: Option<&str>,
+                messages: &[ChatMessage],
+                options: &ChatRequestOptions,
+                cancel: &CancellationToken,
+            ) -> Result<ChatStream> {
+                let text = self
+                    .chat(model, system, messages, options, cancel)
+                    .await?
+                    .text;
+                let (head, tail) = text.split_at(text.len() / 2);
+                let events = [head, tail].map(|chunk| Ok(StreamEvent::Text(chunk.to_string())));
+                Ok(futures::stream::iter(events).boxed())
+            }
+        }
+
+        #[tokio::test]
+        async fn requests_are_answered_over_the_socket() {
+            let tmp = tempfile::tempdir().unwrap();
+            let dir = tmp.path().to_path_buf();
+            let socket = dir.join(SOCKET_FILE);
+            let daemon = Arc::new(Daemon {
+                hello: Hello {
+                    pid: 1,
+                    started: Utc::now(),
+                    config_path: dir.join("config.toml"),
+                    config_modified: None,
+                    config: String::new(),
+                    credentials_withheld: false,
+                    providers: vec!["echo".to_string()],
+                },
+                providers: HashMap::from([("echo".to_string(), Arc::new(Reverse) as DynProvider)]),
+                stop: Notify::new(),
+            });
+            tokio::spawn(listen(UnixListener::bind(&socket).unwrap(), daemon));
+
+            let remote = DaemonProvider {
+                socket: socket.clone(),
+                name: "echo".to_string(),
+            };
+            let messages = [
+                ChatMessage::user("first"),
+                ChatMessage::assistant_with_tools(String::new(), Vec::new()),
+                ChatMessage::user("stressed"),
+            ];
+            let options = ChatRequestOptions {
+                tools: vec![ToolSpec {
+                    name: "clock".to_string(),
+                    description: "The time".to_string(),
+                    parameters: serde_json::json!({"type": "object"}),
+                }],
+                ..ChatRequestOptions::default()
+            };
+            let cancel = CancellationToken::new();
+            let response = remote
+                .chat("m", None, &messages, &options, &cancel)
+                .await
+                .unwrap();
+            assert_eq!(response.text, "desserts");
+            assert_eq!(response.usage.unwrap().input_tokens, 1);
+
+            let events: Vec<StreamEvent> = remote
+                .stream_chat("m", Some("be brief"), &messages, &options, &cancel)
+                .await
+                .unwrap()
+                .map(Result::unwrap)
+                .collect()
+                .await;
+            assert_eq!(
+                events,
+                [
+                    StreamEvent::Text("dess".to_string()),
+                    StreamEvent::Text("erts".to_string())
+                ]
+            );
+
+            // The daemon's failure keeps its exit status, so it is retried.
+            let err = remote
+                .stream_chat("broken", None, &messages, &options, &cancel)
+                .await
+                .err()
+                .unwrap();
+            assert_eq!(exit::status_for(&err), ExitStatus::RateLimited);
+            assert_eq!(hello(&socket).await.unwrap().providers, ["echo"]);
+        }
+
+        /// Answers nothing until its request is canceled, then says so.
+        struct Hang(Arc<Notify>);
+
+        #[async_trait]
+        impl Provider for Hang {
+            async fn chat(
+                &self,
+                _model: &str,
+                _system: Option<&str>,
+                _messages: &[ChatMessage],
+                _options: &ChatRequestOptions,
+                cancel: &CancellationToken,
+            ) -> Result<ChatResponse> {
+                cancel.cancelled().await;
+                self.0.notify_one();
+                bail!("canceled")
+            }
+
+            fn preview_chat(
+                &self,
+                _model: &str,
+                _system: Option<&str>,
+                _messages: &[ChatMessage],
+                _options: &ChatRequestOptions,
+            ) -> Result<RequestPreview> {
+                bail!("not used")
+            }
+
+            async fn stream_chat(
+                &self,
+                _model: &str,
+                _system: Option<&str>,
+                _messages: &[ChatMessage],
+                _options: &ChatRequestOptions,
+                _cancel: &CancellationToken,
+            ) -> Result<ChatStream> {
+                bail!("not used")
+            }
+        }
+
+        #[tokio::test]
+        async fn a_client_hanging_up_cancels_its_request() {
+            let tmp = tempfile::tempdir().unwrap();
+            let socket = tmp.path().join(SOCKET_FILE);
+            let canceled = Arc::new(Notify::new());
+            let daemon = Arc::new(Daemon {
+                hello: Hello {
+                    pid: 1,
+                    started: Utc::now(),
+                    config_path: tmp.path().join("config.toml"),
+                    config_modified: None,
+                    config: String::new(),
+                    credentials_withheld: false,
+                    providers: vec!["hang".to_string()],
+                },
+                providers: HashMap::from([(
+                    "hang".to_string(),
+                    Arc::new(Hang(canceled.clone())) as DynProvider,
+                )]),
+                stop: Notify::new(),
+            });
+            tokio::spawn(listen(UnixListener::bind(&socket).unwrap(), daemon));
+
+            let mut connection = Connection::open(&socket).await.unwrap();
+            let messages = [ChatMessage::user("hello?")];
+            let call = ChatCall::new("m", None, &messages, &ChatRequestOptions::default());
+            connection
+                .send(&Request::Chat {
+                    provider: "hang".to_string(),
+                    stream: false,
+                    call,
+                })
+                .await
+                .unwrap();
+            drop(connection);
+            tokio::time::timeout(std::time::Duration::from_secs(5), canceled.notified())
+                .await
+                .expect("the request was not canceled");
+        }
+
+        #[test]
+        fn the_socket_directory_must_be_private() {
+            let tmp = tempfile::tempdir().unwrap();
+            let dir = tmp.path().join("run");
+            private_dir(&dir).unwrap();
+            assert_eq!(
+                std::fs::metadata(&dir).unwrap().permissions().mode() & 0o777,
+                0o700
+            );
+            std::fs::set_permissions(&dir, Permissions::from_mode(0o755)).unwrap();
+            assert!(private_dir(&dir).is_err());
+        }
+    }
+}
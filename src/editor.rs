@@ -0,0 +1,175 @@
+use std::env;
+use std::fs;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context, Result};
+use rustyline::{
+    Cmd, ConditionalEventHandler, Config, DefaultEditor, EditMode, Event, EventContext,
+    EventHandler, KeyCode, KeyEvent, Modifiers, RepeatCount,
+};
+
+use crate::config::{EditModeSetting, KeyAction, UiConfig};
+
+/// Line editor for the REPL plus a flag raised when the "editor" binding fires.
+pub struct LineEditor {
+    pub editor: DefaultEditor,
+    open_external: Arc<AtomicBool>,
+}
+
+impl LineEditor {
+    pub fn new(ui: &UiConfig) -> Result<Self> {
+        let edit_mode = match ui.edit_mode {
+            Some(EditModeSetting::Vi) => EditMode::Vi,
+            Some(EditModeSetting::Emacs) | None => EditMode::Emacs,
+        };
+        let config = Config::builder().edit_mode(edit_mode).build();
+        let mut editor =
+            DefaultEditor::with_config(config).context("failed to start line editor")?;
+        let open_external = Arc::new(AtomicBool::new(false));
+
+        let mut bindings = default_bindings();
+        for (spec, action) in &ui.keybindings {
+            let key = parse_key(spec).with_context(|| format!("invalid keybinding '{spec}'"))?;
+            bindings.retain(|(existing, _)| *existing != key);
+            bindings.push((key, *action));
+        }
+        for (key, action) in bindings {
+            let handler = match action {
+                KeyAction::Newline => EventHandler::Simple(Cmd::Newline),
+                KeyAction::Submit => EventHandler::Simple(Cmd::AcceptLine),
+                KeyAction::Editor => EventHandler::Conditional(Box::new(OpenEditorHandler {
+                    flag: Arc::clone(&open_external),
+                })),
+            };
+            editor.bind_sequence(key, handler);
+        }
+
+        Ok(Self {
+            editor,
+            open_external,
+        })
+    }
+
+    /// Returns true (once) if the last accepted line was submitted through the
+    /// external-editor binding.
+    pub fn take_editor_request(&self) -> bool {
+        self.open_external.swap(false, Ordering::SeqCst)
+    }
+}
+
+fn default_bindings() -> Vec<(KeyEvent, KeyAction)> {
+    vec![
+        (KeyEvent(KeyCode::Enter, Modifiers::ALT), KeyAction::Newline),
+        (
+            KeyEvent(KeyCode::Char('e'), Modifiers::ALT),
+            KeyAction::Editor,
+        ),
+    ]
+}
+
+struct OpenEditorHandler {
+    flag: Arc<AtomicBool>,
+}
+
+impl ConditionalEventHandler for OpenEditorHandler {
+    fn handle(
+        &self,
+        _evt: &Event,
+        _n: RepeatCount,
+        _positive: bool,
+        _ctx: &EventContext,
+    ) -> Option<Cmd> {
+        self.flag.store(true, Ordering::SeqCst);
+        Some(Cmd::AcceptLine)
+    }
+}
+
+/// Parses specs such as `alt-enter`, `ctrl-x`, or `ctrl-alt-e`.
+pub fn parse_key(spec: &str) -> Result<KeyEvent> {
+    let lowered = spec.trim().to_ascii_lowercase();
+    let mut parts: Vec<&str> = lowered.split('-').collect();
+    let key = parts
+        .pop()
+        .filter(|k| !k.is_empty())
+        .ok_or_else(|| anyhow!("missing key"))?;
+    let mut modifiers = Modifiers::NONE;
+    for part in parts {
+        modifiers |= match part {
+            "ctrl" | "c" => Modifiers::CTRL,
+            "alt" | "meta" | "m" => Modifiers::ALT,
+            "shift" | "s" => Modifiers::SHIFT,
+            other => bail!("unknown modifier '{other}'"),
+        };
+    }
+    let code = match key {
+        "enter" | "return" => KeyCode::Enter,
+        "tab" => KeyCode::Tab,
+        "esc" | "escape" => KeyCode::Esc,
+        "backspace" => KeyCode::Backspace,
+        "space" => KeyCode::Char(' '),
+        other => {
+            let mut chars = other.chars();
+            match (chars.next(), chars.next()) {
+                (Some(ch), None) => KeyCode::Char(ch),
+                _ => bail!("unknown key '{other}'"),
+            }
+        }
+    };
+    Ok(KeyEvent(code, modifiers))
+}
+
+/// Opens `$VISUAL`/`$EDITOR` (falling back to `vi`/`notepad`) on `initial` and
+/// returns the edited text.
+pub fn edit_in_external_editor(initial: &str) -> Result<String> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| {
+            if cfg!(windows) {
+                "notepad".to_string()
+            } else {
+                "vi".to_string()
+            }
+        });
+    let mut words = editor.split_whitespace();
+    let program = words
+        .next()
+        .ok_or_else(|| anyhow!("editor command is empty"))?;
+    let path = env::temp_dir().join(format!("rustchat-{}.md", std::process::id()));
+    fs::write(&path, initial)
+        .with_context(|| format!("failed to write scratch file {}", path.display()))?;
+    let status = Command::new(program)
+        .args(words)
+        .arg(&path)
+        .status()
+        .with_context(|| format!("failed to launch editor '{editor}'"))?;
+    let edited = fs::read_to_string(&path);
+    fs::remove_file(&path).ok();
+    if !status.success() {
+        bail!("editor '{editor}' exited with {status}");
+    }
+    Ok(edited
+        .context("failed to read back edited text")?
+        .trim_end()
+        .to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_modifier_combinations() {
+        assert_eq!(
+            parse_key("alt-enter").unwrap(),
+            KeyEvent(KeyCode::Enter, Modifiers::ALT)
+        );
+        assert_eq!(
+            parse_key("Ctrl-Alt-E").unwrap(),
+            KeyEvent(KeyCode::Char('e'), Modifiers::CTRL | Modifiers::ALT)
+        );
+        assert!(parse_key("hyper-x").is_err());
+        assert!(parse_key("ctrl-").is_err());
+    }
+}
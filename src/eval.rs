@@ -0,0 +1,424 @@
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::path::Path;
+use std::time::Instant;
+
+use anyhow::{anyhow, bail, Context, Result};
+use futures::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::cli::{EvalCommand, ReportFormatArg};
+use crate::compare::Target;
+use crate::config::AppConfig;
+use crate::exit::{ExitStatus, ResultExt};
+use crate::provider::{build_providers, ChatMessage, ChatRequestOptions, DynProvider};
+use crate::shutdown;
+
+const DEFAULT_RUBRIC: &str = "Score how correct, complete and clear the answer is.";
+
+const JUDGE_SYSTEM: &str = "You grade answers written by AI assistants. Apply the rubric \
+strictly and reply with only a JSON object: {\"score\": <integer 1-10>, \"reason\": \"<one sentence>\"}.";
+
+/// One line of the eval set.
+#[derive(Debug, Deserialize)]
+struct EvalCase {
+    /// Defaults to the line number
+    #[serde(default)]
+    id: Option<serde_json::Value>,
+    prompt: String,
+    /// A known-good answer the judge can compare against
+    #[serde(default)]
+    reference: Option<String>,
+    /// Replaces `--rubric` for this case
+    #[serde(default)]
+    rubric: Option<String>,
+}
+
+/// The judge's verdict, parsed from its reply.
+#[derive(Debug, Deserialize, PartialEq)]
+struct Verdict {
+    score: f64,
+    #[serde(default)]
+    reason: String,
+}
+
+/// One row of the report: a candidate's answer to a case and its score.
+#[derive(Debug, Serialize)]
+struct EvalRow {
+    id: String,
+    provider: String,
+    model: String,
+    score: Option<f64>,
+    reason: Option<String>,
+    answer: Option<String>,
+    latency_ms: Option<u128>,
+    input_tokens: Option<u32>,
+    output_tokens: Option<u32>,
+    error: Option<String>,
+}
+
+/// Per-model averages over the rows that got a score.
+#[derive(Debug, Serialize)]
+struct ModelSummary {
+    provider: String,
+    model: String,
+    mean_score: Option<f64>,
+    scored: usize,
+    failed: usize,
+    mean_latency_ms: Option<u128>,
+}
+
+/// The model doing the scoring.
+struct Judge<'a> {
+    model: &'a str,
+    provider: &'a DynProvider,
+}
+
+#[derive(Serialize)]
+struct EvalReport<'a> {
+    judge: String,
+    summary: &'a [ModelSummary],
+    results: &'a [EvalRow],
+}
+
+pub async fn run_eval(args: EvalCommand, cfg: &AppConfig) -> Result<()> {
+    let targets = args
+        .targets
+        .iter()
+        .map(|spec| Target::parse(spec, cfg))
+        .collect::<Result<Vec<_>>>()
+        .exit_status(ExitStatus::Config)?;
+    let judge_spec = match &args.judge {
+        Some(spec) => spec.clone(),
+        None => cfg
+            .infer_default_provider(&None)
+            .exit_status(ExitStatus::Config)?,
+    };
+    let judge = Target::parse(&judge_spec, cfg).exit_status(ExitStatus::Config)?;
+    let rubric = match (&args.rubric, &args.rubric_file) {
+        (_, Some(path)) => std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?,
+        (Some(rubric), None) => rubric.clone(),
+        (None, None) => DEFAULT_RUBRIC.to_string(),
+    };
+    let cases = read_cases(&args.input)?;
+    let providers = build_providers(
+        targets
+            .iter()
+            .chain([&judge])
+            .map(|target| target.provider.as_str()),
+        cfg,
+        args.secret_env.as_deref(),
+        !args.no_prompt,
+    )
+    .await?;
+
+    let candidate_options = ChatRequestOptions {
+        temperature: args.temperature,
+        max_output_tokens: args.max_output_tokens,
+        ..Default::default()
+    };
+    let scorer = Judge {
+        model: &judge.model,
+        provider: &providers[&judge.provider],
+    };
+    let jobs: Vec<(&(String, EvalCase), &Target)> = cases
+        .iter()
+        .flat_map(|case| targets.iter().map(move |target| (case, target)))
+        .collect();
+    let total = jobs.len();
+    let mut rows = Vec::with_capacity(total);
+    let mut results = stream::iter(jobs.into_iter().enumerate())
+        .map(|(index, ((id, case), target))| {
+            let candidate = &providers[&target.provider];
+            let rubric = case.rubric.as_deref().unwrap_or(&rubric);
+            let (system, options, scorer) = (args.system.as_deref(), &candidate_options, &scorer);
+            async move {
+                let mut row = EvalRow {
+                    id: id.clone(),
+                    provider: target.provider.clone(),
+                    model: target.model.clone(),
+                    score: None,
+                    reason: None,
+                    answer: None,
+                    latency_ms: None,
+                    input_tokens: None,
+                    output_tokens: None,
+                    error: None,
+                };
+                let outcome = run_case(&mut row, case, candidate, system, options, rubric, scorer);
+                if let Err(err) = outcome.await {
+                    row.error = Some(format!("{err:#}"));
+                }
+                (index, row)
+            }
+        })
+        .buffer_unordered(usize::from(args.concurrency));
+    while let Some((index, row)) = results.next().await {
+        let outcome = match (&row.score, &row.error) {
+            (Some(score), _) => format!("score {score}"),
+            (None, Some(err)) => format!("failed: {err}"),
+            (None, None) => "no score".to_string(),
+        };
+        if !args.quiet {
+            eprintln!(
+                "[{}/{total}] {} {}:{} {outcome}",
+                rows.len() + 1,
+                row.id,
+                row.provider,
+                row.model
+            );
+        }
+        rows.push((index, row));
+    }
+    // Back into eval-set order, candidates in the order they were given.
+    rows.sort_by_key(|(index, _)| *index);
+    let rows: Vec<EvalRow> = rows.into_iter().map(|(_, row)| row).collect();
+
+    let summary = summarize(&targets, &rows);
+    if !args.quiet {
+        for model in &summary {
+            let score = model
+                .mean_score
+                .map_or("-".to_string(), |score| format!("{score:.2}"));
+            eprintln!(
+                "{}:{}  mean score {score} over {} case(s), {} failed",
+                model.provider, model.model, model.scored, model.failed
+            );
+        }
+    }
+    let report = match args.format {
+        ReportFormatArg::Json => {
+            serde_json::to_string_pretty(&EvalReport {
+                judge: format!("{}:{}", judge.provider, judge.model),
+                summary: &summary,
+                results: &rows,
+            })? + "\n"
+        }
+        ReportFormatArg::Csv => to_csv(&rows),
+    };
+    match &args.out {
+        Some(path) => std::fs::write(path, report)
+            .with_context(|| format!("failed to write {}", path.display()))?,
+        None => print!("{report}"),
+    }
+    Ok(())
+}
+
+/// Asks the candidate, then has the judge score its answer.
+async fn run_case(
+    row: &mut EvalRow,
+    case: &EvalCase,
+    candidate: &DynProvider,
+    system: Option<&str>,
+    options: &ChatRequestOptions,
+    rubric: &str,
+    judge: &Judge<'_>,
+) -> Result<()> {
+    let started = Instant::now();
+    let response = candidate
+        .chat(
+            &row.model,
+            system,
+            &[ChatMessage::user(case.prompt.clone())],
+            options,
+            &shutdown::token(),
+        )
+        .await?;
+    row.answer = Some(response.text.clone());
+    row.latency_ms = Some(started.elapsed().as_millis());
+    row.input_tokens = response.usage.map(|usage| usage.input_tokens);
+    row.output_tokens = response.usage.map(|usage| usage.output_tokens);
+
+    let judge_options = ChatRequestOptions {
+        temperature: Some(0.0),
+        ..Default::default()
+    };
+    let verdict = judge
+        .provider
+        .chat(
+            judge.model,
+            Some(JUDGE_SYSTEM),
+            &[ChatMessage::user(judge_prompt(
+                case,
+                rubric,
+                &response.text,
+            ))],
+            &judge_options,
+            &shutdown::token(),
+        )
+        .await
+        .context("judge request failed")?;
+    let verdict = parse_verdict(&verdict.text)?;
+    row.score = Some(verdict.score);
+    row.reason = Some(verdict.reason);
+    Ok(())
+}
+
+fn judge_prompt(case: &EvalCase, rubric: &str, answer: &str) -> String {
+    let mut prompt = format!("Rubric:\n{rubric}\n\nQuestion:\n{}\n\n", case.prompt);
+    if let Some(reference) = &case.reference {
+        let _ = write!(prompt, "Reference answer:\n{reference}\n\n");
+    }
+    let _ = write!(prompt, "Answer to grade:\n{answer}");
+    prompt
+}
+
+/// The first `{...}` object in the judge's reply, which may be wrapped in
+/// prose or a code fence.
+fn parse_verdict(reply: &str) -> Result<Verdict> {
+    let start = reply.find('{');
+    let end = reply.rfind('}');
+    let object = match (start, end) {
+        (Some(start), Some(end)) if start < end => &reply[start..=end],
+        _ => bail!("the judge did not reply with a JSON score: {reply}"),
+    };
+    serde_json::from_str(object).map_err(|err| anyhow!("unreadable judge verdict {object}: {err}"))
+}
+
+fn read_cases(path: &Path) -> Result<Vec<(String, EvalCase)>> {
+    let data = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let mut cases = Vec::new();
+    let mut seen = HashSet::new();
+    for (index, line) in data.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let case: EvalCase = serde_json::from_str(line)
+            .with_context(|| format!("{}:{}: invalid eval case", path.display(), index + 1))?;
+        let id = match &case.id {
+            Some(serde_json::Value::String(id)) => id.clone(),
+            Some(id) => id.to_string(),
+            None => (index + 1).to_string(),
+        };
+        if !seen.insert(id.clone()) {
+            bail!("{}:{}: duplicate id '{id}'", path.display(), index + 1);
+        }
+        cases.push((id, case));
+    }
+    if cases.is_empty() {
+        bail!("{} has no eval cases", path.display());
+    }
+    Ok(cases)
+}
+
+fn summarize(targets: &[Target], rows: &[EvalRow]) -> Vec<ModelSummary> {
+    targets
+        .iter()
+        .map(|target| {
+            let rows: Vec<&EvalRow> = rows
+                .iter()
+                .filter(|row| row.provider == target.provider && row.model == target.model)
+                .collect();
+            let scores: Vec<f64> = rows.iter().filter_map(|row| row.score).collect();
+            let latencies: Vec<u128> = rows.iter().filter_map(|row| row.latency_ms).collect();
+            ModelSummary {
+                provider: target.provider.clone(),
+                model: target.model.clone(),
+                mean_score: (!scores.is_empty())
+                    .then(|| scores.iter().sum::<f64>() / scores.len() as f64),
+                scored: scores.len(),
+                failed: rows.len() - scores.len(),
+                mean_latency_ms: (!latencies.is_empty())
+                    .then(|| latencies.iter().sum::<u128>() / latencies.len() as u128),
+            }
+        })
+        .collect()
+}
+
+fn to_csv(rows: &[EvalRow]) -> String {
+    let mut out = String::from(
+        "id,provider,model,score,reason,latency_ms,input_tokens,output_tokens,error,answer\n",
+    );
+    for row in rows {
+        let fields = [
+            row.id.clone(),
+            row.provider.clone(),
+            row.model.clone(),
+            opt(row.score),
+            row.reason.clone().unwrap_or_default(),
+            opt(row.latency_ms),
+            opt(row.input_tokens),
+            opt(row.output_tokens),
+            row.error.clone().unwrap_or_default(),
+            row.answer.clone().unwrap_or_default(),
+        ];
+        let line: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        out.push_str(&line.join(","));
+        out.push('\n');
+    }
+    out
+}
+
+fn opt<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+/// Quotes a field when it holds a comma, quote or line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verdicts_are_found_in_chatty_replies() {
+        let verdict = parse_verdict(
+            "Here you go:\n```json\n{\"score\": 8, \"reason\": \"Mostly right.\"}\n```",
+        )
+        .unwrap();
+        assert_eq!(
+            verdict,
+            Verdict {
+                score: 8.0,
+                reason: "Mostly right.".to_string()
+            }
+        );
+        assert!(parse_verdict("I'd give it an 8").is_err());
+    }
+
+    #[test]
+    fn csv_report_quotes_awkward_fields_and_summary_averages() {
+        let row = |model: &str, score: Option<f64>| EvalRow {
+            id: "1".to_string(),
+            provider: "p".to_string(),
+            model: model.to_string(),
+            score,
+            reason: score.map(|_| "fine, \"mostly\"".to_string()),
+            answer: Some("line one\nline two".to_string()),
+            latency_ms: Some(100),
+            input_tokens: None,
+            output_tokens: None,
+            error: None,
+        };
+        let rows = [row("a", Some(6.0)), row("a", Some(9.0)), row("b", None)];
+        let csv = to_csv(&rows[..1]);
+        assert_eq!(
+            csv.lines().nth(1).unwrap(),
+            "1,p,a,6,\"fine, \"\"mostly\"\"\",100,,,,\"line one"
+        );
+
+        let targets = [
+            Target {
+                provider: "p".to_string(),
+                model: "a".to_string(),
+            },
+            Target {
+                provider: "p".to_string(),
+                model: "b".to_string(),
+            },
+        ];
+        let summary = summarize(&targets, &rows);
+        assert_eq!(summary[0].mean_score, Some(7.5));
+        assert_eq!(summary[0].scored, 2);
+        assert_eq!(summary[1].mean_score, None);
+        assert_eq!(summary[1].failed, 1);
+    }
+}
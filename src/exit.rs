@@ -0,0 +1,149 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::process::ExitCode;
+
+use reqwest::StatusCode;
+
+use crate::provider::{Canceled, ContentBlocked};
+
+/// Process exit codes, so wrapper scripts can branch on the kind of failure.
+/// Usage errors exit with 2 (from clap).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// Anything not covered below
+    Failure = 1,
+    /// Unreadable config, unknown provider or profile, missing API key
+    Config = 3,
+    /// The provider rejected the credentials (HTTP 401/403)
+    Auth = 4,
+    /// Still rate limited (HTTP 429) after any retries
+    RateLimited = 5,
+    /// The provider could not be reached or timed out
+    Network = 6,
+    /// The provider's safety filters blocked the prompt or the reply
+    ContentBlocked = 7,
+    /// A hard `[usage.budgets]` limit is used up
+    OverBudget = 8,
+    /// `message --out`: the reply stopped at the output token limit
+    Incomplete = 9,
+    /// Interrupted with Ctrl-C
+    Canceled = 130,
+}
+
+impl ExitStatus {
+    /// The status a code stands for, as it comes back from another process.
+    pub fn from_code(code: u8) -> Self {
+        [
+            Self::Config,
+            Self::Auth,
+            Self::RateLimited,
+            Self::Network,
+            Self::ContentBlocked,
+            Self::OverBudget,
+            Self::Incomplete,
+            Self::Canceled,
+        ]
+        .into_iter()
+        .find(|status| *status as u8 == code)
+        .unwrap_or(Self::Failure)
+    }
+}
+
+impl From<ExitStatus> for ExitCode {
+    fn from(status: ExitStatus) -> Self {
+        ExitCode::from(status as u8)
+    }
+}
+
+/// An error marked with the status it should exit with. Displays and chains
+/// exactly like the error it wraps.
+#[derive(Debug)]
+struct Tagged {
+    status: ExitStatus,
+    error: anyhow::Error,
+}
+
+impl fmt::Display for Tagged {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.error, f)
+    }
+}
+
+impl StdError for Tagged {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.error.source()
+    }
+}
+
+pub fn tag(error: anyhow::Error, status: ExitStatus) -> anyhow::Error {
+    anyhow::Error::new(Tagged { status, error })
+}
+
+pub trait ResultExt<T> {
+    /// Exits with `status` if this is the error that ends the program.
+    fn exit_status(self, status: ExitStatus) -> anyhow::Result<T>;
+}
+
+impl<T> ResultExt<T> for anyhow::Result<T> {
+    fn exit_status(self, status: ExitStatus) -> anyhow::Result<T> {
+        self.map_err(|error| tag(error, status))
+    }
+}
+
+/// The outermost tag in the chain, else what the HTTP error underneath says.
+pub fn status_for(error: &anyhow::Error) -> ExitStatus {
+    for cause in error.chain() {
+        if let Some(tagged) = cause.downcast_ref::<Tagged>() {
+            return tagged.status;
+        }
+        if cause.is::<ContentBlocked>() {
+            return ExitStatus::ContentBlocked;
+        }
+        if cause.is::<Canceled>() {
+            return ExitStatus::Canceled;
+        }
+        if let Some(err) = cause.downcast_ref::<reqwest::Error>() {
+            match err.status() {
+                Some(StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) => return ExitStatus::Auth,
+                Some(StatusCode::TOO_MANY_REQUESTS) => return ExitStatus::RateLimited,
+                Some(_) => {}
+                None if err.is_connect() || err.is_timeout() || err.is_request() => {
+                    return ExitStatus::Network
+                }
+                None => {}
+            }
+        }
+    }
+    ExitStatus::Failure
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{anyhow, Context};
+
+    use super::*;
+
+    #[test]
+    fn errors_map_to_their_exit_status() {
+        let plain = anyhow!("boom");
+        assert_eq!(status_for(&plain), ExitStatus::Failure);
+
+        let config: anyhow::Result<()> = Err(anyhow!("provider 'x' not found"));
+        let config = config
+            .exit_status(ExitStatus::Config)
+            .context("failed to start chat")
+            .unwrap_err();
+        assert_eq!(status_for(&config), ExitStatus::Config);
+        assert_eq!(
+            format!("{config:#}"),
+            "failed to start chat: provider 'x' not found"
+        );
+
+        let blocked = anyhow::Error::new(ContentBlocked {
+            provider: "gemini",
+            reason: "SAFETY".to_string(),
+        });
+        assert_eq!(status_for(&blocked), ExitStatus::ContentBlocked);
+        assert_eq!(ExitCode::from(ExitStatus::Canceled), ExitCode::from(130));
+    }
+}
@@ -0,0 +1,234 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::{json, Value};
+
+use crate::config::HooksConfig;
+use crate::provider::{ChatMessage, ChatResponse};
+use rustchat_core::logger;
+
+static HOOKS: OnceLock<HooksConfig> = OnceLock::new();
+
+/// Turns on the `[hooks]` commands for the rest of the process.
+pub fn init(cfg: &HooksConfig) {
+    let _ = HOOKS.set(cfg.clone());
+}
+
+fn pre_request_command() -> Option<&'static str> {
+    HOOKS.get()?.pre_request.as_deref()
+}
+
+fn post_response_command() -> Option<&'static str> {
+    HOOKS.get()?.post_response.as_deref()
+}
+
+/// Whether a `post_response` hook may rewrite replies, which means they
+/// cannot be shown before they are complete.
+pub fn rewrites_replies() -> bool {
+    post_response_command().is_some()
+}
+
+/// The system prompt and messages a `pre_request` hook asked to send instead.
+pub struct Rewrite {
+    pub system: Option<String>,
+    pub messages: Vec<ChatMessage>,
+}
+
+/// Runs `pre_request`, if set. `None` means send the request as it is.
+pub async fn pre_request(
+    provider: &str,
+    model: &str,
+    system: Option<&str>,
+    messages: &[ChatMessage],
+) -> Result<Option<Rewrite>> {
+    let Some(command) = pre_request_command() else {
+        return Ok(None);
+    };
+    let input = pre_request_input(provider, model, system, messages, false);
+    let output = run_async("pre_request", command, input).await?;
+    output.map(read_rewrite).transpose()
+}
+
+/// `pre_request` for `--dry-run`, which shows the request the hook produces;
+/// the hook sees `"dry_run": true` and can skip any logging.
+pub fn pre_request_preview(
+    provider: &str,
+    model: &str,
+    system: Option<&str>,
+    messages: &[ChatMessage],
+) -> Result<Option<Rewrite>> {
+    let Some(command) = pre_request_command() else {
+        return Ok(None);
+    };
+    let input = pre_request_input(provider, model, system, messages, true);
+    run("pre_request", command, &input)?
+        .map(read_rewrite)
+        .transpose()
+}
+
+/// Runs `post_response`, if set, and replaces the reply text with the hook's.
+pub async fn post_response(
+    provider: &str,
+    model: &str,
+    system: Option<&str>,
+    messages: &[ChatMessage],
+    response: &mut ChatResponse,
+) -> Result<()> {
+    let Some(command) = post_response_command() else {
+        return Ok(());
+    };
+    let input = post_response_input(provider, model, system, messages, response);
+    if let Some(output) = run_async("post_response", command, input).await? {
+        response.text = read_reply_text(&output)?;
+    }
+    Ok(())
+}
+
+fn pre_request_input(
+    provider: &str,
+    model: &str,
+    system: Option<&str>,
+    messages: &[ChatMessage],
+    dry_run: bool,
+) -> Value {
+    json!({
+        "event": "pre_request",
+        "provider": provider,
+        "model": model,
+        "dry_run": dry_run,
+        "messages": logger::messages_to_json(system, messages),
+    })
+}
+
+fn post_response_input(
+    provider: &str,
+    model: &str,
+    system: Option<&str>,
+    messages: &[ChatMessage],
+    response: &ChatResponse,
+) -> Value {
+    json!({
+        "event": "post_response",
+        "provider": provider,
+        "model": model,
+        "messages": logger::messages_to_json(system, messages),
+        "response": {
+            "text": response.text,
+            "finish_reason": response.finish_reason,
+            "usage": response.usage,
+            "tool_calls": response.tool_calls,
+        },
+    })
+}
+
+fn read_rewrite(mut output: Value) -> Result<Rewrite> {
+    let messages = output
+        .get_mut("messages")
+        .map(Value::take)
+        .ok_or_else(|| anyhow!("pre_request hook printed JSON without \"messages\""))?;
+    let (system, messages) = logger::messages_from_json(messages)
+        .context("pre_request hook printed invalid messages")?;
+    Ok(Rewrite { system, messages })
+}
+
+fn read_reply_text(output: &Value) -> Result<String> {
+    output
+        .pointer("/response/text")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("post_response hook printed JSON without \"response.text\""))
+}
+
+async fn run_async(
+    event: &'static str,
+    command: &'static str,
+    input: Value,
+) -> Result<Option<Value>> {
+    tokio::task::spawn_blocking(move || run(event, command, &input))
+        .await
+        .with_context(|| format!("{event} hook task panicked"))?
+}
+
+/// Runs a hook through the platform shell with `input` on stdin. Empty
+/// output leaves things unchanged; a failing hook stops the request.
+fn run(event: &str, command: &str, input: &Value) -> Result<Option<Value>> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.args(["/C", command]);
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.args(["-c", command]);
+        shell
+    };
+    let mut child = shell
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .with_context(|| format!("failed to run {event} hook `{command}`"))?;
+    // Written from another thread so a hook that prints before it has read
+    // everything cannot deadlock against a full pipe.
+    let payload = input.to_string();
+    let writer = child.stdin.take().map(|mut stdin| {
+        std::thread::spawn(move || {
+            let _ = stdin.write_all(payload.as_bytes());
+        })
+    });
+    let output = child.wait_with_output()?;
+    if let Some(writer) = writer {
+        let _ = writer.join();
+    }
+    if !output.status.success() {
+        bail!("{event} hook `{command}` failed ({})", output.status);
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if stdout.trim().is_empty() {
+        return Ok(None);
+    }
+    serde_json::from_str(&stdout)
+        .map(Some)
+        .with_context(|| format!("{event} hook `{command}` did not print JSON"))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::provider::MessageRole;
+
+    use super::*;
+
+    #[test]
+    fn hooks_rewrite_requests_and_replies() {
+        let messages = [ChatMessage::user("my key is sk-123")];
+        let input = pre_request_input("openai", "gpt-4o", Some("Be brief."), &messages, false);
+
+        // A hook that prints nothing leaves the request alone.
+        assert!(run("pre_request", "cat >/dev/null", &input)
+            .unwrap()
+            .is_none());
+
+        let redact = r#"sed 's/sk-[0-9]*/[redacted]/g'"#;
+        let output = run("pre_request", redact, &input).unwrap().unwrap();
+        let rewrite = read_rewrite(output).unwrap();
+        assert_eq!(rewrite.system.as_deref(), Some("Be brief."));
+        assert_eq!(rewrite.messages.len(), 1);
+        assert_eq!(rewrite.messages[0].role, MessageRole::User);
+        assert_eq!(rewrite.messages[0].content, "my key is [redacted]");
+
+        let response = ChatResponse {
+            text: "Your key sk-123 is fine.".to_string(),
+            ..Default::default()
+        };
+        let input = post_response_input("openai", "gpt-4o", None, &messages, &response);
+        let output = run("post_response", redact, &input).unwrap().unwrap();
+        assert_eq!(
+            read_reply_text(&output).unwrap(),
+            "Your key [redacted] is fine."
+        );
+
+        assert!(run("pre_request", "exit 3", &input).is_err());
+        assert!(read_rewrite(json!({"ok": true})).is_err());
+    }
+}
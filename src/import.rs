@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Deserialize;
+
+use crate::logger::{self, SessionMeta};
+use crate::provider::{ChatMessage, MessageRole};
+
+/// Tag added to every conversation imported from ChatGPT.
+pub const CHATGPT_TAG: &str = "chatgpt";
+
+/// A conversation converted from another tool, ready for `SessionStore::save`.
+pub struct ImportedSession {
+    pub id: String,
+    pub meta: SessionMeta,
+    pub system: Option<String>,
+    pub messages: Vec<ChatMessage>,
+}
+
+#[derive(Deserialize)]
+struct ChatgptConversation {
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    conversation_id: Option<String>,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    create_time: Option<f64>,
+    #[serde(default)]
+    default_model_slug: Option<String>,
+    #[serde(default)]
+    current_node: Option<String>,
+    #[serde(default)]
+    mapping: HashMap<String, ChatgptNode>,
+}
+
+#[derive(Deserialize)]
+struct ChatgptNode {
+    #[serde(default)]
+    parent: Option<String>,
+    #[serde(default)]
+    message: Option<ChatgptMessage>,
+}
+
+#[derive(Deserialize)]
+struct ChatgptMessage {
+    author: ChatgptAuthor,
+    #[serde(default)]
+    create_time: Option<f64>,
+    #[serde(default)]
+    content: Option<ChatgptContent>,
+    #[serde(default)]
+    metadata: Option<ChatgptMetadata>,
+}
+
+#[derive(Deserialize)]
+struct ChatgptAuthor {
+    role: String,
+}
+
+#[derive(Deserialize)]
+struct ChatgptContent {
+    #[serde(default)]
+    parts: Vec<serde_json::Value>,
+    #[serde(default)]
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ChatgptMetadata {
+    #[serde(default)]
+    model_slug: Option<String>,
+}
+
+/// Converts the `conversations.json` file of a ChatGPT data export. Only the
+/// branch ending at each conversation's current node is kept; tool output,
+/// images and empty messages are dropped.
+pub fn parse_chatgpt_export(data: &str) -> Result<Vec<ImportedSession>> {
+    let conversations: Vec<ChatgptConversation> =
+        serde_json::from_str(data).context("not a ChatGPT conversations.json export")?;
+    Ok(conversations
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, conversation)| convert_conversation(index, conversation))
+        .collect())
+}
+
+fn convert_conversation(
+    index: usize,
+    mut conversation: ChatgptConversation,
+) -> Option<ImportedSession> {
+    let mut branch = Vec::new();
+    let mut cursor = conversation.current_node.take();
+    while let Some(node_id) = cursor {
+        let node = conversation.mapping.remove(&node_id)?;
+        cursor = node.parent;
+        branch.extend(node.message);
+    }
+    branch.reverse();
+
+    let mut model = conversation.default_model_slug.take();
+    let mut system = None;
+    let mut messages = Vec::new();
+    for message in branch {
+        let Some(role) = MessageRole::parse(&message.author.role) else {
+            continue;
+        };
+        let content = message.content.map(text_of).unwrap_or_default();
+        if content.trim().is_empty() {
+            continue;
+        }
+        match role {
+            MessageRole::System if messages.is_empty() => system = Some(content),
+            MessageRole::User | MessageRole::Assistant => {
+                if role == MessageRole::Assistant {
+                    if let Some(slug) = message.metadata.and_then(|meta| meta.model_slug) {
+                        model = Some(slug);
+                    }
+                }
+                let mut converted = ChatMessage::new(role, content);
+                converted.timestamp = message.create_time.and_then(from_epoch);
+                messages.push(converted);
+            }
+            _ => {}
+        }
+    }
+    if messages.is_empty() {
+        return None;
+    }
+
+    let created_at = conversation.create_time.and_then(from_epoch);
+    let source_id = conversation
+        .conversation_id
+        .or(conversation.id)
+        .unwrap_or_else(|| index.to_string());
+    let short_id: String = source_id.chars().take(8).collect();
+    let id = logger::session_id_at(
+        &format!("{CHATGPT_TAG}-{short_id}"),
+        created_at.unwrap_or_default(),
+    );
+    Some(ImportedSession {
+        id,
+        meta: SessionMeta {
+            provider: Some("openai".to_string()),
+            model,
+            created_at,
+            title: conversation.title.filter(|title| !title.trim().is_empty()),
+            tags: vec![CHATGPT_TAG.to_string()],
+        },
+        system,
+        messages,
+    })
+}
+
+fn text_of(content: ChatgptContent) -> String {
+    let parts: Vec<String> = content
+        .parts
+        .into_iter()
+        .filter_map(|part| match part {
+            serde_json::Value::String(text) => Some(text),
+            _ => None,
+        })
+        .collect();
+    if parts.is_empty() {
+        content.text.unwrap_or_default()
+    } else {
+        parts.join("\n")
+    }
+}
+
+fn from_epoch(seconds: f64) -> Option<DateTime<Utc>> {
+    Utc.timestamp_millis_opt((seconds * 1000.0) as i64).single()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chatgpt_export_follows_current_branch() {
+        let export = r#"[{
+            "title": "Borrowing",
+            "create_time": 1714566600.5,
+            "conversation_id": "abcdef12-3456",
+            "current_node": "a2",
+            "default_model_slug": "gpt-4",
+            "mapping": {
+                "root": {"parent": null, "message": null},
+                "sys": {"parent": "root", "message": {"author": {"role": "system"},
+                    "content": {"content_type": "text", "parts": [""]}}},
+                "u1": {"parent": "sys", "message": {"author": {"role": "user"},
+                    "create_time": 1714566601.0,
+                    "content": {"content_type": "text", "parts": ["Why &mut?"]}}},
+                "a1": {"parent": "u1", "message": {"author": {"role": "assistant"},
+                    "content": {"content_type": "text", "parts": ["Old answer"]}}},
+                "a2": {"parent": "u1", "message": {"author": {"role": "assistant"},
+                    "metadata": {"model_slug": "gpt-4o"},
+                    "content": {"content_type": "text", "parts": ["Exclusive access."]}}}
+            }
+        }]"#;
+        let sessions = parse_chatgpt_export(export).expect("parse");
+        assert_eq!(sessions.len(), 1);
+        let session = &sessions[0];
+        assert_eq!(session.id, "20240501-123000-chatgpt-abcdef12");
+        assert_eq!(session.meta.title.as_deref(), Some("Borrowing"));
+        assert_eq!(session.meta.model.as_deref(), Some("gpt-4o"));
+        assert_eq!(session.system, None);
+        let contents: Vec<&str> = session
+            .messages
+            .iter()
+            .map(|m| m.content.as_str())
+            .collect();
+        assert_eq!(contents, ["Why &mut?", "Exclusive access."]);
+    }
+}
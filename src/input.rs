@@ -0,0 +1,258 @@
+use std::io::{self, IsTerminal, Read};
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+
+use crate::cli::StdinFilterArgs;
+use crate::tokens::Tokenizer;
+
+/// Reads piped stdin for `message`; `None` when stdin is a terminal.
+pub fn read_piped_stdin() -> Result<Option<String>> {
+    let mut stdin = io::stdin();
+    if stdin.is_terminal() {
+        return Ok(None);
+    }
+    let mut text = String::new();
+    stdin
+        .read_to_string(&mut text)
+        .context("failed to read the prompt from stdin")?;
+    Ok(Some(text))
+}
+
+/// Applies `--strip-ansi`, then `--tail-lines`, then `--max-bytes`. Both
+/// limits keep the end, where build logs report what went wrong, and say
+/// how much was cut.
+pub fn filter_stdin(mut text: String, filters: &StdinFilterArgs) -> String {
+    if filters.strip_ansi {
+        text = strip_ansi(&text);
+    }
+    if let Some(keep) = filters.tail_lines {
+        let lines: Vec<&str> = text.lines().collect();
+        if lines.len() > keep {
+            let cut = lines.len() - keep;
+            text = format!("[... {cut} earlier lines cut]\n{}", lines[cut..].join("\n"));
+        }
+    }
+    if let Some(max) = filters.max_bytes {
+        let max = usize::try_from(max).unwrap_or(usize::MAX);
+        if text.len() > max {
+            let mut start = text.len() - max;
+            while !text.is_char_boundary(start) {
+                start += 1;
+            }
+            // Start on a whole line unless that would leave nothing.
+            if let Some(newline) = text[start..].find('\n') {
+                if start + newline + 1 < text.len() {
+                    start += newline + 1;
+                }
+            }
+            text = format!("[... {} earlier bytes cut]\n{}", start, &text[start..]);
+        }
+    }
+    text
+}
+
+/// Drops terminal escape sequences: CSI (colors, cursor moves), OSC
+/// (titles, hyperlinks) and two-character escapes.
+fn strip_ansi(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\u{1b}' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('[') => {
+                // Parameters and intermediates, up to the final byte.
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            Some(']') => {
+                // Ends at BEL or at ESC \.
+                while let Some(c) = chars.next() {
+                    if c == '\u{7}' {
+                        break;
+                    }
+                    if c == '\u{1b}' && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+/// Builds the `message` prompt from its arguments and piped stdin. A lone
+/// `-` (or no arguments) makes stdin the whole prompt; otherwise stdin is
+/// appended to the arguments as a fenced block of context.
+pub fn compose_prompt(args: &[String], stdin: Option<String>) -> Result<String> {
+    let stdin = stdin.filter(|text| !text.trim().is_empty());
+    let from_stdin_only = args.is_empty() || (args.len() == 1 && args[0] == "-");
+    match (from_stdin_only, stdin) {
+        (true, Some(text)) => Ok(text.trim_end().to_string()),
+        (true, None) => bail!("no prompt given - pass it as arguments or pipe it on stdin"),
+        (false, Some(text)) => Ok(format!(
+            "{}\n\n```\n{}\n```",
+            args.join(" "),
+            text.trim_end()
+        )),
+        (false, None) => Ok(args.join(" ")),
+    }
+}
+
+/// Reads every `--file` path or glob into one block of context, one fenced
+/// section per file, refusing attachments above `budget` tokens as `model`
+/// counts them.
+pub fn attach_files(patterns: &[String], budget: usize, model: &str) -> Result<Option<String>> {
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for pattern in patterns {
+        let matches = expand(pattern)?;
+        if matches.is_empty() {
+            bail!("--file {pattern} matched no files");
+        }
+        for path in matches {
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+    }
+    if paths.is_empty() {
+        return Ok(None);
+    }
+
+    let tokenizer = Tokenizer::for_model(model);
+    let mut sections = Vec::with_capacity(paths.len());
+    let mut tokens = 0;
+    for path in &paths {
+        let bytes =
+            std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        let text = String::from_utf8(bytes)
+            .map_err(|_| anyhow::anyhow!("{} is not a UTF-8 text file", path.display()))?;
+        tokens += tokenizer.count(&text);
+        if tokens > budget {
+            bail!(
+                "--file attachments exceed the budget of {budget} tokens at {} \
+                 (~{tokens} so far); raise --file-budget or attach fewer files",
+                path.display()
+            );
+        }
+        sections.push(format!(
+            "File `{}`:\n```\n{}\n```",
+            path.display(),
+            text.trim_end()
+        ));
+    }
+    Ok(Some(sections.join("\n\n")))
+}
+
+/// A literal path, or the regular files a glob pattern matches.
+pub fn expand(pattern: &str) -> Result<Vec<PathBuf>> {
+    if !pattern.contains(['*', '?', '[']) {
+        return Ok(vec![PathBuf::from(pattern)]);
+    }
+    let entries = glob::glob(pattern).with_context(|| format!("invalid glob {pattern}"))?;
+    let mut paths = Vec::new();
+    for entry in entries {
+        let path = entry?;
+        if path.is_file() {
+            paths.push(path);
+        }
+    }
+    Ok(paths)
+}
+
+/// Puts attached file context ahead of the user's own text.
+pub fn with_context(context: Option<&str>, text: String) -> String {
+    match context {
+        Some(context) => format!("{context}\n\n{text}"),
+        None => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn piped_stdin_becomes_prompt_or_context() {
+        let diff = Some("+fn main() {}\n".to_string());
+        assert_eq!(
+            compose_prompt(&args(&["review", "this"]), diff.clone()).unwrap(),
+            "review this\n\n```\n+fn main() {}\n```"
+        );
+        assert_eq!(
+            compose_prompt(&args(&["-"]), diff).unwrap(),
+            "+fn main() {}"
+        );
+        assert_eq!(compose_prompt(&args(&["hi"]), None).unwrap(), "hi");
+        assert!(compose_prompt(&[], Some("  \n".into())).is_err());
+    }
+
+    #[test]
+    fn piped_logs_lose_escapes_and_keep_their_end() {
+        let log = "\u{1b}[1m\u{1b}[32mCompiling\u{1b}[0m foo\n\u{1b}]0;title\u{7}warning: x\nerror[E0308]: mismatched\n";
+        let filters = StdinFilterArgs {
+            strip_ansi: true,
+            tail_lines: None,
+            max_bytes: None,
+        };
+        assert_eq!(
+            filter_stdin(log.to_string(), &filters),
+            "Compiling foo\nwarning: x\nerror[E0308]: mismatched\n"
+        );
+        let filters = StdinFilterArgs {
+            tail_lines: Some(2),
+            ..filters
+        };
+        assert_eq!(
+            filter_stdin(log.to_string(), &filters),
+            "[... 1 earlier lines cut]\nwarning: x\nerror[E0308]: mismatched"
+        );
+        let filters = StdinFilterArgs {
+            strip_ansi: false,
+            tail_lines: None,
+            max_bytes: Some(25),
+        };
+        assert_eq!(
+            filter_stdin(
+                "line one\nline two\nerror: é at the end".to_string(),
+                &filters
+            ),
+            "[... 18 earlier bytes cut]\nerror: é at the end"
+        );
+    }
+
+    #[test]
+    fn files_are_globbed_fenced_and_budgeted() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        std::fs::write(dir.join("a.rs"), "fn a() {}\n").unwrap();
+        std::fs::write(dir.join("b.rs"), "fn b() {}\n").unwrap();
+        std::fs::write(dir.join("notes.txt"), "skip me").unwrap();
+        let glob = dir.join("*.rs").display().to_string();
+        let literal = dir.join("a.rs").display().to_string();
+
+        let context = attach_files(&[glob.clone(), literal], 1_000, "")
+            .unwrap()
+            .unwrap();
+        assert_eq!(context.matches("File `").count(), 2);
+        assert!(context.contains("a.rs`:\n```\nfn a() {}\n```\n\nFile `"));
+        assert!(!context.contains("skip me"));
+
+        assert!(attach_files(&[glob], 3, "").is_err());
+        let missing = dir.join("*.py").display().to_string();
+        assert!(attach_files(&[missing], 1_000, "").is_err());
+        assert_eq!(attach_files(&[], 1_000, "").unwrap(), None);
+    }
+}
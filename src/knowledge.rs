@@ -0,0 +1,451 @@
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context, Result};
+use ring::digest;
+
+use crate::cli::{IndexAddArgs, IndexCommand};
+use crate::config::{AppConfig, KnowledgeConfig, ProviderConfig};
+use crate::exit::{ExitStatus, ResultExt};
+use crate::input;
+use crate::provider::{self, DynProvider};
+use crate::vector_store::{self, Chunk, DynVectorStore, IndexDb, IndexInfo};
+
+/// Chunks sent to the embeddings API per request.
+const EMBED_BATCH: usize = 64;
+/// Files bigger than this are skipped when a directory is walked.
+const MAX_FILE_BYTES: u64 = 1024 * 1024;
+
+fn validate_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        bail!("index names may only use letters, digits, '-' and '_'");
+    }
+    Ok(())
+}
+
+/// Opens the index database, moving in indexes from the old JSON files.
+fn open_db(cfg: &KnowledgeConfig) -> Result<Arc<IndexDb>> {
+    let db = IndexDb::open(&vector_store::database_path(cfg)?)?;
+    if let Some(dir) = vector_store::legacy_dir() {
+        db.migrate_json(&dir)?;
+    }
+    Ok(Arc::new(db))
+}
+
+/// Splits `text` into pieces of about `size` characters on line boundaries,
+/// each repeating up to `overlap` characters from the end of the one before.
+/// Returns each piece with its first line number.
+fn chunk_text(text: &str, size: usize, overlap: usize) -> Vec<(usize, String)> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < lines.len() {
+        let mut end = start;
+        let mut length = 0;
+        while end < lines.len() && (end == start || length + lines[end].len() < size) {
+            length += lines[end].len() + 1;
+            end += 1;
+        }
+        let piece = lines[start..end].join("\n");
+        if !piece.trim().is_empty() {
+            chunks.push((start + 1, piece));
+        }
+        if end == lines.len() {
+            break;
+        }
+        // Step back over whole lines that fit in the overlap, but always
+        // move forward.
+        let mut next = end;
+        let mut carried = 0;
+        while next > start + 1 && carried + lines[next - 1].len() < overlap {
+            carried += lines[next - 1].len() + 1;
+            next -= 1;
+        }
+        start = next;
+    }
+    chunks
+}
+
+/// The embedding model used when `index add` is not given one.
+fn default_embedding_model(cfg: &ProviderConfig) -> Option<&'static str> {
+    match cfg {
+        ProviderConfig::Openai(_) => Some("text-embedding-3-small"),
+        ProviderConfig::Google(_) => Some("text-embedding-004"),
+        ProviderConfig::Anthropic(_) => None,
+    }
+}
+
+pub async fn handle_index(command: IndexCommand, cfg: &AppConfig) -> Result<()> {
+    match command {
+        IndexCommand::Add(args) => add(args, cfg).await,
+        IndexCommand::List => list(cfg),
+    }
+}
+
+async fn add(args: IndexAddArgs, cfg: &AppConfig) -> Result<()> {
+    validate_name(&args.index)?;
+    let db = open_db(&cfg.knowledge)?;
+    let existing = db.index(&args.index)?;
+    let provider_name = match (&existing, &args.provider) {
+        (Some(index), None) => index.provider.clone(),
+        _ => cfg
+            .infer_default_provider(&args.provider)
+            .exit_status(ExitStatus::Config)?,
+    };
+    let provider_cfg = cfg
+        .require_provider(&provider_name)
+        .exit_status(ExitStatus::Config)?;
+    let model = match (&existing, args.embedding_model.clone()) {
+        (_, Some(model)) => model,
+        (Some(index), None) => index.model.clone(),
+        (None, None) => default_embedding_model(provider_cfg)
+            .ok_or_else(|| {
+                anyhow!(
+                    "provider '{provider_name}' has no embeddings API; \
+                     pass --provider with an OpenAI or Gemini provider"
+                )
+            })
+            .exit_status(ExitStatus::Config)?
+            .to_string(),
+    };
+    let is_new = existing.is_none();
+    let index = match existing {
+        Some(existing) => {
+            if existing.provider != provider_name || existing.model != model {
+                bail!(
+                    "index '{}' was built with {}/{}; add to it with the same provider and model, \
+                     or pick another --index",
+                    args.index,
+                    existing.provider,
+                    existing.model
+                );
+            }
+            existing
+        }
+        None => IndexInfo {
+            name: args.index.clone(),
+            provider: provider_name.clone(),
+            model: model.clone(),
+            store: cfg.knowledge.store,
+            files: 0,
+            chunks: 0,
+        },
+    };
+    let store = vector_store::open_store(&cfg.knowledge, index.store, &db)
+        .exit_status(ExitStatus::Config)?;
+
+    let files = collect_files(&args.paths)?;
+    let known = db.sources(&index.name)?;
+    let mut pending: Vec<PendingFile> = Vec::new();
+    let (mut unchanged, mut skipped) = (0, 0);
+    for path in &files {
+        let Ok(text) = std::fs::read_to_string(path) else {
+            skipped += 1;
+            continue;
+        };
+        // Absolute, so adding the same file from elsewhere replaces it.
+        let source = std::fs::canonicalize(path)
+            .unwrap_or_else(|_| path.clone())
+            .display()
+            .to_string();
+        let hash = sha256_hex(text.as_bytes());
+        if known.get(&source) == Some(&hash) {
+            unchanged += 1;
+            continue;
+        }
+        let chunks = chunk_text(&text, args.chunk_size, args.chunk_overlap)
+            .into_iter()
+            .map(|(line, text)| Chunk {
+                source: source.clone(),
+                line,
+                text,
+                embedding: Vec::new(),
+            })
+            .collect();
+        pending.push(PendingFile {
+            source,
+            hash,
+            chunks,
+        });
+    }
+
+    if is_new {
+        db.create(&index)?;
+    }
+    if !pending.is_empty() {
+        let providers = provider::build_providers(
+            [provider_name.as_str()],
+            cfg,
+            args.secret_env.as_deref(),
+            !args.no_prompt,
+        )
+        .await?;
+        let embedder = Embedder {
+            provider: &providers[&provider_name],
+            provider_name: &provider_name,
+            model: &model,
+            total: pending.iter().map(|file| file.chunks.len()).sum(),
+            done: 0,
+        };
+        store_files(embedder, pending, &db, &store, &index.name).await?;
+    }
+    let index = db.index(&index.name)?.unwrap_or(index);
+    println!(
+        "Index '{}': {} files ({unchanged} unchanged, {skipped} not text), {} chunks in total in {}",
+        index.name,
+        files.len(),
+        index.chunks,
+        store.describe(&index.name)
+    );
+    Ok(())
+}
+
+/// A new or changed file whose chunks still need embedding.
+struct PendingFile {
+    source: String,
+    hash: String,
+    chunks: Vec<Chunk>,
+}
+
+struct Embedder<'a> {
+    provider: &'a DynProvider,
+    provider_name: &'a str,
+    model: &'a str,
+    total: usize,
+    done: usize,
+}
+
+impl Embedder<'_> {
+    async fn embed(&mut self, chunks: &mut [&mut Chunk]) -> Result<()> {
+        for batch in chunks.chunks_mut(EMBED_BATCH) {
+            let texts: Vec<String> = batch.iter().map(|chunk| chunk.text.clone()).collect();
+            let embeddings = self.provider.embed(self.model, &texts).await?;
+            if embeddings.len() != batch.len() {
+                bail!(
+                    "{} returned {} embeddings for {} chunks",
+                    self.provider_name,
+                    embeddings.len(),
+                    batch.len()
+                );
+            }
+            for (chunk, embedding) in batch.iter_mut().zip(embeddings) {
+                chunk.embedding = embedding;
+            }
+            self.done += batch.len();
+            eprintln!("[index] embedded {}/{} chunks", self.done, self.total);
+        }
+        Ok(())
+    }
+}
+
+/// Embeds files a batch at a time and stores each as soon as it is done, so
+/// an interrupted run keeps what it finished.
+async fn store_files(
+    mut embedder: Embedder<'_>,
+    pending: Vec<PendingFile>,
+    db: &IndexDb,
+    store: &DynVectorStore,
+    index: &str,
+) -> Result<()> {
+    let mut group: Vec<PendingFile> = Vec::new();
+    let mut files = pending.into_iter().peekable();
+    while let Some(file) = files.next() {
+        group.push(file);
+        let size: usize = group.iter().map(|file| file.chunks.len()).sum();
+        if size < EMBED_BATCH && files.peek().is_some() {
+            continue;
+        }
+        let mut chunks: Vec<&mut Chunk> = group
+            .iter_mut()
+            .flat_map(|file| file.chunks.iter_mut())
+            .collect();
+        embedder.embed(&mut chunks).await?;
+        for file in group.drain(..) {
+            store.remove_source(index, &file.source).await?;
+            store.insert(index, &file.chunks).await?;
+            db.set_source(index, &file.source, &file.hash, file.chunks.len())?;
+        }
+    }
+    Ok(())
+}
+
+/// Files named by `paths`: literal files, glob matches, and the text-sized
+/// files under directories (hidden entries skipped).
+fn collect_files(paths: &[String]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for pattern in paths {
+        let matches = input::expand(pattern)?;
+        if matches.is_empty() {
+            bail!("{pattern} matched no files");
+        }
+        for path in matches {
+            if path.is_dir() {
+                walk(&path, &mut files)?;
+            } else if path.is_file() {
+                files.push(path);
+            } else {
+                bail!("{} does not exist", path.display());
+            }
+        }
+    }
+    files.dedup();
+    Ok(files)
+}
+
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries: Vec<_> = std::fs::read_dir(dir)
+        .with_context(|| format!("failed to read {}", dir.display()))?
+        .flatten()
+        .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+        .collect();
+    entries.sort_by_key(|entry| entry.path());
+    for entry in entries {
+        let path = entry.path();
+        let Ok(meta) = entry.metadata() else {
+            continue;
+        };
+        if meta.is_dir() {
+            walk(&path, files)?;
+        } else if meta.is_file() && meta.len() <= MAX_FILE_BYTES {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    digest::digest(&digest::SHA256, bytes)
+        .as_ref()
+        .iter()
+        .fold(String::new(), |mut out, byte| {
+            let _ = write!(out, "{byte:02x}");
+            out
+        })
+}
+
+fn list(cfg: &AppConfig) -> Result<()> {
+    let indexes = open_db(&cfg.knowledge)?.list()?;
+    if indexes.is_empty() {
+        println!("No indexes yet; create one with `index add <paths>`.");
+        return Ok(());
+    }
+    for index in indexes {
+        println!(
+            "{}  {} files, {} chunks  ({}/{}, {})",
+            index.name,
+            index.files,
+            index.chunks,
+            index.provider,
+            index.model,
+            index.store.as_str()
+        );
+    }
+    Ok(())
+}
+
+/// An index opened for `chat --knowledge`, with the provider that embeds
+/// each question.
+#[derive(Clone)]
+pub struct Knowledge {
+    pub name: String,
+    index: IndexInfo,
+    store: DynVectorStore,
+    provider: DynProvider,
+    top_k: usize,
+}
+
+impl Knowledge {
+    /// Opens index `name`, reusing `chat_provider` when the index was built
+    /// with the same provider.
+    pub async fn open(
+        name: &str,
+        top_k: usize,
+        cfg: &AppConfig,
+        chat_provider: (&str, &DynProvider),
+        secret_env: Option<&str>,
+        allow_prompt: bool,
+    ) -> Result<Self> {
+        let db = open_db(&cfg.knowledge)?;
+        let index = db
+            .index(name)?
+            .ok_or_else(|| anyhow!("no index named '{name}'; create it with `index add`"))
+            .exit_status(ExitStatus::Config)?;
+        let store = vector_store::open_store(&cfg.knowledge, index.store, &db)
+            .exit_status(ExitStatus::Config)?;
+        let provider = if index.provider == chat_provider.0 {
+            chat_provider.1.clone()
+        } else {
+            let mut providers =
+                provider::build_providers([index.provider.as_str()], cfg, secret_env, allow_prompt)
+                    .await?;
+            providers
+                .remove(&index.provider)
+                .ok_or_else(|| anyhow!("provider '{}' is not configured", index.provider))?
+        };
+        Ok(Self {
+            name: name.to_string(),
+            index,
+            store,
+            provider,
+            top_k,
+        })
+    }
+
+    /// The passages closest to `question`, formatted as context for the
+    /// model; `None` when the index is empty.
+    pub async fn context_for(&self, question: &str) -> Result<Option<String>> {
+        if self.index.chunks == 0 {
+            return Ok(None);
+        }
+        let embedding = self
+            .provider
+            .embed(&self.index.model, &[question.to_string()])
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("{} returned no embedding", self.index.provider))?;
+        let hits = self
+            .store
+            .search(&self.name, &embedding, self.top_k)
+            .await?;
+        let mut context = format!(
+            "Passages from the '{}' knowledge index that may help:",
+            self.name
+        );
+        for hit in &hits {
+            context.push_str(&format!(
+                "\n\n`{}` (line {}):\n```\n{}\n```",
+                hit.source,
+                hit.line,
+                hit.text.trim_end()
+            ));
+        }
+        Ok(Some(context))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn documents_are_chunked() {
+        let text = "alpha\nbeta\ngamma\ndelta\nepsilon";
+        let chunks = chunk_text(text, 14, 7);
+        assert_eq!(
+            chunks,
+            [
+                (1, "alpha\nbeta".to_string()),
+                (2, "beta\ngamma".to_string()),
+                (3, "gamma\ndelta".to_string()),
+                (4, "delta\nepsilon".to_string()),
+            ]
+        );
+        assert_eq!(chunk_text("", 100, 10), []);
+    }
+}
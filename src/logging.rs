@@ -0,0 +1,255 @@
+use std::cell::RefCell;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+use anyhow::{Context, Result};
+use chrono::Local;
+use opentelemetry_sdk::trace::SdkTracer;
+use tracing::subscriber::DefaultGuard;
+use tracing::{Event, Level, Subscriber};
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::filter::{filter_fn, EnvFilter, LevelFilter};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{self, FmtContext, FormatEvent, FormatFields, MakeWriter};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{reload, Layer, Registry};
+
+use crate::cli::{LogFormat, LogLevel};
+use crate::{telemetry, trace};
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        }
+    }
+}
+
+type Spans = OpenTelemetryLayer<Registry, SdkTracer>;
+
+/// Spans go here once `[telemetry]` turns exporting on.
+static SPANS: OnceLock<reload::Handle<Option<Spans>, Registry>> = OnceLock::new();
+
+thread_local! {
+    /// Until the flags are parsed: warnings and errors, as text, to stderr.
+    static EARLY: RefCell<Option<DefaultGuard>> = const { RefCell::new(None) };
+}
+
+/// Logs warnings raised before the flags are parsed, which all happens on
+/// the main thread.
+pub fn install() {
+    let early = Registry::default().with(output(LogFormat::Text, io::stderr, LevelFilter::WARN));
+    let guard = tracing::subscriber::set_default(early);
+    EARLY.with(|slot| *slot.borrow_mut() = Some(guard));
+}
+
+/// Applies `-v`/`--log-level`, `--log-format` and `--log-file`, and makes the
+/// result the process-wide `tracing` subscriber. `-v` means debug, `-vv`
+/// trace; a log file on its own implies debug, and warnings still reach
+/// stderr alongside it.
+pub fn configure(
+    verbosity: u8,
+    level: Option<LogLevel>,
+    format: LogFormat,
+    log_file: Option<&Path>,
+) -> Result<()> {
+    let file = log_file
+        .map(|path| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("failed to open log file {}", path.display()))
+        })
+        .transpose()?;
+    let verbosity = match file {
+        Some(_) => verbosity.max(trace::REQUESTS),
+        None => verbosity,
+    };
+    let level = level.map(LevelFilter::from).unwrap_or(match verbosity {
+        0 => LevelFilter::WARN,
+        trace::REQUESTS => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    });
+    let (log_file, stderr_level) = match file {
+        Some(file) => (
+            Some(output(format, Mutex::new(file), level)),
+            level.min(LevelFilter::WARN),
+        ),
+        None => (None, level),
+    };
+    let (spans, handle) = reload::Layer::new(None);
+    let subscriber = Registry::default()
+        .with(spans.with_filter(filter_fn(telemetry::collected)))
+        .with(log_file)
+        .with(output(format, io::stderr, stderr_level));
+    if tracing::subscriber::set_global_default(subscriber).is_ok() {
+        let _ = SPANS.set(handle);
+    }
+    EARLY.with(|slot| slot.borrow_mut().take());
+    Ok(())
+}
+
+/// Hands spans, and the events inside them, to the OTLP exporter as well.
+pub fn attach(spans: Spans) {
+    if let Some(handle) = SPANS.get() {
+        let _ = handle.reload(Some(spans));
+    }
+}
+
+/// This crate's events (dependencies' are ignored) at or above `level`, one
+/// line each, written to `writer`.
+fn output<S, W>(format: LogFormat, writer: W, level: LevelFilter) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let filter = EnvFilter::new(format!("rustchat={level}"));
+    let layer = fmt::layer().with_writer(Redacted(writer)).with_ansi(false);
+    match format {
+        LogFormat::Text => layer.event_format(Text).with_filter(filter).boxed(),
+        LogFormat::Json => layer
+            .json()
+            .flatten_event(true)
+            .with_current_span(false)
+            .with_span_list(false)
+            .with_filter(filter)
+            .boxed(),
+    }
+}
+
+/// Keeps the familiar `[warn] ...` for warnings and errors and a local
+/// timestamp for the rest.
+struct Text;
+
+impl<S, N> FormatEvent<S, N> for Text
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        match *event.metadata().level() {
+            level @ (Level::ERROR | Level::WARN) => {
+                write!(writer, "[{}] ", level.as_str().to_lowercase())?
+            }
+            _ => write!(writer, "[{}] ", Local::now().format("%H:%M:%S%.3f"))?,
+        }
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
+
+/// Masks API keys and tokens in what is logged. The fmt layer hands over
+/// each line in a single write.
+struct Redacted<W>(W);
+
+impl<'a, W: MakeWriter<'a>> MakeWriter<'a> for Redacted<W> {
+    type Writer = Redacted<W::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        Redacted(self.0.make_writer())
+    }
+}
+
+impl<W: Write> Write for Redacted<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .write_all(trace::redact(&String::from_utf8_lossy(buf)).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use parking_lot::Mutex;
+    use serde_json::Value;
+
+    use super::*;
+
+    /// Collects what is logged, for the test to read back.
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Captured {
+        fn lines(&self) -> Vec<String> {
+            let bytes = self.0.lock();
+            String::from_utf8_lossy(&bytes)
+                .lines()
+                .map(str::to_string)
+                .collect()
+        }
+    }
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Captured {
+        type Writer = Captured;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    fn log(format: LogFormat, level: LevelFilter) -> Vec<String> {
+        let captured = Captured::default();
+        let subscriber = Registry::default().with(output(format, captured.clone(), level));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::warn!(
+                status = 429,
+                url = "https://api.example.com/v1?key=sk-secret",
+                "request failed"
+            );
+            tracing::debug!("retrying");
+            tracing::warn!(target: "hyper::client", "not ours");
+        });
+        captured.lines()
+    }
+
+    #[test]
+    fn lines_format_as_text_or_json() {
+        let lines = log(LogFormat::Text, LevelFilter::WARN);
+        assert_eq!(
+            lines,
+            [r#"[warn] request failed status=429 url="https://api.example.com/v1?key=****""#]
+        );
+
+        let lines = log(LogFormat::Json, LevelFilter::DEBUG);
+        assert_eq!(lines.len(), 2);
+        let value: Value = serde_json::from_str(&lines[0]).unwrap();
+        assert!(value["timestamp"].is_string());
+        assert_eq!(value["level"], "WARN");
+        assert_eq!(value["target"], "rustchat_cli::logging::tests");
+        assert_eq!(value["message"], "request failed");
+        assert_eq!(value["status"], 429);
+        assert_eq!(value["url"], "https://api.example.com/v1?key=****");
+        let value: Value = serde_json::from_str(&lines[1]).unwrap();
+        assert_eq!(value["message"], "retrying");
+    }
+}
@@ -1,53 +1,229 @@
+mod alias;
+mod audit;
+mod batch;
+mod benchmark;
+mod cache;
+mod cancel;
 mod cli;
+mod cmd;
+mod commit_msg;
+mod compare;
+mod completions;
 mod config;
-mod logger;
+mod context;
+mod daemon;
+mod editor;
+mod eval;
+mod exit;
+mod hooks;
+mod import;
+mod input;
+mod knowledge;
+mod logging;
+mod manpage;
+mod memory;
+mod notify;
+mod output;
+mod pager;
+mod plugin;
+mod pricing;
+mod probe;
+mod prompts;
 mod provider;
+mod remote;
+mod render;
 mod repl;
-mod secrets;
-mod streaming;
-mod utils;
+mod retry;
+mod roles;
+mod serve;
+mod sessions;
+mod shell;
+mod shutdown;
+mod stats;
+mod store;
+mod tee;
+mod telemetry;
+mod template;
+mod token_count;
+mod tools;
+mod tui;
+mod usage;
+mod vcr;
+mod vector_store;
+mod webhook;
 
-use anyhow::{anyhow, Result};
+use std::process::ExitCode;
+
+use rustchat_core::{logger, sealed, secrets, streaming, tokens, trace, utils};
+
+use anyhow::{anyhow, bail, Context, Result};
 use clap::Parser;
+use futures::StreamExt;
+use tracing::Instrument;
 
 use crate::cli::{
-    ChatCommand, Cli, Commands, CommonChatArgs, ConfigCommand, MessageCommand, SaveFormatArg,
+    BatchCommand, ChatCommand, Cli, Commands, CommonChatArgs, ConfigCommand, MessageCommand,
+    OutputArg, PluginsCommand, PromptsCommand,
 };
-use crate::config::{build_provider_config, AppConfig, ProviderKind};
+use crate::config::{apply_overrides, build_provider_config, AppConfig, ProviderKind};
+use crate::exit::{ExitStatus, ResultExt};
 use crate::logger as history_logger;
-use crate::logger::HistoryFormat;
-use crate::provider::{build_provider, ChatMessage, ChatRequestOptions};
+use crate::logger::{Compression, HistoryFormat};
+use crate::provider::{build_provider, ChatMessage, ChatRequestOptions, ReplyMeta};
 use crate::secrets::{optional_passphrase_from_env, DEFAULT_MASTER_ENV};
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    let cli = Cli::parse();
-    let mut app_config = match AppConfig::load() {
-        Ok(cfg) => cfg,
+async fn main() -> ExitCode {
+    shutdown::install();
+    let outcome = run().await;
+    shutdown::flush().await;
+    if let Some(code) = shutdown::signal_code() {
+        return ExitCode::from(code);
+    }
+    match outcome {
+        Ok(()) => ExitCode::SUCCESS,
         Err(err) => {
-            eprintln!("[warn] failed to load config: {err:#}. Starting with empty config.");
-            AppConfig::default()
+            eprintln!("Error: {err:?}");
+            exit::status_for(&err).into()
+        }
+    }
+}
+
+async fn run() -> Result<()> {
+    logging::install();
+    completions::complete_from_env();
+    let mut args: Vec<std::ffi::OsString> = std::env::args_os().collect();
+    // The config is only read before parsing when the subcommand may be an
+    // alias, so `--help` and friends never ask for a passphrase.
+    let mut loaded = None;
+    if alias::candidate(&args).is_some() {
+        if let Some(path) = alias::config_arg(&args) {
+            config::use_config_path(path);
         }
+        let cfg = load_config()?;
+        args = alias::expand(args, &cfg.alias).exit_status(ExitStatus::Config)?;
+        // Like git, an unknown subcommand runs `rustchat-<name>` from PATH.
+        if let Some((name, rest)) = alias::external(&args) {
+            if let Some(plugin) = plugin::find(name) {
+                let status = plugin.run_command(rest)?;
+                std::process::exit(status.code().unwrap_or(1));
+            }
+        }
+        loaded = Some(cfg);
+    }
+    let command = alias::subcommand(&args).unwrap_or_default().to_string();
+    let cli = Cli::parse_from(args);
+    logging::configure(
+        cli.verbose,
+        cli.log_level,
+        cli.log_format,
+        cli.log_file.as_deref(),
+    )?;
+    vcr::init(cli.record.clone(), cli.replay.clone()).exit_status(ExitStatus::Config)?;
+    if let Some(path) = cli.config.clone() {
+        config::use_config_path(path);
+    }
+    let daemon_config = match (&loaded, &cli.command) {
+        // The daemon caches by its own config, so cache flags skip it.
+        (None, Commands::Message(_)) if !cli.no_cache && cli.cache_ttl.is_none() => {
+            daemon::connect().await
+        }
+        _ => None,
+    };
+    let mut app_config = match loaded.or(daemon_config) {
+        Some(cfg) => cfg,
+        None => load_config()?,
     };
+    usage::init(&app_config.usage);
+    hooks::init(&app_config.hooks);
+    let _remote_parent = telemetry::init(&app_config.telemetry);
+    audit::init(&app_config.audit, &command).exit_status(ExitStatus::Config)?;
+    cache::init(&app_config.cache, cli.no_cache, cli.cache_ttl).exit_status(ExitStatus::Config)?;
 
-    match cli.command {
-        Commands::Config { command } => handle_config(command, &mut app_config).await?,
-        Commands::Chat(args) => run_chat(args, &app_config).await?,
-        Commands::Message(args) => run_message(args, &app_config).await?,
+    // One trace per invocation, with every request it makes nested inside.
+    let span = tracing::info_span!(
+        "command",
+        otel.name = %format!("rustchat-cli {command}"),
+        rustchat.command = %command,
+        otel.status_code = tracing::field::Empty,
+        otel.status_message = tracing::field::Empty,
+    );
+    let outcome = dispatch(cli.command, &mut app_config)
+        .instrument(span.clone())
+        .await;
+    if let Err(err) = &outcome {
+        span.record("otel.status_code", "ERROR");
+        span.record("otel.status_message", format!("{err:#}"));
+    }
+    outcome
+}
+
+async fn dispatch(command: Commands, app_config: &mut AppConfig) -> Result<()> {
+    match command {
+        Commands::Config { command } => handle_config(command, app_config).await?,
+        Commands::Chat(args) => {
+            let (provider, opts) = prepare_chat_session(args, app_config, false).await?;
+            repl::run_chat_repl(provider, opts).await?
+        }
+        Commands::Tui(args) => {
+            let (provider, opts) = prepare_chat_session(args, app_config, true).await?;
+            tui::run_tui(provider, opts).await?
+        }
+        Commands::Message(args) => run_message(args, app_config).await?,
+        Commands::Sessions(args) => sessions::handle_sessions(args, &app_config.sessions)?,
+        Commands::Prompts {
+            command: PromptsCommand::Run(args),
+        } => run_message(prompts::message_for(args)?, app_config).await?,
+        Commands::Prompts { command } => prompts::handle_prompts(command)?,
+        Commands::Compare(args) => compare::run_compare(args, app_config).await?,
+        Commands::Eval(args) => eval::run_eval(args, app_config).await?,
+        Commands::Cmd(args) => cmd::run_cmd(args, app_config).await?,
+        Commands::CommitMsg(args) => commit_msg::run_commit_msg(args, app_config).await?,
+        Commands::Benchmark(args) => benchmark::run_benchmark(args, app_config).await?,
+        Commands::Tokens(args) => token_count::run_tokens(args, app_config).await?,
+        Commands::Batch {
+            command: BatchCommand::Run(args),
+        } => batch::run_batch(args, app_config).await?,
+        Commands::Usage { command } => usage::handle_usage(command, &app_config.usage)?,
+        Commands::Index { command } => knowledge::handle_index(command, app_config).await?,
+        Commands::Serve(args) => serve::run(args, app_config).await?,
+        Commands::Daemon { command } => daemon::run(command, app_config).await?,
+        Commands::Plugins {
+            command: PluginsCommand::List,
+        } => plugin::list()?,
+        Commands::Completions {
+            shell,
+            static_script,
+            bin,
+        } => completions::print_script(shell, static_script, &bin)?,
+        Commands::Man { out_dir } => manpage::write_man_pages(out_dir.as_deref())?,
     }
 
     Ok(())
 }
 
+fn load_config() -> Result<AppConfig> {
+    match AppConfig::load() {
+        Ok(cfg) => Ok(cfg),
+        Err(err) if config::config_is_encrypted() => Err(exit::tag(err, ExitStatus::Config)),
+        Err(err) => {
+            tracing::warn!("failed to load config: {err:#}. Starting with empty config.");
+            Ok(AppConfig::default())
+        }
+    }
+}
+
 async fn handle_config(cmd: ConfigCommand, cfg: &mut AppConfig) -> Result<()> {
     match cmd {
         ConfigCommand::Set(args) => {
+            let existing = cfg.providers.get(&args.provider).filter(|_| !args.replace);
             let kind = args
                 .provider_kind
                 .map(ProviderKind::from)
+                .or_else(|| existing.map(|existing| existing.kind()))
                 .or_else(|| ProviderKind::infer(&args.provider))
                 .ok_or_else(|| anyhow!("unable to infer provider kind - use --kind"))?;
-            let provider_cfg = build_provider_config(kind, &args)?;
+            let provider_cfg = build_provider_config(kind, &args, existing).await?;
             cfg.upsert_provider(args.provider.clone(), provider_cfg);
             if args.default {
                 cfg.default_provider = Some(args.provider.clone());
@@ -58,12 +234,23 @@ async fn handle_config(cmd: ConfigCommand, cfg: &mut AppConfig) -> Result<()> {
             }
             println!("Saved provider '{}'", args.provider);
         }
-        ConfigCommand::Show => {
-            let serialized = toml::to_string_pretty(cfg)?;
+        ConfigCommand::Show { reveal } => {
+            let serialized = if reveal {
+                toml::to_string_pretty(cfg)?
+            } else {
+                toml::to_string_pretty(&cfg.masked())?
+            };
             println!("{serialized}");
         }
         ConfigCommand::Remove { provider } => {
+            let keychain = cfg
+                .providers
+                .get(&provider)
+                .is_some_and(|provider_cfg| provider_cfg.uses_keychain());
             if cfg.remove_provider(&provider) {
+                if keychain {
+                    secrets::delete_from_keychain(&provider).await?;
+                }
                 if cfg.default_provider.as_deref() == Some(provider.as_str()) {
                     cfg.default_provider = None;
                 }
@@ -73,177 +260,791 @@ async fn handle_config(cmd: ConfigCommand, cfg: &mut AppConfig) -> Result<()> {
                 println!("Provider '{provider}' not found");
             }
         }
+        ConfigCommand::Export(args) => {
+            let env_label = args.secret_env.as_deref().unwrap_or(DEFAULT_MASTER_ENV);
+            let passphrase = optional_passphrase_from_env(env_label, args.secret_env.is_some())?;
+            let new_passphrase = match args.reencrypt_env.as_deref() {
+                Some(env) => Some(secrets::require_passphrase_from_env(env)?),
+                None => None,
+            };
+            let handling = match &new_passphrase {
+                Some(new_passphrase) => config::SecretExport::Reencrypt {
+                    passphrase: passphrase.as_deref(),
+                    env_label,
+                    new_passphrase,
+                },
+                None if args.redact_secrets => config::SecretExport::Redact,
+                None => config::SecretExport::Keep,
+            };
+            let serialized = toml::to_string_pretty(&cfg.export(&handling)?)?;
+            match &args.output {
+                Some(path) => {
+                    std::fs::write(path, serialized)
+                        .with_context(|| format!("failed to write {}", path.display()))?;
+                    let _ = config::ensure_permissions(path);
+                    println!("Exported config to {}", path.display());
+                }
+                None => print!("{serialized}"),
+            }
+        }
+        ConfigCommand::Import { path, replace } => {
+            let data = std::fs::read_to_string(&path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            let imported: AppConfig = toml::from_str(&data)
+                .with_context(|| format!("{} is not a rustchat config", path.display()))?;
+            let (providers, profiles) = if replace {
+                let counts = (imported.providers.len(), imported.profiles.len());
+                *cfg = AppConfig {
+                    project: cfg.project.take(),
+                    seal: cfg.seal.take(),
+                    ..imported
+                };
+                counts
+            } else {
+                cfg.import(imported)
+            };
+            cfg.save()?;
+            if let Ok(path) = config::config_path() {
+                let _ = config::ensure_permissions(&path);
+            }
+            println!("Imported {providers} provider(s) and {profiles} profile(s)");
+        }
+        ConfigCommand::RotatePassphrase {
+            secret_env,
+            new_secret_env,
+        } => {
+            let env_label = secret_env.as_deref().unwrap_or(DEFAULT_MASTER_ENV);
+            let passphrase = optional_passphrase_from_env(env_label, secret_env.is_some())?;
+            let passphrase = secrets::prompt_for_passphrase(passphrase, true, true, env_label)?
+                .unwrap_or_default();
+            let new_passphrase = match new_secret_env.as_deref() {
+                Some(env) => secrets::require_passphrase_from_env(env)?,
+                None => secrets::prompt_new_passphrase()?,
+            };
+            let resealed = cfg.sealed_with_passphrase();
+            let rotated = cfg.rotate_passphrase(&passphrase, &new_passphrase)?;
+            if rotated == 0 && !resealed {
+                println!("No encrypted API keys to rotate");
+            } else {
+                cfg.save()?;
+                if rotated > 0 {
+                    println!("Re-encrypted {rotated} API key(s) under the new passphrase");
+                }
+                if resealed {
+                    println!("Re-encrypted the config file under the new passphrase");
+                }
+            }
+        }
+        ConfigCommand::Test {
+            provider,
+            secret_env,
+        } => {
+            let env_label = secret_env.as_deref().unwrap_or(DEFAULT_MASTER_ENV);
+            probe::test_providers(cfg, provider.as_deref(), env_label, secret_env.is_some())
+                .await?;
+        }
+        ConfigCommand::Encrypt { keychain } => {
+            if cfg.seal.is_some() {
+                bail!("the config is already encrypted");
+            }
+            cfg.seal = Some(if keychain {
+                sealed::Seal::new_in_keychain()?
+            } else {
+                let passphrase = optional_passphrase_from_env(DEFAULT_MASTER_ENV, false)?;
+                let passphrase = match passphrase {
+                    Some(passphrase) => passphrase,
+                    None => secrets::prompt_new_passphrase()?,
+                };
+                sealed::Seal::with_passphrase(passphrase)
+            });
+            cfg.save()?;
+            println!("Encrypted the whole config file");
+        }
+        ConfigCommand::Decrypt => {
+            let Some(seal) = cfg.seal.take() else {
+                bail!("the config is not encrypted");
+            };
+            cfg.save()?;
+            seal.forget()?;
+            println!("Stored the config as plain TOML again");
+        }
+        ConfigCommand::Path { json } => {
+            let path = config::config_path()?;
+            if json {
+                println!("{}", serde_json::json!({ "config": path }));
+            } else {
+                println!("{}", path.display());
+            }
+        }
+        ConfigCommand::Dirs { json } => {
+            let paths = config::StatePaths::resolve(cfg);
+            if json {
+                println!("{}", serde_json::to_string_pretty(&paths)?);
+            } else {
+                for (label, path) in paths.rows() {
+                    match path {
+                        Some(path) => println!("{label:<9}{}", path.display()),
+                        None => println!("{label:<9}(unavailable)"),
+                    }
+                }
+            }
+        }
     }
     Ok(())
 }
 
-async fn run_chat(args: ChatCommand, cfg: &AppConfig) -> Result<()> {
-    let provider_name = cfg.infer_default_provider(&args.common.provider)?;
-    let provider_cfg = cfg.require_provider(&provider_name)?;
+async fn prepare_chat_session(
+    mut args: ChatCommand,
+    cfg: &AppConfig,
+    sidebar: bool,
+) -> Result<(provider::DynProvider, repl::ReplOptions)> {
+    apply_overrides(cfg, &mut args.common).exit_status(ExitStatus::Config)?;
+    let needs_store = sidebar
+        || args.continue_session
+        || args
+            .resume
+            .as_deref()
+            .is_some_and(|arg| !std::path::Path::new(arg).is_file());
+    let history = build_history_config(&args.common, cfg, needs_store)?;
+    let attachments = input::attach_files(
+        &args.common.files,
+        args.common.file_budget,
+        args.common.model.as_deref().unwrap_or_default(),
+    )?;
+    let raw = output::configure(&args.common, false);
+    if history.auto_save_request_failed {
+        tracing::warn!("auto-save requested but no history directory is available");
+    }
+    let mut resumed = match args.resume.as_deref() {
+        Some(arg) => Some(sessions::resume(history.store.as_ref(), arg)?),
+        None => None,
+    };
+    // A resumed session keeps its provider unless --provider says otherwise.
+    let resumed_provider = resumed
+        .as_ref()
+        .and_then(|session| session.transcript.meta.provider.clone())
+        .filter(|name| args.common.provider.is_none() && cfg.providers.contains_key(name));
+    let provider_name = match resumed_provider {
+        Some(name) => name,
+        None => cfg
+            .infer_default_provider(&args.common.provider)
+            .exit_status(ExitStatus::Config)?,
+    };
+    if args.continue_session {
+        resumed = Some(sessions::resume_latest(
+            history.store.as_ref(),
+            &provider_name,
+        )?);
+    }
+    let provider_cfg = cfg
+        .require_provider(&provider_name)
+        .exit_status(ExitStatus::Config)?;
     let env_label = args
         .common
         .secret_env
         .as_deref()
         .unwrap_or(DEFAULT_MASTER_ENV);
-    let passphrase =
-        optional_passphrase_from_env(env_label, args.common.secret_env.is_some())?;
+    let passphrase = optional_passphrase_from_env(env_label, args.common.secret_env.is_some())?;
+    let passphrase = secrets::prompt_for_passphrase(
+        passphrase,
+        provider_cfg.has_encrypted_secret() && !vcr::replaying(),
+        !args.common.no_prompt,
+        env_label,
+    )?;
     let provider = build_provider(
         &provider_name,
         provider_cfg,
         passphrase.as_deref(),
         env_label,
     )
-    .await?;
+    .await
+    .exit_status(ExitStatus::Config)?;
+    let resumed_meta = resumed
+        .as_ref()
+        .map(|session| &session.transcript.meta)
+        .filter(|meta| meta.provider.as_deref() == Some(provider_name.as_str()));
     let model = args
         .common
         .model
         .clone()
+        .or_else(|| resumed_meta.and_then(|meta| meta.model.clone()))
         .or_else(|| provider_cfg.default_model().map(|m| m.to_string()))
         .unwrap_or_else(|| "gemini-pro".to_string());
+    let knowledge = match args.knowledge.as_deref() {
+        Some(name) => Some(
+            knowledge::Knowledge::open(
+                name,
+                args.top_k,
+                cfg,
+                (&provider_name, &provider),
+                args.common.secret_env.as_deref(),
+                !args.common.no_prompt,
+            )
+            .await?,
+        ),
+        None => None,
+    };
+    let approval = tools::session_approval(&cfg.tools, &args.tool_approval);
+    let tools = tools::ToolRegistry::new(&args.tools, approval);
     let request_options = ChatRequestOptions {
         temperature: args.common.temperature,
         max_output_tokens: args.common.max_output_tokens,
+        tools: tools.specs(),
+        ..Default::default()
+    };
+    let loaded = match args.load {
+        Some(path) => {
+            let transcript = history_logger::load_history(&path)
+                .with_context(|| format!("cannot load {}", path.display()))?;
+            Some((path, transcript))
+        }
+        None => None,
+    };
+    // Saved transcripts keep the system prompt they were rendered with.
+    let vars = template::Vars::parse(&args.common.vars)?;
+    let default_system = provider_cfg
+        .default_system()
+        .map(|system| template::render(system, &vars))
+        .transpose()?;
+    let system = match &args.common.system {
+        Some(system) => Some(template::render(system, &vars)?),
+        None => resumed
+            .as_ref()
+            .map(|session| &session.transcript)
+            .or(loaded.as_ref().map(|(_, transcript)| transcript))
+            .and_then(|transcript| transcript.system.clone())
+            .or(default_system),
     };
-    let history = build_history_config(&args.common);
-    if history.auto_save_request_failed {
-        eprintln!("[warn] auto-save requested but no history directory is available");
-    }
 
-    repl::run_chat_repl(
+    Ok((
         provider,
         repl::ReplOptions {
             provider_name,
             model,
-            system: args.common.system.clone(),
-            save_path: history.explicit_path.clone(),
-            history_dir: history.history_dir.clone(),
+            system,
+            save_target: history.explicit.clone(),
+            store: history.store.clone(),
             auto_save: history.auto_save,
             save_format: history.format,
-            webhook_url: args.common.webhook_url.clone(),
+            retention: history.retention,
+            remote: history.remote,
+            webhook: history.webhook,
+            tee: args
+                .common
+                .tee
+                .as_deref()
+                .map(|path| tee::Tee::create(path, args.common.tee_prompt))
+                .transpose()?,
             request_options,
+            context: cfg.context.clone(),
             stream: args.stream,
+            render_markdown: !args.common.plain && !raw,
+            raw,
+            stats: args.common.stats || cfg.ui.stats,
+            ui: cfg.ui.clone(),
+            tools,
+            knowledge,
+            notifier: notify::ReplyNotifier::new(&cfg.ui, args.common.notify_after),
+            resumed,
+            loaded,
+            title: args.common.title.clone(),
+            tags: args.common.tags.clone(),
+            attachments,
         },
-    )
-    .await
+    ))
 }
 
-async fn run_message(args: MessageCommand, cfg: &AppConfig) -> Result<()> {
-    let provider_name = cfg.infer_default_provider(&args.common.provider)?;
-    let provider_cfg = cfg.require_provider(&provider_name)?;
-    let env_label = args
+async fn run_message(mut args: MessageCommand, cfg: &AppConfig) -> Result<()> {
+    apply_overrides(cfg, &mut args.common).exit_status(ExitStatus::Config)?;
+    let conversation = args.history.as_deref().map(load_conversation).transpose()?;
+    // A continued conversation keeps its provider and model unless overridden.
+    let history_meta = conversation
+        .as_ref()
+        .map(|transcript| &transcript.meta)
+        .filter(|meta| {
+            args.common.provider.is_none()
+                && meta
+                    .provider
+                    .as_ref()
+                    .is_some_and(|name| cfg.providers.contains_key(name))
+        });
+    let provider_name = match history_meta.and_then(|meta| meta.provider.clone()) {
+        Some(name) => name,
+        None => cfg
+            .infer_default_provider(&args.common.provider)
+            .exit_status(ExitStatus::Config)?,
+    };
+    let provider_cfg = cfg
+        .require_provider(&provider_name)
+        .exit_status(ExitStatus::Config)?;
+    let history_model = conversation
+        .as_ref()
+        .map(|transcript| &transcript.meta)
+        .filter(|meta| meta.provider.as_deref() == Some(provider_name.as_str()))
+        .and_then(|meta| meta.model.clone());
+    // A continued conversation keeps the system prompt it was rendered with.
+    let system_from_history = conversation
+        .as_ref()
+        .filter(|_| args.common.system.is_none())
+        .and_then(|transcript| transcript.system.clone());
+    if args.common.system.is_none() {
+        args.common.system = provider_cfg.default_system().map(str::to_string);
+    }
+    // Piped stdin fills {{stdin}} when a template asks for it, and is
+    // otherwise appended to the prompt as context.
+    let mut stdin =
+        input::read_piped_stdin()?.map(|text| input::filter_stdin(text, &args.stdin_filters));
+    let mut vars = template::Vars::parse(&args.common.vars)?;
+    let wants_stdin = args
+        .prompt
+        .iter()
+        .chain(args.common.system.as_ref())
+        .any(|text| template::mentions(text, "stdin"));
+    if wants_stdin {
+        vars = vars.with_stdin(stdin.take());
+    }
+    args.common.system = args
         .common
-        .secret_env
-        .as_deref()
-        .unwrap_or(DEFAULT_MASTER_ENV);
-    let passphrase =
-        optional_passphrase_from_env(env_label, args.common.secret_env.is_some())?;
-    let provider = build_provider(
-        &provider_name,
-        provider_cfg,
-        passphrase.as_deref(),
-        env_label,
-    )
-    .await?;
+        .system
+        .map(|system| template::render(&system, &vars))
+        .transpose()?;
+    if system_from_history.is_some() {
+        args.common.system = system_from_history;
+    }
+    let prompt_words = args
+        .prompt
+        .iter()
+        .map(|word| template::render(word, &vars))
+        .collect::<Result<Vec<_>>>()?;
+    // A running daemon already holds the provider set up, key decrypted.
+    let provider = match daemon::provider(&provider_name).filter(|_| !args.dry_run) {
+        Some(provider) => provider,
+        None => {
+            let env_label = args
+                .common
+                .secret_env
+                .as_deref()
+                .unwrap_or(DEFAULT_MASTER_ENV);
+            let passphrase =
+                optional_passphrase_from_env(env_label, args.common.secret_env.is_some())?;
+            let passphrase = secrets::prompt_for_passphrase(
+                passphrase,
+                provider_cfg.has_encrypted_secret() && !vcr::replaying(),
+                !args.common.no_prompt,
+                env_label,
+            )?;
+            build_provider(
+                &provider_name,
+                provider_cfg,
+                passphrase.as_deref(),
+                env_label,
+            )
+            .await
+            .exit_status(ExitStatus::Config)?
+        }
+    };
     let model = args
         .common
         .model
         .clone()
+        .or(history_model)
         .or_else(|| provider_cfg.default_model().map(|m| m.to_string()))
         .unwrap_or_else(|| "gemini-pro".to_string());
     let request_options = ChatRequestOptions {
         temperature: args.common.temperature,
         max_output_tokens: args.common.max_output_tokens,
+        ..Default::default()
     };
-    let prompt = args.prompt.join(" ");
-    let mut messages = vec![ChatMessage::user(prompt.clone())];
-    let response = provider
-        .chat(
+    // Checked before the request so a refused --save target costs no tokens.
+    let history = build_history_config(&args.common, cfg, false)?;
+    let files = input::attach_files(&args.common.files, args.common.file_budget, &model)?;
+    let prompt = input::compose_prompt(&prompt_words, stdin)?;
+    let typed = prompt.clone();
+    let prompt = input::with_context(files.as_deref(), prompt);
+    let mut messages = conversation
+        .as_ref()
+        .map(|transcript| transcript.messages.clone())
+        .unwrap_or_default();
+    messages.push(ChatMessage::user(prompt.clone()));
+    if args.cost.estimate_cost || args.cost.max_cost.is_some() {
+        check_cost(
+            &args.cost,
+            &model,
+            args.common.system.as_deref(),
+            &messages,
+            args.common.max_output_tokens,
+        )?;
+    }
+    if args.dry_run {
+        let preview = provider.preview_chat(
             &model,
             args.common.system.as_deref(),
             &messages,
             &request_options,
-        )
-        .await?;
-    println!("{response}");
-    messages.push(ChatMessage::assistant(response.clone()));
+        )?;
+        match args.output {
+            OutputArg::Json => println!("{}", serde_json::to_string_pretty(&preview)?),
+            OutputArg::Text => println!("{preview}"),
+        }
+        return Ok(());
+    }
+    let tee = args
+        .common
+        .tee
+        .as_deref()
+        .map(|path| tee::Tee::create(path, args.common.tee_prompt))
+        .transpose()?;
+    if let Some(tee) = &tee {
+        tee.prompt(&typed);
+    }
+    let started = std::time::Instant::now();
+    let policy = retry::RetryPolicy {
+        retries: args.retry.retries,
+        backoff: std::time::Duration::from_millis(args.retry.retry_backoff_ms),
+    };
+    let json = args.output == OutputArg::Json;
+    let raw = output::configure(&args.common, json);
+    let renderer = render::MarkdownRenderer::new(!args.common.plain && !raw);
+    let system = args.common.system.as_deref();
+    let cancel = cancel::on_ctrl_c();
+    let (response, attempts) = if !args.stream || json {
+        policy
+            .chat(
+                &provider,
+                &model,
+                system,
+                &messages,
+                &request_options,
+                &cancel,
+            )
+            .await
+    } else {
+        match policy
+            .stream_chat(
+                &provider,
+                &model,
+                system,
+                &messages,
+                &request_options,
+                &cancel,
+            )
+            .await
+        {
+            (Ok(stream), attempts) => {
+                let wrap = !raw && cfg.ui.wrap != Some(false);
+                (
+                    print_streamed(stream, &renderer, wrap, tee.as_ref()).await,
+                    attempts,
+                )
+            }
+            (Err(err), attempts) => (Err(err), attempts),
+        }
+    };
+    let response = match response {
+        Ok(response) => response,
+        Err(err) => {
+            let err = if attempts > 1 {
+                err.context(format!("gave up after {attempts} attempts"))
+            } else {
+                err
+            };
+            if json {
+                let report = output::FailureReport {
+                    provider: &provider_name,
+                    model: &model,
+                    error: format!("{err:#}"),
+                    exit_code: exit::status_for(&err) as u8,
+                    attempts,
+                };
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            }
+            return Err(err);
+        }
+    };
+    let latency = started.elapsed();
+    if json {
+        let report = output::ReplyReport::new(&provider_name, &model, &response, latency);
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else if !args.stream && args.out.is_none() {
+        renderer.print_reply("", &response.text);
+    }
+    if let Some(path) = &args.out {
+        output::write_reply(path, &response.text, args.append)?;
+        output::status(&format!("[wrote the reply to {}]", path.display()));
+    }
+    if let Some(tee) = tee.as_ref().filter(|_| !args.stream || json) {
+        tee.reply(&response.text);
+        tee.end_reply();
+    }
+    notify::ReplyNotifier::new(&cfg.ui, args.common.notify_after).reply_finished(
+        &model,
+        started.elapsed(),
+        &response.text,
+    );
+    if args.common.stats || cfg.ui.stats {
+        stats::TurnStats {
+            model: &model,
+            elapsed: started.elapsed(),
+            usage: response.usage,
+        }
+        .print();
+    }
+    if args.cost.estimate_cost {
+        match response
+            .usage
+            .and_then(|usage| Some((usage, pricing::estimate_cost(&model, &usage)?)))
+        {
+            Some((usage, cost)) => eprintln!(
+                "actual cost: {} ({} in / {} out tokens)",
+                pricing::format_cost(cost),
+                usage.input_tokens,
+                usage.output_tokens
+            ),
+            None => eprintln!("actual cost: unknown ({} reported no usage)", provider_name),
+        }
+    }
+    let cut_off = output::cut_off(&response);
+    messages.push(ChatMessage::assistant(response.text).with_reply(ReplyMeta {
+        provider: provider_name.clone(),
+        model: model.clone(),
+        usage: response.usage,
+        latency,
+    }));
 
-    let history = build_history_config(&args.common);
-    if let Some(path) = history.resolve_path(&provider_name) {
+    let meta = history_logger::SessionMeta {
+        title: args.common.title.clone(),
+        tags: args.common.tags.clone(),
+        ..history_logger::SessionMeta::new(&provider_name, &model)
+    };
+    if let (Some(path), Some(transcript)) = (&args.history, conversation) {
+        let meta = history_logger::SessionMeta {
+            provider: meta.provider.clone(),
+            model: meta.model.clone(),
+            created_at: transcript.meta.created_at.or(meta.created_at),
+            title: meta.title.clone().or(transcript.meta.title),
+            tags: if meta.tags.is_empty() {
+                transcript.meta.tags
+            } else {
+                meta.tags.clone()
+            },
+        };
         history_logger::save_history(
-            &path,
-            history.format,
+            path,
+            HistoryFormat::for_path(path),
+            &meta,
             args.common.system.as_deref(),
             &messages,
         )?;
-        println!("[saved chat history to {}]", path.display());
+        output::status(&format!("[saved chat history to {}]", path.display()));
+    }
+    let target = history.resolve_target(&provider_name);
+    if let Some(target) = &target {
+        target.save(&meta, args.common.system.as_deref(), &messages)?;
+        output::status(&format!("[saved chat history to {}]", target.describe()));
+        target.apply_retention(&history.retention);
     } else if history.auto_save_request_failed {
-        eprintln!("[warn] auto-save requested but no history directory is available");
+        tracing::warn!("auto-save requested but no history directory is available");
     }
+    remote::upload_finished(
+        history.remote.as_ref(),
+        &repl::upload_name(target.as_ref(), &provider_name),
+        history.format,
+        &meta,
+        args.common.system.as_deref(),
+        &messages,
+    )
+    .await;
 
-    if let Some(url) = args.common.webhook_url.as_deref() {
-        if let Err(err) = history_logger::send_history_webhook(
-            url,
-            history.format,
-            args.common.system.as_deref(),
-            &messages,
-        )
-        .await
-        {
-            eprintln!("[warn] failed to POST chat history: {err:#}");
-        } else {
-            println!("[pushed chat history to webhook]");
-        }
+    webhook::post_finished(
+        history.webhook.as_ref(),
+        history.format,
+        &meta,
+        args.common.system.as_deref(),
+        &messages,
+    )
+    .await;
+
+    if cut_off && args.out.is_some() {
+        return Err(anyhow!(
+            "the reply stopped at the output token limit; raise --max-tokens for the rest"
+        ))
+        .exit_status(ExitStatus::Incomplete);
     }
+    Ok(())
+}
 
+/// Prints the `--estimate-cost` estimate and enforces `--max-cost` before
+/// anything is sent.
+fn check_cost(
+    args: &cli::CostArgs,
+    model: &str,
+    system: Option<&str>,
+    messages: &[ChatMessage],
+    max_output_tokens: Option<u32>,
+) -> Result<()> {
+    let input_tokens = tokens::Tokenizer::for_model(model).count_chat(system, messages);
+    let estimate = pricing::estimate_request(model, input_tokens as u32, max_output_tokens);
+    if args.estimate_cost {
+        match &estimate {
+            Some(estimate) => eprintln!("estimated cost: {}", estimate.describe()),
+            None => eprintln!("estimated cost: unknown (no bundled price for '{model}')"),
+        }
+    }
+    if let Some(cap) = args.max_cost {
+        let Some(estimate) = estimate else {
+            bail!("no bundled price for '{model}', so --max-cost cannot be checked");
+        };
+        if estimate.cost > cap {
+            bail!(
+                "estimated cost {} exceeds --max-cost {}; nothing was sent",
+                pricing::format_cost(estimate.cost),
+                pricing::format_cost(cap)
+            );
+        }
+    }
     Ok(())
 }
 
+/// Prints a reply as it streams in, then swaps the raw text for its rendered
+/// form.
+async fn print_streamed(
+    mut stream: streaming::ChatStream,
+    renderer: &render::MarkdownRenderer,
+    wrap: bool,
+    tee: Option<&tee::Tee>,
+) -> Result<provider::ChatResponse> {
+    let mut printer = render::StreamPrinter::start("", wrap);
+    let mut usage: Option<provider::TokenUsage> = None;
+    let mut finish_reason = None;
+    while let Some(event) = stream.next().await {
+        match event {
+            Ok(streaming::StreamEvent::Text(token)) => {
+                printer.push(&token);
+                if let Some(tee) = tee {
+                    tee.reply(&token);
+                }
+            }
+            Ok(streaming::StreamEvent::Usage(reported)) => {
+                usage
+                    .get_or_insert_with(provider::TokenUsage::default)
+                    .absorb(reported);
+            }
+            Ok(streaming::StreamEvent::Finish(reason)) => finish_reason = Some(reason),
+            Err(err) => {
+                printer.finish();
+                println!();
+                if let Some(tee) = tee {
+                    tee.end_reply();
+                }
+                return Err(err.context("the reply stream broke off"));
+            }
+        }
+    }
+    printer.finish();
+    println!();
+    if let Some(tee) = tee {
+        tee.end_reply();
+    }
+    renderer.rerender_streamed(&printer);
+    Ok(provider::ChatResponse {
+        text: printer.into_text(),
+        usage,
+        finish_reason,
+        ..Default::default()
+    })
+}
+
+/// The `--history` transcript, or an empty one when the file does not exist
+/// yet.
+fn load_conversation(path: &std::path::Path) -> Result<history_logger::Transcript> {
+    if !path.exists() {
+        return Ok(history_logger::Transcript {
+            meta: history_logger::SessionMeta::default(),
+            system: None,
+            messages: Vec::new(),
+        });
+    }
+    history_logger::load_history(path).with_context(|| format!("cannot load {}", path.display()))
+}
+
 struct HistoryConfig {
-    explicit_path: Option<std::path::PathBuf>,
-    history_dir: Option<std::path::PathBuf>,
+    /// The `--save` file, if any
+    explicit: Option<store::SaveTarget>,
+    store: Option<store::DynStore>,
     auto_save: bool,
     format: HistoryFormat,
     auto_save_request_failed: bool,
+    retention: store::RetentionPolicy,
+    remote: Option<remote::RemoteSink>,
+    webhook: Option<webhook::Webhook>,
 }
 
 impl HistoryConfig {
-    fn resolve_path(&self, provider_name: &str) -> Option<std::path::PathBuf> {
-        if let Some(path) = &self.explicit_path {
-            return Some(path.clone());
+    fn resolve_target(&self, provider_name: &str) -> Option<store::SaveTarget> {
+        if let Some(target) = &self.explicit {
+            return Some(target.clone());
         }
         if self.auto_save {
-            if let Some(dir) = &self.history_dir {
-                return Some(history_logger::timestamped_history_path(
-                    dir,
-                    provider_name,
-                    self.format,
-                ));
+            if let Some(store) = &self.store {
+                return Some(store::SaveTarget::Session {
+                    store: std::sync::Arc::clone(store),
+                    id: history_logger::timestamped_session_id(provider_name),
+                });
             }
         }
         None
     }
 }
 
-fn build_history_config(args: &CommonChatArgs) -> HistoryConfig {
-    let format = match args.save_format {
-        SaveFormatArg::Json => HistoryFormat::Json,
-        SaveFormatArg::Markdown => HistoryFormat::Markdown,
+/// The session store is only opened for auto-save or when `needs_store` says
+/// the caller will browse or resume sessions.
+fn build_history_config(
+    args: &CommonChatArgs,
+    cfg: &AppConfig,
+    needs_store: bool,
+) -> Result<HistoryConfig> {
+    let format = HistoryFormat::from(args.save_format);
+    let store = if args.auto_save || needs_store {
+        let history_dir = args
+            .history_dir
+            .clone()
+            .or_else(history_logger::default_history_dir);
+        store::open_store(
+            &cfg.sessions,
+            history_dir,
+            format,
+            args.compress.map(Compression::from),
+        )?
+    } else {
+        None
     };
-    let history_dir = args
-        .history_dir
-        .clone()
-        .or_else(|| history_logger::default_history_dir());
     let mut auto_save = args.auto_save;
     let mut auto_save_request_failed = false;
-    if auto_save && history_dir.is_none() {
+    if auto_save && store.is_none() {
         auto_save = false;
         auto_save_request_failed = true;
     }
-    HistoryConfig {
-        explicit_path: args.save_path.clone(),
-        history_dir,
+    Ok(HistoryConfig {
+        explicit: args
+            .save_path
+            .clone()
+            .map(|path| {
+                store::SaveTarget::file(
+                    path,
+                    format,
+                    args.save_mode,
+                    args.force,
+                    args.compress.map(Compression::from),
+                )
+            })
+            .transpose()?,
+        store,
         auto_save,
         format,
         auto_save_request_failed,
-    }
+        retention: store::RetentionPolicy::from_config(&cfg.sessions.retention)
+            .context("invalid [sessions.retention] settings")?,
+        remote: cfg.sessions.remote.clone().map(remote::RemoteSink::new),
+        webhook: webhook::resolve(args, cfg)?,
+    })
 }
@@ -0,0 +1,43 @@
+use std::io;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use clap::CommandFactory;
+
+use crate::cli::Cli;
+
+/// Prints the top-level man page, or with `out_dir` writes `rustchat-cli.1`
+/// plus one page per subcommand (`rustchat-cli-config-set.1`, ...) there.
+pub fn write_man_pages(out_dir: Option<&Path>) -> Result<()> {
+    let Some(dir) = out_dir else {
+        clap_mangen::Man::new(Cli::command()).render(&mut io::stdout())?;
+        return Ok(());
+    };
+    std::fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    clap_mangen::generate_to(Cli::command(), dir)
+        .with_context(|| format!("failed to write man pages to {}", dir.display()))?;
+    eprintln!("Wrote man pages to {}", dir.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages_are_written_for_nested_subcommands() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        write_man_pages(Some(&dir)).unwrap();
+        for page in [
+            "rustchat-cli.1",
+            "rustchat-cli-chat.1",
+            "rustchat-cli-config-set.1",
+        ] {
+            assert!(dir.join(page).is_file(), "missing {page}");
+        }
+        let page = std::fs::read_to_string(dir.join("rustchat-cli-message.1")).unwrap();
+        assert!(page.starts_with(".ie \\n(.g"));
+        assert!(page.contains("\\-\\-output"));
+    }
+}
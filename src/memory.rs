@@ -0,0 +1,140 @@
+use anyhow::{bail, Result};
+
+use crate::config::ContextConfig;
+use crate::context;
+use crate::provider::{CancellationToken, ChatMessage, ChatRequestOptions, DynProvider};
+use crate::sessions;
+use crate::tokens;
+
+const SYSTEM_PROMPT: &str = "You condense chat transcripts into a memory for the assistant that \
+continues the conversation. Reply with the memory only: short bullet points covering the user's \
+goals, facts and preferences they stated, decisions reached, open questions, and any names, \
+numbers, file paths or code the rest of the conversation may rely on. Fold in the earlier memory \
+if one is given. No preamble, no commentary.";
+/// Share of the context window the history may fill before it is summarized.
+const DEFAULT_SUMMARIZE_AT: f64 = 0.75;
+
+/// Older turns folded into a summary: sent in place of `messages[..covers]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Memory {
+    pub text: String,
+    /// How many leading messages the summary stands for
+    pub covers: usize,
+}
+
+impl Memory {
+    /// The system prompt with the memory appended, as sent to the model.
+    pub fn system_prompt(&self, system: Option<&str>) -> String {
+        let memory = format!("Summary of the earlier conversation:\n{}", self.text.trim());
+        match system {
+            Some(system) => format!("{system}\n\n{memory}"),
+            None => memory,
+        }
+    }
+}
+
+/// Where to cut when the history has grown past `[context] summarize_at` of
+/// the window: everything before the last `keep_last` messages, from a user
+/// message on. `None` when no summary is due.
+pub fn due(
+    cfg: &ContextConfig,
+    model: &str,
+    system: Option<&str>,
+    memory: Option<&Memory>,
+    messages: &[ChatMessage],
+) -> Option<usize> {
+    if !cfg.summarize {
+        return None;
+    }
+    let limit = context::limit(cfg, model)?;
+    let covers = memory.map_or(0, |memory| memory.covers);
+    let memory_tokens = memory.map_or(0, |memory| tokens::count(model, &memory.text));
+    let tokens = context::history_tokens(model, system, &messages[covers..]) + memory_tokens;
+    let threshold = cfg
+        .summarize_at
+        .unwrap_or(DEFAULT_SUMMARIZE_AT)
+        .clamp(0.0, 1.0);
+    if (tokens as f64) < limit as f64 * threshold {
+        return None;
+    }
+    let keep_last = cfg.keep_last.unwrap_or(context::DEFAULT_KEEP_LAST).max(1);
+    let cut = context::turn_start(messages, messages.len().saturating_sub(keep_last));
+    (cut > covers).then_some(cut)
+}
+
+/// Asks `model` to fold `messages[..cut]` (and the memory so far) into a new
+/// memory.
+pub async fn summarize(
+    provider: &DynProvider,
+    model: &str,
+    memory: Option<&Memory>,
+    messages: &[ChatMessage],
+    cut: usize,
+    cancel: &CancellationToken,
+) -> Result<Memory> {
+    let covers = memory.map_or(0, |memory| memory.covers);
+    let mut prompt = String::new();
+    if let Some(memory) = memory {
+        prompt.push_str(&format!("Earlier memory:\n{}\n\n", memory.text.trim()));
+    }
+    prompt.push_str("Transcript:\n");
+    prompt.push_str(&sessions::format_transcript(
+        None,
+        &messages[covers..cut],
+        false,
+    ));
+    let response = provider
+        .chat(
+            model,
+            Some(SYSTEM_PROMPT),
+            &[ChatMessage::user(prompt)],
+            &ChatRequestOptions::default(),
+            cancel,
+        )
+        .await?;
+    let text = response.text.trim();
+    if text.is_empty() {
+        bail!("{model} returned an empty summary");
+    }
+    Ok(Memory {
+        text: text.to_string(),
+        covers: cut,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summaries_are_due_near_the_window() {
+        let mut messages = Vec::new();
+        for turn in 0..10 {
+            messages.push(ChatMessage::user(format!(
+                "question {turn} {}",
+                "q".repeat(200)
+            )));
+            messages.push(ChatMessage::assistant("a".repeat(200)));
+        }
+        let mut cfg = ContextConfig {
+            keep_last: Some(4),
+            max_tokens: Some(2_000),
+            ..ContextConfig::default()
+        };
+        assert_eq!(due(&cfg, "gpt-4o", None, None, &messages), None);
+
+        // About 1,000 tokens of history is over half of the 2,000 budget.
+        cfg.summarize = true;
+        cfg.summarize_at = Some(0.5);
+        assert_eq!(due(&cfg, "gpt-4o", None, None, &messages), Some(16));
+        let memory = Memory {
+            text: "- asked ten questions".to_string(),
+            covers: 16,
+        };
+        assert_eq!(due(&cfg, "gpt-4o", None, Some(&memory), &messages), None);
+        assert_eq!(
+            memory.system_prompt(Some("Be brief.")),
+            "Be brief.\n\nSummary of the earlier conversation:\n- asked ten questions"
+        );
+    }
+}
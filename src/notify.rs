@@ -0,0 +1,108 @@
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::Duration;
+
+use crate::config::{NotifyMethod, UiConfig};
+
+const FOCUS_UNKNOWN: u8 = 0;
+const FOCUS_IN: u8 = 1;
+const FOCUS_OUT: u8 = 2;
+
+/// The terminal's focus, once something that reads its events (the TUI, with
+/// focus reporting on) has heard about it.
+static FOCUS: AtomicU8 = AtomicU8::new(FOCUS_UNKNOWN);
+
+/// Records a focus change reported by the terminal.
+pub fn focus_changed(focused: bool) {
+    FOCUS.store(
+        if focused { FOCUS_IN } else { FOCUS_OUT },
+        Ordering::Relaxed,
+    );
+}
+
+/// Decides whether a finished reply warrants a notification.
+///
+/// Where the terminal reports focus, a reply notifies exactly when it finished
+/// while the terminal was in the background. Elsewhere (the line REPL and
+/// `message`, whose input is not read as terminal events, or terminals that
+/// never report it) the elapsed-time threshold stands in: anything slower than
+/// it is assumed to have been tabbed away from.
+#[derive(Clone, Copy, Debug)]
+pub struct ReplyNotifier {
+    threshold: Option<Duration>,
+    method: NotifyMethod,
+}
+
+impl ReplyNotifier {
+    pub fn new(ui: &UiConfig, override_secs: Option<u64>) -> Self {
+        Self {
+            threshold: override_secs
+                .or(ui.notify_after_secs)
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            method: ui.notify_method,
+        }
+    }
+
+    pub fn reply_finished(&self, model: &str, elapsed: Duration, preview: &str) {
+        if !self.should_notify(elapsed, FOCUS.load(Ordering::Relaxed)) {
+            return;
+        }
+        if matches!(self.method, NotifyMethod::Bell | NotifyMethod::Both)
+            && io::stderr().is_terminal()
+        {
+            eprint!("\x07");
+            io::stderr().flush().ok();
+        }
+        if matches!(self.method, NotifyMethod::Desktop | NotifyMethod::Both) {
+            let body: String = preview.chars().take(200).collect();
+            let summary = format!("{model} replied after {:.0}s", elapsed.as_secs_f64());
+            if let Err(err) = notify_rust::Notification::new()
+                .appname("rustchat-cli")
+                .summary(&summary)
+                .body(&body)
+                .show()
+            {
+                tracing::warn!("desktop notification failed: {err}");
+            }
+        }
+    }
+
+    /// Setting a threshold turns notifications on; a known focus then
+    /// decides in its place.
+    fn should_notify(&self, elapsed: Duration, focus: u8) -> bool {
+        let Some(threshold) = self.threshold else {
+            return false;
+        };
+        match focus {
+            FOCUS_IN => false,
+            FOCUS_OUT => true,
+            _ => elapsed >= threshold,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn focus_decides_when_the_terminal_reports_it() {
+        let notifier = ReplyNotifier {
+            threshold: Some(Duration::from_secs(20)),
+            method: NotifyMethod::Bell,
+        };
+        let quick = Duration::from_secs(2);
+        let slow = Duration::from_secs(60);
+        assert!(!notifier.should_notify(quick, FOCUS_UNKNOWN));
+        assert!(notifier.should_notify(slow, FOCUS_UNKNOWN));
+        assert!(notifier.should_notify(quick, FOCUS_OUT));
+        assert!(!notifier.should_notify(slow, FOCUS_IN));
+
+        let off = ReplyNotifier {
+            threshold: None,
+            method: NotifyMethod::Bell,
+        };
+        assert!(!off.should_notify(slow, FOCUS_OUT));
+    }
+}
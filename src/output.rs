@@ -0,0 +1,188 @@
+use std::fs::OpenOptions;
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::cli::CommonChatArgs;
+use crate::pricing;
+use crate::provider::{ChatResponse, Citation, TokenUsage};
+
+/// Where bracketed status lines such as `[saved chat history to ...]` go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum StatusLines {
+    Stdout,
+    /// Keeps stdout to the reply alone when it is piped or JSON
+    Stderr,
+    /// `--quiet` / `--raw`
+    Hidden,
+}
+
+static STATUS_LINES: AtomicU8 = AtomicU8::new(StatusLines::Stdout as u8);
+
+/// Decides how `chat`/`message` decorate their output and returns whether
+/// replies print raw (no markdown, no `bot>` label): with `--raw`, or when
+/// stdout is not a terminal.
+pub fn configure(args: &CommonChatArgs, json: bool) -> bool {
+    let raw = args.raw || !io::stdout().is_terminal();
+    let status = if args.quiet || args.raw {
+        StatusLines::Hidden
+    } else if raw || json {
+        StatusLines::Stderr
+    } else {
+        StatusLines::Stdout
+    };
+    STATUS_LINES.store(status as u8, Ordering::Relaxed);
+    raw
+}
+
+/// Prints a status line wherever [`configure`] sent them.
+pub fn status(line: &str) {
+    match STATUS_LINES.load(Ordering::Relaxed) {
+        value if value == StatusLines::Stdout as u8 => println!("{line}"),
+        value if value == StatusLines::Stderr as u8 => eprintln!("{line}"),
+        _ => {}
+    }
+}
+
+/// The `message --output json` object: the reply plus what scripts usually
+/// want to know about it.
+#[derive(Debug, Serialize)]
+pub struct ReplyReport<'a> {
+    pub provider: &'a str,
+    pub model: &'a str,
+    pub text: &'a str,
+    pub finish_reason: Option<&'a str>,
+    pub usage: Option<TokenUsage>,
+    /// Estimated from the built-in price table; null for unknown models
+    pub cost_usd: Option<f64>,
+    pub latency_ms: u128,
+    pub citations: &'a [Citation],
+}
+
+impl<'a> ReplyReport<'a> {
+    pub fn new(
+        provider: &'a str,
+        model: &'a str,
+        response: &'a ChatResponse,
+        latency: Duration,
+    ) -> Self {
+        Self {
+            provider,
+            model,
+            text: &response.text,
+            finish_reason: response.finish_reason.as_deref(),
+            usage: response.usage,
+            cost_usd: response
+                .usage
+                .and_then(|usage| pricing::estimate_cost(model, &usage)),
+            latency_ms: latency.as_millis(),
+            citations: &response.citations,
+        }
+    }
+}
+
+/// What `message --output json` prints instead when the request failed, so
+/// scripts can tell the failure apart without parsing stderr.
+#[derive(Debug, Serialize)]
+pub struct FailureReport<'a> {
+    pub provider: &'a str,
+    pub model: &'a str,
+    pub error: String,
+    /// The code the process exits with
+    pub exit_code: u8,
+    pub attempts: u32,
+}
+
+/// Writes a reply to the `message --out` file, ending it with a newline.
+/// Appended replies are set off from what the file held by a blank line.
+pub fn write_reply(path: &Path, text: &str, append: bool) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)
+        .with_context(|| format!("failed to open --out file {}", path.display()))?;
+    let mut reply = String::new();
+    if append && file.metadata().is_ok_and(|meta| meta.len() > 0) {
+        reply.push('\n');
+    }
+    reply.push_str(text.trim_end_matches('\n'));
+    reply.push('\n');
+    file.write_all(reply.as_bytes())
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// Whether the provider stopped the reply at the output token limit.
+pub fn cut_off(response: &ChatResponse) -> bool {
+    response.finish_reason.as_deref().is_some_and(|reason| {
+        reason.eq_ignore_ascii_case("length") || reason.eq_ignore_ascii_case("max_tokens")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_report_carries_usage_and_citations() {
+        let response = ChatResponse {
+            text: "Paris.".to_string(),
+            usage: Some(TokenUsage {
+                input_tokens: 12,
+                output_tokens: 3,
+            }),
+            finish_reason: Some("stop".to_string()),
+            citations: vec![Citation {
+                url: Some("https://en.wikipedia.org/wiki/Paris".to_string()),
+                title: None,
+            }],
+            ..Default::default()
+        };
+        let report = ReplyReport::new(
+            "openai",
+            "made-up-model",
+            &response,
+            Duration::from_millis(840),
+        );
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["text"], "Paris.");
+        assert_eq!(json["finish_reason"], "stop");
+        assert_eq!(json["usage"]["output_tokens"], 3);
+        assert_eq!(json["latency_ms"], 840);
+        assert!(json["cost_usd"].is_null());
+        assert_eq!(
+            json["citations"],
+            serde_json::json!([{ "url": "https://en.wikipedia.org/wiki/Paris" }])
+        );
+    }
+
+    #[test]
+    fn token_limit_stops_count_as_cut_off() {
+        let stopped = |reason: Option<&str>| ChatResponse {
+            finish_reason: reason.map(str::to_string),
+            ..Default::default()
+        };
+        for reason in ["length", "max_tokens", "MAX_TOKENS", "Length"] {
+            assert!(cut_off(&stopped(Some(reason))), "{reason}");
+        }
+        assert!(!cut_off(&stopped(Some("stop"))));
+        assert!(!cut_off(&stopped(None)));
+    }
+
+    #[test]
+    fn appended_replies_follow_a_blank_line() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("out.md");
+        write_reply(&path, "stale", false).unwrap();
+        write_reply(&path, "First.\n", false).unwrap();
+        write_reply(&path, "Second.", true).unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(written, "First.\n\nSecond.\n");
+    }
+}
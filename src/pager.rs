@@ -0,0 +1,40 @@
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::process::{Command, Stdio};
+
+/// Shows `text` through `$PAGER` (default `less -R`), printing it directly when
+/// stdout is not a terminal or the pager cannot be started.
+pub fn page(text: &str) {
+    if !io::stdout().is_terminal() {
+        print!("{text}");
+        return;
+    }
+    let pager = env::var("PAGER").unwrap_or_else(|_| {
+        if cfg!(windows) {
+            "more".to_string()
+        } else {
+            "less -R".to_string()
+        }
+    });
+    let mut words = pager.split_whitespace();
+    let Some(program) = words.next() else {
+        print!("{text}");
+        return;
+    };
+    let child = Command::new(program)
+        .args(words)
+        .stdin(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(_) => {
+            print!("{text}");
+            return;
+        }
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        // The pager may exit before reading everything (e.g. `q` in less).
+        let _ = stdin.write_all(text.as_bytes());
+    }
+    let _ = child.wait();
+}
@@ -0,0 +1,325 @@
+use std::ffi::OsString;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::config;
+use crate::provider::ToolSpec;
+
+/// Version of the JSON exchange below; plugins echo it in `describe`.
+pub const PROTOCOL_VERSION: u32 = 1;
+const PREFIX: &str = "rustchat-";
+/// Flag that asks a plugin for one JSON request on stdin and one JSON
+/// response on stdout, instead of running as a subcommand.
+const PROTOCOL_FLAG: &str = "--rustchat-plugin";
+/// `rustchat-cli` itself is not a plugin.
+const RESERVED: [&str; 1] = ["cli"];
+
+/// An executable named `rustchat-<name>` found on `PATH`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plugin {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+/// What a plugin says about itself in reply to `describe`.
+#[derive(Debug, Deserialize)]
+pub struct Manifest {
+    pub protocol: u32,
+    #[serde(default)]
+    pub description: String,
+    /// Tools offered to the model with `--tools plugins`
+    #[serde(default)]
+    pub tools: Vec<ManifestTool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ManifestTool {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// JSON schema of the arguments; an object with no properties if omitted
+    #[serde(default = "empty_schema")]
+    pub parameters: Value,
+    /// Set when calls only read; `read-only` approval runs them unasked once
+    /// `[tools] trusted_read_only` names the tool too
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+fn empty_schema() -> Value {
+    json!({"type": "object", "properties": {}})
+}
+
+impl ManifestTool {
+    pub fn spec(&self) -> ToolSpec {
+        ToolSpec {
+            name: self.name.clone(),
+            description: self.description.clone(),
+            parameters: self.parameters.clone(),
+        }
+    }
+}
+
+/// The reply to `call_tool`: `output` on success, `error` otherwise.
+#[derive(Debug, Deserialize)]
+struct ToolReply {
+    output: Option<String>,
+    error: Option<String>,
+}
+
+/// Every plugin on `PATH`, by name; the first of a name wins, like a shell.
+pub fn discover() -> Vec<Plugin> {
+    let dirs = std::env::var_os("PATH")
+        .map(|path| std::env::split_paths(&path).collect::<Vec<_>>())
+        .unwrap_or_default();
+    discover_in(&dirs)
+}
+
+fn discover_in(dirs: &[PathBuf]) -> Vec<Plugin> {
+    let mut plugins: Vec<Plugin> = Vec::new();
+    for dir in dirs {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        let mut found: Vec<Plugin> = entries
+            .flatten()
+            .filter_map(|entry| {
+                let path = entry.path();
+                let name = plugin_name(&path)?;
+                is_executable(&path).then_some(Plugin { name, path })
+            })
+            .collect();
+        found.sort_by(|a, b| a.name.cmp(&b.name));
+        for plugin in found {
+            if !plugins.iter().any(|known| known.name == plugin.name) {
+                plugins.push(plugin);
+            }
+        }
+    }
+    plugins.sort_by(|a, b| a.name.cmp(&b.name));
+    plugins
+}
+
+/// The plugin that would run for `rustchat-cli <name>`.
+pub fn find(name: &str) -> Option<Plugin> {
+    discover().into_iter().find(|plugin| plugin.name == name)
+}
+
+fn plugin_name(path: &Path) -> Option<String> {
+    let file = path.file_name()?.to_str()?;
+    let file = file
+        .strip_suffix(std::env::consts::EXE_SUFFIX)
+        .filter(|_| !std::env::consts::EXE_SUFFIX.is_empty())
+        .unwrap_or(file);
+    let name = file.strip_prefix(PREFIX)?;
+    let valid = !name.is_empty()
+        && !RESERVED.contains(&name)
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    valid.then(|| name.to_string())
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+impl Plugin {
+    pub fn describe(&self) -> Result<Manifest> {
+        let reply = self.request(json!({"method": "describe"}))?;
+        let manifest: Manifest = serde_json::from_value(reply)
+            .with_context(|| format!("plugin '{}' sent an invalid manifest", self.name))?;
+        if manifest.protocol != PROTOCOL_VERSION {
+            bail!(
+                "plugin '{}' speaks protocol {}, but this rustchat-cli speaks {PROTOCOL_VERSION}",
+                self.name,
+                manifest.protocol
+            );
+        }
+        Ok(manifest)
+    }
+
+    /// Runs one of the plugin's tools and returns its output for the model.
+    pub fn call_tool(&self, tool: &str, arguments: &Value) -> Result<String> {
+        let reply = self.request(json!({
+            "method": "call_tool",
+            "tool": tool,
+            "arguments": arguments,
+        }))?;
+        let reply: ToolReply = serde_json::from_value(reply)
+            .with_context(|| format!("plugin '{}' sent an invalid tool reply", self.name))?;
+        match (reply.output, reply.error) {
+            (_, Some(error)) => Err(anyhow!(error)),
+            (Some(output), None) => Ok(output),
+            (None, None) => bail!(
+                "plugin '{}' replied with neither output nor error",
+                self.name
+            ),
+        }
+    }
+
+    /// Runs the plugin as `rustchat-cli <name>`, attached to the terminal.
+    pub fn run_command(&self, args: &[OsString]) -> Result<std::process::ExitStatus> {
+        self.command()
+            .args(args)
+            .status()
+            .with_context(|| format!("failed to run {}", self.path.display()))
+    }
+
+    /// Sends one request and reads back one JSON value.
+    fn request(&self, mut body: Value) -> Result<Value> {
+        body["protocol"] = json!(PROTOCOL_VERSION);
+        let mut child = self
+            .command()
+            .arg(PROTOCOL_FLAG)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .with_context(|| format!("failed to run {}", self.path.display()))?;
+        // Written from another thread so a plugin that prints before it has
+        // read a large request cannot deadlock against a full pipe. One that
+        // exits without reading is reported by its reply.
+        let writer = child.stdin.take().map(|mut stdin| {
+            std::thread::spawn(move || {
+                let _ = writeln!(stdin, "{body}");
+            })
+        });
+        let output = child.wait_with_output()?;
+        if let Some(writer) = writer {
+            let _ = writer.join();
+        }
+        if !output.status.success() {
+            bail!("plugin '{}' exited with {}", self.name, output.status);
+        }
+        serde_json::from_slice(&output.stdout)
+            .with_context(|| format!("plugin '{}' did not reply with JSON", self.name))
+    }
+
+    /// Tells the plugin how to call back into rustchat-cli.
+    fn command(&self) -> Command {
+        let mut command = Command::new(&self.path);
+        command.env("RUSTCHAT_PLUGIN_PROTOCOL", PROTOCOL_VERSION.to_string());
+        if let Ok(exe) = std::env::current_exe() {
+            command.env("RUSTCHAT_BIN", exe);
+        }
+        if let Ok(path) = config::config_path() {
+            command.env("RUSTCHAT_CONFIG", path);
+        }
+        command
+    }
+}
+
+/// `plugins list`: each plugin with its description and tools.
+pub fn list() -> Result<()> {
+    let plugins = discover();
+    if plugins.is_empty() {
+        println!("No plugins found. Plugins are executables named {PREFIX}<name> on PATH.");
+        return Ok(());
+    }
+    for plugin in plugins {
+        println!("{}  ({})", plugin.name, plugin.path.display());
+        match plugin.describe() {
+            Ok(manifest) => {
+                if !manifest.description.is_empty() {
+                    println!("    {}", manifest.description);
+                }
+                for tool in &manifest.tools {
+                    println!("    tool {}: {}", tool.name, tool.description);
+                }
+            }
+            Err(err) => println!("    [error] {err:#}"),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    #[test]
+    fn plugins_are_found_on_path_and_speak_json() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().to_path_buf();
+        let (first, second) = (root.join("a"), root.join("b"));
+        std::fs::create_dir_all(&first).unwrap();
+        std::fs::create_dir_all(&second).unwrap();
+        let script = r#"#!/bin/sh
+read -r request
+case "$request" in
+  *describe*) echo '{"protocol":1,"description":"Echoes","tools":[{"name":"echo","description":"Echo text"}]}' ;;
+  *'"text":"boom"'*) echo '{"error":"no"}' ;;
+  *) echo '{"output":"pong"}' ;;
+esac
+"#;
+        for (dir, name, mode) in [
+            (&first, "rustchat-echo", 0o755),
+            (&second, "rustchat-echo", 0o755),
+            (&first, "rustchat-notes.txt", 0o644),
+            (&first, "rustchat-cli", 0o755),
+        ] {
+            let path = dir.join(name);
+            std::fs::write(&path, script).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+        }
+
+        let plugins = discover_in(&[first.clone(), second]);
+        assert_eq!(
+            plugins,
+            [Plugin {
+                name: "echo".to_string(),
+                path: first.join("rustchat-echo"),
+            }]
+        );
+        let manifest = plugins[0].describe().unwrap();
+        assert_eq!(manifest.description, "Echoes");
+        assert_eq!(manifest.tools[0].spec().parameters["type"], "object");
+        assert_eq!(
+            plugins[0]
+                .call_tool("echo", &json!({"text": "ping"}))
+                .unwrap(),
+            "pong"
+        );
+        assert!(plugins[0]
+            .call_tool("echo", &json!({"text": "boom"}))
+            .is_err());
+    }
+
+    #[test]
+    fn plugins_may_reply_before_reading_a_large_request() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("rustchat-eager");
+        // More output than a pipe holds, all of it before reading anything.
+        let script = r#"#!/bin/sh
+printf '{"output":"'
+head -c 131072 /dev/zero | tr '\0' x
+printf '"}\n'
+cat >/dev/null
+"#;
+        std::fs::write(&path, script).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let plugin = Plugin {
+            name: "eager".to_string(),
+            path,
+        };
+        let text = "x".repeat(256 * 1024);
+        let output = plugin.call_tool("eager", &json!({"text": text})).unwrap();
+        assert_eq!(output.len(), 131072);
+    }
+}
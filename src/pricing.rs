@@ -0,0 +1,159 @@
+use crate::provider::TokenUsage;
+
+/// USD price per million tokens for a model family.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ModelPrice {
+    pub input_per_million: f64,
+    pub output_per_million: f64,
+}
+
+/// Bundled list prices, matched by longest model-name prefix. These are
+/// estimates for display only; providers bill from their own metering.
+const PRICE_TABLE: &[(&str, f64, f64)] = &[
+    ("gpt-4o-mini", 0.15, 0.60),
+    ("gpt-4o", 2.50, 10.00),
+    ("gpt-4.1-nano", 0.10, 0.40),
+    ("gpt-4.1-mini", 0.40, 1.60),
+    ("gpt-4.1", 2.00, 8.00),
+    ("gpt-4-turbo", 10.00, 30.00),
+    ("gpt-3.5-turbo", 0.50, 1.50),
+    ("gpt-5-nano", 0.05, 0.40),
+    ("gpt-5-mini", 0.25, 2.00),
+    ("gpt-5", 1.25, 10.00),
+    ("o1-mini", 1.10, 4.40),
+    ("o1", 15.00, 60.00),
+    ("o3-mini", 1.10, 4.40),
+    ("o3", 2.00, 8.00),
+    ("o4-mini", 1.10, 4.40),
+    ("claude-3-haiku", 0.25, 1.25),
+    ("claude-3-5-haiku", 0.80, 4.00),
+    ("claude-haiku-4", 1.00, 5.00),
+    ("claude-3-sonnet", 3.00, 15.00),
+    ("claude-3-5-sonnet", 3.00, 15.00),
+    ("claude-3-7-sonnet", 3.00, 15.00),
+    ("claude-sonnet-4", 3.00, 15.00),
+    ("claude-3-opus", 15.00, 75.00),
+    ("claude-opus-4", 15.00, 75.00),
+    ("gemini-1.5-flash", 0.075, 0.30),
+    ("gemini-1.5-pro", 1.25, 5.00),
+    ("gemini-2.0-flash-lite", 0.075, 0.30),
+    ("gemini-2.0-flash", 0.10, 0.40),
+    ("gemini-2.5-flash-lite", 0.10, 0.40),
+    ("gemini-2.5-flash", 0.30, 2.50),
+    ("gemini-2.5-pro", 1.25, 10.00),
+    ("gemini-pro", 0.50, 1.50),
+];
+
+/// Model names in the price table, usable as-is with their providers.
+pub fn model_families() -> impl Iterator<Item = &'static str> {
+    PRICE_TABLE.iter().map(|(prefix, _, _)| *prefix)
+}
+
+pub fn lookup(model: &str) -> Option<ModelPrice> {
+    let model = model.to_ascii_lowercase();
+    let model = model.rsplit('/').next().unwrap_or(&model);
+    PRICE_TABLE
+        .iter()
+        .filter(|(prefix, _, _)| model.starts_with(prefix))
+        .max_by_key(|(prefix, _, _)| prefix.len())
+        .map(|&(_, input, output)| ModelPrice {
+            input_per_million: input,
+            output_per_million: output,
+        })
+}
+
+pub fn estimate_cost(model: &str, usage: &TokenUsage) -> Option<f64> {
+    let price = lookup(model)?;
+    Some(
+        usage.input_tokens as f64 * price.input_per_million / 1_000_000.0
+            + usage.output_tokens as f64 * price.output_per_million / 1_000_000.0,
+    )
+}
+
+/// What a request should cost before it is sent: its estimated prompt size
+/// plus, when `--max-tokens` caps the reply, the most it could write back.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RequestEstimate {
+    pub input_tokens: u32,
+    pub max_output_tokens: Option<u32>,
+    pub cost: f64,
+}
+
+impl RequestEstimate {
+    pub fn describe(&self) -> String {
+        match self.max_output_tokens {
+            Some(output) => format!(
+                "~{} input + up to {output} output tokens: {}",
+                self.input_tokens,
+                format_cost(self.cost)
+            ),
+            None => format!(
+                "~{} input tokens: {} plus the reply (set --max-tokens to cap it)",
+                self.input_tokens,
+                format_cost(self.cost)
+            ),
+        }
+    }
+}
+
+/// `None` when the model is not in the price table.
+pub fn estimate_request(
+    model: &str,
+    input_tokens: u32,
+    max_output_tokens: Option<u32>,
+) -> Option<RequestEstimate> {
+    let usage = TokenUsage {
+        input_tokens,
+        output_tokens: max_output_tokens.unwrap_or(0),
+    };
+    Some(RequestEstimate {
+        input_tokens,
+        max_output_tokens,
+        cost: estimate_cost(model, &usage)?,
+    })
+}
+
+pub fn format_cost(cost: f64) -> String {
+    if cost < 0.01 {
+        format!("${cost:.4}")
+    } else {
+        format!("${cost:.2}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup_prefers_longest_prefix() {
+        let mini = lookup("gpt-4o-mini-2024-07-18").expect("known model");
+        assert_eq!(mini.input_per_million, 0.15);
+        let full = lookup("gpt-4o").expect("known model");
+        assert_eq!(full.input_per_million, 2.50);
+        assert!(lookup("models/gemini-2.0-flash").is_some());
+        assert!(lookup("mystery-model").is_none());
+    }
+
+    #[test]
+    fn cost_scales_with_usage() {
+        let usage = TokenUsage {
+            input_tokens: 1_000_000,
+            output_tokens: 500_000,
+        };
+        let cost = estimate_cost("gpt-4o", &usage).unwrap();
+        assert!((cost - 7.5).abs() < 1e-9);
+
+        let capped = estimate_request("gpt-4o", 1_000_000, Some(100_000)).unwrap();
+        assert!((capped.cost - 3.5).abs() < 1e-9);
+        assert_eq!(
+            capped.describe(),
+            "~1000000 input + up to 100000 output tokens: $3.50"
+        );
+        let open = estimate_request("gpt-4o", 4_000, None).unwrap();
+        assert!(open
+            .describe()
+            .ends_with("$0.01 plus the reply (set --max-tokens to cap it)"));
+        assert!(estimate_request("mystery-model", 10, None).is_none());
+    }
+}
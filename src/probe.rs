@@ -0,0 +1,190 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use reqwest::StatusCode;
+
+use crate::config::{AppConfig, ProviderConfig, ProviderKind};
+use crate::provider::{build_provider, ChatMessage, ChatRequestOptions};
+use crate::{secrets, shutdown};
+
+/// Outcome of one `config test` probe.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ProbeStatus {
+    Ok,
+    /// The endpoint answered but refused the credentials
+    AuthFailed(String),
+    /// No HTTP response at all (DNS, TLS, connection refused, timeout)
+    Unreachable(String),
+    Error(String),
+}
+
+impl ProbeStatus {
+    fn label(&self) -> &'static str {
+        match self {
+            ProbeStatus::Ok => "ok",
+            ProbeStatus::AuthFailed(_) => "auth failed",
+            ProbeStatus::Unreachable(_) => "unreachable",
+            ProbeStatus::Error(_) => "error",
+        }
+    }
+}
+
+/// Sends a one-token request to `only` (or every configured provider) and
+/// prints reachability, auth validity and latency. Fails if any probe did.
+pub async fn test_providers(
+    cfg: &AppConfig,
+    only: Option<&str>,
+    env_label: &str,
+    strict: bool,
+) -> Result<()> {
+    let providers: Vec<(&str, &ProviderConfig)> = match only {
+        Some(name) => vec![(name, cfg.require_provider(name)?)],
+        None => cfg
+            .providers
+            .iter()
+            .map(|(name, provider)| (name.as_str(), provider))
+            .collect(),
+    };
+    if providers.is_empty() {
+        bail!("no providers configured - add one with `config set`");
+    }
+    let passphrase = secrets::optional_passphrase_from_env(env_label, strict)?;
+    let passphrase = secrets::prompt_for_passphrase(
+        passphrase,
+        providers
+            .iter()
+            .any(|(_, provider)| provider.has_encrypted_secret()),
+        true,
+        env_label,
+    )?;
+
+    let width = providers
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    let mut failures = 0;
+    for (name, provider_cfg) in providers {
+        let model = provider_cfg
+            .default_model()
+            .unwrap_or(fallback_model(provider_cfg.kind()))
+            .to_string();
+        let (status, elapsed) =
+            probe(name, provider_cfg, &model, passphrase.as_deref(), env_label).await;
+        let detail = match &status {
+            ProbeStatus::Ok => format!("{} ms  {model}", elapsed.as_millis()),
+            ProbeStatus::AuthFailed(detail)
+            | ProbeStatus::Unreachable(detail)
+            | ProbeStatus::Error(detail) => detail.clone(),
+        };
+        if status != ProbeStatus::Ok {
+            failures += 1;
+        }
+        println!("{name:<width$}  {:<11}  {detail}", status.label());
+    }
+    if failures > 0 {
+        bail!("{failures} provider(s) failed the check");
+    }
+    Ok(())
+}
+
+async fn probe(
+    name: &str,
+    provider_cfg: &ProviderConfig,
+    model: &str,
+    passphrase: Option<&str>,
+    env_label: &str,
+) -> (ProbeStatus, Duration) {
+    let started = Instant::now();
+    let provider = match build_provider(name, provider_cfg, passphrase, env_label).await {
+        Ok(provider) => provider,
+        Err(err) => return (classify(&err), started.elapsed()),
+    };
+    let options = ChatRequestOptions {
+        max_output_tokens: Some(1),
+        ..Default::default()
+    };
+    let started = Instant::now();
+    let messages = [ChatMessage::user("ping")];
+    let result = provider
+        .chat(model, None, &messages, &options, &shutdown::token())
+        .await;
+    let elapsed = started.elapsed();
+    match result {
+        Ok(_) => (ProbeStatus::Ok, elapsed),
+        Err(err) => (classify(&err), elapsed),
+    }
+}
+
+/// Sorts a failed probe by the HTTP error underneath it, if any.
+fn classify(err: &anyhow::Error) -> ProbeStatus {
+    let http = err
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<reqwest::Error>());
+    match http {
+        Some(http) => match http.status() {
+            Some(status @ (StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN)) => {
+                ProbeStatus::AuthFailed(status.to_string())
+            }
+            Some(status) => ProbeStatus::Error(format!("HTTP {status}")),
+            None if http.is_connect() || http.is_timeout() => {
+                ProbeStatus::Unreachable(format!("{err:#}"))
+            }
+            None => ProbeStatus::Error(format!("{err:#}")),
+        },
+        None => ProbeStatus::Error(format!("{err:#}")),
+    }
+}
+
+/// A cheap model to probe with when the provider has no `default_model`.
+fn fallback_model(kind: ProviderKind) -> &'static str {
+    match kind {
+        ProviderKind::Google => "gemini-pro",
+        ProviderKind::Anthropic => "claude-3-haiku-20240307",
+        ProviderKind::Openai => "gpt-4o-mini",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    #[tokio::test]
+    async fn failures_are_sorted_by_http_status() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request);
+            let _ = stream.write_all(b"HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n");
+        });
+        let err = reqwest::get(format!("http://{addr}/v1/models"))
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(anyhow::Error::from)
+            .unwrap_err();
+        assert_eq!(
+            classify(&err.context("openai request failed")),
+            ProbeStatus::AuthFailed("401 Unauthorized".into())
+        );
+
+        // Nothing listens on a freshly released port.
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let err = reqwest::get(format!("http://127.0.0.1:{port}/"))
+            .await
+            .map_err(anyhow::Error::from)
+            .unwrap_err();
+        assert!(matches!(classify(&err), ProbeStatus::Unreachable(_)));
+        assert!(matches!(
+            classify(&anyhow::anyhow!("missing api key")),
+            ProbeStatus::Error(_)
+        ));
+    }
+}
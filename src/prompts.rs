@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::cli::{MessageCommand, PromptRunCommand, PromptsCommand};
+use crate::config::{self, ProfileConfig};
+use crate::utils;
+
+const PROMPTS_FILE: &str = "prompts.toml";
+
+/// Named prompt templates kept in `prompts.toml` beside the config.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PromptLibrary {
+    #[serde(default)]
+    pub prompts: BTreeMap<String, SavedPrompt>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedPrompt {
+    /// Prompt text; `{{var}}` placeholders are filled when it runs
+    pub template: String,
+    /// Provider, model, system prompt and temperature used unless flags say
+    /// otherwise
+    #[serde(flatten)]
+    pub defaults: ProfileConfig,
+}
+
+/// `prompts.toml` in the same directory as the config file.
+pub fn prompts_path() -> Result<PathBuf> {
+    Ok(config::config_path()?.with_file_name(PROMPTS_FILE))
+}
+
+impl PromptLibrary {
+    pub fn load(path: &Path) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(data) => {
+                toml::from_str(&data).with_context(|| format!("invalid {}", path.display()))
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_context(|| format!("failed to read {}", path.display())),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        utils::write_atomic(path, toml::to_string_pretty(self)?.as_bytes())
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    pub fn get(&self, name: &str) -> Result<&SavedPrompt> {
+        self.prompts
+            .get(name)
+            .ok_or_else(|| anyhow!("no saved prompt named '{name}' - see `prompts list`"))
+    }
+}
+
+/// Handles every `prompts` subcommand except `run`, which goes through
+/// `message` (see [`message_for`]).
+pub fn handle_prompts(command: PromptsCommand) -> Result<()> {
+    let path = prompts_path()?;
+    let mut library = PromptLibrary::load(&path)?;
+    match command {
+        PromptsCommand::Add {
+            name,
+            template,
+            provider,
+            model,
+            system,
+            temperature,
+            force,
+        } => {
+            if !is_prompt_name(&name) {
+                bail!("prompt names may only use letters, digits, '_' and '-'");
+            }
+            if library.prompts.contains_key(&name) && !force {
+                bail!("a prompt named '{name}' already exists - pass --force to replace it");
+            }
+            library.prompts.insert(
+                name.clone(),
+                SavedPrompt {
+                    template,
+                    defaults: ProfileConfig {
+                        provider,
+                        model,
+                        system,
+                        temperature,
+                        ..ProfileConfig::default()
+                    },
+                },
+            );
+            library.save(&path)?;
+            println!("Saved prompt '{name}' to {}", path.display());
+        }
+        PromptsCommand::List => {
+            if library.prompts.is_empty() {
+                println!("No saved prompts. Add one with `prompts add <name> <template>`.");
+            }
+            let width = library.prompts.keys().map(String::len).max().unwrap_or(0);
+            for (name, prompt) in &library.prompts {
+                println!("{name:<width$}  {}", summary(prompt));
+            }
+        }
+        PromptsCommand::Show { name } => {
+            let prompt = library.get(&name)?;
+            let defaults = &prompt.defaults;
+            let settings = [
+                ("provider", defaults.provider.clone()),
+                ("model", defaults.model.clone()),
+                ("temperature", defaults.temperature.map(|t| t.to_string())),
+                ("system", defaults.system.clone()),
+            ];
+            for (label, value) in settings {
+                if let Some(value) = value {
+                    println!("{label}: {value}");
+                }
+            }
+            println!("{}", prompt.template);
+        }
+        PromptsCommand::Remove { name } => {
+            if library.prompts.remove(&name).is_none() {
+                bail!("no saved prompt named '{name}'");
+            }
+            library.save(&path)?;
+            println!("Removed prompt '{name}'");
+        }
+        PromptsCommand::Run(_) => unreachable!("`prompts run` is handled as a message"),
+    }
+    Ok(())
+}
+
+/// Turns `prompts run` into the `message` it stands for; flags passed to
+/// `run` win over the settings saved with the prompt.
+pub fn message_for(mut args: Box<PromptRunCommand>) -> Result<MessageCommand> {
+    let library = PromptLibrary::load(&prompts_path()?)?;
+    let prompt = library.get(&args.name)?;
+    config::apply_profile(&prompt.defaults, &mut args.common);
+    Ok(MessageCommand {
+        common: args.common,
+        prompt: vec![prompt.template.clone()],
+        output: args.output,
+        dry_run: args.dry_run,
+        stream: false,
+        out: None,
+        append: false,
+        history: None,
+        retry: args.retry,
+        cost: args.cost,
+        stdin_filters: args.stdin_filters,
+    })
+}
+
+/// Provider/model and the first line of the template, for `prompts list`.
+fn summary(prompt: &SavedPrompt) -> String {
+    let target = match (&prompt.defaults.provider, &prompt.defaults.model) {
+        (Some(provider), Some(model)) => format!("[{provider}/{model}] "),
+        (Some(name), None) | (None, Some(name)) => format!("[{name}] "),
+        (None, None) => String::new(),
+    };
+    let first_line = prompt.template.lines().next().unwrap_or_default();
+    let mut line: String = first_line.chars().take(60).collect();
+    if line.len() < first_line.len() || prompt.template.lines().nth(1).is_some() {
+        line.push('…');
+    }
+    format!("{target}{line}")
+}
+
+fn is_prompt_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saved_prompts_round_trip_with_flattened_defaults() {
+        let library: PromptLibrary = toml::from_str(
+            "[prompts.review]\ntemplate = \"Review this {{lang}} diff:\\n{{stdin}}\"\nprovider = \"openai\"\nmodel = \"gpt-4o\"\n",
+        )
+        .unwrap();
+        let review = library.get("review").unwrap();
+        assert_eq!(review.defaults.model.as_deref(), Some("gpt-4o"));
+        assert_eq!(
+            summary(review),
+            "[openai/gpt-4o] Review this {{lang}} diff:…"
+        );
+
+        let saved = toml::to_string_pretty(&library).unwrap();
+        assert!(saved.contains("provider = \"openai\""));
+        assert!(!saved.contains("system"));
+        assert!(library.get("missing").is_err());
+        assert!(is_prompt_name("code-review_2"));
+        assert!(!is_prompt_name("../evil"));
+    }
+}
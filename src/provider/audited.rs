@@ -0,0 +1,143 @@
+use anyhow::Result;
+use async_stream::try_stream;
+use async_trait::async_trait;
+use futures::StreamExt;
+
+use super::{
+    CancellationToken, ChatMessage, ChatRequestOptions, ChatResponse, DynProvider, Provider,
+    RequestPreview,
+};
+use crate::audit::{self, Entry};
+use crate::streaming::{ChatStream, StreamEvent};
+
+/// Writes a line to the `[audit]` log before every request goes out, and
+/// another with its outcome, including the ones that fail or are refused over
+/// budget.
+pub struct Audited {
+    name: String,
+    inner: DynProvider,
+}
+
+impl Audited {
+    pub fn wrap(name: &str, inner: DynProvider) -> DynProvider {
+        std::sync::Arc::new(Self {
+            name: name.to_string(),
+            inner,
+        })
+    }
+}
+
+#[async_trait]
+impl Provider for Audited {
+    async fn chat(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+        cancel: &CancellationToken,
+    ) -> Result<ChatResponse> {
+        let mut entry = Entry::start(&self.name, model, "chat", || {
+            audit::chat_prompt(system, messages)
+        })?;
+        let result = self
+            .inner
+            .chat(model, system, messages, options, cancel)
+            .await;
+        let Some(entry) = &mut entry else {
+            return result;
+        };
+        match &result {
+            Ok(response) => entry.usage = response.usage,
+            Err(err) => entry.fail(err),
+        }
+        entry.finish(result)
+    }
+
+    fn preview_chat(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+    ) -> Result<RequestPreview> {
+        self.inner.preview_chat(model, system, messages, options)
+    }
+
+    async fn stream_chat(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+        cancel: &CancellationToken,
+    ) -> Result<ChatStream> {
+        let mut entry = Entry::start(&self.name, model, "stream", || {
+            audit::chat_prompt(system, messages)
+        })?;
+        let stream = match self
+            .inner
+            .stream_chat(model, system, messages, options, cancel)
+            .await
+        {
+            Ok(stream) => stream,
+            Err(err) => {
+                return match &mut entry {
+                    Some(entry) => {
+                        entry.fail(&err);
+                        entry.finish(Err(err))
+                    }
+                    None => Err(err),
+                };
+            }
+        };
+        let Some(mut entry) = entry else {
+            return Ok(stream);
+        };
+        // The entry goes with the stream: it is written when the stream ends,
+        // or when it is dropped if it is abandoned first.
+        let audited = try_stream! {
+            let mut stream = stream;
+            while let Some(event) = stream.next().await {
+                match event {
+                    Ok(event) => {
+                        if let StreamEvent::Usage(usage) = &event {
+                            entry.add_usage(*usage);
+                        }
+                        yield event;
+                    }
+                    Err(err) => {
+                        entry.fail(&err);
+                        entry.finish(Err(err))?;
+                    }
+                }
+            }
+            entry.finish(Ok(()))?;
+        };
+        Ok(audited.boxed())
+    }
+
+    async fn count_tokens(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+    ) -> Result<Option<usize>> {
+        self.inner
+            .count_tokens(model, system, messages, options)
+            .await
+    }
+
+    async fn embed(&self, model: &str, inputs: &[String]) -> Result<Vec<Vec<f32>>> {
+        let mut entry = Entry::start(&self.name, model, "embed", || audit::embed_prompt(inputs))?;
+        let result = self.inner.embed(model, inputs).await;
+        let Some(entry) = &mut entry else {
+            return result;
+        };
+        if let Err(err) = &result {
+            entry.fail(err);
+        }
+        entry.finish(result)
+    }
+}
@@ -0,0 +1,226 @@
+use anyhow::Result;
+use async_stream::try_stream;
+use async_trait::async_trait;
+use futures::StreamExt;
+use serde_json::Value;
+
+use super::{
+    CancellationToken, ChatMessage, ChatRequestOptions, ChatResponse, DynProvider, Provider,
+    RequestPreview,
+};
+use crate::streaming::ChatStream;
+use crate::vcr::{self, RecordedEvent, RecordedResponse, Reply};
+use crate::{audit, cache};
+
+/// Answers a request from the `[cache]` when the same one was sent within the
+/// TTL, so it is neither billed nor waited on again. The answer is still
+/// written to the `[audit]` log.
+pub struct Cached {
+    name: String,
+    endpoint: Value,
+    inner: DynProvider,
+}
+
+impl Cached {
+    pub fn wrap(name: &str, endpoint: Value, inner: DynProvider) -> DynProvider {
+        std::sync::Arc::new(Self {
+            name: name.to_string(),
+            endpoint,
+            inner,
+        })
+    }
+}
+
+#[async_trait]
+impl Provider for Cached {
+    async fn chat(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+        cancel: &CancellationToken,
+    ) -> Result<ChatResponse> {
+        if !cache::enabled() {
+            return self
+                .inner
+                .chat(model, system, messages, options, cancel)
+                .await;
+        }
+        let request =
+            vcr::chat_request(&self.name, &self.endpoint, model, system, messages, options);
+        if let Some(reply) = cache::load(&request) {
+            tracing::debug!(provider = %self.name, model, "answered from the reply cache");
+            audit::cached(&self.name, model, "chat", || {
+                audit::chat_prompt(system, messages)
+            })?;
+            return reply.into_response();
+        }
+        let response = self
+            .inner
+            .chat(model, system, messages, options, cancel)
+            .await?;
+        cache::save(request, Reply::Response(RecordedResponse::from(&response)));
+        Ok(response)
+    }
+
+    fn preview_chat(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+    ) -> Result<RequestPreview> {
+        self.inner.preview_chat(model, system, messages, options)
+    }
+
+    async fn stream_chat(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+        cancel: &CancellationToken,
+    ) -> Result<ChatStream> {
+        if !cache::enabled() {
+            return self
+                .inner
+                .stream_chat(model, system, messages, options, cancel)
+                .await;
+        }
+        let request =
+            vcr::chat_request(&self.name, &self.endpoint, model, system, messages, options);
+        if let Some(reply) = cache::load(&request) {
+            tracing::debug!(provider = %self.name, model, "answered from the reply cache");
+            audit::cached(&self.name, model, "stream", || {
+                audit::chat_prompt(system, messages)
+            })?;
+            let events = reply.into_events()?;
+            return Ok(futures::stream::iter(events.into_iter().map(Ok)).boxed());
+        }
+        let mut stream = self
+            .inner
+            .stream_chat(model, system, messages, options, cancel)
+            .await?;
+        // Only a stream that runs to the end is cached.
+        let caching = try_stream! {
+            let mut events = Vec::new();
+            while let Some(event) = stream.next().await {
+                let event = event?;
+                events.push(RecordedEvent::from(&event));
+                yield event;
+            }
+            cache::save(request, Reply::Events(events));
+        };
+        Ok(caching.boxed())
+    }
+
+    async fn count_tokens(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+    ) -> Result<Option<usize>> {
+        self.inner
+            .count_tokens(model, system, messages, options)
+            .await
+    }
+
+    async fn embed(&self, model: &str, inputs: &[String]) -> Result<Vec<Vec<f32>>> {
+        self.inner.embed(model, inputs).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use anyhow::bail;
+
+    use super::*;
+    use crate::audit::AuditRecord;
+    use crate::config::{AuditConfig, CacheConfig};
+    use crate::provider::TokenUsage;
+
+    /// Answers "Paris." and counts how often it was asked.
+    struct Paris(AtomicUsize);
+
+    #[async_trait]
+    impl Provider for Paris {
+        async fn chat(
+            &self,
+            _model: &str,
+            _system: Option<&str>,
+            _messages: &[ChatMessage],
+            _options: &ChatRequestOptions,
+            _cancel: &CancellationToken,
+        ) -> Result<ChatResponse> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(ChatResponse {
+                text: "Paris.".to_string(),
+                usage: Some(TokenUsage {
+                    input_tokens: 5,
+                    output_tokens: 2,
+                }),
+                ..ChatResponse::default()
+            })
+        }
+
+        fn preview_chat(
+            &self,
+            _model: &str,
+            _system: Option<&str>,
+            _messages: &[ChatMessage],
+            _options: &ChatRequestOptions,
+        ) -> Result<RequestPreview> {
+            bail!("not used")
+        }
+    }
+
+    // The only test that turns the cache and the audit log on, as both are
+    // set once per process.
+    #[tokio::test]
+    async fn cache_hits_are_audited() {
+        let tmp = tempfile::tempdir().unwrap();
+        let log = tmp.path().join("audit.jsonl");
+        let audit = AuditConfig {
+            enabled: true,
+            path: Some(log.clone()),
+            include_prompts: false,
+        };
+        audit::init(&audit, "message").unwrap();
+        let cache = CacheConfig {
+            ttl: Some("1h".to_string()),
+            dir: Some(tmp.path().join("responses")),
+        };
+        cache::init(&cache, false, None).unwrap();
+
+        let paris = Arc::new(Paris(AtomicUsize::new(0)));
+        let audited = crate::provider::audited::Audited::wrap("openai", paris.clone());
+        let provider = Cached::wrap("openai", Value::Null, audited);
+        let messages = [ChatMessage::user("Capital of France?")];
+        let options = ChatRequestOptions::default();
+        let cancel = CancellationToken::new();
+        for _ in 0..2 {
+            let response = provider
+                .chat("gpt-4o", None, &messages, &options, &cancel)
+                .await
+                .unwrap();
+            assert_eq!(response.text, "Paris.");
+        }
+        assert_eq!(paris.0.load(Ordering::Relaxed), 1);
+
+        let records: Vec<AuditRecord> = std::fs::read_to_string(&log)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let outcomes: Vec<&str> = records.iter().map(|r| r.outcome.as_str()).collect();
+        assert_eq!(outcomes, ["started", "ok", "cached"]);
+        assert_eq!(records[1].output_tokens, Some(2));
+        assert_eq!(records[2].output_tokens, Some(0));
+        assert_eq!(records[2].prompt_sha256, records[0].prompt_sha256);
+    }
+}
@@ -0,0 +1,112 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::StreamExt;
+
+use super::{
+    CancellationToken, ChatMessage, ChatRequestOptions, ChatResponse, DynProvider, Provider,
+    RequestPreview,
+};
+use crate::hooks;
+use crate::streaming::{ChatStream, StreamEvent};
+
+/// Passes each request through the `pre_request` hook and each reply through
+/// the `post_response` hook, whichever command sends them.
+pub struct Hooked {
+    name: String,
+    inner: DynProvider,
+}
+
+impl Hooked {
+    pub fn wrap(name: &str, inner: DynProvider) -> DynProvider {
+        std::sync::Arc::new(Self {
+            name: name.to_string(),
+            inner,
+        })
+    }
+}
+
+#[async_trait]
+impl Provider for Hooked {
+    async fn chat(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+        cancel: &CancellationToken,
+    ) -> Result<ChatResponse> {
+        let rewrite = hooks::pre_request(&self.name, model, system, messages).await?;
+        let (system, messages) = match &rewrite {
+            Some(rewrite) => (rewrite.system.as_deref(), rewrite.messages.as_slice()),
+            None => (system, messages),
+        };
+        let mut response = self
+            .inner
+            .chat(model, system, messages, options, cancel)
+            .await?;
+        hooks::post_response(&self.name, model, system, messages, &mut response).await?;
+        Ok(response)
+    }
+
+    fn preview_chat(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+    ) -> Result<RequestPreview> {
+        let rewrite = hooks::pre_request_preview(&self.name, model, system, messages)?;
+        let (system, messages) = match &rewrite {
+            Some(rewrite) => (rewrite.system.as_deref(), rewrite.messages.as_slice()),
+            None => (system, messages),
+        };
+        self.inner.preview_chat(model, system, messages, options)
+    }
+
+    async fn stream_chat(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+        cancel: &CancellationToken,
+    ) -> Result<ChatStream> {
+        // Text already printed cannot be rewritten, so with a post_response
+        // hook the whole reply is fetched first and replayed as one chunk.
+        if hooks::rewrites_replies() {
+            let response = self.chat(model, system, messages, options, cancel).await?;
+            let mut events = vec![Ok(StreamEvent::Text(response.text))];
+            events.extend(response.usage.map(|usage| Ok(StreamEvent::Usage(usage))));
+            events.extend(
+                response
+                    .finish_reason
+                    .map(|reason| Ok(StreamEvent::Finish(reason))),
+            );
+            return Ok(futures::stream::iter(events).boxed());
+        }
+        let rewrite = hooks::pre_request(&self.name, model, system, messages).await?;
+        let (system, messages) = match &rewrite {
+            Some(rewrite) => (rewrite.system.as_deref(), rewrite.messages.as_slice()),
+            None => (system, messages),
+        };
+        self.inner
+            .stream_chat(model, system, messages, options, cancel)
+            .await
+    }
+
+    async fn count_tokens(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+    ) -> Result<Option<usize>> {
+        self.inner
+            .count_tokens(model, system, messages, options)
+            .await
+    }
+
+    async fn embed(&self, model: &str, inputs: &[String]) -> Result<Vec<Vec<f32>>> {
+        self.inner.embed(model, inputs).await
+    }
+}
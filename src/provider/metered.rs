@@ -0,0 +1,119 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::StreamExt;
+
+use super::{
+    CancellationToken, ChatMessage, ChatRequestOptions, ChatResponse, DynProvider, Provider,
+    RequestPreview, TokenUsage,
+};
+use crate::streaming::{ChatStream, StreamEvent};
+use crate::usage;
+
+/// Records every completed request to the usage log, and holds back requests
+/// over a hard budget, whichever command sends them.
+pub struct Metered {
+    name: String,
+    inner: DynProvider,
+}
+
+impl Metered {
+    pub fn wrap(name: &str, inner: DynProvider) -> DynProvider {
+        std::sync::Arc::new(Self {
+            name: name.to_string(),
+            inner,
+        })
+    }
+}
+
+#[async_trait]
+impl Provider for Metered {
+    async fn chat(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+        cancel: &CancellationToken,
+    ) -> Result<ChatResponse> {
+        usage::check_budget(&self.name)?;
+        let response = self
+            .inner
+            .chat(model, system, messages, options, cancel)
+            .await?;
+        usage::record(&self.name, model, response.usage);
+        Ok(response)
+    }
+
+    fn preview_chat(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+    ) -> Result<RequestPreview> {
+        self.inner.preview_chat(model, system, messages, options)
+    }
+
+    async fn stream_chat(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+        cancel: &CancellationToken,
+    ) -> Result<ChatStream> {
+        usage::check_budget(&self.name)?;
+        let stream = self
+            .inner
+            .stream_chat(model, system, messages, options, cancel)
+            .await?;
+        // Usage arrives in pieces; record it once the stream is drained.
+        let tally = Tally {
+            provider: self.name.clone(),
+            model: model.to_string(),
+            usage: None,
+        };
+        let metered = stream
+            .scan(tally, |tally, event| {
+                if let Ok(StreamEvent::Usage(usage)) = &event {
+                    tally
+                        .usage
+                        .get_or_insert_with(TokenUsage::default)
+                        .absorb(*usage);
+                }
+                futures::future::ready(Some(event))
+            })
+            .boxed();
+        Ok(metered)
+    }
+
+    async fn count_tokens(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+    ) -> Result<Option<usize>> {
+        self.inner
+            .count_tokens(model, system, messages, options)
+            .await
+    }
+
+    async fn embed(&self, model: &str, inputs: &[String]) -> Result<Vec<Vec<f32>>> {
+        usage::check_budget(&self.name)?;
+        self.inner.embed(model, inputs).await
+    }
+}
+
+/// Usage seen so far on one stream, recorded when the stream is dropped.
+struct Tally {
+    provider: String,
+    model: String,
+    usage: Option<TokenUsage>,
+}
+
+impl Drop for Tally {
+    fn drop(&mut self) {
+        usage::record(&self.provider, &self.model, self.usage);
+    }
+}
@@ -1,44 +1,112 @@
-mod trait_provider;
+mod audited;
+mod cached;
+mod hooked;
+mod metered;
+mod recorded;
+mod traced;
 
-pub mod anthropic;
-pub mod google;
-pub mod openai;
+use std::collections::HashMap;
 
-use anyhow::Result;
-use trait_provider::Provider;
+use anyhow::{bail, Result};
 
-pub use trait_provider::{ChatMessage, ChatRequestOptions, DynProvider, MessageRole};
+pub use rustchat_core::provider::*;
 
-use crate::config::ProviderConfig;
+use crate::config::{AppConfig, ProviderConfig};
+use crate::exit::{ExitStatus, ResultExt};
+use crate::secrets;
+use crate::vcr::{self, Mode};
 
+/// The core provider, with every request it sends traced, recorded for
+/// `usage report`, checked against `[usage.budgets]`, written to the
+/// `[audit]` log and passed through `[hooks]`. A reply still fresh in the
+/// `[cache]` answers without any of that but the audit line. Under
+/// `--record` each exchange is also saved; under `--replay` the saved ones
+/// answer instead, with no credentials or network needed.
 pub async fn build_provider(
     name: &str,
     cfg: &ProviderConfig,
     passphrase: Option<&str>,
     env_label: &str,
-) -> Result<trait_provider::DynProvider> {
-    Ok(match cfg {
-        ProviderConfig::Google(google_cfg) => {
-            let provider =
-                google::GoogleProvider::new(name.into(), google_cfg.clone(), passphrase, env_label)
-                    .await?;
-            std::sync::Arc::new(provider) as trait_provider::DynProvider
+) -> Result<DynProvider> {
+    if let Some(Mode::Replay(dir)) = vcr::mode() {
+        return Ok(recorded::Recorded::replay(
+            name,
+            cfg.endpoint(),
+            dir.clone(),
+        ));
+    }
+    let provider = rustchat_core::build_provider(name, cfg, passphrase, env_label).await?;
+    let traced = traced::Traced::wrap(name, provider);
+    let metered = metered::Metered::wrap(name, traced);
+    let audited = audited::Audited::wrap(name, metered);
+    let cached = cached::Cached::wrap(name, cfg.endpoint(), audited);
+    let hooked = hooked::Hooked::wrap(name, cached);
+    Ok(match vcr::mode() {
+        Some(Mode::Record(dir)) => {
+            recorded::Recorded::record(name, cfg.endpoint(), dir.clone(), hooked)
         }
-        ProviderConfig::Anthropic(anthropic_cfg) => {
-            let provider = anthropic::AnthropicProvider::new(
-                name.into(),
-                anthropic_cfg.clone(),
-                passphrase,
-                env_label,
-            )
-            .await?;
-            std::sync::Arc::new(provider) as trait_provider::DynProvider
+        _ => hooked,
+    })
+}
+
+/// Builds each named provider once, asking for the master passphrase at most
+/// once, for commands that talk to several providers.
+pub async fn build_providers<'a>(
+    names: impl IntoIterator<Item = &'a str>,
+    cfg: &AppConfig,
+    secret_env: Option<&str>,
+    allow_prompt: bool,
+) -> Result<HashMap<String, DynProvider>> {
+    let mut configs = Vec::new();
+    for name in names {
+        if !configs.iter().any(|(known, _)| *known == name) {
+            let provider_cfg = cfg.require_provider(name).exit_status(ExitStatus::Config)?;
+            configs.push((name, provider_cfg));
         }
-        ProviderConfig::Openai(openai_cfg) => {
-            let provider =
-                openai::OpenAiProvider::new(name.into(), openai_cfg.clone(), passphrase, env_label)
-                    .await?;
-            std::sync::Arc::new(provider) as trait_provider::DynProvider
+    }
+    let env_label = secret_env.unwrap_or(secrets::DEFAULT_MASTER_ENV);
+    let passphrase = secrets::optional_passphrase_from_env(env_label, secret_env.is_some())?;
+    let needed = !vcr::replaying()
+        && configs
+            .iter()
+            .any(|(_, provider_cfg)| provider_cfg.has_encrypted_secret());
+    let passphrase = secrets::prompt_for_passphrase(passphrase, needed, allow_prompt, env_label)?;
+    let mut providers = HashMap::new();
+    for (name, provider_cfg) in configs {
+        let provider = build_provider(name, provider_cfg, passphrase.as_deref(), env_label)
+            .await
+            .exit_status(ExitStatus::Config)?;
+        providers.insert(name.to_string(), provider);
+    }
+    Ok(providers)
+}
+
+/// Builds every configured provider it can, asking for the master
+/// passphrase at most once; one that fails is left out with a warning.
+pub async fn build_all(
+    cfg: &AppConfig,
+    secret_env: Option<&str>,
+    allow_prompt: bool,
+) -> Result<HashMap<String, DynProvider>> {
+    let env_label = secret_env.unwrap_or(secrets::DEFAULT_MASTER_ENV);
+    let passphrase = secrets::optional_passphrase_from_env(env_label, secret_env.is_some())?;
+    let needed = !vcr::replaying()
+        && cfg
+            .providers
+            .values()
+            .any(|provider| provider.has_encrypted_secret());
+    let passphrase = secrets::prompt_for_passphrase(passphrase, needed, allow_prompt, env_label)?;
+    let mut providers = HashMap::new();
+    for (name, provider_cfg) in &cfg.providers {
+        match build_provider(name, provider_cfg, passphrase.as_deref(), env_label).await {
+            Ok(provider) => {
+                providers.insert(name.clone(), provider);
+            }
+            Err(err) => tracing::warn!("skipping provider '{name}': {err:#}"),
         }
-    })
+    }
+    if providers.is_empty() {
+        bail!("no provider could be set up - check `config test`");
+    }
+    Ok(providers)
 }
@@ -0,0 +1,138 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+use async_stream::try_stream;
+use async_trait::async_trait;
+use futures::StreamExt;
+use serde_json::Value;
+
+use super::{
+    CancellationToken, ChatMessage, ChatRequestOptions, ChatResponse, DynProvider, Provider,
+    RequestPreview,
+};
+use crate::streaming::ChatStream;
+use crate::vcr::{self, Cassette, RecordedEvent, RecordedResponse, Reply};
+
+/// Saves every exchange under `--record`, or answers from the recordings
+/// under `--replay` without a provider behind it.
+pub struct Recorded {
+    name: String,
+    endpoint: Value,
+    dir: PathBuf,
+    /// `None` when replaying
+    inner: Option<DynProvider>,
+}
+
+impl Recorded {
+    pub fn record(name: &str, endpoint: Value, dir: PathBuf, inner: DynProvider) -> DynProvider {
+        std::sync::Arc::new(Self {
+            name: name.to_string(),
+            endpoint,
+            dir,
+            inner: Some(inner),
+        })
+    }
+
+    pub fn replay(name: &str, endpoint: Value, dir: PathBuf) -> DynProvider {
+        std::sync::Arc::new(Self {
+            name: name.to_string(),
+            endpoint,
+            dir,
+            inner: None,
+        })
+    }
+}
+
+#[async_trait]
+impl Provider for Recorded {
+    async fn chat(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+        cancel: &CancellationToken,
+    ) -> Result<ChatResponse> {
+        let request =
+            vcr::chat_request(&self.name, &self.endpoint, model, system, messages, options);
+        let Some(inner) = &self.inner else {
+            return vcr::load(&self.dir, &request)?.into_response();
+        };
+        let response = inner.chat(model, system, messages, options, cancel).await?;
+        let reply = Reply::Response(RecordedResponse::from(&response));
+        vcr::save(&self.dir, &Cassette { request, reply })?;
+        Ok(response)
+    }
+
+    fn preview_chat(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+    ) -> Result<RequestPreview> {
+        match &self.inner {
+            Some(inner) => inner.preview_chat(model, system, messages, options),
+            None => bail!("--dry-run shows the HTTP request, which --replay never sends"),
+        }
+    }
+
+    async fn stream_chat(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+        cancel: &CancellationToken,
+    ) -> Result<ChatStream> {
+        let request =
+            vcr::chat_request(&self.name, &self.endpoint, model, system, messages, options);
+        let Some(inner) = &self.inner else {
+            let events = vcr::load(&self.dir, &request)?.into_events()?;
+            return Ok(futures::stream::iter(events.into_iter().map(Ok)).boxed());
+        };
+        let mut stream = inner
+            .stream_chat(model, system, messages, options, cancel)
+            .await?;
+        let dir = self.dir.clone();
+        // Saved once the stream has run to the end; a stream that fails or is
+        // cancelled part way leaves no recording.
+        let recording = try_stream! {
+            let mut events = Vec::new();
+            while let Some(event) = stream.next().await {
+                let event = event?;
+                events.push(RecordedEvent::from(&event));
+                yield event;
+            }
+            vcr::save(&dir, &Cassette { request, reply: Reply::Events(events) })?;
+        };
+        Ok(recording.boxed())
+    }
+
+    async fn count_tokens(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+    ) -> Result<Option<usize>> {
+        match &self.inner {
+            Some(inner) => inner.count_tokens(model, system, messages, options).await,
+            None => Ok(None),
+        }
+    }
+
+    async fn embed(&self, model: &str, inputs: &[String]) -> Result<Vec<Vec<f32>>> {
+        let request = vcr::embed_request(&self.name, &self.endpoint, model, inputs);
+        let Some(inner) = &self.inner else {
+            return match vcr::load(&self.dir, &request)? {
+                Reply::Embeddings(embeddings) => Ok(embeddings),
+                _ => bail!("the recording holds a chat reply, not embeddings"),
+            };
+        };
+        let embeddings = inner.embed(model, inputs).await?;
+        let reply = Reply::Embeddings(embeddings.clone());
+        vcr::save(&self.dir, &Cassette { request, reply })?;
+        Ok(embeddings)
+    }
+}
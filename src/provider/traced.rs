@@ -0,0 +1,169 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::StreamExt;
+use tracing::field::Empty;
+use tracing::{Instrument, Span};
+
+use super::{
+    CancellationToken, ChatMessage, ChatRequestOptions, ChatResponse, DynProvider, Provider,
+    RequestPreview, TokenUsage,
+};
+use crate::streaming::{ChatStream, StreamEvent};
+
+/// Puts each request in a span named after the GenAI semantic conventions,
+/// with its model, token counts and outcome; a streamed reply's span lasts
+/// until the stream ends and marks the first token.
+pub struct Traced {
+    name: String,
+    inner: DynProvider,
+}
+
+impl Traced {
+    pub fn wrap(name: &str, inner: DynProvider) -> DynProvider {
+        std::sync::Arc::new(Self {
+            name: name.to_string(),
+            inner,
+        })
+    }
+
+    fn span(&self, operation: &str, model: &str, streaming: bool) -> Span {
+        tracing::info_span!(
+            "provider",
+            otel.name = %format!("{operation} {model}"),
+            otel.kind = "client",
+            gen_ai.operation.name = operation,
+            gen_ai.request.model = model,
+            rustchat.provider = %self.name,
+            rustchat.streaming = streaming,
+            gen_ai.usage.input_tokens = Empty,
+            gen_ai.usage.output_tokens = Empty,
+            gen_ai.response.finish_reasons = Empty,
+            otel.status_code = Empty,
+            otel.status_message = Empty,
+        )
+    }
+}
+
+fn record_usage(span: &Span, usage: Option<TokenUsage>) {
+    if let Some(usage) = usage {
+        span.record("gen_ai.usage.input_tokens", i64::from(usage.input_tokens));
+        span.record("gen_ai.usage.output_tokens", i64::from(usage.output_tokens));
+    }
+}
+
+fn record_error(span: &Span, err: &anyhow::Error) {
+    span.record("otel.status_code", "ERROR");
+    span.record("otel.status_message", format!("{err:#}"));
+}
+
+#[async_trait]
+impl Provider for Traced {
+    async fn chat(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+        cancel: &CancellationToken,
+    ) -> Result<ChatResponse> {
+        let span = self.span("chat", model, false);
+        let result = self
+            .inner
+            .chat(model, system, messages, options, cancel)
+            .instrument(span.clone())
+            .await;
+        match &result {
+            Ok(response) => {
+                record_usage(&span, response.usage);
+                if let Some(reason) = &response.finish_reason {
+                    span.record("gen_ai.response.finish_reasons", reason.as_str());
+                }
+            }
+            Err(err) => record_error(&span, err),
+        }
+        result
+    }
+
+    fn preview_chat(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+    ) -> Result<RequestPreview> {
+        self.inner.preview_chat(model, system, messages, options)
+    }
+
+    async fn stream_chat(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+        cancel: &CancellationToken,
+    ) -> Result<ChatStream> {
+        let span = self.span("chat", model, true);
+        let stream = match self
+            .inner
+            .stream_chat(model, system, messages, options, cancel)
+            .instrument(span.clone())
+            .await
+        {
+            Ok(stream) => stream,
+            Err(err) => {
+                record_error(&span, &err);
+                return Err(err);
+            }
+        };
+        tracing::info!(parent: &span, "stream opened");
+        // The span is held by the stream and ends when it is dropped.
+        let mut usage: Option<TokenUsage> = None;
+        let mut first = true;
+        let traced = stream.map(move |event| {
+            match &event {
+                Ok(StreamEvent::Text(_)) if first => {
+                    first = false;
+                    tracing::info!(parent: &span, "first token");
+                }
+                Ok(StreamEvent::Text(_)) => {}
+                Ok(StreamEvent::Usage(reported)) => {
+                    usage
+                        .get_or_insert_with(TokenUsage::default)
+                        .absorb(*reported);
+                    record_usage(&span, usage);
+                }
+                Ok(StreamEvent::Finish(reason)) => {
+                    span.record("gen_ai.response.finish_reasons", reason.as_str());
+                }
+                Err(err) => record_error(&span, err),
+            }
+            event
+        });
+        Ok(traced.boxed())
+    }
+
+    async fn count_tokens(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+    ) -> Result<Option<usize>> {
+        self.inner
+            .count_tokens(model, system, messages, options)
+            .await
+    }
+
+    async fn embed(&self, model: &str, inputs: &[String]) -> Result<Vec<Vec<f32>>> {
+        let span = self.span("embeddings", model, false);
+        let result = self
+            .inner
+            .embed(model, inputs)
+            .instrument(span.clone())
+            .await;
+        if let Err(err) = &result {
+            record_error(&span, err);
+        }
+        result
+    }
+}
@@ -0,0 +1,359 @@
+use std::fmt::Write as _;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use reqwest::{Client, RequestBuilder, Url};
+use ring::{digest, hmac};
+use yup_oauth2::{read_service_account_key, ServiceAccountAuthenticator};
+
+use crate::config::{RemoteConfig, RemoteTarget};
+use crate::logger::{self, HistoryFormat, SessionMeta};
+use crate::provider::ChatMessage;
+use crate::retry::RetryPolicy;
+use crate::trace::ResponseExt;
+use crate::{output, shutdown, trace};
+
+const GCS_SCOPE: &str = "https://www.googleapis.com/auth/devstorage.read_write";
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Uploads finished transcripts to the `[sessions.remote]` object store.
+#[derive(Clone)]
+pub struct RemoteSink {
+    config: RemoteConfig,
+    client: Client,
+}
+
+impl RemoteSink {
+    pub fn new(config: RemoteConfig) -> Self {
+        Self {
+            config,
+            client: Client::new(),
+        }
+    }
+
+    /// Uploads the conversation as `<name>.<ext>`, retrying transient
+    /// failures until shutdown is requested, and returns where it went.
+    pub async fn upload(
+        &self,
+        name: &str,
+        format: HistoryFormat,
+        meta: &SessionMeta,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+    ) -> Result<String> {
+        let body = logger::render_history(format, meta, system, messages)?.into_bytes();
+        let key = format!(
+            "{}{name}.{}",
+            self.config.prefix.as_deref().unwrap_or_default(),
+            format.extension()
+        );
+        let policy = RetryPolicy {
+            retries: self.config.retries,
+            backoff: RETRY_DELAY,
+        };
+        let (result, _) = policy
+            .run(&shutdown::token(), || self.put(&key, format, &body))
+            .await;
+        result
+    }
+
+    /// One attempt at the upload; rate limits, network failures and server
+    /// errors are left for the retry policy to try again.
+    async fn put(&self, key: &str, format: HistoryFormat, body: &[u8]) -> Result<String> {
+        let request = self
+            .request(key, body)
+            .await?
+            .header("content-type", format.content_type())
+            .body(body.to_vec());
+        trace::send(request)
+            .await
+            .with_context(|| format!("failed to reach {}", self.describe(key)))?
+            .check_status()
+            .with_context(|| format!("{} rejected the upload", self.describe(key)))?;
+        Ok(self.describe(key))
+    }
+
+    fn describe(&self, key: &str) -> String {
+        match &self.config.target {
+            RemoteTarget::S3 { bucket, .. } => format!("s3://{bucket}/{key}"),
+            RemoteTarget::Gcs { bucket, .. } => format!("gs://{bucket}/{key}"),
+            RemoteTarget::Webdav { url, .. } => format!("{}/{key}", url.trim_end_matches('/')),
+        }
+    }
+
+    async fn request(&self, key: &str, body: &[u8]) -> Result<RequestBuilder> {
+        match &self.config.target {
+            RemoteTarget::S3 {
+                bucket,
+                region,
+                endpoint,
+                access_key_id,
+                secret_access_key,
+            } => {
+                let url = match endpoint {
+                    Some(endpoint) => format!(
+                        "{}/{bucket}/{}",
+                        endpoint.trim_end_matches('/'),
+                        uri_encode(key, true)
+                    ),
+                    None => format!(
+                        "https://{bucket}.s3.{region}.amazonaws.com/{}",
+                        uri_encode(key, true)
+                    ),
+                };
+                let url = Url::parse(&url).with_context(|| format!("invalid S3 URL {url}"))?;
+                let credentials = S3Credentials {
+                    access_key_id: credential(access_key_id, "AWS_ACCESS_KEY_ID")?,
+                    secret_access_key: credential(secret_access_key, "AWS_SECRET_ACCESS_KEY")?,
+                    session_token: std::env::var("AWS_SESSION_TOKEN").ok(),
+                };
+                let mut request = self.client.put(url.clone());
+                for (name, value) in sign_s3_put(&url, region, &credentials, body, Utc::now()) {
+                    request = request.header(name, value);
+                }
+                Ok(request)
+            }
+            RemoteTarget::Gcs {
+                bucket,
+                service_account_file,
+            } => {
+                let key_file = read_service_account_key(service_account_file)
+                    .await
+                    .with_context(|| {
+                        format!(
+                            "failed to read service account JSON at {}",
+                            service_account_file.display()
+                        )
+                    })?;
+                let auth = ServiceAccountAuthenticator::builder(key_file)
+                    .build()
+                    .await
+                    .context("failed to build google authenticator")?;
+                let token = auth
+                    .token(&[GCS_SCOPE])
+                    .await
+                    .context("failed to obtain oauth token")?;
+                let bearer = token
+                    .token()
+                    .ok_or_else(|| anyhow!("oauth token response missing access_token"))?;
+                Ok(self
+                    .client
+                    .post(format!(
+                        "https://storage.googleapis.com/upload/storage/v1/b/{bucket}/o"
+                    ))
+                    .query(&[("uploadType", "media"), ("name", key)])
+                    .bearer_auth(bearer))
+            }
+            RemoteTarget::Webdav {
+                url,
+                username,
+                password,
+            } => {
+                let url = format!("{}/{}", url.trim_end_matches('/'), uri_encode(key, true));
+                let request = self.client.put(url);
+                Ok(match username {
+                    Some(username) => request.basic_auth(username, password.as_deref()),
+                    None => request,
+                })
+            }
+        }
+    }
+}
+
+/// Uploads a finished session, reporting the outcome; a failed upload never
+/// fails the session itself.
+pub async fn upload_finished(
+    sink: Option<&RemoteSink>,
+    name: &str,
+    format: HistoryFormat,
+    meta: &SessionMeta,
+    system: Option<&str>,
+    messages: &[ChatMessage],
+) {
+    let Some(sink) = sink else {
+        return;
+    };
+    if messages.is_empty() {
+        return;
+    }
+    match sink.upload(name, format, meta, system, messages).await {
+        Ok(location) => output::status(&format!("[uploaded chat history to {location}]")),
+        Err(err) => tracing::warn!("failed to upload chat history: {err:#}"),
+    }
+}
+
+fn credential(configured: &Option<String>, env: &str) -> Result<String> {
+    configured
+        .clone()
+        .or_else(|| std::env::var(env).ok())
+        .ok_or_else(|| {
+            anyhow!(
+                "S3 upload needs credentials - set {env} or configure them in [sessions.remote]"
+            )
+        })
+}
+
+struct S3Credentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+/// Headers authenticating a PUT of `body` to `url` with AWS Signature V4.
+fn sign_s3_put(
+    url: &Url,
+    region: &str,
+    credentials: &S3Credentials,
+    body: &[u8],
+    now: DateTime<Utc>,
+) -> Vec<(&'static str, String)> {
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+    let payload_hash = hex(digest::digest(&digest::SHA256, body).as_ref());
+    let host = match url.port() {
+        Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+        None => url.host_str().unwrap_or_default().to_string(),
+    };
+
+    let mut headers = vec![
+        ("host", host),
+        ("x-amz-content-sha256", payload_hash.clone()),
+        ("x-amz-date", amz_date.clone()),
+    ];
+    if let Some(token) = &credentials.session_token {
+        headers.push(("x-amz-security-token", token.clone()));
+    }
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_request = format!(
+        "PUT\n{}\n{}\n{canonical_headers}\n{signed_headers}\n{payload_hash}",
+        url.path(),
+        url.query().unwrap_or_default()
+    );
+
+    let scope = format!("{date}/{region}/s3/aws4_request");
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+        hex(digest::digest(&digest::SHA256, canonical_request.as_bytes()).as_ref())
+    );
+    let key = signing_key(&credentials.secret_access_key, &date, region, "s3");
+    let signature = hex(hmac::sign(
+        &hmac::Key::new(hmac::HMAC_SHA256, &key),
+        string_to_sign.as_bytes(),
+    )
+    .as_ref());
+
+    // reqwest sets Host itself.
+    headers.remove(0);
+    headers.push((
+        "authorization",
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+            credentials.access_key_id
+        ),
+    ));
+    headers
+}
+
+fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let mut key = format!("AWS4{secret}").into_bytes();
+    for part in [date, region, service, "aws4_request"] {
+        let tag = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, &key), part.as_bytes());
+        key = tag.as_ref().to_vec();
+    }
+    key
+}
+
+/// Percent-encodes everything outside RFC 3986's unreserved set, optionally
+/// keeping `/` so keys can contain folders.
+fn uri_encode(text: &str, keep_slash: bool) -> String {
+    let mut encoded = String::new();
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if keep_slash => encoded.push('/'),
+            _ => {
+                let _ = write!(encoded, "%{byte:02X}");
+            }
+        }
+    }
+    encoded
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut out, byte| {
+        let _ = write!(out, "{byte:02x}");
+        out
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn s3_requests_are_signed_with_sigv4() {
+        // Signing key example from the AWS SigV4 documentation.
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20150830",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex(&key),
+            "c4afb1cc5771d871763a393e44b703571b55cc28424d1a5e86da6ed3c154a4b9"
+        );
+
+        let url = Url::parse("https://chats.s3.eu-west-1.amazonaws.com/laptop/a%20b.json").unwrap();
+        let credentials = S3Credentials {
+            access_key_id: "AKIDEXAMPLE".to_string(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            session_token: None,
+        };
+        let now = Utc.with_ymd_and_hms(2024, 5, 1, 12, 30, 45).unwrap();
+        let headers = sign_s3_put(&url, "eu-west-1", &credentials, b"{}", now);
+        assert_eq!(
+            headers.last().unwrap().1,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20240501/eu-west-1/s3/aws4_request, \
+             SignedHeaders=host;x-amz-content-sha256;x-amz-date, \
+             Signature=f823c6da6fcf13e871746b295d95dc4fa32cba0a4cfb931ddd06a8e980cbaad7"
+        );
+        assert!(headers.contains(&("x-amz-date", "20240501T123045Z".to_string())));
+    }
+
+    #[test]
+    fn remote_config_selects_target_by_kind() {
+        let config: RemoteConfig = toml::from_str(
+            "kind = \"webdav\"\nurl = \"https://dav.example.com/chats\"\nusername = \"me\"",
+        )
+        .unwrap();
+        assert!(matches!(config.target, RemoteTarget::Webdav { .. }));
+        assert_eq!(config.retries, 3);
+        let sink = RemoteSink::new(config);
+        assert_eq!(
+            sink.describe("a.json"),
+            "https://dav.example.com/chats/a.json"
+        );
+    }
+
+    #[test]
+    fn object_keys_are_percent_encoded() {
+        assert_eq!(
+            uri_encode("laptop/a b+c.json", true),
+            "laptop/a%20b%2Bc.json"
+        );
+        assert_eq!(uri_encode("a/b", false), "a%2Fb");
+    }
+}
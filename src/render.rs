@@ -0,0 +1,284 @@
+use std::io::{self, IsTerminal, Write};
+
+use termimad::crossterm::{cursor, terminal, QueueableCommand};
+use termimad::MadSkin;
+use unicode_width::UnicodeWidthStr;
+
+/// Renders assistant markdown for the terminal. Rendering is skipped entirely
+/// when disabled or when stdout is not a TTY, so pipes always get raw text.
+pub struct MarkdownRenderer {
+    skin: MadSkin,
+    enabled: bool,
+}
+
+impl MarkdownRenderer {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            skin: MadSkin::default(),
+            enabled: enabled && io::stdout().is_terminal(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled && io::stdout().is_terminal();
+    }
+
+    pub fn render(&self, markdown: &str) -> String {
+        if !self.enabled {
+            return markdown.to_string();
+        }
+        let width = terminal_width();
+        self.skin.text(markdown, Some(width)).to_string()
+    }
+
+    /// Prints a complete reply, rendered when enabled.
+    pub fn print_reply(&self, prefix: &str, markdown: &str) {
+        if self.enabled {
+            println!("{prefix}");
+            print!("{}", self.render(markdown));
+        } else {
+            println!("{prefix}{markdown}");
+        }
+        io::stdout().flush().ok();
+    }
+
+    /// Replaces a reply that was streamed raw by its rendered form once the
+    /// stream has completed.
+    pub fn rerender_streamed(&self, streamed: &StreamPrinter) {
+        if !self.enabled {
+            return;
+        }
+        let rows = printed_rows(&streamed.on_screen(), terminal_width());
+        // Rows scrolled off the top cannot be reached with the cursor; leave
+        // the raw text in place rather than clearing only part of it.
+        let height = terminal::size().map_or(u16::MAX, |(_, rows)| rows);
+        if rows >= height {
+            return;
+        }
+        let mut stdout = io::stdout();
+        if rows > 0 {
+            stdout.queue(cursor::MoveUp(rows)).ok();
+        }
+        stdout.queue(cursor::MoveToColumn(0)).ok();
+        stdout
+            .queue(terminal::Clear(terminal::ClearType::FromCursorDown))
+            .ok();
+        stdout.flush().ok();
+        self.print_reply(&streamed.prefix, &streamed.text);
+    }
+}
+
+/// Prints a reply as it streams in. With wrapping on, lines break between
+/// words at the terminal width, fenced code blocks are left alone, and a
+/// resize mid-reply reflows what is on screen to the new width.
+pub struct StreamPrinter {
+    prefix: String,
+    /// Everything received so far
+    text: String,
+    /// What has been printed after the prefix, breaks included
+    shown: String,
+    /// Width `shown` was wrapped to; `None` prints tokens as they come
+    width: Option<usize>,
+}
+
+impl StreamPrinter {
+    /// Prints `prefix` (the `bot>` label, if any) and starts the reply.
+    pub fn start(prefix: &str, wrap: bool) -> Self {
+        print!("{prefix}");
+        io::stdout().flush().ok();
+        Self {
+            prefix: prefix.to_string(),
+            text: String::new(),
+            shown: String::new(),
+            width: wrap.then(terminal_width),
+        }
+    }
+
+    /// Prints `token`, holding back a word that may still be cut off.
+    pub fn push(&mut self, token: &str) {
+        self.text.push_str(token);
+        if self.width.is_none() {
+            print!("{token}");
+            io::stdout().flush().ok();
+            return;
+        }
+        let end = complete_words(&self.text);
+        self.show(end);
+    }
+
+    /// Prints whatever was held back; call once the stream has ended.
+    pub fn finish(&mut self) {
+        if self.width.is_some() {
+            self.show(self.text.len());
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn into_text(self) -> String {
+        self.text
+    }
+
+    /// The reply as it stands on screen, label included.
+    fn on_screen(&self) -> String {
+        match self.width {
+            Some(_) => format!("{}{}", self.prefix, self.shown),
+            None => format!("{}{}", self.prefix, self.text),
+        }
+    }
+
+    /// Brings the screen up to `text[..end]`, reflowing it first when the
+    /// terminal has been resized.
+    fn show(&mut self, end: usize) {
+        let Some(width) = self.width else {
+            return;
+        };
+        let current = terminal_width();
+        if current != width && self.reflow(current, end) {
+            return;
+        }
+        let wrapped = wrap(&self.text[..end], width, self.prefix.width());
+        match wrapped.strip_prefix(self.shown.as_str()) {
+            Some(new) => {
+                print!("{new}");
+                io::stdout().flush().ok();
+                self.shown = wrapped;
+            }
+            None => {
+                self.reflow(width, end);
+            }
+        }
+    }
+
+    /// Clears the reply and prints it again wrapped to `width`. Returns false
+    /// when part of it has scrolled out of reach, leaving the old layout.
+    fn reflow(&mut self, width: usize, end: usize) -> bool {
+        let above = printed_rows(&self.on_screen(), width).saturating_sub(1);
+        let height = terminal::size().map_or(u16::MAX, |(_, rows)| rows);
+        if above >= height {
+            return false;
+        }
+        let mut stdout = io::stdout();
+        if above > 0 {
+            stdout.queue(cursor::MoveUp(above)).ok();
+        }
+        stdout.queue(cursor::MoveToColumn(0)).ok();
+        stdout
+            .queue(terminal::Clear(terminal::ClearType::FromCursorDown))
+            .ok();
+        self.shown = wrap(&self.text[..end], width, self.prefix.width());
+        self.width = Some(width);
+        print!("{}{}", self.prefix, self.shown);
+        stdout.flush().ok();
+        true
+    }
+}
+
+/// Where the text that can be wrapped for good ends: before the trailing
+/// word, which may continue in the next token, and the spaces ahead of it,
+/// which may yet become a line break.
+fn complete_words(text: &str) -> usize {
+    let line_start = text.rfind('\n').map_or(0, |index| index + 1);
+    let tail = &text[line_start..];
+    let held = tail.trim_end_matches(|c: char| !c.is_whitespace());
+    line_start + held.trim_end().len()
+}
+
+/// Greedy word wrap to `width` columns, the first line starting at `column`.
+/// Spaces at a break are dropped. Fenced code blocks and words wider than a
+/// line are left for the terminal to wrap.
+fn wrap(text: &str, width: usize, mut column: usize) -> String {
+    let mut out = String::with_capacity(text.len() + text.len() / width);
+    let mut in_code = false;
+    for (index, line) in text.split('\n').enumerate() {
+        if index > 0 {
+            out.push('\n');
+            column = 0;
+        }
+        let fence = line.trim_start().starts_with("```");
+        if fence || in_code {
+            in_code ^= fence;
+            out.push_str(line);
+            column += line.width();
+            continue;
+        }
+        let mut rest = line;
+        while !rest.is_empty() {
+            let word_start = rest.len() - rest.trim_start().len();
+            let (spaces, after) = rest.split_at(word_start);
+            let word_end = after.find(char::is_whitespace).unwrap_or(after.len());
+            let (word, after) = after.split_at(word_end);
+            rest = after;
+            if column > 0 && !word.is_empty() && column + spaces.width() + word.width() > width {
+                out.push('\n');
+                column = 0;
+            } else {
+                out.push_str(spaces);
+                column += spaces.width();
+            }
+            out.push_str(word);
+            column += word.width();
+        }
+    }
+    out
+}
+
+pub fn terminal_width() -> usize {
+    terminal::size()
+        .map(|(cols, _)| cols as usize)
+        .unwrap_or(80)
+        .max(20)
+}
+
+/// Number of terminal rows above the cursor occupied by `text`, assuming the
+/// cursor sits on the line following it.
+fn printed_rows(text: &str, width: usize) -> u16 {
+    let rows: usize = text
+        .split('\n')
+        .map(|line| {
+            let len = line.width();
+            if len == 0 {
+                1
+            } else {
+                len.div_ceil(width)
+            }
+        })
+        .sum();
+    rows.min(u16::MAX as usize) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn streamed_text_wraps_between_words() {
+        assert_eq!(wrap("the quick brown fox", 10, 0), "the quick\nbrown fox");
+        assert_eq!(wrap("the quick brown", 10, 5), "the\nquick\nbrown");
+        let code = "see:\n```\nlet x = some_long_call(a, b);\n```\nok";
+        assert_eq!(wrap(code, 10, 0), code);
+        assert_eq!(
+            wrap("a supercalifragilistic b", 8, 0),
+            "a\nsupercalifragilistic\nb"
+        );
+
+        // Only words known to be whole are wrapped, so each step extends the last.
+        assert_eq!(complete_words("hello wor"), "hello".len());
+        assert_eq!(complete_words("hello world "), "hello world".len());
+        assert_eq!(complete_words("one\ntw"), "one\n".len());
+    }
+
+    #[test]
+    fn printed_rows_accounts_for_wrapping() {
+        assert_eq!(printed_rows("bot> hi", 80), 1);
+        assert_eq!(printed_rows("a\nb\n", 80), 3);
+        assert_eq!(printed_rows(&"x".repeat(100), 40), 3);
+        assert_eq!(printed_rows(&"字".repeat(30), 40), 2);
+    }
+}
@@ -1,125 +1,1159 @@
-use std::io::{self, Write};
+use std::io;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
 
-use anyhow::{Context, Result};
+use anyhow::Result;
 use futures::StreamExt;
+use parking_lot::Mutex;
 use rustyline::error::ReadlineError;
-use rustyline::DefaultEditor;
+use termimad::crossterm::{cursor, execute, terminal};
+use tokio::task::JoinHandle;
 
-use crate::logger::{self, HistoryFormat};
-use crate::provider::{ChatMessage, ChatRequestOptions, DynProvider};
+use crate::config::{ContextConfig, UiConfig, WebhookEvent};
+use crate::context;
+use crate::editor::{self, LineEditor};
+use crate::exit::{self, ExitStatus};
+use crate::knowledge::Knowledge;
+use crate::logger::{self, HistoryFormat, SessionMeta, Transcript};
+use crate::memory::{self, Memory};
+use crate::notify::ReplyNotifier;
+use crate::output;
+use crate::pager;
+use crate::provider::{
+    CancellationToken, ChatMessage, ChatRequestOptions, ChatResponse, DynProvider, MessageRole,
+    ReplyMeta, TokenUsage,
+};
+use crate::remote::{self, RemoteSink};
+use crate::render::{MarkdownRenderer, StreamPrinter};
+use crate::sessions::{self, ResumedSession};
+use crate::shell;
+use crate::shutdown::{self, Registration};
+use crate::stats::TurnStats;
+use crate::store::{DynStore, RetentionPolicy, SaveTarget};
+use crate::streaming::StreamEvent;
+use crate::tee::Tee;
+use crate::tokens::Tokenizer;
+use crate::tools::{ToolRegistry, MAX_TOOL_ROUNDS};
+use crate::webhook::{self, Webhook};
+
+const DEFAULT_PROMPT: &str = "you> ";
 
 pub struct ReplOptions {
     pub provider_name: String,
     pub model: String,
     pub system: Option<String>,
-    pub save_path: Option<PathBuf>,
-    pub history_dir: Option<PathBuf>,
+    /// The `--save` file, if any
+    pub save_target: Option<SaveTarget>,
+    /// Store that auto-saved sessions go to
+    pub store: Option<DynStore>,
     pub auto_save: bool,
     pub save_format: HistoryFormat,
-    pub webhook_url: Option<String>,
+    /// Pruning applied after the session is auto-saved
+    pub retention: RetentionPolicy,
+    /// Object storage the finished session is uploaded to
+    pub remote: Option<RemoteSink>,
+    /// Where the session is posted (--webhook-url or --webhook)
+    pub webhook: Option<Webhook>,
+    /// File replies are copied to as they stream (--tee)
+    pub tee: Option<Tee>,
     pub request_options: ChatRequestOptions,
+    /// How the history is cut down before each request
+    pub context: ContextConfig,
     pub stream: bool,
+    pub render_markdown: bool,
+    /// Print replies without the `bot>` label (`--raw`, or stdout piped)
+    pub raw: bool,
+    pub stats: bool,
+    pub ui: UiConfig,
+    pub tools: ToolRegistry,
+    /// Index searched with each message (--knowledge)
+    pub knowledge: Option<Knowledge>,
+    pub notifier: ReplyNotifier,
+    /// Conversation reopened with --continue / --resume
+    pub resumed: Option<ResumedSession>,
+    /// Transcript whose messages seed a new session (--load)
+    pub loaded: Option<(PathBuf, Transcript)>,
+    /// Session title and tags from --title / --tag
+    pub title: Option<String>,
+    pub tags: Vec<String>,
+    /// `--file` contents sent along with the first message
+    pub attachments: Option<String>,
 }
 
-pub async fn run_chat_repl(provider: DynProvider, opts: ReplOptions) -> Result<()> {
-    println!("Type /reset to clear history, blank line to exit.");
+impl ReplOptions {
+    /// Metadata for a new session started with these options.
+    pub fn session_meta(&self) -> SessionMeta {
+        SessionMeta {
+            title: self.title.clone(),
+            tags: self.tags.clone(),
+            ..SessionMeta::new(&self.provider_name, &self.model)
+        }
+    }
+}
 
-    let mut rl = DefaultEditor::new().context("failed to start line editor")?;
-    let mut messages: Vec<ChatMessage> = Vec::new();
+pub async fn run_chat_repl(provider: DynProvider, mut opts: ReplOptions) -> Result<()> {
+    println!(
+        "Type /reset to clear history (/reset --hard also drops the system prompt), /clear to clear the screen, /history to review it, /tokens to see its size, /render to toggle markdown, blank line to exit."
+    );
+
+    let mut line_editor = LineEditor::new(&opts.ui)?;
+    // Resolved once so per-turn autosaves keep rewriting the same file.
+    let history_target = resolve_history_target(&opts);
+    let (meta, messages) = match opts.resumed.take() {
+        Some(resumed) => {
+            println!(
+                "[resumed {} with {} messages; /history to review]",
+                resumed.target.describe(),
+                resumed.transcript.messages.len()
+            );
+            (
+                resumed_meta(resumed.transcript.meta, &opts),
+                resumed.transcript.messages,
+            )
+        }
+        None => match opts.loaded.take() {
+            Some((path, transcript)) => {
+                println!(
+                    "[loaded {} messages from {}; /history to review]",
+                    transcript.messages.len(),
+                    path.display()
+                );
+                (opts.session_meta(), transcript.messages)
+            }
+            None => (opts.session_meta(), Vec::new()),
+        },
+    };
+    let pending_context: Vec<String> = opts.attachments.take().into_iter().collect();
+    if !pending_context.is_empty() {
+        output::status("[--file contents will be included with your first message]");
+    }
+    let guard = TranscriptGuard::install(history_target.clone(), meta.clone(), opts.system.clone());
+    guard.sync(&messages);
+    let mut session = ReplSession {
+        renderer: MarkdownRenderer::new(opts.render_markdown),
+        provider,
+        opts,
+        messages,
+        meta,
+        history_target,
+        guard,
+        pending_context,
+        interrupted: false,
+        trimmed: Trimmed::default(),
+        memory: None,
+        turn_posts: Vec::new(),
+    };
 
     loop {
-        match rl.readline("you> ") {
-            Ok(line) => {
+        let prompt = session.prompt();
+        match line_editor.editor.readline(&prompt) {
+            Ok(mut line) => {
+                if line_editor.take_editor_request() || line.trim() == "/edit" {
+                    let initial = if line.trim() == "/edit" {
+                        ""
+                    } else {
+                        line.as_str()
+                    };
+                    match editor::edit_in_external_editor(initial) {
+                        Ok(edited) => {
+                            if edited.trim().is_empty() {
+                                continue;
+                            }
+                            println!("{edited}");
+                            line = edited;
+                        }
+                        Err(err) => {
+                            tracing::warn!("{err:#}");
+                            continue;
+                        }
+                    }
+                }
                 let trimmed = line.trim();
                 if trimmed.is_empty() {
                     break;
                 }
-                if trimmed == "/reset" {
-                    messages.clear();
-                    println!("[history reset]");
+                if trimmed.starts_with('/') && session.handle_command(trimmed).await {
                     continue;
                 }
 
-                rl.add_history_entry(trimmed).ok();
-                messages.push(ChatMessage::user(line.clone()));
-
-                if opts.stream {
-                    let mut stream = provider
-                        .stream_chat(
-                            &opts.model,
-                            opts.system.as_deref(),
-                            &messages,
-                            &opts.request_options,
-                        )
-                        .await?;
-                    print!("bot> ");
-                    io::stdout().flush().ok();
-                    let mut assistant_response = String::new();
-                    while let Some(chunk) = stream.next().await {
-                        let token = chunk?;
-                        print!("{token}");
-                        io::stdout().flush().ok();
-                        assistant_response.push_str(&token);
-                    }
-                    println!();
-                    messages.push(ChatMessage::assistant(assistant_response));
-                } else {
-                    let response = provider
-                        .chat(
-                            &opts.model,
-                            opts.system.as_deref(),
-                            &messages,
-                            &opts.request_options,
-                        )
-                        .await?;
-                    println!("bot> {response}");
-                    messages.push(ChatMessage::assistant(response));
+                line_editor.editor.add_history_entry(trimmed).ok();
+                if let Some(tee) = &session.opts.tee {
+                    tee.prompt(&line);
+                }
+                let content = session.attach_pending_context(line);
+                let content = session.attach_knowledge(content).await;
+                session.begin_turn();
+                session.messages.push(ChatMessage::user(content));
+                session.complete_turn().await;
+                if shutdown::requested() {
+                    break;
                 }
             }
             Err(ReadlineError::Interrupted) => continue,
             Err(ReadlineError::Eof) => break,
+            // A closed terminal still gets the session saved.
+            Err(_) if shutdown::requested() => break,
             Err(err) => return Err(err.into()),
         }
     }
 
-    match resolve_history_target(&opts) {
-        Some(path) => {
-            logger::save_history(&path, opts.save_format, opts.system.as_deref(), &messages)?;
-            println!("[saved chat history to {}]", path.display());
+    session.finish().await
+}
+
+struct ReplSession {
+    provider: DynProvider,
+    opts: ReplOptions,
+    messages: Vec<ChatMessage>,
+    renderer: MarkdownRenderer,
+    meta: SessionMeta,
+    history_target: Option<SaveTarget>,
+    guard: TranscriptGuard,
+    /// Shell output captured with /shell, prepended to the next user message
+    pending_context: Vec<String>,
+    /// The last message is a reply cut short by a failed stream
+    interrupted: bool,
+    /// What the context strategy left out of the last request
+    trimmed: Trimmed,
+    /// Summary sent in place of the oldest messages (`[context] summarize`)
+    memory: Option<Memory>,
+    /// `--webhook-per-turn` posts still being sent
+    turn_posts: Vec<JoinHandle<()>>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Trimmed {
+    sent: usize,
+    dropped: usize,
+    dropped_system: bool,
+}
+
+impl ReplSession {
+    fn prompt(&self) -> String {
+        let Some(template) = self.opts.ui.prompt.as_deref() else {
+            return DEFAULT_PROMPT.to_string();
+        };
+        let turn = self
+            .messages
+            .iter()
+            .filter(|message| message.role == MessageRole::User)
+            .count()
+            + 1;
+        let tokens = context::history_tokens(
+            &self.opts.model,
+            self.opts.system.as_deref(),
+            &self.messages,
+        );
+        render_prompt(
+            template,
+            &self.opts.provider_name,
+            &self.opts.model,
+            turn,
+            tokens,
+        )
+    }
+
+    /// Handles a slash command. Returns false for unknown commands so they are
+    /// sent to the model as ordinary text.
+    async fn handle_command(&mut self, line: &str) -> bool {
+        let (command, arg) = line.split_once(' ').unwrap_or((line, ""));
+        let arg = arg.trim();
+        match command {
+            "/reset" => match arg {
+                "" => self.reset(false),
+                "--hard" => self.reset(true),
+                _ => println!("[usage: /reset [--hard]]"),
+            },
+            "/clear" => {
+                let mut stdout = io::stdout();
+                let _ = execute!(
+                    stdout,
+                    terminal::Clear(terminal::ClearType::All),
+                    cursor::MoveTo(0, 0)
+                );
+            }
+            "/render" => {
+                let enable = match arg {
+                    "on" => true,
+                    "off" => false,
+                    _ => !self.renderer.enabled(),
+                };
+                self.renderer.set_enabled(enable);
+                if enable && !self.renderer.enabled() {
+                    println!("[markdown rendering unavailable: stdout is not a terminal]");
+                } else {
+                    println!("[markdown rendering {}]", if enable { "on" } else { "off" });
+                }
+            }
+            "/retry" => {
+                if self.awaiting_reply() {
+                    if std::mem::take(&mut self.interrupted) {
+                        self.messages.pop();
+                    }
+                    self.complete_turn().await;
+                } else {
+                    println!("[nothing to retry]");
+                }
+            }
+            "/drop" => {
+                if self.awaiting_reply() {
+                    if std::mem::take(&mut self.interrupted) {
+                        self.messages.pop();
+                    }
+                    drop_unanswered(&mut self.messages);
+                    self.guard.sync(&self.messages);
+                    println!("[dropped unanswered message]");
+                } else {
+                    println!("[nothing to drop]");
+                }
+            }
+            "/shell" => self.run_shell(arg),
+            "/tokens" => self.print_tokens(),
+            "/memory" => self.handle_memory(arg),
+            "/title" => {
+                if arg.is_empty() {
+                    match self.meta.title.as_deref() {
+                        Some(title) => println!("[title: {title}]"),
+                        None => println!("[untitled; /title <name> to name this session]"),
+                    }
+                } else {
+                    self.meta.title = Some(arg.to_string());
+                    self.guard.set_meta(self.meta.clone());
+                    self.autosave();
+                    println!("[title set to {arg}]");
+                }
+            }
+            "/history" => {
+                let full = arg == "--full";
+                if self.messages.is_empty() {
+                    println!("[no messages yet]");
+                } else {
+                    pager::page(&sessions::format_transcript(
+                        self.opts.system.as_deref(),
+                        &self.messages,
+                        !full,
+                    ));
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Prints the size of the history, counted with the model's tokenizer,
+    /// and what the context strategy left out of the last request.
+    fn print_tokens(&self) {
+        let tokenizer = Tokenizer::for_model(&self.opts.model);
+        // Only OpenAI vocabularies are published; other counts are estimates.
+        let about = if tokenizer.is_exact() { "" } else { "~" };
+        let system = self.opts.system.as_deref();
+        let window = match context::context_window(&self.opts.model) {
+            Some(window) => format!("context window {window}"),
+            None => "context window unknown".to_string(),
+        };
+        println!(
+            "[{about}{} tokens ({}): system {about}{}, {} message(s); {window} for {}]",
+            tokenizer.count_chat(system, &self.messages),
+            tokenizer.as_str(),
+            tokenizer.count_chat(system, &[]),
+            self.messages.len(),
+            self.opts.model
+        );
+        let strategy = self.opts.context.strategy.as_str();
+        let Trimmed {
+            sent,
+            dropped,
+            dropped_system,
+        } = self.trimmed;
+        if let Some(memory) = &self.memory {
+            println!(
+                "[memory: {about}{} tokens standing in for the first {} message(s); /memory to view it]",
+                tokenizer.count(&memory.text),
+                memory.covers
+            );
         }
-        None if opts.auto_save => {
-            eprintln!("[warn] auto-save requested but no history directory is available");
+        if dropped == 0 && !dropped_system {
+            println!("[context strategy {strategy}: the last request sent the whole history]");
+        } else {
+            let system = if dropped_system {
+                " and the system prompt"
+            } else {
+                ""
+            };
+            println!(
+                "[context strategy {strategy}: the last request sent {sent} of {} message(s), leaving out {dropped}{system}]",
+                sent + dropped
+            );
         }
-        _ => {}
     }
 
-    if let Some(url) = opts.webhook_url.as_deref() {
-        if let Err(err) =
-            logger::send_history_webhook(url, opts.save_format, opts.system.as_deref(), &messages)
-                .await
+    /// `/memory` shows the summary of older turns, `/memory edit` opens it in
+    /// the editor and `/memory clear` drops it so the full history is sent.
+    fn handle_memory(&mut self, arg: &str) {
+        match arg {
+            "" => match &self.memory {
+                Some(memory) => println!(
+                    "[memory of the first {} message(s)]\n{}",
+                    memory.covers,
+                    memory.text.trim_end()
+                ),
+                None => println!("[no memory yet; older turns are summarized once the history nears the context window]"),
+            },
+            "edit" => {
+                let initial = self.memory.as_ref().map_or("", |memory| memory.text.as_str());
+                match editor::edit_in_external_editor(initial) {
+                    Ok(text) if text.trim().is_empty() => {
+                        self.memory = None;
+                        println!("[memory cleared]");
+                    }
+                    Ok(text) => {
+                        let covers = self.memory.as_ref().map_or(0, |memory| memory.covers);
+                        self.memory = Some(Memory { text, covers });
+                        println!("[memory updated]");
+                    }
+                    Err(err) => tracing::warn!("{err:#}"),
+                }
+            }
+            "clear" => {
+                self.memory = None;
+                println!("[memory cleared; the full history is sent again]");
+            }
+            _ => println!("[usage: /memory [edit|clear]]"),
+        }
+    }
+
+    /// Folds older turns into the memory once the history nears the context
+    /// window. A failed summary only warns; the request goes out as it is.
+    async fn refresh_memory(&mut self, cancel: &CancellationToken) {
+        let opts = &self.opts;
+        let Some(cut) = memory::due(
+            &opts.context,
+            &opts.model,
+            opts.system.as_deref(),
+            self.memory.as_ref(),
+            &self.messages,
+        ) else {
+            return;
+        };
+        let model = opts.context.summary_model.as_deref().unwrap_or(&opts.model);
+        let covers = self.memory.as_ref().map_or(0, |memory| memory.covers);
+        match memory::summarize(
+            &self.provider,
+            model,
+            self.memory.as_ref(),
+            &self.messages,
+            cut,
+            cancel,
+        )
+        .await
+        {
+            Ok(memory) => {
+                output::status(&format!(
+                    "[summarized {} older message(s) into memory; /memory to view or edit it]",
+                    cut - covers
+                ));
+                self.memory = Some(memory);
+            }
+            Err(err) if exit::status_for(&err) == ExitStatus::Canceled => {}
+            Err(err) => tracing::warn!("failed to summarize older messages: {err:#}"),
+        }
+    }
+
+    /// Clears the conversation. A soft reset keeps the system prompt; `hard`
+    /// also drops it along with any pending `/shell` output.
+    fn reset(&mut self, hard: bool) {
+        // Autosaved conversations move to their own file; anything else asks first.
+        if !self.messages.is_empty()
+            && !self.rotate_autosave_target()
+            && !shell::confirm(&format!(
+                "Discard {} unsaved message(s)?",
+                self.messages.len()
+            ))
         {
-            eprintln!("[warn] failed to POST chat history: {err:#}");
+            println!("[reset cancelled]");
+            return;
+        }
+        self.messages.clear();
+        self.interrupted = false;
+        self.trimmed = Trimmed::default();
+        self.memory = None;
+        if hard {
+            self.opts.system = None;
+            self.pending_context.clear();
+        }
+        self.guard.reset(
+            self.history_target.clone(),
+            self.meta.clone(),
+            self.opts.system.clone(),
+        );
+        println!("[{} reset]", if hard { "session" } else { "history" });
+    }
+
+    /// When autosaving into the history directory, saves the current
+    /// conversation and points later saves at a fresh file. Returns false when
+    /// the conversation would otherwise be lost (no target, or an explicit
+    /// `--save` path that the next save would overwrite).
+    fn rotate_autosave_target(&mut self) -> bool {
+        if !self.opts.auto_save {
+            return false;
+        }
+        let Some(SaveTarget::Session { store, id }) = &self.history_target else {
+            return false;
+        };
+        let next = logger::timestamped_session_id(&self.opts.provider_name);
+        if &next == id {
+            return false;
+        }
+        if let Err(err) = store.save(id, &self.meta, self.opts.system.as_deref(), &self.messages) {
+            tracing::warn!("failed to save chat history: {err:#}");
+            return false;
+        }
+        output::status(&format!("[saved chat history to {}]", store.describe(id)));
+        self.history_target = Some(SaveTarget::Session {
+            store: Arc::clone(store),
+            id: next,
+        });
+        self.meta = self.opts.session_meta();
+        true
+    }
+
+    fn run_shell(&mut self, command: &str) {
+        if command.is_empty() {
+            println!("[usage: /shell <command>]");
+            return;
+        }
+        if !shell::confirm(&format!("Run `{command}`?")) {
+            println!("[skipped]");
+            return;
+        }
+        match shell::run_command(command) {
+            Ok(output) => {
+                let context = output.as_context(command);
+                println!("{context}");
+                self.pending_context.push(context);
+                println!("[output will be included with your next message]");
+            }
+            Err(err) => eprintln!("[error] {err:#}"),
+        }
+    }
+
+    fn reply_label(&self) -> &'static str {
+        if self.opts.raw {
+            ""
         } else {
-            println!("[pushed chat history to webhook]");
+            "bot> "
+        }
+    }
+
+    fn attach_pending_context(&mut self, line: String) -> String {
+        if self.pending_context.is_empty() {
+            return line;
+        }
+        let mut content = self
+            .pending_context
+            .drain(..)
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        content.push_str("\n\n");
+        content.push_str(&line);
+        content
+    }
+
+    /// Prepends the `--knowledge` passages closest to `content`. A failed
+    /// search is reported and the message is sent without them.
+    async fn attach_knowledge(&self, content: String) -> String {
+        let Some(knowledge) = &self.opts.knowledge else {
+            return content;
+        };
+        match knowledge.context_for(&content).await {
+            Ok(Some(context)) => format!("{context}\n\n{content}"),
+            Ok(None) => content,
+            Err(err) => {
+                tracing::warn!("searching index '{}' failed: {err:#}", knowledge.name);
+                content
+            }
+        }
+    }
+
+    /// True when the last message still expects a model reply (a user turn or
+    /// tool result), which is the state left behind by a failed request, or
+    /// is a reply that was cut short.
+    fn awaiting_reply(&self) -> bool {
+        self.interrupted
+            || self.messages.last().is_some_and(|message| {
+                matches!(message.role, MessageRole::User | MessageRole::Tool)
+            })
+    }
+
+    /// Called before a new user message is added. A message whose request
+    /// failed is replaced, since providers reject two user turns in a row; a
+    /// partial reply is kept as the answer.
+    fn begin_turn(&mut self) {
+        if std::mem::take(&mut self.interrupted) {
+            return;
+        }
+        if self.awaiting_reply() {
+            drop_unanswered(&mut self.messages);
+            println!("[replacing the unanswered message]");
         }
     }
 
-    Ok(())
+    /// Requests a reply for the pending user message. Provider failures are
+    /// reported and leave the user message in place instead of ending the session.
+    /// Ctrl-C meanwhile stops the reply rather than the session.
+    async fn complete_turn(&mut self) {
+        let cancel = self.guard.begin_reply();
+        self.run_turn(&cancel).await;
+        self.guard.end_reply();
+    }
+
+    async fn run_turn(&mut self, cancel: &CancellationToken) {
+        self.guard.sync(&self.messages);
+        self.refresh_memory(cancel).await;
+        let started = Instant::now();
+        let mut total_usage: Option<TokenUsage> = None;
+        for round in 0..=MAX_TOOL_ROUNDS {
+            // Past the limit the model still gets to answer, just not with tools.
+            let final_round = round == MAX_TOOL_ROUNDS;
+            if final_round {
+                eprintln!(
+                    "[tool call limit of {MAX_TOOL_ROUNDS} rounds reached - asking for an answer without tools]"
+                );
+            }
+            let round_started = Instant::now();
+            let (reply, interruption) = match self.request_reply(final_round, cancel).await {
+                Ok(reply) => reply,
+                Err(err) => {
+                    report_failure(&err);
+                    eprintln!("[message kept: /retry to resend it, /drop to discard it]");
+                    return;
+                }
+            };
+            if let Some(usage) = reply.usage {
+                let total = total_usage.get_or_insert_with(TokenUsage::default);
+                total.input_tokens += usage.input_tokens;
+                total.output_tokens += usage.output_tokens;
+            }
+            let reply_meta = ReplyMeta {
+                provider: self.opts.provider_name.clone(),
+                model: self.opts.model.clone(),
+                usage: reply.usage,
+                latency: round_started.elapsed(),
+            };
+            if let Some(err) = interruption {
+                report_failure(&err);
+                eprintln!(
+                    "[partial reply kept: /retry to replace it, /drop to discard the exchange]"
+                );
+                self.messages
+                    .push(ChatMessage::assistant(reply.text).with_reply(reply_meta));
+                self.interrupted = true;
+                self.guard.sync(&self.messages);
+                return;
+            }
+            if reply.tool_calls.is_empty() || final_round {
+                self.messages
+                    .push(ChatMessage::assistant(reply.text).with_reply(reply_meta));
+                break;
+            }
+            let calls = reply.tool_calls.clone();
+            self.messages.push(
+                ChatMessage::assistant_with_tools(reply.text, reply.tool_calls)
+                    .with_reply(reply_meta),
+            );
+            for call in &calls {
+                let result = self.opts.tools.execute(call).await;
+                self.messages
+                    .push(ChatMessage::tool_result(&call.id, result));
+            }
+            self.guard.sync(&self.messages);
+        }
+        if let Some(last) = self.messages.last() {
+            self.opts
+                .notifier
+                .reply_finished(&self.opts.model, started.elapsed(), &last.content);
+        }
+        if self.opts.stats {
+            TurnStats {
+                model: &self.opts.model,
+                elapsed: started.elapsed(),
+                usage: total_usage,
+            }
+            .print();
+        }
+        self.guard.sync(&self.messages);
+        self.autosave();
+        self.post_turn();
+    }
+
+    /// Fetches and prints the next reply. A stream that fails after producing
+    /// text yields that text along with the error.
+    async fn request_reply(
+        &mut self,
+        forbid_tool_calls: bool,
+        cancel: &CancellationToken,
+    ) -> Result<(ChatResponse, Option<anyhow::Error>)> {
+        let opts = &self.opts;
+        let request_options = ChatRequestOptions {
+            forbid_tool_calls,
+            ..opts.request_options.clone()
+        };
+        // Messages folded into the memory are sent as part of the system prompt.
+        let (system, messages) = match &self.memory {
+            Some(memory) => {
+                let covers = memory.covers.min(self.messages.len());
+                (
+                    Some(memory.system_prompt(opts.system.as_deref())),
+                    &self.messages[covers..],
+                )
+            }
+            None => (opts.system.clone(), self.messages.as_slice()),
+        };
+        let window = context::apply(
+            &opts.context,
+            &opts.model,
+            system.as_deref(),
+            messages,
+            request_options.max_output_tokens,
+        );
+        let trimmed = Trimmed {
+            sent: window.messages.len(),
+            dropped: window.dropped,
+            dropped_system: window.dropped_system,
+        };
+        if trimmed.dropped > 0 && self.trimmed.dropped == 0 {
+            output::status(&format!(
+                "[older messages are no longer sent (context strategy {}); /tokens for details]",
+                opts.context.strategy.as_str()
+            ));
+        }
+        self.trimmed = trimmed;
+        // Tool calls are only surfaced by non-streaming responses.
+        if !opts.stream || !opts.tools.is_empty() {
+            let response = self
+                .provider
+                .chat(
+                    &opts.model,
+                    window.system,
+                    window.messages,
+                    &request_options,
+                    cancel,
+                )
+                .await?;
+            if !response.text.is_empty() {
+                self.renderer
+                    .print_reply(self.reply_label(), &response.text);
+                if let Some(tee) = &opts.tee {
+                    tee.reply(&response.text);
+                    tee.end_reply();
+                }
+            }
+            return Ok((response, None));
+        }
+
+        let mut stream = self
+            .provider
+            .stream_chat(
+                &opts.model,
+                window.system,
+                window.messages,
+                &request_options,
+                cancel,
+            )
+            .await?;
+        let wrap = !opts.raw && opts.ui.wrap != Some(false);
+        let mut printer = StreamPrinter::start(self.reply_label(), wrap);
+        let mut usage: Option<TokenUsage> = None;
+        let mut finish_reason = None;
+        while let Some(event) = stream.next().await {
+            let event = match event {
+                Ok(event) => event,
+                Err(err) => {
+                    printer.finish();
+                    println!();
+                    if let Some(tee) = &opts.tee {
+                        tee.end_reply();
+                    }
+                    if printer.text().is_empty() {
+                        return Err(err);
+                    }
+                    let partial = ChatResponse {
+                        text: printer.into_text(),
+                        usage,
+                        ..Default::default()
+                    };
+                    return Ok((partial, Some(err)));
+                }
+            };
+            match event {
+                StreamEvent::Text(token) => {
+                    printer.push(&token);
+                    if let Some(tee) = &opts.tee {
+                        tee.reply(&token);
+                    }
+                }
+                StreamEvent::Usage(reported) => {
+                    usage
+                        .get_or_insert_with(TokenUsage::default)
+                        .absorb(reported);
+                }
+                StreamEvent::Finish(reason) => finish_reason = Some(reason),
+            }
+        }
+        printer.finish();
+        println!();
+        if let Some(tee) = &opts.tee {
+            tee.end_reply();
+        }
+        self.renderer.rerender_streamed(&printer);
+        let response = ChatResponse {
+            text: printer.into_text(),
+            usage,
+            finish_reason,
+            ..Default::default()
+        };
+        Ok((response, None))
+    }
+
+    fn autosave(&self) {
+        if !self.opts.auto_save {
+            return;
+        }
+        if let Some(target) = self.history_target.as_ref() {
+            if let Err(err) = target.save(&self.meta, self.opts.system.as_deref(), &self.messages) {
+                tracing::warn!("failed to auto-save chat history: {err:#}");
+            }
+        }
+    }
+
+    /// Sends the exchange that just finished, from its user message on, to a
+    /// webhook that takes `turn` events.
+    fn post_turn(&mut self) {
+        let Some(webhook) = self.opts.webhook.as_ref() else {
+            return;
+        };
+        if !webhook.posts(WebhookEvent::Turn) {
+            return;
+        }
+        let start = self
+            .messages
+            .iter()
+            .rposition(|message| message.role == MessageRole::User)
+            .unwrap_or(0);
+        self.turn_posts.retain(|post| !post.is_finished());
+        self.turn_posts.push(webhook::post_turn(
+            webhook,
+            self.opts.save_format,
+            self.meta.clone(),
+            self.opts.system.clone(),
+            self.messages[start..].to_vec(),
+        ));
+    }
+
+    async fn finish(self) -> Result<()> {
+        let ReplSession {
+            opts,
+            messages,
+            meta,
+            history_target,
+            guard,
+            turn_posts,
+            ..
+        } = self;
+        drop(guard);
+        for post in turn_posts {
+            let _ = post.await;
+        }
+        match &history_target {
+            Some(target) => {
+                target.save(&meta, opts.system.as_deref(), &messages)?;
+                output::status(&format!("[saved chat history to {}]", target.describe()));
+                target.apply_retention(&opts.retention);
+            }
+            None if opts.auto_save => {
+                tracing::warn!("auto-save requested but no history directory is available");
+            }
+            _ => {}
+        }
+        remote::upload_finished(
+            opts.remote.as_ref(),
+            &upload_name(history_target.as_ref(), &opts.provider_name),
+            opts.save_format,
+            &meta,
+            opts.system.as_deref(),
+            &messages,
+        )
+        .await;
+
+        webhook::post_finished(
+            opts.webhook.as_ref(),
+            opts.save_format,
+            &meta,
+            opts.system.as_deref(),
+            &messages,
+        )
+        .await;
+
+        Ok(())
+    }
+}
+
+/// Object name for the remote copy: the local session's name when it has one.
+pub fn upload_name(target: Option<&SaveTarget>, provider: &str) -> String {
+    target.map_or_else(
+        || logger::timestamped_session_id(provider),
+        SaveTarget::name,
+    )
+}
+
+/// Removes the trailing unanswered user message along with any tool
+/// round-trips already made for it.
+fn drop_unanswered(messages: &mut Vec<ChatMessage>) {
+    while messages.last().is_some_and(|message| {
+        message.role != MessageRole::Assistant || !message.tool_calls.is_empty()
+    }) {
+        if messages.pop().map(|m| m.role) == Some(MessageRole::User) {
+            break;
+        }
+    }
+}
+
+fn render_prompt(
+    template: &str,
+    provider: &str,
+    model: &str,
+    turn: usize,
+    tokens: usize,
+) -> String {
+    template
+        .replace("{provider}", provider)
+        .replace("{model}", model)
+        .replace("{turn}", &turn.to_string())
+        .replace("{tokens}", &tokens.to_string())
+}
+
+/// Keeps a resumed session's start time, title and tags while recording the
+/// provider and model now in use; --title replaces the title and --tag adds tags.
+pub fn resumed_meta(mut meta: SessionMeta, opts: &ReplOptions) -> SessionMeta {
+    meta.provider = Some(opts.provider_name.clone());
+    meta.model = Some(opts.model.clone());
+    meta.created_at = meta.created_at.or_else(|| Some(chrono::Utc::now()));
+    if opts.title.is_some() {
+        meta.title = opts.title.clone();
+    }
+    for tag in &opts.tags {
+        if !meta.tags.contains(tag) {
+            meta.tags.push(tag.clone());
+        }
+    }
+    meta
 }
 
-fn resolve_history_target(opts: &ReplOptions) -> Option<PathBuf> {
-    if let Some(path) = opts.save_path.as_ref() {
-        return Some(path.clone());
+/// Resolves where the conversation is saved: an explicit `--save` file, the
+/// resumed session, or a fresh auto-saved session.
+pub fn resolve_history_target(opts: &ReplOptions) -> Option<SaveTarget> {
+    if let Some(target) = opts.save_target.as_ref() {
+        return Some(target.clone());
+    }
+    if let Some(resumed) = opts.resumed.as_ref() {
+        return Some(resumed.target.clone());
     }
     if opts.auto_save {
-        if let Some(dir) = opts.history_dir.as_ref() {
-            return Some(logger::timestamped_history_path(
-                dir,
-                &opts.provider_name,
-                opts.save_format,
-            ));
+        if let Some(store) = opts.store.as_ref() {
+            return Some(SaveTarget::Session {
+                store: Arc::clone(store),
+                id: logger::timestamped_session_id(&opts.provider_name),
+            });
         }
     }
     None
 }
+
+/// Keeps a copy of the live conversation so it can be flushed to the history
+/// target when the process is interrupted (Ctrl-C outside a reply), shut down
+/// before the loop ends, or panics, rather than only on a clean exit from the
+/// loop.
+struct TranscriptGuard {
+    state: Arc<Mutex<GuardState>>,
+    signal_task: tokio::task::JoinHandle<()>,
+    _flush: Registration,
+    /// Reinstates the panic hook that was active before `install`
+    restore_hook: Option<Box<dyn FnOnce() + Send>>,
+}
+
+struct GuardState {
+    target: Option<SaveTarget>,
+    meta: SessionMeta,
+    system: Option<String>,
+    messages: Vec<ChatMessage>,
+    /// The reply in flight, which Ctrl-C stops instead of exiting
+    reply: Option<CancellationToken>,
+}
+
+impl GuardState {
+    fn flush(&self) {
+        let Some(target) = self.target.as_ref() else {
+            return;
+        };
+        if self.messages.is_empty() {
+            return;
+        }
+        match target.save(&self.meta, self.system.as_deref(), &self.messages) {
+            Ok(()) => eprintln!("[saved chat history to {}]", target.describe()),
+            Err(err) => tracing::warn!("failed to save chat history: {err:#}"),
+        }
+    }
+}
+
+impl TranscriptGuard {
+    fn install(target: Option<SaveTarget>, meta: SessionMeta, system: Option<String>) -> Self {
+        let state = Arc::new(Mutex::new(GuardState {
+            target,
+            meta,
+            system,
+            messages: Vec::new(),
+            reply: None,
+        }));
+
+        let panic_state = Arc::clone(&state);
+        let previous_hook = Arc::new(std::panic::take_hook());
+        let chained_hook = Arc::clone(&previous_hook);
+        std::panic::set_hook(Box::new(move |info| {
+            // try_lock: the panic may have happened while the state was held.
+            if let Some(state) = panic_state.try_lock() {
+                state.flush();
+            }
+            chained_hook(info);
+        }));
+        let restore_hook: Box<dyn FnOnce() + Send> = Box::new(move || {
+            std::panic::set_hook(Box::new(move |info| previous_hook(info)));
+        });
+
+        let flush_state = Arc::clone(&state);
+        let flush = shutdown::on_flush(move || {
+            // Exiting mid-stream may leave the terminal in raw mode or the
+            // cursor hidden; exit() skips the usual cleanup.
+            let _ = terminal::disable_raw_mode();
+            let _ = execute!(io::stdout(), cursor::Show);
+            flush_state.lock().flush();
+        });
+        let signal_state = Arc::clone(&state);
+        let signal_task = tokio::spawn(async move {
+            loop {
+                let _ = tokio::signal::ctrl_c().await;
+                let Some(reply) = signal_state.lock().reply.take() else {
+                    break;
+                };
+                reply.cancel();
+            }
+            eprintln!();
+            shutdown::exit(ExitStatus::Canceled as u8).await;
+        });
+
+        Self {
+            state,
+            signal_task,
+            _flush: flush,
+            restore_hook: Some(restore_hook),
+        }
+    }
+
+    fn sync(&self, messages: &[ChatMessage]) {
+        self.state.lock().messages = messages.to_vec();
+    }
+
+    /// A token for the reply about to be requested, fired by the next Ctrl-C
+    /// or at shutdown.
+    fn begin_reply(&self) -> CancellationToken {
+        let cancel = shutdown::token();
+        self.state.lock().reply = Some(cancel.clone());
+        cancel
+    }
+
+    fn end_reply(&self) {
+        self.state.lock().reply = None;
+    }
+
+    fn set_meta(&self, meta: SessionMeta) {
+        self.state.lock().meta = meta;
+    }
+
+    /// Starts tracking an empty conversation, possibly with a new target file
+    /// and system prompt.
+    fn reset(&self, target: Option<SaveTarget>, meta: SessionMeta, system: Option<String>) {
+        let mut state = self.state.lock();
+        state.target = target;
+        state.meta = meta;
+        state.system = system;
+        state.messages.clear();
+    }
+}
+
+impl Drop for TranscriptGuard {
+    fn drop(&mut self) {
+        self.signal_task.abort();
+        if let Some(restore) = self.restore_hook.take() {
+            restore();
+        }
+    }
+}
+
+/// Prints why a request failed; a reply stopped with Ctrl-C is no error.
+fn report_failure(err: &anyhow::Error) {
+    if exit::status_for(err) == ExitStatus::Canceled {
+        eprintln!("[stopped]");
+    } else {
+        eprintln!("[error] {err:#}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::ToolCall;
+
+    #[test]
+    fn dropping_unanswered_message_unwinds_tool_rounds() {
+        let call = ToolCall {
+            id: "call-1".to_string(),
+            name: "shell".to_string(),
+            arguments: serde_json::json!({ "command": "ls" }),
+        };
+        let mut messages = vec![
+            ChatMessage::user("hi"),
+            ChatMessage::assistant("hello"),
+            ChatMessage::user("list files"),
+            ChatMessage::assistant_with_tools("", vec![call]),
+            ChatMessage::tool_result("call-1", "a.txt"),
+        ];
+        drop_unanswered(&mut messages);
+        let contents: Vec<&str> = messages.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["hi", "hello"]);
+
+        // A conversation ending in a reply has nothing to drop.
+        drop_unanswered(&mut messages);
+        assert_eq!(messages.len(), 2);
+    }
+
+    #[test]
+    fn prompt_template_expands_placeholders() {
+        assert_eq!(
+            render_prompt(
+                "{provider}/{model} [{turn}, ~{tokens}]> ",
+                "openai",
+                "gpt-4o",
+                3,
+                120
+            ),
+            "openai/gpt-4o [3, ~120]> "
+        );
+        assert_eq!(
+            render_prompt("{turn}{turn}> ", "openai", "gpt-4o", 2, 0),
+            "22> "
+        );
+        assert_eq!(
+            render_prompt("{unknown}> ", "openai", "gpt-4o", 1, 0),
+            "{unknown}> "
+        );
+    }
+}
@@ -0,0 +1,175 @@
+use std::future::Future;
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::exit::{self, ExitStatus};
+use crate::provider::{
+    until_canceled, CancellationToken, ChatMessage, ChatRequestOptions, ChatResponse, DynProvider,
+};
+use crate::streaming::ChatStream;
+use crate::trace::RetryAfter;
+
+/// Longest wait between attempts. A server that asks for longer is not
+/// retried: the wait would outlast any user.
+pub const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// How many times to resend a request that failed for a reason that may go
+/// away by itself, and how long to wait first.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    /// Delay before the first retry; doubles on each further one, up to
+    /// `MAX_DELAY`. A 429's `Retry-After` takes its place.
+    pub backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Sends the request until it succeeds, fails for good, or the retries
+    /// run out. Returns the outcome and how many attempts it took.
+    pub async fn chat(
+        &self,
+        provider: &DynProvider,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+        cancel: &CancellationToken,
+    ) -> (Result<ChatResponse>, u32) {
+        self.run(cancel, || {
+            provider.chat(model, system, messages, options, cancel)
+        })
+        .await
+    }
+
+    /// Opens a reply stream, retrying only until it starts: text already
+    /// printed cannot be taken back.
+    pub async fn stream_chat(
+        &self,
+        provider: &DynProvider,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &ChatRequestOptions,
+        cancel: &CancellationToken,
+    ) -> (Result<ChatStream>, u32) {
+        self.run(cancel, || {
+            provider.stream_chat(model, system, messages, options, cancel)
+        })
+        .await
+    }
+
+    /// Waits between attempts unless `cancel` fires, which ends the loop.
+    pub async fn run<T, F, Fut>(&self, cancel: &CancellationToken, mut send: F) -> (Result<T>, u32)
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            match send().await {
+                Err(err) if attempt <= self.retries && is_transient(&err) => {
+                    let Some(delay) = self.delay(attempt, retry_after(&err)) else {
+                        return (Err(err), attempt);
+                    };
+                    tracing::info!(
+                        retry.attempt = attempt,
+                        retry.max = self.retries,
+                        retry.delay_ms = delay.as_millis() as u64,
+                        error = %format!("{err:#}"),
+                        "retry"
+                    );
+                    if let Err(canceled) = until_canceled(cancel, tokio::time::sleep(delay)).await {
+                        return (Err(canceled), attempt);
+                    }
+                }
+                result => return (result, attempt),
+            }
+        }
+    }
+
+    /// The wait before retry number `attempt`, or `None` when the server
+    /// asked for more than `MAX_DELAY`.
+    fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        match retry_after {
+            Some(after) => (after <= MAX_DELAY).then_some(after),
+            None => Some(
+                self.backoff
+                    .checked_mul(2u32.saturating_pow(attempt - 1))
+                    .map_or(MAX_DELAY, |delay| delay.min(MAX_DELAY)),
+            ),
+        }
+    }
+}
+
+fn retry_after(err: &anyhow::Error) -> Option<Duration> {
+    err.chain()
+        .find_map(|cause| cause.downcast_ref::<RetryAfter>())
+        .map(|retry| retry.after)
+}
+
+/// Rate limits, network failures and server errors.
+pub fn is_transient(err: &anyhow::Error) -> bool {
+    matches!(
+        exit::status_for(err),
+        ExitStatus::RateLimited | ExitStatus::Network
+    ) || err.chain().any(|cause| {
+        cause
+            .downcast_ref::<reqwest::Error>()
+            .and_then(reqwest::Error::status)
+            .is_some_and(|status| status.is_server_error())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use super::*;
+
+    #[test]
+    fn only_transient_failures_are_retried() {
+        assert!(!is_transient(&anyhow!("openai response missing content")));
+        let canceled = exit::tag(anyhow!("canceled"), ExitStatus::Canceled);
+        assert!(!is_transient(&canceled));
+        let limited = exit::tag(anyhow!("slow down"), ExitStatus::RateLimited);
+        assert!(is_transient(&limited));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let policy = RetryPolicy {
+            retries: 40,
+            backoff: Duration::from_secs(1),
+        };
+        let secs = |attempt| policy.delay(attempt, None).unwrap().as_secs();
+        assert_eq!([secs(1), secs(2), secs(3)], [1, 2, 4]);
+        assert_eq!(secs(12), 60);
+        assert_eq!(secs(40), 60);
+        let asked = Some(Duration::from_secs(30));
+        assert_eq!(policy.delay(1, asked), asked);
+        assert_eq!(policy.delay(1, Some(Duration::from_secs(3600))), None);
+    }
+
+    #[tokio::test]
+    async fn cancellation_ends_the_backoff() {
+        let policy = RetryPolicy {
+            retries: 3,
+            backoff: Duration::from_secs(60),
+        };
+        let cancel = CancellationToken::new();
+        let fire = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            fire.cancel();
+        });
+        let (result, attempts) = policy
+            .run(&cancel, || async {
+                Err::<(), _>(exit::tag(anyhow!("slow down"), ExitStatus::RateLimited))
+            })
+            .await;
+        assert_eq!(attempts, 1);
+        assert_eq!(exit::status_for(&result.unwrap_err()), ExitStatus::Canceled);
+    }
+}
@@ -0,0 +1,110 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::config::{self, ProfileConfig};
+
+const ROLES_DIR: &str = "roles";
+
+/// `roles/` in the same directory as the config file.
+pub fn roles_dir() -> Result<PathBuf> {
+    Ok(config::config_path()?.with_file_name(ROLES_DIR))
+}
+
+/// Loads `--role <name>`: `roles/<name>.md` or `roles/<name>.toml`, or a
+/// path to either kind of file.
+pub fn load_role(name: &str) -> Result<ProfileConfig> {
+    let path = find_role(name)?;
+    let text = std::fs::read_to_string(&path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let role = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => toml::from_str(&text).map_err(anyhow::Error::from),
+        _ => parse_markdown_role(&text),
+    };
+    role.with_context(|| format!("invalid role file {}", path.display()))
+}
+
+fn find_role(name: &str) -> Result<PathBuf> {
+    let path = Path::new(name);
+    if path.extension().is_some() && path.is_file() {
+        return Ok(path.to_path_buf());
+    }
+    let dir = roles_dir()?;
+    ["md", "toml"]
+        .iter()
+        .map(|ext| dir.join(format!("{name}.{ext}")))
+        .find(|candidate| candidate.is_file())
+        .ok_or_else(|| {
+            anyhow!(
+                "role '{name}' not found - expected {}/{name}.md or .toml",
+                dir.display()
+            )
+        })
+}
+
+/// A Markdown role is the system prompt, optionally preceded by `+++` TOML or
+/// `---` YAML front matter carrying the other settings.
+fn parse_markdown_role(text: &str) -> Result<ProfileConfig> {
+    let text = text.trim_start_matches('\u{feff}');
+    let (mut role, body) = match front_matter(text) {
+        Some(("+++", matter, body)) => (toml::from_str::<ProfileConfig>(matter)?, body),
+        Some((_, matter, body)) => (serde_yaml::from_str::<ProfileConfig>(matter)?, body),
+        None => (ProfileConfig::default(), text),
+    };
+    let body = body.trim();
+    if !body.is_empty() {
+        if role.system.is_some() {
+            bail!("the system prompt is set in both the front matter and the body");
+        }
+        role.system = Some(body.to_string());
+    }
+    Ok(role)
+}
+
+/// Splits `+++`/`---` delimited front matter from the rest of the file.
+fn front_matter(text: &str) -> Option<(&'static str, &str, &str)> {
+    let fence = ["+++", "---"]
+        .into_iter()
+        .find(|fence| text.lines().next().map(str::trim_end) == Some(*fence))?;
+    let rest = text.split_once('\n')?.1;
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == fence {
+            return Some((fence, &rest[..offset], &rest[offset + line.len()..]));
+        }
+        offset += line.len();
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markdown_roles_take_settings_from_front_matter() {
+        let role = parse_markdown_role(
+            "+++\nmodel = \"gpt-4o\"\ntemperature = 0.2\n+++\n\nYou are a reviewer.\nBe blunt.\n",
+        )
+        .unwrap();
+        assert_eq!(role.model.as_deref(), Some("gpt-4o"));
+        assert_eq!(role.temperature, Some(0.2));
+        assert_eq!(
+            role.system.as_deref(),
+            Some("You are a reviewer.\nBe blunt.")
+        );
+
+        let role = parse_markdown_role("---\nprovider: claude\n---\nTranslate to French.").unwrap();
+        assert_eq!(role.provider.as_deref(), Some("claude"));
+        assert_eq!(role.system.as_deref(), Some("Translate to French."));
+
+        let role = parse_markdown_role("# Pirate\nSpeak like a pirate.\n").unwrap();
+        assert_eq!(
+            role.system.as_deref(),
+            Some("# Pirate\nSpeak like a pirate.")
+        );
+        assert!(role.model.is_none());
+
+        assert!(parse_markdown_role("+++\nsystem = \"twice\"\n+++\nbody").is_err());
+    }
+}
@@ -0,0 +1,728 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_stream::stream;
+use futures::StreamExt;
+use hyper::body::Bytes;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::Instrument;
+
+use crate::cli::ServeCommand;
+use crate::compare::Target;
+use crate::config::{AppConfig, ProviderKind};
+use crate::exit::{ExitStatus, ResultExt};
+use crate::provider::{
+    self, ChatMessage, ChatRequestOptions, ChatResponse, DynProvider, TokenUsage, ToolCall,
+    ToolSpec,
+};
+use crate::shutdown;
+use crate::streaming::StreamEvent;
+
+/// Largest request body read; anything bigger is refused with 413.
+const MAX_BODY_BYTES: usize = 8 * 1024 * 1024;
+
+/// Where a request's `model` sends it.
+#[derive(Debug, PartialEq)]
+struct Route {
+    provider: String,
+    model: String,
+    system: Option<String>,
+    temperature: Option<f32>,
+}
+
+/// `model` may name a profile, a `provider[:model]` target as `compare`
+/// takes it, or a model of the default provider (`llama3:8b` included).
+fn route(model: &str, cfg: &AppConfig) -> Result<Route> {
+    let default_system = |provider: &str| {
+        cfg.providers
+            .get(provider)
+            .and_then(|provider| provider.default_system())
+            .map(str::to_string)
+    };
+    if let Some(profile) = cfg.profiles.get(model) {
+        let provider = cfg.infer_default_provider(&profile.provider)?;
+        let target = Target::parse(
+            &format!("{provider}:{}", profile.model.as_deref().unwrap_or("")),
+            cfg,
+        )?;
+        return Ok(Route {
+            system: profile
+                .system
+                .clone()
+                .or_else(|| default_system(&target.provider)),
+            temperature: profile.temperature,
+            provider: target.provider,
+            model: target.model,
+        });
+    }
+    let prefix = model.split_once(':').map_or(model, |(prefix, _)| prefix);
+    let target = if cfg.providers.contains_key(prefix) || ProviderKind::infer(prefix).is_some() {
+        Target::parse(model, cfg)?
+    } else {
+        Target {
+            provider: cfg.infer_default_provider(&None)?,
+            model: model.to_string(),
+        }
+    };
+    Ok(Route {
+        system: default_system(&target.provider),
+        temperature: None,
+        provider: target.provider,
+        model: target.model,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct CompletionRequest {
+    model: String,
+    messages: Vec<WireMessage>,
+    #[serde(default)]
+    stream: bool,
+    #[serde(default)]
+    stream_options: Option<StreamOptions>,
+    temperature: Option<f32>,
+    max_tokens: Option<u32>,
+    max_completion_tokens: Option<u32>,
+    #[serde(default)]
+    tools: Vec<WireTool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamOptions {
+    #[serde(default)]
+    include_usage: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct WireMessage {
+    role: String,
+    #[serde(default)]
+    content: Option<Content>,
+    #[serde(default)]
+    tool_calls: Vec<WireToolCall>,
+    tool_call_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Content {
+    Text(String),
+    Parts(Vec<Part>),
+}
+
+#[derive(Debug, Deserialize)]
+struct Part {
+    #[serde(rename = "type")]
+    kind: String,
+    text: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WireTool {
+    function: WireFunction,
+}
+
+#[derive(Debug, Deserialize)]
+struct WireFunction {
+    name: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default = "empty_schema")]
+    parameters: Value,
+}
+
+fn empty_schema() -> Value {
+    json!({"type": "object", "properties": {}})
+}
+
+#[derive(Debug, Deserialize)]
+struct WireToolCall {
+    id: String,
+    function: WireCall,
+}
+
+#[derive(Debug, Deserialize)]
+struct WireCall {
+    name: String,
+    /// A JSON document in a string, as OpenAI sends it
+    #[serde(default)]
+    arguments: String,
+}
+
+impl Content {
+    fn into_text(self) -> Result<String> {
+        match self {
+            Content::Text(text) => Ok(text),
+            Content::Parts(parts) => parts
+                .into_iter()
+                .map(|part| match (part.kind.as_str(), part.text) {
+                    ("text", Some(text)) => Ok(text),
+                    (kind, _) => bail!("only text content is supported, not '{kind}'"),
+                })
+                .collect::<Result<Vec<_>>>()
+                .map(|texts| texts.join("\n")),
+        }
+    }
+}
+
+/// Splits OpenAI-style messages into a system prompt and chat messages.
+fn convert_messages(messages: Vec<WireMessage>) -> Result<(Option<String>, Vec<ChatMessage>)> {
+    let mut system: Vec<String> = Vec::new();
+    let mut converted = Vec::new();
+    for message in messages {
+        let text = message
+            .content
+            .map(Content::into_text)
+            .transpose()?
+            .unwrap_or_default();
+        match message.role.as_str() {
+            "system" | "developer" => system.push(text),
+            "user" => converted.push(ChatMessage::user(text)),
+            "assistant" => {
+                let calls = message
+                    .tool_calls
+                    .into_iter()
+                    .map(|call| ToolCall {
+                        id: call.id,
+                        arguments: serde_json::from_str(&call.function.arguments)
+                            .unwrap_or(Value::String(call.function.arguments)),
+                        name: call.function.name,
+                    })
+                    .collect();
+                converted.push(ChatMessage::assistant_with_tools(text, calls));
+            }
+            "tool" => {
+                let id = message
+                    .tool_call_id
+                    .ok_or_else(|| anyhow!("a tool message needs tool_call_id"))?;
+                converted.push(ChatMessage::tool_result(&id, text));
+            }
+            role => bail!("unknown message role '{role}'"),
+        }
+    }
+    let system = (!system.is_empty()).then(|| system.join("\n\n"));
+    Ok((system, converted))
+}
+
+/// OpenAI's finish reason for a reply.
+fn finish_reason(response: &ChatResponse) -> &'static str {
+    if !response.tool_calls.is_empty() {
+        return "tool_calls";
+    }
+    wire_finish_reason(response.finish_reason.as_deref())
+}
+
+/// OpenAI's finish reasons for what providers report.
+fn wire_finish_reason(reason: Option<&str>) -> &'static str {
+    match reason.map(str::to_ascii_lowercase).as_deref() {
+        Some("length" | "max_tokens") => "length",
+        Some("content_filter" | "safety") => "content_filter",
+        _ => "stop",
+    }
+}
+
+fn wire_tool_calls(calls: &[ToolCall]) -> Vec<Value> {
+    calls
+        .iter()
+        .enumerate()
+        .map(|(index, call)| {
+            json!({
+                "index": index,
+                "id": call.id,
+                "type": "function",
+                "function": {"name": call.name, "arguments": call.arguments.to_string()},
+            })
+        })
+        .collect()
+}
+
+fn wire_usage(usage: Option<TokenUsage>) -> Value {
+    usage.map_or(Value::Null, |usage| {
+        json!({
+            "prompt_tokens": usage.input_tokens,
+            "completion_tokens": usage.output_tokens,
+            "total_tokens": usage.input_tokens + usage.output_tokens,
+        })
+    })
+}
+
+/// An error reply in OpenAI's shape.
+#[derive(Debug)]
+struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+
+    fn bad_request(err: anyhow::Error) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, format!("{err:#}"))
+    }
+
+    fn into_response(self) -> Response<Body> {
+        let kind = if self.status.is_server_error() {
+            "api_error"
+        } else {
+            "invalid_request_error"
+        };
+        json_response(
+            self.status,
+            &json!({"error": {"message": self.message, "type": kind}}),
+        )
+    }
+}
+
+/// Compares without stopping at the first differing byte, so response times
+/// do not give the key away.
+fn same_key(sent: &[u8], key: &[u8]) -> bool {
+    let diff = sent
+        .iter()
+        .zip(key)
+        .fold(0u8, |diff, (a, b)| diff | std::hint::black_box(a ^ b));
+    sent.len() == key.len() && diff == 0
+}
+
+/// Reads a request body, refusing one over `MAX_BODY_BYTES` before it is all
+/// in memory.
+async fn read_body(mut body: Body) -> Result<Vec<u8>, ApiError> {
+    use hyper::body::HttpBody;
+
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|err| ApiError::bad_request(err.into()))?;
+        if bytes.len() + chunk.len() > MAX_BODY_BYTES {
+            return Err(ApiError::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("request body is over {MAX_BODY_BYTES} bytes"),
+            ));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+fn json_response(status: StatusCode, body: &Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap_or_default()
+}
+
+struct Gateway {
+    cfg: AppConfig,
+    providers: HashMap<String, DynProvider>,
+    api_key: Option<String>,
+    requests: AtomicU64,
+}
+
+impl Gateway {
+    /// Each request is traced on its own, as a server span.
+    async fn handle(self: Arc<Self>, request: Request<Body>) -> Response<Body> {
+        let span = tracing::info_span!(
+            "request",
+            otel.name = %format!("{} {}", request.method(), request.uri().path()),
+            otel.kind = "server",
+            http.request.method = %request.method(),
+            url.path = %request.uri().path(),
+            http.response.status_code = tracing::field::Empty,
+            otel.status_code = tracing::field::Empty,
+        );
+        let response = self.respond(request).instrument(span.clone()).await;
+        span.record(
+            "http.response.status_code",
+            i64::from(response.status().as_u16()),
+        );
+        if response.status().is_server_error() {
+            span.record("otel.status_code", "ERROR");
+        }
+        response
+    }
+
+    async fn respond(self: Arc<Self>, request: Request<Body>) -> Response<Body> {
+        if let Some(key) = &self.api_key {
+            let sent = request
+                .headers()
+                .get(header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "));
+            if !sent.is_some_and(|sent| same_key(sent.as_bytes(), key.as_bytes())) {
+                return ApiError::new(StatusCode::UNAUTHORIZED, "missing or wrong API key")
+                    .into_response();
+            }
+        }
+        let path = request.uri().path().trim_end_matches('/').to_string();
+        let result = match (request.method(), path.as_str()) {
+            (&Method::GET, "/v1/models" | "/models") => Ok(self.models()),
+            (&Method::POST, "/v1/chat/completions" | "/chat/completions") => {
+                self.complete(request).await
+            }
+            _ => Err(ApiError::new(
+                StatusCode::NOT_FOUND,
+                format!("no route for {} {path}", request.method()),
+            )),
+        };
+        result.unwrap_or_else(ApiError::into_response)
+    }
+
+    /// Profiles first, then each provider's default model.
+    fn models(&self) -> Response<Body> {
+        let ids = self.cfg.profiles.keys().cloned().chain(
+            self.cfg
+                .providers
+                .iter()
+                .filter(|(name, _)| self.providers.contains_key(*name))
+                .map(|(name, provider)| match provider.default_model() {
+                    Some(model) => format!("{name}:{model}"),
+                    None => name.clone(),
+                }),
+        );
+        let data: Vec<Value> = ids
+            .map(|id| json!({"id": id, "object": "model", "owned_by": "rustchat"}))
+            .collect();
+        json_response(StatusCode::OK, &json!({"object": "list", "data": data}))
+    }
+
+    async fn complete(&self, request: Request<Body>) -> Result<Response<Body>, ApiError> {
+        let body = read_body(request.into_body()).await?;
+        let request: CompletionRequest = serde_json::from_slice(&body)
+            .context("invalid chat completion request")
+            .map_err(ApiError::bad_request)?;
+        let route = route(&request.model, &self.cfg).map_err(ApiError::bad_request)?;
+        let provider = self
+            .providers
+            .get(&route.provider)
+            .cloned()
+            .ok_or_else(|| {
+                ApiError::new(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    format!("provider '{}' is not available", route.provider),
+                )
+            })?;
+        let (system, messages) =
+            convert_messages(request.messages).map_err(ApiError::bad_request)?;
+        let system = system.or(route.system.clone());
+        let options = ChatRequestOptions {
+            temperature: request.temperature.or(route.temperature),
+            max_output_tokens: request.max_completion_tokens.or(request.max_tokens),
+            tools: request
+                .tools
+                .into_iter()
+                .map(|tool| ToolSpec {
+                    name: tool.function.name,
+                    description: tool.function.description,
+                    parameters: tool.function.parameters,
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let number = self.requests.fetch_add(1, Ordering::Relaxed);
+        let reply = Reply {
+            id: format!("chatcmpl-{}-{number}", std::process::id()),
+            created: chrono::Utc::now().timestamp(),
+            model: request.model,
+        };
+        tracing::info!(
+            "{} -> {}/{}{}",
+            reply.model,
+            route.provider,
+            route.model,
+            if request.stream { " (stream)" } else { "" }
+        );
+        let include_usage = request
+            .stream_options
+            .is_some_and(|options| options.include_usage);
+
+        // Tool calls only come back whole, so with tools a stream is sent
+        // as one chunk.
+        if request.stream && options.tools.is_empty() {
+            let stream = provider
+                .stream_chat(
+                    &route.model,
+                    system.as_deref(),
+                    &messages,
+                    &options,
+                    &shutdown::token(),
+                )
+                .await
+                .map_err(|err| ApiError::new(StatusCode::BAD_GATEWAY, format!("{err:#}")))?;
+            return Ok(sse_response(reply.stream(stream, include_usage)));
+        }
+        let response = provider
+            .chat(
+                &route.model,
+                system.as_deref(),
+                &messages,
+                &options,
+                &shutdown::token(),
+            )
+            .await
+            .map_err(|err| ApiError::new(StatusCode::BAD_GATEWAY, format!("{err:#}")))?;
+        if request.stream {
+            return Ok(sse_response(reply.whole_as_stream(response, include_usage)));
+        }
+        Ok(json_response(StatusCode::OK, &reply.completion(&response)))
+    }
+}
+
+/// The fields every chunk or completion for one request repeats.
+struct Reply {
+    id: String,
+    created: i64,
+    model: String,
+}
+
+impl Reply {
+    fn completion(&self, response: &ChatResponse) -> Value {
+        let mut message = json!({"role": "assistant", "content": response.text});
+        if !response.tool_calls.is_empty() {
+            message["tool_calls"] = json!(wire_tool_calls(&response.tool_calls));
+        }
+        json!({
+            "id": self.id,
+            "object": "chat.completion",
+            "created": self.created,
+            "model": self.model,
+            "choices": [{"index": 0, "message": message, "finish_reason": finish_reason(response)}],
+            "usage": wire_usage(response.usage),
+        })
+    }
+
+    fn chunk(&self, delta: Value, finish_reason: Option<&str>) -> Value {
+        json!({
+            "id": self.id,
+            "object": "chat.completion.chunk",
+            "created": self.created,
+            "model": self.model,
+            "choices": [{"index": 0, "delta": delta, "finish_reason": finish_reason}],
+        })
+    }
+
+    fn usage_chunk(&self, usage: Option<TokenUsage>) -> Value {
+        json!({
+            "id": self.id,
+            "object": "chat.completion.chunk",
+            "created": self.created,
+            "model": self.model,
+            "choices": [],
+            "usage": wire_usage(usage),
+        })
+    }
+
+    /// Relays a provider stream as chat completion chunks. A failure part way
+    /// is sent as an `error` event, since the status line is already out.
+    fn stream(
+        self,
+        mut events: crate::streaming::ChatStream,
+        include_usage: bool,
+    ) -> impl futures::Stream<Item = Value> + Send + 'static {
+        stream! {
+            yield self.chunk(json!({"role": "assistant", "content": ""}), None);
+            let mut usage: Option<TokenUsage> = None;
+            let mut finish = None;
+            while let Some(event) = events.next().await {
+                match event {
+                    Ok(StreamEvent::Text(text)) => yield self.chunk(json!({"content": text}), None),
+                    Ok(StreamEvent::Usage(reported)) => {
+                        usage.get_or_insert_with(TokenUsage::default).absorb(reported);
+                    }
+                    Ok(StreamEvent::Finish(reason)) => finish = Some(reason),
+                    Err(err) => {
+                        yield json!({"error": {"message": format!("{err:#}"), "type": "api_error"}});
+                        return;
+                    }
+                }
+            }
+            yield self.chunk(json!({}), Some(wire_finish_reason(finish.as_deref())));
+            if include_usage {
+                yield self.usage_chunk(usage);
+            }
+        }
+    }
+
+    fn whole_as_stream(
+        self,
+        response: ChatResponse,
+        include_usage: bool,
+    ) -> impl futures::Stream<Item = Value> {
+        let mut delta = json!({"role": "assistant", "content": response.text});
+        if !response.tool_calls.is_empty() {
+            delta["tool_calls"] = json!(wire_tool_calls(&response.tool_calls));
+        }
+        let mut chunks = vec![
+            self.chunk(delta, None),
+            self.chunk(json!({}), Some(finish_reason(&response))),
+        ];
+        if include_usage {
+            chunks.push(self.usage_chunk(response.usage));
+        }
+        futures::stream::iter(chunks)
+    }
+}
+
+/// Server-sent events, ending with OpenAI's `[DONE]` marker.
+fn sse_response(chunks: impl futures::Stream<Item = Value> + Send + 'static) -> Response<Body> {
+    let events = chunks
+        .map(|chunk| format!("data: {chunk}\n\n"))
+        .chain(futures::stream::once(async {
+            "data: [DONE]\n\n".to_string()
+        }))
+        .map(|event| Ok::<_, Infallible>(Bytes::from(event)));
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/event-stream")
+        .header(header::CACHE_CONTROL, "no-cache")
+        .body(Body::wrap_stream(events))
+        .unwrap_or_default()
+}
+
+pub async fn run(args: ServeCommand, cfg: &AppConfig) -> Result<()> {
+    let addr: SocketAddr = (args.host.as_str(), args.port)
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .ok_or_else(|| anyhow!("cannot listen on {}:{}", args.host, args.port))
+        .exit_status(ExitStatus::Config)?;
+    let api_key = match &args.api_key_env {
+        Some(env) => Some(
+            std::env::var(env)
+                .with_context(|| format!("--api-key-env: {env} is not set"))
+                .exit_status(ExitStatus::Config)?,
+        ),
+        None => None,
+    };
+    if api_key.is_none() && !addr.ip().is_loopback() {
+        tracing::warn!(
+            "serving on {addr} without --api-key-env: anyone who can reach it can spend your keys"
+        );
+    }
+    let providers = provider::build_all(cfg, args.secret_env.as_deref(), !args.no_prompt)
+        .await
+        .exit_status(ExitStatus::Config)?;
+    let gateway = Arc::new(Gateway {
+        cfg: cfg.clone(),
+        providers,
+        api_key,
+        requests: AtomicU64::new(0),
+    });
+    let make_service = make_service_fn(move |_| {
+        let gateway = gateway.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let gateway = gateway.clone();
+                async move { Ok::<_, Infallible>(gateway.handle(request).await) }
+            }))
+        }
+    });
+    let server = Server::try_bind(&addr)
+        .with_context(|| format!("cannot listen on {addr}"))?
+        .serve(make_service);
+    eprintln!("Serving an OpenAI-compatible API on http://{addr}/v1 (Ctrl-C to stop)");
+    server
+        .with_graceful_shutdown(async {
+            let stop = shutdown::token();
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = stop.cancelled() => {}
+            }
+        })
+        .await
+        .context("server failed")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{ProfileConfig, ProviderConfig};
+
+    use super::*;
+
+    #[test]
+    fn requests_are_routed_and_converted() {
+        let mut cfg: AppConfig = toml::from_str(
+            r#"
+            default_provider = "work"
+            [providers.work]
+            type = "openai"
+            api_key = "sk-test"
+            default_model = "gpt-4o"
+            system = "Be brief."
+            "#,
+        )
+        .unwrap();
+        assert!(matches!(cfg.providers["work"], ProviderConfig::Openai(_)));
+        cfg.profiles.insert(
+            "writer".to_string(),
+            ProfileConfig {
+                model: Some("gpt-4o-mini".to_string()),
+                system: Some("Write well.".to_string()),
+                temperature: Some(0.9),
+                ..ProfileConfig::default()
+            },
+        );
+        let routed = |model: &str| route(model, &cfg).unwrap();
+        assert_eq!(
+            routed("writer"),
+            Route {
+                provider: "work".to_string(),
+                model: "gpt-4o-mini".to_string(),
+                system: Some("Write well.".to_string()),
+                temperature: Some(0.9),
+            }
+        );
+        assert_eq!(routed("work").model, "gpt-4o");
+        assert_eq!(routed("work:o3").model, "o3");
+        assert_eq!(routed("openai").provider, "work");
+        assert_eq!(routed("llama3:8b").model, "llama3:8b");
+        assert_eq!(routed("llama3:8b").system.as_deref(), Some("Be brief."));
+
+        let messages: Vec<WireMessage> = serde_json::from_value(json!([
+            {"role": "system", "content": "Answer in French."},
+            {"role": "user", "content": [{"type": "text", "text": "Weather?"}]},
+            {"role": "assistant", "content": null, "tool_calls": [
+                {"id": "call_1", "type": "function",
+                 "function": {"name": "weather", "arguments": "{\"city\":\"Paris\"}"}}
+            ]},
+            {"role": "tool", "tool_call_id": "call_1", "content": "Sunny"},
+        ]))
+        .unwrap();
+        let (system, messages) = convert_messages(messages).unwrap();
+        assert_eq!(system.as_deref(), Some("Answer in French."));
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0].content, "Weather?");
+        assert_eq!(messages[1].tool_calls[0].arguments["city"], "Paris");
+        assert_eq!(messages[2].tool_name(&messages), Some("weather"));
+
+        let image: Vec<WireMessage> = serde_json::from_value(json!([
+            {"role": "user", "content": [{"type": "image_url", "image_url": {"url": "x"}}]}
+        ]))
+        .unwrap();
+        assert!(convert_messages(image).is_err());
+    }
+
+    #[tokio::test]
+    async fn wrong_keys_and_oversized_bodies_are_refused() {
+        assert!(same_key(b"sk-local", b"sk-local"));
+        assert!(!same_key(b"sk-locaX", b"sk-local"));
+        assert!(!same_key(b"sk-local-and-more", b"sk-local"));
+        assert!(!same_key(b"", b"sk-local"));
+
+        let fits = read_body(Body::from(vec![b' '; 1024])).await.unwrap();
+        assert_eq!(fits.len(), 1024);
+        let err = read_body(Body::from(vec![b' '; MAX_BODY_BYTES + 1]))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
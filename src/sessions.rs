@@ -0,0 +1,683 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{Local, NaiveDate, Utc};
+
+use crate::cli::{ImportSourceArg, SessionsArgs, SessionsCommand};
+use crate::config::{RetentionConfig, SessionsConfig};
+use crate::import;
+use crate::logger::{self, HistoryFormat, Transcript};
+use crate::pager;
+use crate::provider::ChatMessage;
+use crate::shell;
+use crate::store::{
+    self, DynStore, RetentionPolicy, SaveTarget, SearchHit, SessionStore, SessionSummary,
+};
+use crate::utils;
+
+/// Messages longer than this many lines are cut short unless asked otherwise.
+const PREVIEW_LINES: usize = 12;
+
+/// A session named on the command line: a transcript file or a stored id.
+enum Located {
+    File(PathBuf),
+    Stored(String),
+}
+
+pub fn handle_sessions(args: SessionsArgs, cfg: &SessionsConfig) -> Result<()> {
+    let history_dir = args.history_dir.or_else(logger::default_history_dir);
+    let store = store::open_store(cfg, history_dir, HistoryFormat::Json, None)?
+        .ok_or_else(|| anyhow!("no history directory available - pass --history-dir"))?;
+    match args.command {
+        SessionsCommand::List { tags } => {
+            let mut sessions = store.list()?;
+            sessions.retain(|session| tags.iter().all(|tag| session.tags.contains(tag)));
+            print_list(&sessions);
+        }
+        SessionsCommand::Show { id, full } => {
+            let transcript = load(&*store, &locate(&*store, &id)?)?;
+            pager::page(&format_transcript(
+                transcript.system.as_deref(),
+                &transcript.messages,
+                !full,
+            ));
+        }
+        SessionsCommand::Stats => print_stats(&HistoryStats::from_sessions(&store.list()?)),
+        SessionsCommand::Search { query } => print_hits(&store.search(&query)?),
+        SessionsCommand::Delete { id, yes } => {
+            let located = locate(&*store, &id)?;
+            let location = match &located {
+                Located::File(path) => path.display().to_string(),
+                Located::Stored(id) => store.describe(id),
+            };
+            if !yes && !shell::confirm(&format!("Delete {location}?")) {
+                println!("Kept {location}");
+                return Ok(());
+            }
+            match &located {
+                Located::File(path) => fs::remove_file(path)
+                    .with_context(|| format!("failed to delete {}", path.display()))?,
+                Located::Stored(id) => store.delete(id)?,
+            }
+            println!("Deleted {location}");
+        }
+        SessionsCommand::Prune {
+            older_than,
+            max_size,
+            dry_run,
+            yes,
+        } => {
+            // Flags override the configured retention; with neither, use it as is.
+            let policy = if older_than.is_none() && max_size.is_none() {
+                RetentionPolicy::from_config(&cfg.retention)?
+            } else {
+                RetentionPolicy::from_config(&RetentionConfig {
+                    older_than,
+                    max_size,
+                    ..Default::default()
+                })?
+            };
+            if policy.is_empty() {
+                bail!("nothing to prune by - pass --older-than and/or --max-size");
+            }
+            let sessions = store.list()?;
+            let expired: Vec<SessionSummary> = policy
+                .expired(&sessions, Utc::now(), None)
+                .into_iter()
+                .cloned()
+                .collect();
+            if expired.is_empty() {
+                println!("No sessions to prune");
+                return Ok(());
+            }
+            print_list(&expired);
+            let freed = utils::format_size(expired.iter().map(|session| session.size).sum());
+            if dry_run {
+                println!("Would delete {} session(s), freeing {freed}", expired.len());
+                return Ok(());
+            }
+            if !yes && !shell::confirm(&format!("Delete these {} session(s)?", expired.len())) {
+                println!("Kept all sessions");
+                return Ok(());
+            }
+            for session in &expired {
+                store.delete(&session.id)?;
+            }
+            println!("Deleted {} session(s), freeing {freed}", expired.len());
+        }
+        SessionsCommand::Compact { dry_run, yes } => {
+            let sessions = store.list()?;
+            let mut transcripts = Vec::with_capacity(sessions.len());
+            for session in &sessions {
+                transcripts.push((session.id.clone(), store.load(&session.id)?));
+            }
+            let plan = plan_compaction(transcripts);
+            if plan.redundant.is_empty() {
+                println!("No duplicate sessions");
+                return Ok(());
+            }
+            let redundant: Vec<SessionSummary> = sessions
+                .into_iter()
+                .filter(|session| plan.redundant.contains(&session.id))
+                .collect();
+            print_list(&redundant);
+            if dry_run {
+                println!("Would remove {} duplicate session(s)", redundant.len());
+                return Ok(());
+            }
+            if !yes && !shell::confirm(&format!("Remove these {} session(s)?", redundant.len())) {
+                println!("Kept all sessions");
+                return Ok(());
+            }
+            // Canonical sessions are rewritten before anything is deleted.
+            for (id, transcript) in &plan.merged {
+                store.save(
+                    id,
+                    &transcript.meta,
+                    transcript.system.as_deref(),
+                    &transcript.messages,
+                )?;
+            }
+            for session in &redundant {
+                store.delete(&session.id)?;
+            }
+            println!("Removed {} duplicate session(s)", redundant.len());
+        }
+        SessionsCommand::Import { source, path } => {
+            let data = fs::read_to_string(&path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            let sessions = match source {
+                ImportSourceArg::Chatgpt => import::parse_chatgpt_export(&data)?,
+            };
+            for session in &sessions {
+                store.save(
+                    &session.id,
+                    &session.meta,
+                    session.system.as_deref(),
+                    &session.messages,
+                )?;
+            }
+            println!(
+                "Imported {} conversation(s) from {}",
+                sessions.len(),
+                path.display()
+            );
+        }
+        SessionsCommand::Export { id, format, output } => {
+            let transcript = load(&*store, &locate(&*store, &id)?)?;
+            let format = HistoryFormat::from(format);
+            match output {
+                Some(output) => {
+                    logger::save_history(
+                        &output,
+                        format,
+                        &transcript.meta,
+                        transcript.system.as_deref(),
+                        &transcript.messages,
+                    )?;
+                    println!("Exported {id} to {}", output.display());
+                }
+                None => println!(
+                    "{}",
+                    logger::render_history(
+                        format,
+                        &transcript.meta,
+                        transcript.system.as_deref(),
+                        &transcript.messages,
+                    )?
+                ),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A saved conversation reopened with `chat --continue` / `--resume`, plus
+/// where further turns are written back.
+pub struct ResumedSession {
+    pub target: SaveTarget,
+    pub transcript: Transcript,
+}
+
+/// Reopens a session by transcript path, id, or unique id prefix.
+pub fn resume(store: Option<&DynStore>, arg: &str) -> Result<ResumedSession> {
+    let as_path = Path::new(arg);
+    if as_path.is_file() {
+        return Ok(ResumedSession {
+            transcript: logger::load_history(as_path)?,
+            target: SaveTarget::File {
+                path: as_path.to_path_buf(),
+                format: HistoryFormat::for_path(as_path),
+                base: None,
+            },
+        });
+    }
+    let store = store.ok_or_else(|| {
+        anyhow!("'{arg}' is not a file and no session store is available - pass --history-dir")
+    })?;
+    let id = resolve_id(&**store, arg)?;
+    Ok(ResumedSession {
+        transcript: store.load(&id)?,
+        target: SaveTarget::Session {
+            store: Arc::clone(store),
+            id,
+        },
+    })
+}
+
+/// Reopens the newest session recorded for `provider`.
+pub fn resume_latest(store: Option<&DynStore>, provider: &str) -> Result<ResumedSession> {
+    let store =
+        store.ok_or_else(|| anyhow!("no session store is available - pass --history-dir"))?;
+    let id = store
+        .list()?
+        .into_iter()
+        .find(|session| session.provider.as_deref() == Some(provider))
+        .map(|session| session.id)
+        .ok_or_else(|| anyhow!("no saved session for provider '{provider}'"))?;
+    Ok(ResumedSession {
+        transcript: store.load(&id)?,
+        target: SaveTarget::Session {
+            store: Arc::clone(store),
+            id,
+        },
+    })
+}
+
+/// Finds a session by transcript path, exact id, or unique id prefix.
+fn locate(store: &dyn SessionStore, arg: &str) -> Result<Located> {
+    let as_path = Path::new(arg);
+    if as_path.is_file() {
+        return Ok(Located::File(as_path.to_path_buf()));
+    }
+    resolve_id(store, arg).map(Located::Stored)
+}
+
+fn load(store: &dyn SessionStore, located: &Located) -> Result<Transcript> {
+    match located {
+        Located::File(path) => logger::load_history(path),
+        Located::Stored(id) => store.load(id),
+    }
+}
+
+/// Expands an exact id or unique id prefix to a stored session id.
+pub fn resolve_id(store: &dyn SessionStore, id: &str) -> Result<String> {
+    let sessions = store.list()?;
+    if sessions.iter().any(|session| session.id == id) {
+        return Ok(id.to_string());
+    }
+    let matches: Vec<&SessionSummary> = sessions
+        .iter()
+        .filter(|session| session.id.starts_with(id))
+        .collect();
+    match matches.as_slice() {
+        [session] => Ok(session.id.clone()),
+        [] => bail!("no session '{id}'"),
+        _ => bail!(
+            "'{id}' matches {} sessions - use a longer prefix",
+            matches.len()
+        ),
+    }
+}
+
+fn print_hits(hits: &[SearchHit]) {
+    if hits.is_empty() {
+        println!("No matches.");
+        return;
+    }
+    let (highlight, reset) = if io::stdout().is_terminal() {
+        ("\x1b[1;33m", "\x1b[0m")
+    } else {
+        ("", "")
+    };
+    let mut current: Option<&str> = None;
+    for hit in hits {
+        if current != Some(hit.id.as_str()) {
+            if current.is_some() {
+                println!();
+            }
+            println!("{}  {}", hit.id, hit.title);
+            current = Some(hit.id.as_str());
+        }
+        let (before, rest) = hit.snippet.split_at(hit.matched.start);
+        let (matched, after) = rest.split_at(hit.matched.len());
+        println!(
+            "  #{} {}: {before}{highlight}{matched}{reset}{after}",
+            hit.index, hit.role
+        );
+    }
+}
+
+fn print_list(sessions: &[SessionSummary]) {
+    if sessions.is_empty() {
+        println!("No saved sessions.");
+        return;
+    }
+    let id_width = sessions
+        .iter()
+        .map(|s| s.id.len())
+        .max()
+        .unwrap_or(2)
+        .max(2);
+    println!(
+        "{:<id_width$}  {:<16}  {:<10}  {:<24}  {:>5}  TITLE",
+        "ID", "DATE", "PROVIDER", "MODEL", "TURNS"
+    );
+    for session in sessions {
+        let date = session
+            .date
+            .map(|date| {
+                date.with_timezone(&Local)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string()
+            })
+            .unwrap_or_else(|| "-".to_string());
+        let tags: String = session.tags.iter().map(|tag| format!("  #{tag}")).collect();
+        println!(
+            "{:<id_width$}  {:<16}  {:<10}  {:<24}  {:>5}  {}{tags}",
+            session.id,
+            date,
+            session.provider.as_deref().unwrap_or("-"),
+            session.model.as_deref().unwrap_or("-"),
+            session.turns,
+            session.title
+        );
+    }
+}
+
+/// Number of days listed under "Busiest days".
+const BUSIEST_DAYS: usize = 5;
+
+#[derive(Default, Debug, PartialEq)]
+struct Tally {
+    sessions: usize,
+    messages: usize,
+    tokens: usize,
+}
+
+impl Tally {
+    fn add(&mut self, session: &SessionSummary) {
+        self.sessions += 1;
+        self.messages += session.messages;
+        self.tokens += session.tokens;
+    }
+}
+
+/// Aggregates shown by `sessions stats`.
+struct HistoryStats {
+    total: Tally,
+    first: Option<NaiveDate>,
+    last: Option<NaiveDate>,
+    /// Keyed by (provider, model), most sessions first
+    by_model: Vec<((String, String), Tally)>,
+    /// Local dates with the most sessions, busiest first
+    busiest_days: Vec<(NaiveDate, Tally)>,
+}
+
+impl HistoryStats {
+    fn from_sessions(sessions: &[SessionSummary]) -> Self {
+        let mut total = Tally::default();
+        let mut by_model: BTreeMap<(String, String), Tally> = BTreeMap::new();
+        let mut by_day: BTreeMap<NaiveDate, Tally> = BTreeMap::new();
+        for session in sessions {
+            total.add(session);
+            let key = (
+                session.provider.clone().unwrap_or_else(|| "-".to_string()),
+                session.model.clone().unwrap_or_else(|| "-".to_string()),
+            );
+            by_model.entry(key).or_default().add(session);
+            if let Some(date) = session.date {
+                by_day
+                    .entry(date.with_timezone(&Local).date_naive())
+                    .or_default()
+                    .add(session);
+            }
+        }
+        let first = by_day.keys().next().copied();
+        let last = by_day.keys().next_back().copied();
+        let mut by_model: Vec<_> = by_model.into_iter().collect();
+        by_model.sort_by_key(|(_, tally)| std::cmp::Reverse(tally.sessions));
+        let mut busiest_days: Vec<_> = by_day.into_iter().collect();
+        busiest_days.sort_by(|a, b| {
+            (b.1.sessions, b.1.messages, b.0).cmp(&(a.1.sessions, a.1.messages, a.0))
+        });
+        busiest_days.truncate(BUSIEST_DAYS);
+        Self {
+            total,
+            first,
+            last,
+            by_model,
+            busiest_days,
+        }
+    }
+}
+
+fn print_stats(stats: &HistoryStats) {
+    let total = &stats.total;
+    if total.sessions == 0 {
+        println!("No saved sessions.");
+        return;
+    }
+    println!("Sessions:  {}", total.sessions);
+    println!(
+        "Messages:  {} ({:.1} per session)",
+        total.messages,
+        total.messages as f64 / total.sessions as f64
+    );
+    println!("Tokens:    ~{} (estimated)", total.tokens);
+    if let (Some(first), Some(last)) = (stats.first, stats.last) {
+        println!("Span:      {first} to {last}");
+    }
+
+    println!();
+    println!(
+        "{:<10}  {:<24}  {:>8}  {:>8}  {:>10}",
+        "PROVIDER", "MODEL", "SESSIONS", "MESSAGES", "TOKENS"
+    );
+    for ((provider, model), tally) in &stats.by_model {
+        println!(
+            "{provider:<10}  {model:<24}  {:>8}  {:>8}  {:>10}",
+            tally.sessions, tally.messages, tally.tokens
+        );
+    }
+
+    if !stats.busiest_days.is_empty() {
+        println!();
+        println!("Busiest days:");
+        for (day, tally) in &stats.busiest_days {
+            println!(
+                "  {} {day}  {:>3} session(s)  {:>5} messages",
+                day.format("%a"),
+                tally.sessions,
+                tally.messages
+            );
+        }
+    }
+}
+
+/// Formats a conversation for reading (`/history`, `sessions show`), one
+/// numbered block per message. With `truncate`, long messages keep only their
+/// first lines.
+pub fn format_transcript(system: Option<&str>, messages: &[ChatMessage], truncate: bool) -> String {
+    let mut out = String::new();
+    if let Some(system) = system {
+        out.push_str(&format!("--- system\n{}\n\n", system.trim_end()));
+    }
+    for (index, message) in messages.iter().enumerate() {
+        let mut header = format!("--- #{} {}", index + 1, message.role);
+        if let Some(timestamp) = message.timestamp {
+            let local = timestamp.with_timezone(&Local);
+            header.push_str(&format!(" · {}", local.format("%H:%M:%S")));
+        }
+        if let Some(name) = message.tool_name(messages) {
+            header.push_str(&format!(" · {name}"));
+        }
+        out.push_str(&header);
+        out.push('\n');
+        for call in &message.tool_calls {
+            out.push_str(&format!("[tool call] {} {}\n", call.name, call.arguments));
+        }
+        let lines: Vec<&str> = message.content.trim_end().lines().collect();
+        if truncate && lines.len() > PREVIEW_LINES {
+            for line in &lines[..PREVIEW_LINES] {
+                out.push_str(line);
+                out.push('\n');
+            }
+            out.push_str(&format!(
+                "[... {} more lines hidden; add --full to show them]\n",
+                lines.len() - PREVIEW_LINES
+            ));
+        } else if !lines.is_empty() {
+            out.push_str(&lines.join("\n"));
+            out.push('\n');
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// What `sessions compact` does: delete `redundant` sessions, whose messages
+/// all appear at the start of a longer (or identical) session, and save the
+/// `merged` sessions that absorbed them.
+struct Compaction {
+    redundant: Vec<String>,
+    merged: Vec<(String, Transcript)>,
+}
+
+/// Plans a compaction of `sessions`, given newest first. Among identical
+/// copies the newest is kept; its metadata gains the earliest start time and
+/// every tag and title of the copies it replaces.
+fn plan_compaction(mut sessions: Vec<(String, Transcript)>) -> Compaction {
+    // Stable, so equally long sessions stay newest first.
+    sessions.sort_by_key(|(_, transcript)| std::cmp::Reverse(transcript.messages.len()));
+    let mut kept: Vec<(String, Transcript, bool)> = Vec::new();
+    let mut redundant = Vec::new();
+    for (id, transcript) in sessions {
+        if transcript.messages.is_empty() {
+            continue;
+        }
+        let canonical = kept.iter_mut().find(|(_, canonical, _)| {
+            canonical.system == transcript.system
+                && canonical.messages.len() >= transcript.messages.len()
+                && canonical
+                    .messages
+                    .iter()
+                    .zip(&transcript.messages)
+                    .all(|(a, b)| same_message(a, b))
+        });
+        let Some((_, canonical, changed)) = canonical else {
+            kept.push((id, transcript, false));
+            continue;
+        };
+        let meta = &mut canonical.meta;
+        let before = meta.clone();
+        meta.created_at = match (meta.created_at, transcript.meta.created_at) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        meta.title = meta.title.take().or(transcript.meta.title);
+        for tag in transcript.meta.tags {
+            if !meta.tags.contains(&tag) {
+                meta.tags.push(tag);
+            }
+        }
+        *changed |= *meta != before;
+        redundant.push(id);
+    }
+    Compaction {
+        redundant,
+        merged: kept
+            .into_iter()
+            .filter(|(_, _, changed)| *changed)
+            .map(|(id, transcript, _)| (id, transcript))
+            .collect(),
+    }
+}
+
+/// Messages match regardless of when they were saved.
+fn same_message(a: &ChatMessage, b: &ChatMessage) -> bool {
+    a.role == b.role && a.content == b.content && a.tool_calls == b.tool_calls
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(provider: &str, model: &str, date: &str, messages: usize) -> SessionSummary {
+        SessionSummary {
+            id: format!("{provider}-{date}"),
+            date: Some(
+                NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                    .unwrap()
+                    .and_hms_opt(12, 0, 0)
+                    .unwrap()
+                    .and_local_timezone(Local)
+                    .unwrap()
+                    .with_timezone(&Utc),
+            ),
+            provider: Some(provider.to_string()),
+            model: Some(model.to_string()),
+            title: String::new(),
+            turns: messages / 2,
+            messages,
+            tokens: messages * 10,
+            tags: Vec::new(),
+            size: 0,
+        }
+    }
+
+    #[test]
+    fn compaction_keeps_the_longest_snapshot() {
+        let transcript = |messages: &[&str], tags: &[&str], title: Option<&str>| Transcript {
+            meta: logger::SessionMeta {
+                tags: tags.iter().map(|tag| tag.to_string()).collect(),
+                title: title.map(str::to_string),
+                ..Default::default()
+            },
+            system: None,
+            messages: messages
+                .iter()
+                .enumerate()
+                .map(|(i, text)| match i % 2 {
+                    0 => ChatMessage::user(*text),
+                    _ => ChatMessage::assistant(*text),
+                })
+                .collect(),
+        };
+        let plan = plan_compaction(vec![
+            (
+                "full".into(),
+                transcript(&["q1", "a1", "q2", "a2"], &["work"], None),
+            ),
+            (
+                "partial".into(),
+                transcript(&["q1", "a1"], &["draft"], Some("Plan")),
+            ),
+            (
+                "copy".into(),
+                transcript(&["q1", "a1", "q2", "a2"], &["work"], None),
+            ),
+            ("other".into(), transcript(&["q1", "different"], &[], None)),
+        ]);
+        assert_eq!(plan.redundant, ["copy", "partial"]);
+        assert_eq!(plan.merged.len(), 1);
+        let (id, merged) = &plan.merged[0];
+        assert_eq!(id, "full");
+        assert_eq!(merged.meta.title.as_deref(), Some("Plan"));
+        assert_eq!(merged.meta.tags, ["work", "draft"]);
+    }
+
+    #[test]
+    fn stats_group_by_model_and_day() {
+        let sessions = vec![
+            summary("openai", "gpt-4o", "2024-05-02", 4),
+            summary("google", "gemini-pro", "2024-05-02", 2),
+            summary("openai", "gpt-4o", "2024-05-01", 6),
+        ];
+        let stats = HistoryStats::from_sessions(&sessions);
+        assert_eq!(
+            stats.total,
+            Tally {
+                sessions: 3,
+                messages: 12,
+                tokens: 120
+            }
+        );
+        assert_eq!(
+            stats.by_model[0].0,
+            ("openai".to_string(), "gpt-4o".to_string())
+        );
+        assert_eq!(stats.by_model[0].1.messages, 10);
+        assert_eq!(stats.first, NaiveDate::from_ymd_opt(2024, 5, 1));
+        assert_eq!(
+            stats.busiest_days[0].0,
+            NaiveDate::from_ymd_opt(2024, 5, 2).unwrap()
+        );
+        assert_eq!(stats.busiest_days[0].1.sessions, 2);
+    }
+
+    #[test]
+    fn history_truncates_long_messages() {
+        let long = (1..=20)
+            .map(|n| format!("line {n}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let mut loaded = ChatMessage::assistant(long);
+        loaded.timestamp = None;
+        let messages = vec![ChatMessage::user("hi"), loaded];
+
+        let text = format_transcript(Some("be brief"), &messages, true);
+        assert!(text.starts_with("--- system\nbe brief\n"));
+        assert!(text.contains("--- #1 user · "));
+        assert!(text.contains("--- #2 assistant\n"));
+        assert!(text.contains("line 12\n[... 8 more lines"));
+        assert!(!text.contains("line 13"));
+
+        let full = format_transcript(None, &messages, false);
+        assert!(full.contains("line 20"));
+    }
+}
@@ -0,0 +1,88 @@
+use std::io::{self, BufRead, Write};
+use std::process::Command;
+
+use anyhow::{Context, Result};
+
+/// Captured result of a shell command.
+pub struct ShellOutput {
+    pub status: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl ShellOutput {
+    /// Formats the output as a fenced block suitable for conversation context.
+    pub fn as_context(&self, command: &str) -> String {
+        let mut text = format!("Output of `{command}`");
+        match self.status {
+            Some(code) => text.push_str(&format!(" (exit code {code}):\n")),
+            None => text.push_str(" (terminated by signal):\n"),
+        }
+        text.push_str("```\n");
+        text.push_str(self.stdout.trim_end());
+        if !self.stderr.trim().is_empty() {
+            if !self.stdout.trim().is_empty() {
+                text.push('\n');
+            }
+            text.push_str("[stderr]\n");
+            text.push_str(self.stderr.trim_end());
+        }
+        text.push_str("\n```");
+        text
+    }
+}
+
+/// Runs `command` through the platform shell and captures its output.
+pub fn run_command(command: &str) -> Result<ShellOutput> {
+    let output = if cfg!(windows) {
+        Command::new("cmd").args(["/C", command]).output()
+    } else {
+        Command::new("sh").args(["-c", command]).output()
+    }
+    .with_context(|| format!("failed to run `{command}`"))?;
+    Ok(ShellOutput {
+        status: output.status.code(),
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+    })
+}
+
+/// Runs `command` through the platform shell with the terminal attached, so
+/// its output streams and it can prompt.
+pub fn run_attached(command: &str) -> Result<std::process::ExitStatus> {
+    if cfg!(windows) {
+        Command::new("cmd").args(["/C", command]).status()
+    } else {
+        Command::new("sh").args(["-c", command]).status()
+    }
+    .with_context(|| format!("failed to run `{command}`"))
+}
+
+/// Asks a yes/no question on the terminal; anything but `y`/`yes` declines.
+pub fn confirm(question: &str) -> bool {
+    print!("{question} [y/N] ");
+    io::stdout().flush().ok();
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn context_includes_stderr_and_status() {
+        let output = ShellOutput {
+            status: Some(1),
+            stdout: "ok\n".to_string(),
+            stderr: "boom\n".to_string(),
+        };
+        let text = output.as_context("make");
+        assert!(text.starts_with("Output of `make` (exit code 1):"));
+        assert!(text.contains("ok\n[stderr]\nboom"));
+        assert!(text.ends_with("```"));
+    }
+}
@@ -0,0 +1,139 @@
+//! The shutdown controller `main` installs. SIGTERM, or SIGHUP when the
+//! terminal closes, cancels every request in flight so the command can wind
+//! down and save what it has; what is still pending is flushed before exit.
+//! A command that has not finished after `GRACE` is flushed and ended anyway.
+
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use parking_lot::Mutex;
+
+use crate::provider::CancellationToken;
+use crate::telemetry;
+
+/// How long a signaled command gets to finish on its own.
+const GRACE: Duration = Duration::from_secs(5);
+
+type Flush = Arc<dyn Fn() + Send + Sync>;
+
+static REQUESTED: OnceLock<CancellationToken> = OnceLock::new();
+/// Exit code for the signal that asked for shutdown; 0 while none has
+static SIGNAL: AtomicU8 = AtomicU8::new(0);
+static FLUSHES: Mutex<Vec<(u64, Flush)>> = parking_lot::const_mutex(Vec::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+fn requested_token() -> &'static CancellationToken {
+    REQUESTED.get_or_init(CancellationToken::new)
+}
+
+/// Starts watching for shutdown signals. A second signal skips the grace
+/// period.
+pub fn install() {
+    tokio::spawn(async {
+        let code = wait_for_signal().await;
+        SIGNAL.store(code, Ordering::Relaxed);
+        tracing::debug!("shutting down on signal (exit code {code})");
+        requested_token().cancel();
+        tokio::select! {
+            _ = tokio::time::sleep(GRACE) => {}
+            _ = wait_for_signal() => {}
+        }
+        exit(code).await;
+    });
+}
+
+/// A token for one request, fired when the process is shutting down.
+pub fn token() -> CancellationToken {
+    requested_token().child_token()
+}
+
+pub fn requested() -> bool {
+    requested_token().is_cancelled()
+}
+
+/// The exit code for the signal that stopped the process, if one did.
+pub fn signal_code() -> Option<u8> {
+    match SIGNAL.load(Ordering::Relaxed) {
+        0 => None,
+        code => Some(code),
+    }
+}
+
+/// Runs `flush` at shutdown, unless the returned handle is dropped first.
+#[must_use = "the flush is unregistered when the handle is dropped"]
+pub fn on_flush(flush: impl Fn() + Send + Sync + 'static) -> Registration {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    FLUSHES.lock().push((id, Arc::new(flush)));
+    Registration(id)
+}
+
+pub struct Registration(u64);
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        FLUSHES.lock().retain(|(id, _)| *id != self.0);
+    }
+}
+
+/// Runs every registered flush, then exports the spans still buffered.
+pub async fn flush() {
+    let flushes: Vec<Flush> = FLUSHES
+        .lock()
+        .iter()
+        .map(|(_, flush)| flush.clone())
+        .collect();
+    for flush in flushes {
+        flush();
+    }
+    telemetry::flush().await;
+}
+
+/// Flushes and ends the process without unwinding the command.
+pub async fn exit(code: u8) -> ! {
+    flush().await;
+    std::process::exit(i32::from(code));
+}
+
+/// Resolves with the conventional exit code for the received signal.
+async fn wait_for_signal() -> u8 {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match (
+            signal(SignalKind::terminate()),
+            signal(SignalKind::hangup()),
+        ) {
+            (Ok(mut term), Ok(mut hangup)) => tokio::select! {
+                _ = term.recv() => 143,
+                _ = hangup.recv() => 129,
+            },
+            _ => std::future::pending().await,
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        std::future::pending().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn flushes_run_until_unregistered() {
+        let count = Arc::new(AtomicU64::new(0));
+        let counted = count.clone();
+        let registration = on_flush(move || {
+            counted.fetch_add(1, Ordering::Relaxed);
+        });
+        flush().await;
+        drop(registration);
+        flush().await;
+        assert_eq!(count.load(Ordering::Relaxed), 1);
+
+        let request = token();
+        assert!(!request.is_cancelled() && !requested());
+    }
+}
@@ -0,0 +1,42 @@
+use std::io::{self, IsTerminal};
+use std::time::Duration;
+
+use crate::pricing;
+use crate::provider::TokenUsage;
+
+/// Summary of a single request, printed after the reply when `--stats` is on.
+pub struct TurnStats<'a> {
+    pub model: &'a str,
+    pub elapsed: Duration,
+    pub usage: Option<TokenUsage>,
+}
+
+impl TurnStats<'_> {
+    pub fn line(&self) -> String {
+        let mut parts = vec![
+            self.model.to_string(),
+            format!("{:.1}s", self.elapsed.as_secs_f64()),
+        ];
+        if let Some(usage) = self.usage {
+            parts.push(format!(
+                "{} in / {} out tokens",
+                usage.input_tokens, usage.output_tokens
+            ));
+            if let Some(cost) = pricing::estimate_cost(self.model, &usage) {
+                parts.push(pricing::format_cost(cost));
+            }
+        }
+        parts.join(" · ")
+    }
+
+    /// Writes the stats line to stderr (dimmed on a terminal) so piped stdout
+    /// only ever contains the reply.
+    pub fn print(&self) {
+        let line = self.line();
+        if io::stderr().is_terminal() {
+            eprintln!("\x1b[2m{line}\x1b[0m");
+        } else {
+            eprintln!("{line}");
+        }
+    }
+}
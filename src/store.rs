@@ -0,0 +1,1278 @@
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use parking_lot::Mutex;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::cli::SaveModeArg;
+use crate::config::{RetentionConfig, SessionsConfig, StoreKind, APP_DIR};
+use crate::logger::{self, Compression, HistoryFormat, SessionMeta, Transcript};
+use crate::provider::{ChatMessage, MessageRole, ReplyMeta, TokenUsage};
+use crate::utils;
+
+const DATABASE_FILE: &str = "sessions.db";
+/// Titles in listings are cut to this many characters.
+const TITLE_WIDTH: usize = 48;
+
+/// A saved conversation as shown in listings.
+#[derive(Clone)]
+pub struct SessionSummary {
+    pub id: String,
+    pub date: Option<DateTime<Utc>>,
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    /// The session's given title, or its first user line
+    pub title: String,
+    pub turns: usize,
+    pub messages: usize,
+    /// Rough token count of the system prompt and messages
+    pub tokens: usize,
+    pub tags: Vec<String>,
+    /// Bytes the session takes up in the store
+    pub size: u64,
+}
+
+/// A message matching `sessions search`.
+pub struct SearchHit {
+    pub id: String,
+    pub title: String,
+    /// 1-based position of the message, as numbered by `/history`
+    pub index: usize,
+    pub role: MessageRole,
+    pub snippet: String,
+    /// Byte range of the match within `snippet`
+    pub matched: Range<usize>,
+}
+
+/// Characters of context kept on each side of a search match.
+const SNIPPET_CONTEXT: usize = 40;
+
+/// Persistent home for conversations, addressed by session id.
+pub trait SessionStore: Send + Sync {
+    /// Every stored session, newest first.
+    fn list(&self) -> Result<Vec<SessionSummary>>;
+    fn load(&self, id: &str) -> Result<Transcript>;
+    /// Creates or replaces the session `id`.
+    fn save(
+        &self,
+        id: &str,
+        meta: &SessionMeta,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+    ) -> Result<()>;
+    fn delete(&self, id: &str) -> Result<()>;
+    /// Where session `id` lives, for status messages.
+    fn describe(&self, id: &str) -> String;
+
+    /// Compresses sessions started before `before`, except `keep`, returning
+    /// how many were compressed. Stores without per-session files do nothing.
+    fn archive(
+        &self,
+        _before: DateTime<Utc>,
+        _compression: Compression,
+        _keep: Option<&str>,
+    ) -> Result<usize> {
+        Ok(0)
+    }
+
+    /// Messages containing `query` (case-insensitive), newest session first.
+    fn search(&self, query: &str) -> Result<Vec<SearchHit>> {
+        let mut hits = Vec::new();
+        for session in self.list()? {
+            let transcript = self.load(&session.id)?;
+            for (index, message) in transcript.messages.iter().enumerate() {
+                if let Some((snippet, matched)) = snippet(&message.content, query) {
+                    hits.push(SearchHit {
+                        id: session.id.clone(),
+                        title: session.title.clone(),
+                        index: index + 1,
+                        role: message.role,
+                        snippet,
+                        matched,
+                    });
+                }
+            }
+        }
+        Ok(hits)
+    }
+}
+
+pub type DynStore = Arc<dyn SessionStore>;
+
+/// Opens the store selected in `[sessions]`. The file store needs a history
+/// directory and yields `None` without one.
+pub fn open_store(
+    cfg: &SessionsConfig,
+    history_dir: Option<PathBuf>,
+    format: HistoryFormat,
+    compression: Option<Compression>,
+) -> Result<Option<DynStore>> {
+    match cfg.store {
+        StoreKind::Files => Ok(history_dir.map(|dir| {
+            Arc::new(FileStore {
+                dir,
+                format,
+                compression,
+            }) as DynStore
+        })),
+        StoreKind::Sqlite => {
+            let path = cfg
+                .database
+                .clone()
+                .or_else(default_database_path)
+                .ok_or_else(|| {
+                    anyhow!("no location for the session database - set sessions.database")
+                })?;
+            Ok(Some(Arc::new(SqliteStore::open(&path)?)))
+        }
+    }
+}
+
+pub fn default_database_path() -> Option<PathBuf> {
+    let base = dirs::data_local_dir().or_else(dirs::config_dir)?;
+    Some(base.join(APP_DIR).join(DATABASE_FILE))
+}
+
+/// Where a conversation is written: an explicit `--save` file or a session in
+/// the configured store.
+#[derive(Clone)]
+pub enum SaveTarget {
+    File {
+        path: PathBuf,
+        format: HistoryFormat,
+        /// Transcript already in the file when appending; new messages follow it
+        base: Option<Arc<Transcript>>,
+    },
+    Session {
+        store: DynStore,
+        id: String,
+    },
+}
+
+impl SaveTarget {
+    /// A `--save` file. An existing file is only replaced with `force`; in
+    /// append mode its transcript is kept and the conversation follows it.
+    /// With `compression`, its suffix is added unless `path` already has one.
+    /// Appending keeps the existing file's format whatever `format` says.
+    pub fn file(
+        path: PathBuf,
+        format: HistoryFormat,
+        mode: SaveModeArg,
+        force: bool,
+        compression: Option<Compression>,
+    ) -> Result<Self> {
+        let path = match compression {
+            Some(compression) if Compression::split(&path).1.is_none() => {
+                compression.apply_to(&path)
+            }
+            _ => path,
+        };
+        let base = match mode {
+            _ if !path.exists() => None,
+            SaveModeArg::Append => {
+                let transcript = logger::load_history(&path)
+                    .with_context(|| format!("cannot append to {}", path.display()))?;
+                Some(Arc::new(transcript))
+            }
+            SaveModeArg::Overwrite if force => None,
+            SaveModeArg::Overwrite => bail!(
+                "{} already exists - pass --force to overwrite it or --save-mode append to add to it",
+                path.display()
+            ),
+        };
+        let existing = HistoryFormat::for_path(&path);
+        let format = if base.is_some() && existing.extension() != format.extension() {
+            existing
+        } else {
+            format
+        };
+        Ok(SaveTarget::File { path, format, base })
+    }
+
+    pub fn save(
+        &self,
+        meta: &SessionMeta,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+    ) -> Result<()> {
+        match self {
+            SaveTarget::File {
+                path,
+                format,
+                base: None,
+            } => logger::save_history(path, *format, meta, system, messages),
+            SaveTarget::File {
+                path,
+                format,
+                base: Some(base),
+            } => {
+                let mut merged_meta = SessionMeta {
+                    created_at: base.meta.created_at.or(meta.created_at),
+                    title: meta.title.clone().or_else(|| base.meta.title.clone()),
+                    tags: base.meta.tags.clone(),
+                    ..meta.clone()
+                };
+                for tag in &meta.tags {
+                    if !merged_meta.tags.contains(tag) {
+                        merged_meta.tags.push(tag.clone());
+                    }
+                }
+                let merged: Vec<ChatMessage> =
+                    base.messages.iter().chain(messages).cloned().collect();
+                logger::save_history(
+                    path,
+                    *format,
+                    &merged_meta,
+                    base.system.as_deref().or(system),
+                    &merged,
+                )
+            }
+            SaveTarget::Session { store, id } => store.save(id, meta, system, messages),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            SaveTarget::File { path, .. } => path.display().to_string(),
+            SaveTarget::Session { store, id } => store.describe(id),
+        }
+    }
+
+    /// The session id, or a file's name without its extensions.
+    pub fn name(&self) -> String {
+        match self {
+            SaveTarget::File { path, .. } => Compression::split(path)
+                .0
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            SaveTarget::Session { id, .. } => id.clone(),
+        }
+    }
+
+    /// Prunes the store after an auto-save, sparing the session just saved.
+    /// Failures are reported but never fail the save itself.
+    pub fn apply_retention(&self, policy: &RetentionPolicy) {
+        let SaveTarget::Session { store, id } = self else {
+            return;
+        };
+        if policy.is_empty() {
+            return;
+        }
+        match policy.prune(&**store, Some(id)) {
+            Ok(pruned) if !pruned.is_empty() => {
+                eprintln!("[pruned {} old session(s) from history]", pruned.len())
+            }
+            Ok(_) => {}
+            Err(err) => tracing::warn!("failed to prune history: {err:#}"),
+        }
+        if let Some(age) = policy.compress_after {
+            match store.archive(Utc::now() - age, policy.compression, Some(id)) {
+                Ok(0) => {}
+                Ok(count) => eprintln!("[compressed {count} older session(s)]"),
+                Err(err) => tracing::warn!("failed to compress history: {err:#}"),
+            }
+        }
+    }
+}
+
+/// Limits enforced by `sessions prune` and after auto-saves.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RetentionPolicy {
+    pub older_than: Option<chrono::Duration>,
+    pub max_size: Option<u64>,
+    /// Compress auto-saved session files older than this
+    pub compress_after: Option<chrono::Duration>,
+    pub compression: Compression,
+}
+
+impl RetentionPolicy {
+    pub fn from_config(cfg: &RetentionConfig) -> Result<Self> {
+        Ok(Self {
+            older_than: cfg
+                .older_than
+                .as_deref()
+                .map(utils::parse_age)
+                .transpose()?,
+            max_size: cfg.max_size.as_deref().map(utils::parse_size).transpose()?,
+            compress_after: cfg
+                .compress_after
+                .as_deref()
+                .map(utils::parse_age)
+                .transpose()?,
+            compression: cfg.compression,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.older_than.is_none() && self.max_size.is_none() && self.compress_after.is_none()
+    }
+
+    /// Sessions (from a newest-first listing) that break the policy: those
+    /// older than the age limit, then the oldest until the rest fit the size
+    /// limit. `keep` is never selected.
+    pub fn expired<'a>(
+        &self,
+        sessions: &'a [SessionSummary],
+        now: DateTime<Utc>,
+        keep: Option<&str>,
+    ) -> Vec<&'a SessionSummary> {
+        let cutoff = self.older_than.map(|age| now - age);
+        let mut total = 0u64;
+        sessions
+            .iter()
+            .filter(|session| {
+                if keep == Some(session.id.as_str()) {
+                    total += session.size;
+                    return false;
+                }
+                if cutoff.is_some_and(|cutoff| session.date.is_some_and(|date| date < cutoff)) {
+                    return true;
+                }
+                let grown = total + session.size;
+                if self.max_size.is_some_and(|max| grown > max) {
+                    return true;
+                }
+                total = grown;
+                false
+            })
+            .collect()
+    }
+
+    /// Deletes the sessions in `store` that break the policy, returning them.
+    pub fn prune(
+        &self,
+        store: &dyn SessionStore,
+        keep: Option<&str>,
+    ) -> Result<Vec<SessionSummary>> {
+        let sessions = store.list()?;
+        let expired: Vec<String> = self
+            .expired(&sessions, Utc::now(), keep)
+            .into_iter()
+            .map(|session| session.id.clone())
+            .collect();
+        for id in &expired {
+            store.delete(id)?;
+        }
+        Ok(sessions
+            .into_iter()
+            .filter(|session| expired.contains(&session.id))
+            .collect())
+    }
+}
+
+/// One transcript file per session in the history directory; the id is the
+/// file name without its format and compression extensions.
+pub struct FileStore {
+    dir: PathBuf,
+    /// Format used for newly saved sessions
+    format: HistoryFormat,
+    /// Compression for newly saved sessions
+    compression: Option<Compression>,
+}
+
+impl FileStore {
+    fn existing_path(&self, id: &str) -> Option<PathBuf> {
+        HistoryFormat::ALL
+            .iter()
+            .map(HistoryFormat::extension)
+            .chain(["yml"])
+            .map(|ext| self.dir.join(format!("{id}.{ext}")))
+            .flat_map(|path| {
+                let compressed = Compression::ALL.map(|compression| compression.apply_to(&path));
+                std::iter::once(path).chain(compressed)
+            })
+            .find(|path| path.is_file())
+    }
+
+    fn new_path(&self, id: &str) -> PathBuf {
+        let path = self.dir.join(format!("{id}.{}", self.format.extension()));
+        match self.compression {
+            Some(compression) => compression.apply_to(&path),
+            None => path,
+        }
+    }
+
+    fn require_path(&self, id: &str) -> Result<PathBuf> {
+        self.existing_path(id)
+            .ok_or_else(|| anyhow!("no session '{id}' in {}", self.dir.display()))
+    }
+}
+
+impl SessionStore for FileStore {
+    fn list(&self) -> Result<Vec<SessionSummary>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let entries = fs::read_dir(&self.dir)
+            .with_context(|| format!("failed to read {}", self.dir.display()))?;
+        let mut sessions = Vec::new();
+        for path in entries.filter_map(|entry| entry.ok().map(|e| e.path())) {
+            if !is_history_file(&path) {
+                continue;
+            }
+            let transcript = match logger::load_history(&path) {
+                Ok(transcript) => transcript,
+                Err(err) => {
+                    tracing::warn!("skipping {}: {err:#}", path.display());
+                    continue;
+                }
+            };
+            // Transcripts saved before metadata existed still carry the date and
+            // provider in their auto-save file name.
+            let id = Compression::split(&path)
+                .0
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let (stem_date, stem_provider) = parse_timestamped_id(&id);
+            let metadata = fs::metadata(&path).ok();
+            let date = transcript.meta.created_at.or(stem_date).or_else(|| {
+                metadata
+                    .as_ref()
+                    .and_then(|meta| meta.modified().ok())
+                    .map(DateTime::<Utc>::from)
+            });
+            sessions.push(SessionSummary {
+                id,
+                date,
+                provider: transcript.meta.provider.or(stem_provider),
+                model: transcript.meta.model,
+                title: transcript
+                    .meta
+                    .title
+                    .unwrap_or_else(|| session_title(&transcript.messages)),
+                turns: count_turns(&transcript.messages),
+                messages: transcript.messages.len(),
+                tokens: transcript
+                    .system
+                    .iter()
+                    .chain(transcript.messages.iter().map(|message| &message.content))
+                    .map(|text| utils::approx_tokens(text))
+                    .sum(),
+                tags: transcript.meta.tags,
+                size: metadata.map_or(0, |meta| meta.len()),
+            });
+        }
+        sessions.sort_by(|a, b| b.date.cmp(&a.date).then_with(|| b.id.cmp(&a.id)));
+        Ok(sessions)
+    }
+
+    fn load(&self, id: &str) -> Result<Transcript> {
+        logger::load_history(&self.require_path(id)?)
+    }
+
+    fn save(
+        &self,
+        id: &str,
+        meta: &SessionMeta,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+    ) -> Result<()> {
+        let path = self.existing_path(id).unwrap_or_else(|| self.new_path(id));
+        // ShareGPT shares `.json` with plain JSON, so the extension alone
+        // cannot tell them apart.
+        let format = match HistoryFormat::for_path(&path) {
+            existing if existing.extension() == self.format.extension() => self.format,
+            existing => existing,
+        };
+        logger::save_history(&path, format, meta, system, messages)
+    }
+
+    fn delete(&self, id: &str) -> Result<()> {
+        let path = self.require_path(id)?;
+        fs::remove_file(&path).with_context(|| format!("failed to delete {}", path.display()))
+    }
+
+    fn describe(&self, id: &str) -> String {
+        self.existing_path(id)
+            .unwrap_or_else(|| self.new_path(id))
+            .display()
+            .to_string()
+    }
+
+    fn archive(
+        &self,
+        before: DateTime<Utc>,
+        compression: Compression,
+        keep: Option<&str>,
+    ) -> Result<usize> {
+        let mut count = 0;
+        for session in self.list()? {
+            let stale = matches!(session.date, Some(date) if date < before);
+            if !stale || keep == Some(session.id.as_str()) {
+                continue;
+            }
+            let Some(path) = self.existing_path(&session.id) else {
+                continue;
+            };
+            if Compression::split(&path).1.is_some() {
+                continue;
+            }
+            let data =
+                fs::read(&path).with_context(|| format!("failed to read {}", path.display()))?;
+            logger::write_history_file(&compression.apply_to(&path), &data)?;
+            fs::remove_file(&path)
+                .with_context(|| format!("failed to remove {}", path.display()))?;
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+/// Schema steps applied in order; `PRAGMA user_version` records how many ran.
+const SQLITE_MIGRATIONS: &[&str] = &[
+    "
+CREATE TABLE IF NOT EXISTS sessions (
+    id          TEXT PRIMARY KEY,
+    provider    TEXT,
+    model       TEXT,
+    created_at  TEXT,
+    updated_at  TEXT NOT NULL,
+    system      TEXT,
+    title       TEXT NOT NULL,
+    turns       INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS messages (
+    session_id   TEXT NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    seq          INTEGER NOT NULL,
+    role         TEXT NOT NULL,
+    content      TEXT NOT NULL,
+    tool_calls   TEXT,
+    tool_call_id TEXT,
+    created_at   TEXT,
+    PRIMARY KEY (session_id, seq)
+);
+CREATE INDEX IF NOT EXISTS sessions_by_date ON sessions(created_at DESC);
+CREATE INDEX IF NOT EXISTS sessions_by_provider ON sessions(provider, created_at DESC);
+",
+    "
+ALTER TABLE sessions ADD COLUMN custom_title TEXT;
+CREATE TABLE session_tags (
+    session_id  TEXT NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    tag         TEXT NOT NULL,
+    PRIMARY KEY (session_id, tag)
+);
+CREATE INDEX session_tags_by_tag ON session_tags(tag);
+",
+    "
+ALTER TABLE messages ADD COLUMN provider TEXT;
+ALTER TABLE messages ADD COLUMN model TEXT;
+ALTER TABLE messages ADD COLUMN input_tokens INTEGER;
+ALTER TABLE messages ADD COLUMN output_tokens INTEGER;
+ALTER TABLE messages ADD COLUMN latency_ms INTEGER;
+",
+];
+
+/// Separator for tags folded into one column by `group_concat`.
+const TAG_SEPARATOR: char = '\u{1f}';
+
+/// Sessions in a SQLite database: one row per session plus one per message.
+pub struct SqliteStore {
+    path: PathBuf,
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+        let conn = Connection::open(path)
+            .with_context(|| format!("failed to open session database {}", path.display()))?;
+        migrate(&conn).with_context(|| format!("failed to initialise {}", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            conn: Mutex::new(conn),
+        })
+    }
+}
+
+fn migrate(conn: &Connection) -> Result<()> {
+    conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+    let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (index, step) in SQLITE_MIGRATIONS
+        .iter()
+        .enumerate()
+        .skip(version.max(0) as usize)
+    {
+        conn.execute_batch(&format!(
+            "BEGIN; {step} PRAGMA user_version = {}; COMMIT;",
+            index + 1
+        ))?;
+    }
+    Ok(())
+}
+
+fn split_tags(joined: Option<String>) -> Vec<String> {
+    joined
+        .map(|tags| tags.split(TAG_SEPARATOR).map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+impl SessionStore for SqliteStore {
+    fn list(&self) -> Result<Vec<SessionSummary>> {
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare(
+            "SELECT id, provider, model, COALESCE(created_at, updated_at),
+                    COALESCE(custom_title, title), turns,
+                    (SELECT group_concat(tag, char(31)) FROM session_tags WHERE session_id = sessions.id),
+                    length(CAST(COALESCE(system, '') AS BLOB)) +
+                    (SELECT COALESCE(SUM(length(CAST(content AS BLOB))
+                                         + COALESCE(length(CAST(tool_calls AS BLOB)), 0)), 0)
+                     FROM messages WHERE session_id = sessions.id),
+                    (SELECT COUNT(*) FROM messages WHERE session_id = sessions.id),
+                    (length(COALESCE(system, '')) + 3) / 4 +
+                    (SELECT COALESCE(SUM((length(content) + 3) / 4), 0)
+                     FROM messages WHERE session_id = sessions.id)
+             FROM sessions ORDER BY COALESCE(created_at, updated_at) DESC, id DESC",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(SessionSummary {
+                id: row.get(0)?,
+                provider: row.get(1)?,
+                model: row.get(2)?,
+                date: parse_time(row.get::<_, Option<String>>(3)?),
+                title: row.get(4)?,
+                turns: row.get::<_, i64>(5)? as usize,
+                tags: split_tags(row.get(6)?),
+                size: row.get::<_, i64>(7)?.max(0) as u64,
+                messages: row.get::<_, i64>(8)? as usize,
+                tokens: row.get::<_, i64>(9)? as usize,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    fn load(&self, id: &str) -> Result<Transcript> {
+        let conn = self.conn.lock();
+        let (meta, system) = conn
+            .query_row(
+                "SELECT provider, model, created_at, system, custom_title,
+                        (SELECT group_concat(tag, char(31)) FROM session_tags WHERE session_id = sessions.id)
+                 FROM sessions WHERE id = ?1",
+                [id],
+                |row| {
+                    Ok((
+                        SessionMeta {
+                            provider: row.get(0)?,
+                            model: row.get(1)?,
+                            created_at: parse_time(row.get(2)?),
+                            title: row.get(4)?,
+                            tags: split_tags(row.get(5)?),
+                        },
+                        row.get::<_, Option<String>>(3)?,
+                    ))
+                },
+            )
+            .optional()?
+            .ok_or_else(|| anyhow!("no session '{id}' in {}", self.path.display()))?;
+        let mut stmt = conn.prepare(
+            "SELECT role, content, tool_calls, tool_call_id, created_at,
+                    provider, model, input_tokens, output_tokens, latency_ms
+             FROM messages WHERE session_id = ?1 ORDER BY seq",
+        )?;
+        let rows = stmt.query_map([id], |row| {
+            let provider: Option<String> = row.get(5)?;
+            let model: Option<String> = row.get(6)?;
+            let input_tokens: Option<u32> = row.get(7)?;
+            let output_tokens: Option<u32> = row.get(8)?;
+            let latency_ms: Option<i64> = row.get(9)?;
+            let reply = provider.zip(model).map(|(provider, model)| ReplyMeta {
+                provider,
+                model,
+                usage: input_tokens
+                    .zip(output_tokens)
+                    .map(|(input_tokens, output_tokens)| TokenUsage {
+                        input_tokens,
+                        output_tokens,
+                    }),
+                latency: Duration::from_millis(latency_ms.unwrap_or_default().max(0) as u64),
+            });
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, Option<String>>(3)?,
+                row.get::<_, Option<String>>(4)?,
+                reply,
+            ))
+        })?;
+        let mut messages = Vec::new();
+        for row in rows {
+            let (role, content, tool_calls, tool_call_id, created_at, reply) = row?;
+            let role = MessageRole::parse(&role)
+                .ok_or_else(|| anyhow!("unknown role '{role}' in session '{id}'"))?;
+            let mut message = ChatMessage::new(role, content);
+            message.timestamp = parse_time(created_at);
+            message.reply = reply;
+            if let Some(json) = tool_calls {
+                message.tool_calls = serde_json::from_str(&json)
+                    .with_context(|| format!("invalid tool calls in session '{id}'"))?;
+            }
+            message.tool_call_id = tool_call_id;
+            messages.push(message);
+        }
+        Ok(Transcript {
+            meta,
+            system,
+            messages,
+        })
+    }
+
+    fn save(
+        &self,
+        id: &str,
+        meta: &SessionMeta,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+    ) -> Result<()> {
+        let mut conn = self.conn.lock();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO sessions
+                (id, provider, model, created_at, updated_at, system, title, turns, custom_title)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+             ON CONFLICT(id) DO UPDATE SET
+                provider = excluded.provider, model = excluded.model,
+                created_at = excluded.created_at, updated_at = excluded.updated_at,
+                system = excluded.system, title = excluded.title, turns = excluded.turns,
+                custom_title = excluded.custom_title",
+            params![
+                id,
+                meta.provider,
+                meta.model,
+                meta.created_at.map(|time| time.to_rfc3339()),
+                Utc::now().to_rfc3339(),
+                system,
+                session_title(messages),
+                count_turns(messages) as i64,
+                meta.title,
+            ],
+        )?;
+        tx.execute("DELETE FROM session_tags WHERE session_id = ?1", [id])?;
+        for tag in &meta.tags {
+            tx.execute(
+                "INSERT OR IGNORE INTO session_tags (session_id, tag) VALUES (?1, ?2)",
+                params![id, tag],
+            )?;
+        }
+        tx.execute("DELETE FROM messages WHERE session_id = ?1", [id])?;
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO messages (session_id, seq, role, content, tool_calls, tool_call_id, created_at,
+                                       provider, model, input_tokens, output_tokens, latency_ms)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            )?;
+            for (seq, message) in messages.iter().enumerate() {
+                let tool_calls = if message.tool_calls.is_empty() {
+                    None
+                } else {
+                    Some(serde_json::to_string(&message.tool_calls)?)
+                };
+                let reply = message.reply.as_ref();
+                let usage = reply.and_then(|reply| reply.usage);
+                insert.execute(params![
+                    id,
+                    seq as i64,
+                    message.role.to_string(),
+                    message.content,
+                    tool_calls,
+                    message.tool_call_id,
+                    message.timestamp.map(|time| time.to_rfc3339()),
+                    reply.map(|reply| &reply.provider),
+                    reply.map(|reply| &reply.model),
+                    usage.map(|usage| usage.input_tokens),
+                    usage.map(|usage| usage.output_tokens),
+                    reply.map(|reply| reply.latency.as_millis() as i64),
+                ])?;
+            }
+        }
+        tx.commit()
+            .with_context(|| format!("failed to save session '{id}' to {}", self.path.display()))
+    }
+
+    fn delete(&self, id: &str) -> Result<()> {
+        let conn = self.conn.lock();
+        if conn.execute("DELETE FROM sessions WHERE id = ?1", [id])? == 0 {
+            bail!("no session '{id}' in {}", self.path.display());
+        }
+        Ok(())
+    }
+
+    fn describe(&self, id: &str) -> String {
+        format!("{} (session {id})", self.path.display())
+    }
+
+    fn search(&self, query: &str) -> Result<Vec<SearchHit>> {
+        let conn = self.conn.lock();
+        // SQLite's LIKE only folds ASCII case, so matching happens in `snippet`
+        // exactly as it does for the file store.
+        let mut stmt = conn.prepare(
+            "SELECT s.id, COALESCE(s.custom_title, s.title), m.seq, m.role, m.content
+             FROM messages m JOIN sessions s ON s.id = m.session_id
+             ORDER BY COALESCE(s.created_at, s.updated_at) DESC, s.id DESC, m.seq",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+            ))
+        })?;
+        let mut hits = Vec::new();
+        for row in rows {
+            let (id, title, seq, role, content) = row?;
+            let Some(role) = MessageRole::parse(&role) else {
+                continue;
+            };
+            if let Some((snippet, matched)) = snippet(&content, query) {
+                hits.push(SearchHit {
+                    id,
+                    title,
+                    index: seq as usize + 1,
+                    role,
+                    snippet,
+                    matched,
+                });
+            }
+        }
+        Ok(hits)
+    }
+}
+
+/// Finds `query` in `text` ignoring case and returns a single-line excerpt
+/// around the first match together with the match's range in that excerpt.
+pub fn snippet(text: &str, query: &str) -> Option<(String, Range<usize>)> {
+    let Range { start, end } = find_ignore_case(text, query)?;
+    let from = text[..start]
+        .char_indices()
+        .rev()
+        .nth(SNIPPET_CONTEXT - 1)
+        .map_or(0, |(offset, _)| offset);
+    let to = text[end..]
+        .char_indices()
+        .nth(SNIPPET_CONTEXT)
+        .map_or(text.len(), |(offset, _)| end + offset);
+
+    let flatten = |part: &str| part.replace(['\n', '\r', '\t'], " ");
+    let mut excerpt = String::new();
+    if from > 0 {
+        excerpt.push('…');
+    }
+    excerpt.push_str(&flatten(&text[from..start]));
+    let matched_start = excerpt.len();
+    excerpt.push_str(&flatten(&text[start..end]));
+    let matched = matched_start..excerpt.len();
+    excerpt.push_str(&flatten(&text[end..to]));
+    if to < text.len() {
+        excerpt.push('…');
+    }
+    Some((excerpt, matched))
+}
+
+/// Byte range of the first case-insensitive match of `query` in `text`.
+/// Lowercasing can change a character's length (`İ` becomes two), so the end
+/// comes from the text actually consumed rather than the query's length.
+fn find_ignore_case(text: &str, query: &str) -> Option<Range<usize>> {
+    let needle: Vec<char> = query.chars().flat_map(char::to_lowercase).collect();
+    if needle.is_empty() {
+        return None;
+    }
+    text.char_indices().find_map(|(start, _)| {
+        let mut pending = needle.as_slice();
+        for (offset, ch) in text[start..].char_indices() {
+            for lower in ch.to_lowercase() {
+                let (first, rest) = pending.split_first()?;
+                if *first != lower {
+                    return None;
+                }
+                pending = rest;
+                if pending.is_empty() {
+                    return Some(start..start + offset + ch.len_utf8());
+                }
+            }
+        }
+        None
+    })
+}
+
+fn parse_time(value: Option<String>) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(&value?)
+        .ok()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+fn is_history_file(path: &Path) -> bool {
+    path.is_file()
+        && Compression::split(path)
+            .0
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(HistoryFormat::from_extension)
+            .is_some()
+}
+
+fn count_turns(messages: &[ChatMessage]) -> usize {
+    messages
+        .iter()
+        .filter(|message| message.role == MessageRole::User)
+        .count()
+}
+
+/// Splits an auto-save id such as `20240501-123045-openai` into its
+/// timestamp and provider.
+fn parse_timestamped_id(id: &str) -> (Option<DateTime<Utc>>, Option<String>) {
+    let Some((stamp, provider)) = id.get(..15).zip(id.get(16..)) else {
+        return (None, None);
+    };
+    match NaiveDateTime::parse_from_str(stamp, "%Y%m%d-%H%M%S") {
+        Ok(naive) => (
+            Some(naive.and_utc()),
+            Some(provider.to_string()).filter(|p| !p.is_empty()),
+        ),
+        Err(_) => (None, None),
+    }
+}
+
+/// First non-blank line of the first user message, shortened for listings.
+fn session_title(messages: &[ChatMessage]) -> String {
+    let line = messages
+        .iter()
+        .find(|message| message.role == MessageRole::User)
+        .and_then(|message| {
+            message
+                .content
+                .lines()
+                .map(str::trim)
+                .find(|l| !l.is_empty())
+        });
+    let Some(line) = line else {
+        return "(empty)".to_string();
+    };
+    if line.chars().count() <= TITLE_WIDTH {
+        return line.to_string();
+    }
+    let mut title: String = line.chars().take(TITLE_WIDTH - 1).collect();
+    title.truncate(title.trim_end().len());
+    title.push('…');
+    title
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::ToolCall;
+    use chrono::TimeZone;
+
+    fn summary(id: &str, days_ago: i64, size: u64, now: DateTime<Utc>) -> SessionSummary {
+        SessionSummary {
+            id: id.to_string(),
+            date: Some(now - chrono::Duration::days(days_ago)),
+            provider: None,
+            model: None,
+            title: String::new(),
+            turns: 1,
+            messages: 2,
+            tokens: 10,
+            tags: Vec::new(),
+            size,
+        }
+    }
+
+    #[test]
+    fn retention_drops_old_then_oversized_sessions() {
+        let now = Utc::now();
+        let sessions = vec![
+            summary("current", 0, 600, now),
+            summary("recent", 1, 300, now),
+            summary("older", 5, 300, now),
+            summary("ancient", 200, 10, now),
+        ];
+        let policy = RetentionPolicy {
+            older_than: Some(chrono::Duration::days(90)),
+            max_size: Some(1000),
+            ..Default::default()
+        };
+        let expired: Vec<&str> = policy
+            .expired(&sessions, now, Some("current"))
+            .iter()
+            .map(|session| session.id.as_str())
+            .collect();
+        assert_eq!(expired, ["older", "ancient"]);
+        assert!(RetentionPolicy::default()
+            .expired(&sessions, now, None)
+            .is_empty());
+    }
+
+    #[test]
+    fn file_store_reads_and_archives_compressed_sessions() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        let store = FileStore {
+            dir: dir.clone(),
+            format: HistoryFormat::Json,
+            compression: Some(Compression::Zstd),
+        };
+        let plain = FileStore {
+            dir: dir.clone(),
+            format: HistoryFormat::Markdown,
+            compression: None,
+        };
+        let old = SessionMeta {
+            created_at: Some(Utc::now() - chrono::Duration::days(60)),
+            ..SessionMeta::new("openai", "gpt-4o")
+        };
+        store
+            .save(
+                "new",
+                &SessionMeta::new("openai", "gpt-4o"),
+                None,
+                &[ChatMessage::user("fresh")],
+            )
+            .unwrap();
+        plain
+            .save("old", &old, None, &[ChatMessage::user("stale")])
+            .unwrap();
+        assert!(dir.join("new.json.zst").is_file());
+
+        let archived = store
+            .archive(
+                Utc::now() - chrono::Duration::days(30),
+                Compression::Gzip,
+                None,
+            )
+            .unwrap();
+        assert_eq!(archived, 1);
+        assert!(dir.join("old.md.gz").is_file());
+        assert!(!dir.join("old.md").exists());
+
+        let ids: Vec<String> = store.list().unwrap().into_iter().map(|s| s.id).collect();
+        assert_eq!(ids, ["new", "old"]);
+        assert_eq!(store.load("old").unwrap().messages[0].content, "stale");
+        assert_eq!(store.load("new").unwrap().messages[0].content, "fresh");
+    }
+
+    #[test]
+    fn timestamped_path_is_deterministic() {
+        let store = FileStore {
+            dir: PathBuf::from("/tmp/history"),
+            format: HistoryFormat::Markdown,
+            compression: None,
+        };
+        let now = Utc
+            .with_ymd_and_hms(2024, 5, 1, 12, 30, 45)
+            .single()
+            .expect("valid timestamp");
+        let path = store.new_path(&logger::session_id_at("Prod#Provider", now));
+        assert_eq!(
+            path.file_name().unwrap().to_str().unwrap(),
+            "20240501-123045-prod-provider.md"
+        );
+    }
+
+    #[test]
+    fn file_store_auto_saves_sharegpt() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        let store = FileStore {
+            dir: dir.clone(),
+            format: HistoryFormat::ShareGpt,
+            compression: None,
+        };
+        let meta = SessionMeta::new("openai", "gpt-4o");
+        let mut messages = vec![ChatMessage::user("hi"), ChatMessage::assistant("hello")];
+        store.save("chat", &meta, None, &messages).unwrap();
+        messages.push(ChatMessage::user("again"));
+        store.save("chat", &meta, None, &messages).unwrap();
+
+        let raw = fs::read_to_string(dir.join("chat.json")).unwrap();
+        let payload: serde_json::Value = serde_json::from_str(&raw).unwrap();
+        assert!(payload[0]["conversations"].is_array());
+        assert_eq!(store.load("chat").unwrap().messages.len(), 3);
+    }
+
+    #[test]
+    fn append_target_keeps_existing_transcript() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("append.md");
+        let first = SaveTarget::file(
+            path.clone(),
+            HistoryFormat::Markdown,
+            SaveModeArg::Append,
+            false,
+            None,
+        )
+        .unwrap();
+        let meta = SessionMeta {
+            tags: vec!["notes".to_string()],
+            ..SessionMeta::new("openai", "gpt-4o")
+        };
+        first
+            .save(&meta, Some("Be brief"), &[ChatMessage::user("one")])
+            .unwrap();
+
+        // `--save-format` defaults to JSON; appending must not convert the file.
+        let second = SaveTarget::file(
+            path.clone(),
+            HistoryFormat::Json,
+            SaveModeArg::Append,
+            false,
+            None,
+        )
+        .unwrap();
+        let later = SessionMeta::new("google", "gemini-pro");
+        // Rewritten after every turn, the earlier transcript must appear once.
+        for _ in 0..2 {
+            second
+                .save(&later, None, &[ChatMessage::user("two")])
+                .unwrap();
+        }
+        assert!(SaveTarget::file(
+            path.clone(),
+            HistoryFormat::Markdown,
+            SaveModeArg::Overwrite,
+            false,
+            None,
+        )
+        .is_err());
+        let transcript = logger::load_history(&path).unwrap();
+        let raw = fs::read_to_string(&path).unwrap();
+        assert!(!raw.trim_start().starts_with('{'));
+        let contents: Vec<&str> = transcript
+            .messages
+            .iter()
+            .map(|message| message.content.as_str())
+            .collect();
+        assert_eq!(contents, ["one", "two"]);
+        assert_eq!(transcript.system.as_deref(), Some("Be brief"));
+        assert_eq!(transcript.meta.created_at, meta.created_at);
+        assert_eq!(transcript.meta.tags, ["notes"]);
+        assert_eq!(transcript.meta.provider.as_deref(), Some("google"));
+    }
+
+    #[test]
+    fn title_comes_from_first_user_line() {
+        let messages = vec![
+            ChatMessage::user(
+                "\n  How do I reverse a linked list in Rust without unsafe code?\nThanks",
+            ),
+            ChatMessage::assistant("Like this"),
+        ];
+        assert_eq!(
+            session_title(&messages),
+            "How do I reverse a linked list in Rust without…"
+        );
+        assert_eq!(session_title(&[]), "(empty)");
+    }
+
+    #[test]
+    fn snippet_marks_case_insensitive_match() {
+        let text = format!(
+            "{}The Borrow Checker\nrejects this{}",
+            "x".repeat(60),
+            "y".repeat(60)
+        );
+        let (excerpt, matched) = snippet(&text, "borrow checker").expect("match");
+        assert_eq!(&excerpt[matched.clone()], "Borrow Checker");
+        assert!(excerpt.starts_with('…') && excerpt.ends_with('…'));
+        assert!(excerpt.contains("Checker rejects"));
+        assert!(snippet("nothing here", "borrow").is_none());
+
+        let (excerpt, matched) = snippet("İstanbul is big", "i\u{307}stanbul").expect("match");
+        assert_eq!(&excerpt[matched], "İstanbul");
+    }
+
+    #[test]
+    fn legacy_ids_yield_date_and_provider() {
+        let (date, provider) = parse_timestamped_id("20240501-123045-work-openai");
+        assert_eq!(
+            date.map(|d| d.to_rfc3339()).as_deref(),
+            Some("2024-05-01T12:30:45+00:00")
+        );
+        assert_eq!(provider.as_deref(), Some("work-openai"));
+        assert_eq!(parse_timestamped_id("notes"), (None, None));
+    }
+
+    #[test]
+    fn sqlite_store_round_trips_sessions() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("store.db");
+        let store = SqliteStore::open(&path).expect("open");
+        let meta = SessionMeta {
+            tags: vec!["rust".to_string(), "work".to_string()],
+            ..SessionMeta::new("openai", "gpt-4o")
+        };
+        let call = ToolCall {
+            id: "call-1".to_string(),
+            name: "shell".to_string(),
+            arguments: serde_json::json!({ "command": "ls" }),
+        };
+        let reply = ReplyMeta {
+            provider: "openai".to_string(),
+            model: "gpt-4o".to_string(),
+            usage: Some(TokenUsage {
+                input_tokens: 12,
+                output_tokens: 3,
+            }),
+            latency: Duration::from_millis(850),
+        };
+        let messages = vec![
+            ChatMessage::user("List files"),
+            ChatMessage::assistant_with_tools("", vec![call.clone()]).with_reply(reply.clone()),
+            ChatMessage::tool_result("call-1", "a.txt"),
+        ];
+        store
+            .save("s1", &meta, Some("Be brief"), &messages[..1])
+            .expect("first save");
+        store
+            .save("s1", &meta, Some("Be brief"), &messages)
+            .expect("overwrite");
+
+        let listed = store.list().expect("list");
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].title, "List files");
+        assert_eq!(listed[0].turns, 1);
+        assert_eq!(listed[0].tags, ["rust", "work"]);
+
+        let loaded = store.load("s1").expect("load");
+        assert_eq!(loaded.meta, meta);
+        assert_eq!(loaded.system.as_deref(), Some("Be brief"));
+        assert_eq!(loaded.messages.len(), 3);
+        assert_eq!(loaded.messages[1].tool_calls, vec![call]);
+        assert_eq!(loaded.messages[1].reply, Some(reply));
+        assert_eq!(loaded.messages[0].reply, None);
+        assert_eq!(loaded.messages[2].tool_call_id.as_deref(), Some("call-1"));
+
+        let titled = SessionMeta {
+            title: Some("Housekeeping".to_string()),
+            ..meta
+        };
+        store.save("s1", &titled, None, &messages).expect("rename");
+        assert_eq!(store.list().expect("list")[0].title, "Housekeeping");
+        assert_eq!(store.load("s1").expect("load").meta, titled);
+
+        let hits = store.search("A.TXT").expect("search");
+        assert_eq!(hits.len(), 1);
+        assert_eq!((hits[0].index, hits[0].role), (3, MessageRole::Tool));
+        assert!(store.search("100%").expect("search").is_empty());
+
+        store
+            .save("s2", &titled, None, &[ChatMessage::user("Über das Wetter")])
+            .expect("save");
+        assert_eq!(store.search("über").expect("search").len(), 1);
+        store.delete("s2").expect("delete");
+
+        store.delete("s1").expect("delete");
+        assert!(store.load("s1").is_err());
+        drop(store);
+    }
+}
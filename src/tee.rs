@@ -0,0 +1,91 @@
+//! `--tee`: each reply copied to a file as it arrives, so a long generation
+//! survives a lost scrollback. Writes are unbuffered; a failed write is
+//! reported once and teeing stops.
+
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+
+#[derive(Clone)]
+pub struct Tee(Arc<Inner>);
+
+struct Inner {
+    path: PathBuf,
+    file: File,
+    /// `--tee-prompt`: prompts are written too, quoted
+    prompts: bool,
+    failed: AtomicBool,
+}
+
+impl Tee {
+    /// Creates `path`, replacing whatever was there.
+    pub fn create(path: &Path, prompts: bool) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("failed to create --tee file {}", path.display()))?;
+        Ok(Self(Arc::new(Inner {
+            path: path.to_path_buf(),
+            file,
+            prompts,
+            failed: AtomicBool::new(false),
+        })))
+    }
+
+    /// Writes a prompt as a `> ` quote when `--tee-prompt` asked for them.
+    pub fn prompt(&self, text: &str) {
+        if self.0.prompts {
+            self.write(&quote(text));
+        }
+    }
+
+    pub fn reply(&self, text: &str) {
+        self.write(text);
+    }
+
+    /// Ends a reply with a blank line.
+    pub fn end_reply(&self) {
+        self.write("\n\n");
+    }
+
+    fn write(&self, text: &str) {
+        if self.0.failed.load(Ordering::Relaxed) {
+            return;
+        }
+        if let Err(err) = (&self.0.file).write_all(text.as_bytes()) {
+            self.0.failed.store(true, Ordering::Relaxed);
+            tracing::warn!("stopped writing to {}: {err}", self.0.path.display());
+        }
+    }
+}
+
+fn quote(text: &str) -> String {
+    let mut quoted: String = text
+        .trim_end()
+        .lines()
+        .map(|line| format!("> {line}\n").replace("> \n", ">\n"))
+        .collect();
+    quoted.push('\n');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replies_and_quoted_prompts_reach_the_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("tee.md");
+        let tee = Tee::create(&path, true).unwrap();
+        tee.prompt("Name a colour.\n\nJust one.");
+        for token in ["Te", "al."] {
+            tee.reply(token);
+        }
+        tee.end_reply();
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(written, "> Name a colour.\n>\n> Just one.\n\nTeal.\n\n");
+    }
+}
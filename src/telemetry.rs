@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::{ContextGuard, KeyValue};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig, WithHttpConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use tracing::{Level, Metadata};
+
+use crate::config::TelemetryConfig;
+use crate::logging;
+
+const DEFAULT_SERVICE: &str = "rustchat-cli";
+const TRACES_PATH: &str = "/v1/traces";
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(5);
+
+static PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+/// What goes to the collector: this crate's spans (dependencies' are
+/// ignored), and the events at info or above inside them, not the `-v`
+/// request log.
+pub fn collected(metadata: &Metadata<'_>) -> bool {
+    metadata.target().starts_with("rustchat")
+        && (metadata.is_span() || *metadata.level() <= Level::INFO)
+}
+
+/// `[telemetry] endpoint`, with `/v1/traces` appended to a base endpoint.
+/// Without one the exporter reads the standard OTLP environment variables.
+fn endpoint(cfg: &TelemetryConfig) -> Option<String> {
+    let base = cfg.endpoint.as_deref()?.trim_end_matches('/');
+    Some(match base.ends_with(TRACES_PATH) {
+        true => base.to_string(),
+        false => format!("{base}{TRACES_PATH}"),
+    })
+}
+
+fn configured(cfg: &TelemetryConfig) -> bool {
+    let env = |name: &str| std::env::var(name).is_ok_and(|value| !value.is_empty());
+    cfg.endpoint.is_some()
+        || env("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT")
+        || env("OTEL_EXPORTER_OTLP_ENDPOINT")
+}
+
+/// The context of a W3C `traceparent` (`00-<trace id>-<span id>-<flags>`),
+/// so the spans of a run join the trace of whatever launched it.
+fn remote_parent(traceparent: &str) -> opentelemetry::Context {
+    let carrier = HashMap::from([("traceparent".to_string(), traceparent.trim().to_string())]);
+    TraceContextPropagator::new().extract(&carrier)
+}
+
+/// Starts exporting spans when a collector is configured; without one the
+/// `tracing` spans cost next to nothing and go nowhere. While the returned
+/// guard is held, spans without a parent join the trace in `TRACEPARENT`.
+pub fn init(cfg: &TelemetryConfig) -> Option<ContextGuard> {
+    if !configured(cfg) {
+        return None;
+    }
+    let mut exporter = SpanExporter::builder()
+        .with_http()
+        .with_timeout(EXPORT_TIMEOUT)
+        .with_headers(cfg.headers.clone().into_iter().collect());
+    if let Some(endpoint) = endpoint(cfg) {
+        exporter = exporter.with_endpoint(endpoint);
+    }
+    let exporter = match exporter.build() {
+        Ok(exporter) => exporter,
+        Err(err) => {
+            tracing::warn!("failed to set up trace export: {err}");
+            return None;
+        }
+    };
+    let service = cfg
+        .service_name
+        .clone()
+        .or_else(|| std::env::var("OTEL_SERVICE_NAME").ok())
+        .unwrap_or_else(|| DEFAULT_SERVICE.to_string());
+    let resource = Resource::builder()
+        .with_service_name(service)
+        .with_attribute(KeyValue::new("service.version", env!("CARGO_PKG_VERSION")))
+        .build();
+    // The batch processor sends what it has every five seconds from its own
+    // thread, so long-running commands (`serve`, `daemon`) export as they go.
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource)
+        .build();
+    logging::attach(tracing_opentelemetry::layer().with_tracer(provider.tracer(DEFAULT_SERVICE)));
+    let _ = PROVIDER.set(provider);
+    std::env::var("TRACEPARENT")
+        .ok()
+        .map(|traceparent| remote_parent(&traceparent).attach())
+}
+
+/// Sends the spans finished so far. A collector that cannot be reached is
+/// reported and never fails the command.
+pub async fn flush() {
+    let Some(provider) = PROVIDER.get().cloned() else {
+        return;
+    };
+    // The exporter's HTTP client blocks.
+    let outcome = tokio::task::spawn_blocking(move || provider.force_flush()).await;
+    if let Ok(Err(err)) = outcome {
+        tracing::warn!("failed to export traces: {err}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use opentelemetry::trace::{SpanKind, Status};
+    use opentelemetry::Value;
+    use opentelemetry_sdk::error::OTelSdkResult;
+    use opentelemetry_sdk::trace::SpanData;
+    use parking_lot::Mutex;
+    use tracing_subscriber::filter::filter_fn;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::{Layer, Registry};
+
+    use super::*;
+
+    /// Keeps exported spans for the test to look at.
+    #[derive(Debug, Clone, Default)]
+    struct Exported(Arc<Mutex<Vec<SpanData>>>);
+
+    impl opentelemetry_sdk::trace::SpanExporter for Exported {
+        async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
+            self.0.lock().extend(batch);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn spans_nest_under_the_remote_parent() {
+        let exported = Exported::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exported.clone())
+            .build();
+        let layer = tracing_opentelemetry::layer()
+            .with_tracer(provider.tracer("tests"))
+            .with_filter(filter_fn(collected));
+        let _remote =
+            remote_parent("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01").attach();
+        tracing::subscriber::with_default(Registry::default().with(layer), || {
+            let command = tracing::info_span!("command", otel.name = "rustchat-cli message");
+            let _entered = command.enter();
+            let http = tracing::info_span!(
+                "http",
+                otel.kind = "client",
+                http.response.status_code = tracing::field::Empty,
+                otel.status_code = tracing::field::Empty,
+            );
+            http.in_scope(|| {
+                tracing::info!(attempt = 2u32, "retry");
+                tracing::debug!("not exported");
+            });
+            http.record("http.response.status_code", 429i64);
+            http.record("otel.status_code", "ERROR");
+        });
+        let spans = exported.0.lock();
+        let (http, command) = (&spans[0], &spans[1]);
+
+        // The command joins the trace from TRACEPARENT; the request nests in it.
+        assert_eq!(command.name, "rustchat-cli message");
+        assert_eq!(
+            command.span_context.trace_id().to_string(),
+            "0af7651916cd43dd8448eb211c80319c"
+        );
+        assert_eq!(command.parent_span_id.to_string(), "b7ad6b7169203331");
+        assert_eq!(
+            http.span_context.trace_id(),
+            command.span_context.trace_id()
+        );
+        assert_eq!(http.parent_span_id, command.span_context.span_id());
+        assert_eq!(http.span_kind, SpanKind::Client);
+        assert!(matches!(http.status, Status::Error { .. }));
+        assert!(http.attributes.iter().any(|attribute| {
+            attribute.key.as_str() == "http.response.status_code"
+                && attribute.value == Value::I64(429)
+        }));
+        let events: Vec<_> = http
+            .events
+            .iter()
+            .map(|event| event.name.as_ref())
+            .collect();
+        assert_eq!(events, ["retry"]);
+        assert_eq!(command.status, Status::Unset);
+    }
+}
@@ -0,0 +1,149 @@
+use anyhow::{anyhow, bail, Result};
+
+/// Values for `{{name}}` placeholders: `--var` pairs first, then piped stdin
+/// for `{{stdin}}`, then environment variables of the same name.
+#[derive(Debug, Default)]
+pub struct Vars {
+    pairs: Vec<(String, String)>,
+    stdin: Option<String>,
+}
+
+impl Vars {
+    /// Parses repeated `--var key=value` arguments; later pairs win.
+    pub fn parse(args: &[String]) -> Result<Self> {
+        let pairs = args
+            .iter()
+            .map(|arg| {
+                let (key, value) = arg
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!("--var {arg} should look like key=value"))?;
+                let key = key.trim();
+                if !is_name(key) {
+                    bail!("--var name {key:?} may only use letters, digits, '_' and '-'");
+                }
+                Ok((key.to_string(), value.to_string()))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { pairs, stdin: None })
+    }
+
+    /// Makes piped stdin available as `{{stdin}}`.
+    pub fn with_stdin(mut self, stdin: Option<String>) -> Self {
+        self.stdin = stdin.map(|text| text.trim_end().to_string());
+        self
+    }
+
+    fn get(&self, name: &str) -> Option<String> {
+        self.pairs
+            .iter()
+            .rev()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.clone())
+            .or_else(|| (name == "stdin").then(|| self.stdin.clone()).flatten())
+            .or_else(|| std::env::var(name).ok())
+    }
+}
+
+/// Fills every `{{name}}` placeholder in `text` in a single pass, so values
+/// are never expanded again; fails naming any placeholder left without a value.
+pub fn render(text: &str, vars: &Vars) -> Result<String> {
+    let mut out = String::with_capacity(text.len());
+    let mut missing: Vec<&str> = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            break;
+        };
+        let name = after[..end].trim();
+        out.push_str(&rest[..start]);
+        if is_name(name) {
+            match vars.get(name) {
+                Some(value) => out.push_str(&value),
+                None => {
+                    if !missing.contains(&name) {
+                        missing.push(name);
+                    }
+                }
+            }
+        } else {
+            // Not a placeholder (e.g. `{{ }}` in code); keep it verbatim.
+            out.push_str(&rest[start..start + 2 + end + 2]);
+        }
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    if !missing.is_empty() {
+        bail!(
+            "no value for {} - pass --var NAME=VALUE or set the environment variable",
+            missing
+                .iter()
+                .map(|name| format!("{{{{{name}}}}}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    Ok(out)
+}
+
+/// True when `text` contains a `{{name}}` placeholder.
+pub fn mentions(text: &str, name: &str) -> bool {
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            return false;
+        };
+        if after[..end].trim() == name {
+            return true;
+        }
+        rest = &after[end + 2..];
+    }
+    false
+}
+
+fn is_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placeholders_are_filled_from_vars_stdin_and_env() {
+        let vars = Vars::parse(&[
+            "lang=Rust".into(),
+            "tone=terse".into(),
+            "tone=friendly".into(),
+        ])
+        .unwrap()
+        .with_stdin(Some("fn main() {}\n".into()));
+        std::env::set_var("RUSTCHAT_TEMPLATE_TEST", "from env");
+        assert_eq!(
+            render(
+                "Review this {{lang}} in a {{ tone }} tone:\n{{stdin}}\n{{RUSTCHAT_TEMPLATE_TEST}}",
+                &vars
+            )
+            .unwrap(),
+            "Review this Rust in a friendly tone:\nfn main() {}\nfrom env"
+        );
+        // Values are not expanded again, and non-placeholders are left alone.
+        let vars = Vars::parse(&["a={{b}}".into()]).unwrap();
+        assert_eq!(
+            render("{{a}} {{ }} {{x y}}", &vars).unwrap(),
+            "{{b}} {{ }} {{x y}}"
+        );
+
+        let err = render("{{missing_one}} {{missing_one}}", &Vars::default()).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("no value for {{missing_one}} -"));
+        assert!(Vars::parse(&["novalue".into()]).is_err());
+        assert!(mentions("use {{ stdin }}", "stdin"));
+        assert!(!mentions("use {{stdout}}", "stdin"));
+    }
+}
@@ -0,0 +1,110 @@
+use anyhow::{bail, Context, Result};
+
+use crate::cli::TokensCommand;
+use crate::config::AppConfig;
+use crate::exit::{ExitStatus, ResultExt};
+use crate::input;
+use crate::provider::{build_providers, ChatMessage, ChatRequestOptions};
+use crate::tokens::Tokenizer;
+
+pub async fn run_tokens(args: TokensCommand, cfg: &AppConfig) -> Result<()> {
+    let mut sources: Vec<(String, String)> = Vec::new();
+    if !args.text.is_empty() {
+        sources.push(("text".to_string(), args.text.join(" ")));
+    }
+    if let Some(stdin) = input::read_piped_stdin()?.filter(|text| !text.is_empty()) {
+        sources.push(("stdin".to_string(), stdin));
+    }
+    for pattern in &args.files {
+        let paths = input::expand(pattern)?;
+        if paths.is_empty() {
+            bail!("--file {pattern} matched no files");
+        }
+        for path in paths {
+            let text = std::fs::read_to_string(&path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            sources.push((path.display().to_string(), text));
+        }
+    }
+    if sources.is_empty() {
+        bail!("nothing to count - pass text, --file or pipe it on stdin");
+    }
+
+    // A model given outright needs no provider unless --exact asks one.
+    let provider_name = match (&args.model, args.exact) {
+        (Some(_), false) => None,
+        _ => Some(
+            cfg.infer_default_provider(&args.provider)
+                .exit_status(ExitStatus::Config)?,
+        ),
+    };
+    let default_model = match (&args.model, &provider_name) {
+        (None, Some(name)) => cfg
+            .require_provider(name)
+            .exit_status(ExitStatus::Config)?
+            .default_model(),
+        _ => None,
+    };
+    let model = args
+        .model
+        .clone()
+        .or_else(|| default_model.map(str::to_string))
+        .unwrap_or_else(|| "gemini-pro".to_string());
+
+    let counts = match provider_name.filter(|_| args.exact) {
+        Some(provider_name) => {
+            let providers = build_providers(
+                [provider_name.as_str()],
+                cfg,
+                args.secret_env.as_deref(),
+                !args.no_prompt,
+            )
+            .await?;
+            let provider = &providers[&provider_name];
+            let mut counts = Vec::with_capacity(sources.len());
+            for (label, text) in &sources {
+                let messages = [ChatMessage::user(text.as_str())];
+                let options = ChatRequestOptions::default();
+                match provider
+                    .count_tokens(&model, None, &messages, &options)
+                    .await?
+                {
+                    Some(count) => counts.push((label.as_str(), count)),
+                    None => bail!(
+                        "{provider_name} has no token counting endpoint; drop --exact to use the {} count",
+                        Tokenizer::for_model(&model).as_str()
+                    ),
+                }
+            }
+            println!("[counted by {provider_name} for {model}]");
+            counts
+        }
+        None => {
+            let tokenizer = Tokenizer::for_model(&model);
+            let about = if tokenizer.is_exact() {
+                ""
+            } else {
+                ", estimated"
+            };
+            println!("[{} for {model}{about}]", tokenizer.as_str());
+            sources
+                .iter()
+                .map(|(label, text)| (label.as_str(), tokenizer.count(text)))
+                .collect()
+        }
+    };
+
+    let width = counts
+        .iter()
+        .map(|(_, count)| count.to_string().len())
+        .max()
+        .unwrap_or(1);
+    for (label, count) in &counts {
+        println!("{count:>width$}  {label}");
+    }
+    if counts.len() > 1 {
+        let total: usize = counts.iter().map(|(_, count)| count).sum();
+        println!("{total:>width$}  total");
+    }
+    Ok(())
+}
@@ -0,0 +1,540 @@
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use serde_json::json;
+
+use crate::cli::{ToolApprovalArg, ToolArg};
+use crate::config::{ApprovalPolicy, ToolsConfig};
+use crate::plugin::{self, Plugin};
+use crate::provider::{ToolCall, ToolSpec};
+use crate::shell;
+
+/// Upper bound on consecutive tool round-trips within one user turn.
+pub const MAX_TOOL_ROUNDS: usize = 8;
+
+/// Longest tool output handed back to the model, in characters.
+const MAX_OUTPUT_CHARS: usize = 20_000;
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A tool that ships with rustchat-cli.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Builtin {
+    Shell,
+    FetchUrl,
+    ReadFile,
+    WriteFile,
+}
+
+impl Builtin {
+    const ALL: [Builtin; 4] = [
+        Builtin::Shell,
+        Builtin::FetchUrl,
+        Builtin::ReadFile,
+        Builtin::WriteFile,
+    ];
+
+    /// The `--tools` value that turns this tool on.
+    fn group(self) -> ToolArg {
+        match self {
+            Builtin::Shell => ToolArg::Shell,
+            Builtin::FetchUrl => ToolArg::Web,
+            Builtin::ReadFile | Builtin::WriteFile => ToolArg::Fs,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Builtin::Shell => "shell",
+            Builtin::FetchUrl => "fetch_url",
+            Builtin::ReadFile => "read_file",
+            Builtin::WriteFile => "write_file",
+        }
+    }
+
+    fn spec(self) -> ToolSpec {
+        let (description, parameters) = match self {
+            Builtin::Shell => (
+                "Run a shell command on the user's machine and return its stdout, \
+                 stderr and exit code. The user may decline the command.",
+                json!({
+                    "type": "object",
+                    "properties": {
+                        "command": {
+                            "type": "string",
+                            "description": "The command line to execute"
+                        }
+                    },
+                    "required": ["command"]
+                }),
+            ),
+            Builtin::FetchUrl => (
+                "Download a web page over HTTP(S) and return it as Markdown. \
+                 The user may decline the request.",
+                json!({
+                    "type": "object",
+                    "properties": {
+                        "url": {
+                            "type": "string",
+                            "description": "Absolute http:// or https:// URL"
+                        }
+                    },
+                    "required": ["url"]
+                }),
+            ),
+            Builtin::ReadFile => (
+                "Read a UTF-8 text file on the user's machine. \
+                 The user may decline the read.",
+                json!({
+                    "type": "object",
+                    "properties": {
+                        "path": {
+                            "type": "string",
+                            "description": "File path, relative to the working directory"
+                        }
+                    },
+                    "required": ["path"]
+                }),
+            ),
+            Builtin::WriteFile => (
+                "Create or overwrite a text file on the user's machine. \
+                 The user may decline the write.",
+                json!({
+                    "type": "object",
+                    "properties": {
+                        "path": {
+                            "type": "string",
+                            "description": "File path, relative to the working directory"
+                        },
+                        "content": {
+                            "type": "string",
+                            "description": "The complete new contents of the file"
+                        }
+                    },
+                    "required": ["path", "content"]
+                }),
+            ),
+        };
+        ToolSpec {
+            name: self.name().to_string(),
+            description: description.to_string(),
+            parameters,
+        }
+    }
+
+    /// Whether the tool only reads, so `read-only` approval runs it unasked.
+    /// `fetch_url` is not: its URL can carry out whatever was read before.
+    fn read_only(self) -> bool {
+        matches!(self, Builtin::ReadFile)
+    }
+
+    /// What the call would do, shown before it is approved; an error if the
+    /// model left out an argument.
+    fn summary(self, call: &ToolCall) -> Result<String, String> {
+        Ok(match self {
+            Builtin::Shell => format!("run: {}", string_arg(call, "command")?),
+            Builtin::FetchUrl => format!("fetch: {}", string_arg(call, "url")?),
+            Builtin::ReadFile => format!("read: {}", string_arg(call, "path")?),
+            Builtin::WriteFile => {
+                let path = string_arg(call, "path")?;
+                let content = string_arg(call, "content")?;
+                let action = if Path::new(path).exists() {
+                    "overwrite"
+                } else {
+                    "create"
+                };
+                format!("{action} {path} ({} bytes)", content.len())
+            }
+        })
+    }
+
+    /// Runs an approved call whose arguments `summary` has checked.
+    async fn run(self, call: &ToolCall) -> String {
+        let arg = |name: &str| string_arg(call, name).unwrap_or_default();
+        match self {
+            Builtin::Shell => run_shell_tool(arg("command")).await,
+            Builtin::FetchUrl => run_fetch_tool(arg("url")).await,
+            Builtin::ReadFile => run_read_tool(arg("path")),
+            Builtin::WriteFile => run_write_tool(arg("path"), arg("content")),
+        }
+    }
+}
+
+/// A tool offered by a plugin, with the plugin that runs it.
+#[derive(Clone, Debug)]
+struct PluginTool {
+    spec: ToolSpec,
+    plugin: Plugin,
+    read_only: bool,
+}
+
+/// The set of tools enabled for a session, and when their calls may run.
+#[derive(Clone, Debug, Default)]
+pub struct ToolRegistry {
+    builtins: Vec<Builtin>,
+    plugin_tools: Vec<PluginTool>,
+    approval: ToolsConfig,
+}
+
+/// `[tools]` with the session's `--tool-approval` values applied in order: a
+/// bare policy replaces every configured one, `TOOL=POLICY` sets one tool's.
+pub fn session_approval(cfg: &ToolsConfig, overrides: &[ToolApprovalArg]) -> ToolsConfig {
+    let mut approval = cfg.clone();
+    for arg in overrides {
+        match &arg.tool {
+            Some(tool) => {
+                approval.per_tool.insert(tool.clone(), arg.policy);
+            }
+            None => {
+                approval.approval = arg.policy;
+                approval.per_tool.clear();
+            }
+        }
+    }
+    approval
+}
+
+impl ToolRegistry {
+    pub fn new(enabled: &[ToolArg], approval: ToolsConfig) -> Self {
+        let builtins: Vec<Builtin> = Builtin::ALL
+            .into_iter()
+            .filter(|tool| enabled.contains(&tool.group()))
+            .collect();
+        let plugin_tools = if enabled.contains(&ToolArg::Plugins) {
+            discover_plugin_tools(&builtins)
+        } else {
+            Vec::new()
+        };
+        Self {
+            builtins,
+            plugin_tools,
+            approval,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.builtins.is_empty() && self.plugin_tools.is_empty()
+    }
+
+    pub fn specs(&self) -> Vec<ToolSpec> {
+        self.builtins
+            .iter()
+            .map(|tool| tool.spec())
+            .chain(self.plugin_tools.iter().map(|tool| tool.spec.clone()))
+            .collect()
+    }
+
+    /// Runs a model-requested tool call, if the approval policy lets it, and
+    /// returns the text handed back to the model.
+    pub async fn execute(&self, call: &ToolCall) -> String {
+        if let Some(tool) = self.builtins.iter().find(|tool| tool.name() == call.name) {
+            let summary = match tool.summary(call) {
+                Ok(summary) => summary,
+                Err(err) => return err,
+            };
+            println!("[tool] the model wants to {summary}");
+            if let Some(refusal) = self.approve(&call.name, tool.read_only()) {
+                return refusal;
+            }
+            return tool.run(call).await;
+        }
+        match self
+            .plugin_tools
+            .iter()
+            .find(|tool| tool.spec.name == call.name)
+        {
+            Some(tool) => {
+                println!(
+                    "[tool] the model wants plugin '{}' to run {} with {}",
+                    tool.plugin.name, call.name, call.arguments
+                );
+                if let Some(refusal) = self.approve(&call.name, self.plugin_read_only(tool)) {
+                    return refusal;
+                }
+                run_plugin_tool(&tool.plugin, call).await
+            }
+            None => format!("error: tool '{}' is not available", call.name),
+        }
+    }
+
+    /// Applies the tool's approval policy, asking on the terminal when it
+    /// says to. `Some` is the refusal handed back to the model.
+    fn approve(&self, tool: &str, read_only: bool) -> Option<String> {
+        match self.decide(tool, read_only) {
+            Decision::Deny => {
+                println!("[tool] denied by the approval policy");
+                Some(format!(
+                    "The approval policy does not allow the '{tool}' tool."
+                ))
+            }
+            Decision::Allow => {
+                println!("[tool] allowed without asking (read-only)");
+                None
+            }
+            Decision::Ask => {
+                (!shell::confirm("Allow?")).then(|| "The user declined this tool call.".to_string())
+            }
+        }
+    }
+
+    /// Any plugin can claim its tools only read, so the claim counts only
+    /// for tools `[tools] trusted_read_only` names as well.
+    fn plugin_read_only(&self, tool: &PluginTool) -> bool {
+        tool.read_only && self.approval.trusts_read_only(&tool.spec.name)
+    }
+
+    fn decide(&self, tool: &str, read_only: bool) -> Decision {
+        match self.approval.policy_for(tool) {
+            ApprovalPolicy::Deny => Decision::Deny,
+            ApprovalPolicy::ReadOnly if read_only => Decision::Allow,
+            ApprovalPolicy::Ask | ApprovalPolicy::ReadOnly => Decision::Ask,
+        }
+    }
+}
+
+/// What the approval policy makes of one call.
+#[derive(Debug, PartialEq, Eq)]
+enum Decision {
+    Allow,
+    Ask,
+    Deny,
+}
+
+/// Tools from every plugin on PATH; a plugin that fails to describe itself is
+/// skipped with a warning, and built-in tool names cannot be taken over.
+fn discover_plugin_tools(builtins: &[Builtin]) -> Vec<PluginTool> {
+    let mut tools: Vec<PluginTool> = Vec::new();
+    for plugin in plugin::discover() {
+        let manifest = match plugin.describe() {
+            Ok(manifest) => manifest,
+            Err(err) => {
+                tracing::warn!("skipping plugin '{}': {err:#}", plugin.name);
+                continue;
+            }
+        };
+        for tool in &manifest.tools {
+            let taken = builtins.iter().any(|builtin| builtin.name() == tool.name)
+                || tools.iter().any(|known| known.spec.name == tool.name);
+            if taken {
+                tracing::warn!(
+                    "plugin '{}' offers tool '{}', which is already taken",
+                    plugin.name,
+                    tool.name
+                );
+                continue;
+            }
+            tools.push(PluginTool {
+                spec: tool.spec(),
+                plugin: plugin.clone(),
+                read_only: tool.read_only,
+            });
+        }
+    }
+    tools
+}
+
+fn string_arg<'a>(call: &'a ToolCall, name: &str) -> Result<&'a str, String> {
+    call.arguments
+        .get(name)
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| format!("error: missing '{name}' argument"))
+}
+
+/// Cuts `text` to what the model is sent, saying how much was left out.
+fn limit_output(text: String) -> String {
+    let total = text.chars().count();
+    if total <= MAX_OUTPUT_CHARS {
+        return text;
+    }
+    let mut kept: String = text.chars().take(MAX_OUTPUT_CHARS).collect();
+    kept.push_str(&format!(
+        "\n[... {} more characters cut]",
+        total - MAX_OUTPUT_CHARS
+    ));
+    kept
+}
+
+/// Runs the command off the async runtime, as it blocks until it exits.
+async fn run_shell_tool(command: &str) -> String {
+    let owned = command.to_string();
+    let output = match tokio::task::spawn_blocking(move || shell::run_command(&owned)).await {
+        Ok(output) => output,
+        Err(err) => return format!("error: shell tool task failed: {err}"),
+    };
+    match output {
+        Ok(output) => {
+            let context = output.as_context(command);
+            println!("{context}");
+            limit_output(context)
+        }
+        Err(err) => format!("error: {err:#}"),
+    }
+}
+
+async fn run_fetch_tool(url: &str) -> String {
+    match fetch_markdown(url).await {
+        Ok(page) => {
+            println!("[tool] fetched {} characters", page.chars().count());
+            limit_output(page)
+        }
+        Err(err) => format!("error: {err:#}"),
+    }
+}
+
+/// Downloads `url`; HTML comes back as Markdown, other text as it is.
+async fn fetch_markdown(url: &str) -> Result<String> {
+    if !(url.starts_with("http://") || url.starts_with("https://")) {
+        bail!("only http:// and https:// URLs can be fetched");
+    }
+    let client = reqwest::Client::builder()
+        .timeout(FETCH_TIMEOUT)
+        .user_agent(concat!("rustchat-cli/", env!("CARGO_PKG_VERSION")))
+        .build()?;
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .with_context(|| format!("failed to fetch {url}"))?
+        .error_for_status()?;
+    let is_html = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("html"));
+    let body = response.text().await?;
+    Ok(if is_html {
+        html2md::parse_html(&body)
+    } else {
+        body
+    })
+}
+
+fn run_read_tool(path: &str) -> String {
+    match std::fs::read_to_string(path) {
+        Ok(text) => limit_output(text),
+        Err(err) => format!("error: cannot read {path}: {err}"),
+    }
+}
+
+fn run_write_tool(path: &str, content: &str) -> String {
+    match write_file(Path::new(path), content) {
+        Ok(()) => format!("Wrote {} bytes to {path}.", content.len()),
+        Err(err) => format!("error: {err:#}"),
+    }
+}
+
+fn write_file(path: &Path, content: &str) -> Result<()> {
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("cannot create {}", parent.display()))?;
+    }
+    std::fs::write(path, content).with_context(|| format!("cannot write {}", path.display()))
+}
+
+/// Runs the plugin off the async runtime, as it blocks until it replies.
+async fn run_plugin_tool(plugin: &Plugin, call: &ToolCall) -> String {
+    let (plugin, tool, arguments) = (plugin.clone(), call.name.clone(), call.arguments.clone());
+    let reply = tokio::task::spawn_blocking(move || plugin.call_tool(&tool, &arguments)).await;
+    let reply = match reply {
+        Ok(reply) => reply,
+        Err(err) => return format!("error: plugin tool task failed: {err}"),
+    };
+    match reply {
+        Ok(output) => {
+            println!("{output}");
+            output
+        }
+        Err(err) => format!("error: {err:#}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tool_groups_enable_their_tools() {
+        let registry = ToolRegistry::new(&[ToolArg::Fs, ToolArg::Web], ToolsConfig::default());
+        let names: Vec<String> = registry.specs().into_iter().map(|spec| spec.name).collect();
+        assert_eq!(names, ["fetch_url", "read_file", "write_file"]);
+        assert!(ToolRegistry::new(&[], ToolsConfig::default()).is_empty());
+
+        let long = "x".repeat(MAX_OUTPUT_CHARS + 5);
+        assert!(limit_output(long).ends_with("[... 5 more characters cut]"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn shell_output_is_cut_like_the_other_tools() {
+        let output = run_shell_tool("printf '%030000d' 0").await;
+        assert!(output.ends_with("more characters cut]"));
+        assert!(output.chars().count() < MAX_OUTPUT_CHARS + 100);
+    }
+
+    #[test]
+    fn approval_policy_is_applied_per_tool() {
+        let mut cfg = ToolsConfig::default();
+        cfg.per_tool
+            .insert("shell".to_string(), ApprovalPolicy::Deny);
+        let overrides = [ToolApprovalArg {
+            tool: Some("write_file".to_string()),
+            policy: ApprovalPolicy::Deny,
+        }];
+        let approval = session_approval(&cfg, &overrides);
+        assert_eq!(approval.policy_for("shell"), ApprovalPolicy::Deny);
+        assert_eq!(approval.policy_for("read_file"), ApprovalPolicy::Ask);
+
+        let registry = ToolRegistry::new(&[ToolArg::Fs], approval);
+        assert!(registry.approve("write_file", false).is_some());
+
+        let everything = [ToolApprovalArg {
+            tool: None,
+            policy: ApprovalPolicy::ReadOnly,
+        }];
+        let approval = session_approval(&cfg, &everything);
+        assert_eq!(approval.policy_for("shell"), ApprovalPolicy::ReadOnly);
+        let registry = ToolRegistry::new(&[ToolArg::Fs, ToolArg::Web], approval);
+        assert!(registry.approve("read_file", true).is_none());
+        // A fetch could send off what was just read, so it is never unasked.
+        assert_eq!(
+            registry.decide("fetch_url", Builtin::FetchUrl.read_only()),
+            Decision::Ask
+        );
+    }
+
+    #[test]
+    fn plugins_cannot_vouch_for_their_own_tools() {
+        let tool = PluginTool {
+            spec: ToolSpec {
+                name: "grep_docs".to_string(),
+                description: String::new(),
+                parameters: json!({"type": "object", "properties": {}}),
+            },
+            plugin: Plugin {
+                name: "docs".to_string(),
+                path: "rustchat-docs".into(),
+            },
+            read_only: true,
+        };
+        let mut approval = ToolsConfig {
+            approval: ApprovalPolicy::ReadOnly,
+            ..ToolsConfig::default()
+        };
+        let registry = ToolRegistry::new(&[], approval.clone());
+        let read_only = registry.plugin_read_only(&tool);
+        assert_eq!(registry.decide("grep_docs", read_only), Decision::Ask);
+
+        approval.trusted_read_only.push("grep_docs".to_string());
+        let registry = ToolRegistry::new(&[], approval);
+        let read_only = registry.plugin_read_only(&tool);
+        assert_eq!(registry.decide("grep_docs", read_only), Decision::Allow);
+        let writer = PluginTool {
+            read_only: false,
+            ..tool
+        };
+        assert!(!registry.plugin_read_only(&writer));
+    }
+}
@@ -0,0 +1,658 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use futures::StreamExt;
+use ratatui::crossterm::event::{
+    self, DisableFocusChange, EnableFocusChange, Event, KeyCode, KeyEvent, KeyEventKind,
+    KeyModifiers,
+};
+use ratatui::crossterm::execute;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span, Text};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use tokio::sync::mpsc;
+
+use crate::config::WebhookEvent;
+use crate::context;
+use crate::exit::{self, ExitStatus};
+use crate::input;
+use crate::logger::{self, SessionMeta, Transcript};
+use crate::notify;
+use crate::provider::{
+    CancellationToken, ChatMessage, DynProvider, MessageRole, ReplyMeta, TokenUsage,
+};
+use crate::remote;
+use crate::repl::{self, ReplOptions};
+use crate::shutdown;
+use crate::store::{DynStore, SaveTarget, SessionSummary};
+use crate::streaming::StreamEvent;
+use crate::webhook;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+const INPUT_HEIGHT: u16 = 6;
+
+/// Updates sent from the request task back to the UI loop.
+enum ReplyEvent {
+    Delta(String),
+    Done(ReplyMeta),
+    Failed(String),
+    /// `/stop` cut the reply short
+    Stopped,
+}
+
+#[derive(PartialEq, Eq)]
+enum Focus {
+    Input,
+    Sidebar,
+}
+
+struct App {
+    opts: ReplOptions,
+    provider: DynProvider,
+    system: Option<String>,
+    messages: Vec<ChatMessage>,
+    meta: SessionMeta,
+    input: String,
+    cursor: usize,
+    /// Lines scrolled up from the bottom of the conversation.
+    scroll_back: u16,
+    focus: Focus,
+    sessions: Vec<SessionSummary>,
+    session_state: ListState,
+    status: String,
+    pending: Option<mpsc::UnboundedReceiver<ReplyEvent>>,
+    /// Fires to stop the pending reply
+    stop: CancellationToken,
+    history_target: Option<SaveTarget>,
+    quit: bool,
+}
+
+pub async fn run_tui(provider: DynProvider, mut opts: ReplOptions) -> Result<()> {
+    if !opts.tools.is_empty() {
+        tracing::warn!("tools are only available in the chat REPL; ignoring --tools");
+        opts.request_options.tools.clear();
+    }
+    if opts.knowledge.take().is_some() {
+        tracing::warn!("--knowledge is only available in the chat REPL; ignoring it");
+    }
+    if opts
+        .webhook
+        .as_ref()
+        .is_some_and(|webhook| webhook.posts(WebhookEvent::Turn))
+    {
+        tracing::warn!("per-turn webhook posts are only available in the chat REPL");
+    }
+    if opts.tee.take().is_some() {
+        tracing::warn!("--tee is only available in the chat REPL; ignoring it");
+    }
+    let history_target = repl::resolve_history_target(&opts);
+    let (meta, messages) = match opts.resumed.take() {
+        Some(resumed) => (
+            repl::resumed_meta(resumed.transcript.meta, &opts),
+            resumed.transcript.messages,
+        ),
+        None => match opts.loaded.take() {
+            Some((_, transcript)) => (opts.session_meta(), transcript.messages),
+            None => (opts.session_meta(), Vec::new()),
+        },
+    };
+    let mut app = App {
+        system: opts.system.clone(),
+        meta,
+        status: format!(
+            "{}/{} · Enter send · Alt+Enter newline · PgUp/PgDn scroll · Tab sessions · Ctrl+N new · Esc quit",
+            opts.provider_name, opts.model
+        ),
+        opts,
+        provider,
+        messages,
+        input: String::new(),
+        cursor: 0,
+        scroll_back: 0,
+        focus: Focus::Input,
+        sessions: Vec::new(),
+        session_state: ListState::default(),
+        pending: None,
+        stop: shutdown::token(),
+        history_target,
+        quit: false,
+    };
+    app.refresh_sessions();
+
+    let mut terminal = ratatui::init();
+    // Terminals that report focus tell the notifier whether a finished reply
+    // went unseen; the others ignore the request.
+    let _ = execute!(std::io::stdout(), EnableFocusChange);
+    let result = app.run(&mut terminal).await;
+    let _ = execute!(std::io::stdout(), DisableFocusChange);
+    ratatui::restore();
+    result?;
+
+    app.save_session()?;
+    if let Some(target) = &app.history_target {
+        target.apply_retention(&app.opts.retention);
+    }
+    remote::upload_finished(
+        app.opts.remote.as_ref(),
+        &repl::upload_name(app.history_target.as_ref(), &app.opts.provider_name),
+        app.opts.save_format,
+        &app.meta,
+        app.system.as_deref(),
+        &app.messages,
+    )
+    .await;
+    webhook::post_finished(
+        app.opts.webhook.as_ref(),
+        app.opts.save_format,
+        &app.meta,
+        app.system.as_deref(),
+        &app.messages,
+    )
+    .await;
+    Ok(())
+}
+
+impl App {
+    async fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        while !self.quit && !shutdown::requested() {
+            terminal
+                .draw(|frame| self.draw(frame))
+                .context("failed to draw tui")?;
+            self.drain_reply();
+            if event::poll(POLL_INTERVAL).context("failed to poll terminal events")? {
+                match event::read().context("failed to read terminal event")? {
+                    Event::Key(key) if key.kind == KeyEventKind::Press => self.handle_key(key),
+                    Event::FocusGained => notify::focus_changed(true),
+                    Event::FocusLost => notify::focus_changed(false),
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [sidebar, main] =
+            Layout::horizontal([Constraint::Length(28), Constraint::Min(20)]).areas(frame.area());
+        let [conversation, input, status] = Layout::vertical([
+            Constraint::Min(3),
+            Constraint::Length(INPUT_HEIGHT),
+            Constraint::Length(1),
+        ])
+        .areas(main);
+
+        self.draw_sidebar(frame, sidebar);
+        self.draw_conversation(frame, conversation);
+        self.draw_input(frame, input);
+        frame.render_widget(Paragraph::new(self.status.as_str()).dim(), status);
+    }
+
+    fn draw_sidebar(&mut self, frame: &mut Frame, area: Rect) {
+        let items: Vec<ListItem> = self
+            .sessions
+            .iter()
+            .map(|session| ListItem::new(session.title.as_str()))
+            .collect();
+        let list = List::new(items)
+            .block(
+                Block::bordered()
+                    .title("Sessions")
+                    .border_style(self.border_style(Focus::Sidebar)),
+            )
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, area, &mut self.session_state);
+    }
+
+    fn draw_conversation(&self, frame: &mut Frame, area: Rect) {
+        let mut lines: Vec<Line> = Vec::new();
+        if let Some(system) = &self.system {
+            push_entry(&mut lines, "system", Color::Magenta, system);
+        }
+        for message in &self.messages {
+            let (label, color) = match message.role {
+                MessageRole::User => ("you", Color::Cyan),
+                MessageRole::Assistant => ("bot", Color::Green),
+                MessageRole::System => ("system", Color::Magenta),
+                MessageRole::Tool => ("tool", Color::Yellow),
+            };
+            push_entry(&mut lines, label, color, &message.content);
+        }
+        if self.pending.is_some() && !self.last_is_assistant() {
+            lines.push(Line::from("…").dim());
+        }
+
+        let inner_width = area.width.saturating_sub(2).max(1) as usize;
+        let inner_height = area.height.saturating_sub(2);
+        let total = wrapped_height(&lines, inner_width);
+        let max_scroll = total.saturating_sub(inner_height);
+        let offset = max_scroll.saturating_sub(self.scroll_back);
+        let title = format!("{} · {}", self.opts.provider_name, self.opts.model);
+
+        let paragraph = Paragraph::new(Text::from(lines))
+            .block(Block::bordered().title(title))
+            .wrap(Wrap { trim: false })
+            .scroll((offset, 0));
+        frame.render_widget(paragraph, area);
+    }
+
+    fn draw_input(&self, frame: &mut Frame, area: Rect) {
+        let block = Block::bordered()
+            .title(if self.pending.is_some() {
+                "Waiting for reply…"
+            } else {
+                "Message"
+            })
+            .border_style(self.border_style(Focus::Input));
+        let inner = block.inner(area);
+        let before_cursor: String = self.input.chars().take(self.cursor).collect();
+        let cursor_row = before_cursor.matches('\n').count() as u16;
+        let cursor_col = before_cursor
+            .rsplit('\n')
+            .next()
+            .map(|line| line.chars().count())
+            .unwrap_or(0) as u16;
+        let scroll = cursor_row.saturating_sub(inner.height.saturating_sub(1));
+        frame.render_widget(
+            Paragraph::new(self.input.as_str())
+                .block(block)
+                .scroll((scroll, 0)),
+            area,
+        );
+        if self.focus == Focus::Input {
+            frame.set_cursor_position((
+                inner.x + cursor_col.min(inner.width.saturating_sub(1)),
+                inner.y + cursor_row - scroll,
+            ));
+        }
+    }
+
+    fn border_style(&self, focus: Focus) -> Style {
+        if self.focus == focus {
+            Style::new().fg(Color::Yellow)
+        } else {
+            Style::new()
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => self.quit = true,
+            KeyCode::Char('c') if ctrl => self.quit = true,
+            KeyCode::Char('n') if ctrl => self.new_session(),
+            KeyCode::Tab => {
+                self.focus = match self.focus {
+                    Focus::Input => Focus::Sidebar,
+                    Focus::Sidebar => Focus::Input,
+                }
+            }
+            KeyCode::PageUp => self.scroll_back = self.scroll_back.saturating_add(10),
+            KeyCode::PageDown => self.scroll_back = self.scroll_back.saturating_sub(10),
+            _ if self.focus == Focus::Sidebar => self.handle_sidebar_key(key),
+            _ => self.handle_input_key(key),
+        }
+    }
+
+    fn handle_sidebar_key(&mut self, key: KeyEvent) {
+        match key.code {
+            KeyCode::Up => self.session_state.select_previous(),
+            KeyCode::Down => self.session_state.select_next(),
+            KeyCode::Enter => self.open_selected_session(),
+            _ => {}
+        }
+    }
+
+    fn handle_input_key(&mut self, key: KeyEvent) {
+        let alt = key.modifiers.contains(KeyModifiers::ALT);
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Enter if alt || key.modifiers.contains(KeyModifiers::SHIFT) => {
+                self.insert('\n')
+            }
+            KeyCode::Char('j') if ctrl => self.insert('\n'),
+            KeyCode::Enter => self.submit(),
+            KeyCode::Char(ch) => self.insert(ch),
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                let idx = self.byte_index(self.cursor);
+                self.input.remove(idx);
+            }
+            KeyCode::Delete if self.cursor < self.input.chars().count() => {
+                let idx = self.byte_index(self.cursor);
+                self.input.remove(idx);
+            }
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(self.input.chars().count()),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.input.chars().count(),
+            KeyCode::Up => self.scroll_back = self.scroll_back.saturating_add(1),
+            KeyCode::Down => self.scroll_back = self.scroll_back.saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    fn insert(&mut self, ch: char) {
+        let idx = self.byte_index(self.cursor);
+        self.input.insert(idx, ch);
+        self.cursor += 1;
+    }
+
+    fn byte_index(&self, char_index: usize) -> usize {
+        self.input
+            .char_indices()
+            .nth(char_index)
+            .map(|(idx, _)| idx)
+            .unwrap_or(self.input.len())
+    }
+
+    fn submit(&mut self) {
+        let text = self.input.trim().to_string();
+        if self.pending.is_some() {
+            if text == "/stop" {
+                self.stop.cancel();
+                self.input.clear();
+                self.cursor = 0;
+            } else {
+                self.status =
+                    "[busy] wait for the current reply to finish, or send /stop".to_string();
+            }
+            return;
+        }
+        if text.is_empty() {
+            return;
+        }
+        self.input.clear();
+        self.cursor = 0;
+        self.scroll_back = 0;
+        let text = input::with_context(self.opts.attachments.take().as_deref(), text);
+        self.messages.push(ChatMessage::user(text));
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.pending = Some(rx);
+        self.stop = shutdown::token();
+        let cancel = self.stop.clone();
+        let provider = self.provider.clone();
+        let model = self.opts.model.clone();
+        let options = self.opts.request_options.clone();
+        let window = context::apply(
+            &self.opts.context,
+            &self.opts.model,
+            self.system.as_deref(),
+            &self.messages,
+            options.max_output_tokens,
+        );
+        let system = window.system.map(str::to_string);
+        let messages = window.messages.to_vec();
+        let stream = self.opts.stream;
+        let provider_name = self.opts.provider_name.clone();
+        tokio::spawn(async move {
+            let started = Instant::now();
+            let outcome = if stream {
+                stream_reply(
+                    &provider,
+                    &model,
+                    system.as_deref(),
+                    &messages,
+                    &options,
+                    &cancel,
+                    &tx,
+                )
+                .await
+            } else {
+                provider
+                    .chat(&model, system.as_deref(), &messages, &options, &cancel)
+                    .await
+                    .map(|response| {
+                        let _ = tx.send(ReplyEvent::Delta(response.text));
+                        response.usage
+                    })
+            };
+            let _ = tx.send(match outcome {
+                Ok(usage) => ReplyEvent::Done(ReplyMeta {
+                    provider: provider_name,
+                    model,
+                    usage,
+                    latency: started.elapsed(),
+                }),
+                Err(err) if exit::status_for(&err) == ExitStatus::Canceled => ReplyEvent::Stopped,
+                Err(err) => ReplyEvent::Failed(format!("{err:#}")),
+            });
+        });
+    }
+
+    fn drain_reply(&mut self) {
+        let Some(rx) = self.pending.as_mut() else {
+            return;
+        };
+        let mut events = Vec::new();
+        while let Ok(event) = rx.try_recv() {
+            events.push(event);
+        }
+        let mut finished = false;
+        for event in events {
+            match event {
+                ReplyEvent::Delta(text) => {
+                    if !self.last_is_assistant() {
+                        self.messages.push(ChatMessage::assistant(String::new()));
+                    }
+                    if let Some(last) = self.messages.last_mut() {
+                        last.content.push_str(&text);
+                    }
+                }
+                ReplyEvent::Done(reply) => {
+                    if self.last_is_assistant() {
+                        if let Some(last) = self.messages.last_mut() {
+                            self.opts.notifier.reply_finished(
+                                &self.opts.model,
+                                reply.latency,
+                                &last.content,
+                            );
+                            last.reply = Some(reply);
+                        }
+                    }
+                    finished = true;
+                }
+                ReplyEvent::Failed(err) => {
+                    self.status = format!("[error] {err}");
+                    finished = true;
+                }
+                ReplyEvent::Stopped => {
+                    self.status = "[stopped]".to_string();
+                    finished = true;
+                }
+            }
+        }
+        if finished {
+            self.pending = None;
+        }
+    }
+
+    fn last_is_assistant(&self) -> bool {
+        self.messages
+            .last()
+            .is_some_and(|message| message.role == MessageRole::Assistant)
+    }
+
+    fn new_session(&mut self) {
+        if self.pending.is_some() {
+            return;
+        }
+        if let Err(err) = self.save_session() {
+            self.status = format!("[error] {err:#}");
+        }
+        self.messages.clear();
+        self.system = self.opts.system.clone();
+        self.meta = self.opts.session_meta();
+        self.scroll_back = 0;
+        self.history_target = self
+            .opts
+            .store
+            .as_ref()
+            .filter(|_| self.opts.auto_save)
+            .map(|store| SaveTarget::Session {
+                store: Arc::clone(store),
+                id: logger::timestamped_session_id(&self.opts.provider_name),
+            });
+        self.refresh_sessions();
+        self.status = "[new session]".to_string();
+    }
+
+    fn open_selected_session(&mut self) {
+        if self.pending.is_some() {
+            return;
+        }
+        let Some(store) = self.opts.store.as_ref() else {
+            return;
+        };
+        let Some(id) = selected_session_id(&self.sessions, self.session_state.selected()) else {
+            return;
+        };
+        match load_session(store, id) {
+            Ok((transcript, target)) => {
+                self.system = transcript.system.or_else(|| self.opts.system.clone());
+                self.messages = transcript.messages;
+                self.meta = transcript.meta;
+                self.scroll_back = 0;
+                self.status = format!("[loaded {}]", target.describe());
+                self.history_target = Some(target);
+                self.focus = Focus::Input;
+            }
+            Err(err) => self.status = format!("[error] {err:#}"),
+        }
+    }
+
+    fn refresh_sessions(&mut self) {
+        let Some(store) = self.opts.store.as_ref() else {
+            return;
+        };
+        match store.list() {
+            Ok(sessions) => self.sessions = sessions,
+            Err(err) => self.status = format!("[error] {err:#}"),
+        }
+    }
+
+    fn save_session(&self) -> Result<()> {
+        if self.messages.is_empty() {
+            return Ok(());
+        }
+        if let Some(target) = &self.history_target {
+            target.save(&self.meta, self.system.as_deref(), &self.messages)?;
+            println!("[saved chat history to {}]", target.describe());
+        }
+        Ok(())
+    }
+}
+
+fn selected_session_id(sessions: &[SessionSummary], selected: Option<usize>) -> Option<&str> {
+    sessions.get(selected?).map(|session| session.id.as_str())
+}
+
+/// Loads a stored session; saving to the returned target rewrites it in place,
+/// keeping the format it was stored in.
+fn load_session(store: &DynStore, id: &str) -> Result<(Transcript, SaveTarget)> {
+    let transcript = store.load(id)?;
+    let target = SaveTarget::Session {
+        store: Arc::clone(store),
+        id: id.to_string(),
+    };
+    Ok((transcript, target))
+}
+
+async fn stream_reply(
+    provider: &DynProvider,
+    model: &str,
+    system: Option<&str>,
+    messages: &[ChatMessage],
+    options: &crate::provider::ChatRequestOptions,
+    cancel: &CancellationToken,
+    tx: &mpsc::UnboundedSender<ReplyEvent>,
+) -> Result<Option<TokenUsage>> {
+    let mut stream = provider
+        .stream_chat(model, system, messages, options, cancel)
+        .await?;
+    let mut usage: Option<TokenUsage> = None;
+    while let Some(event) = stream.next().await {
+        match event? {
+            StreamEvent::Text(text) => {
+                let _ = tx.send(ReplyEvent::Delta(text));
+            }
+            StreamEvent::Usage(reported) => usage
+                .get_or_insert_with(TokenUsage::default)
+                .absorb(reported),
+            StreamEvent::Finish(_) => {}
+        }
+    }
+    Ok(usage)
+}
+
+fn push_entry<'a>(lines: &mut Vec<Line<'a>>, label: &'a str, color: Color, content: &'a str) {
+    lines.push(Line::from(Span::styled(
+        format!("{label}>"),
+        Style::new().fg(color).add_modifier(Modifier::BOLD),
+    )));
+    lines.extend(content.lines().map(Line::from));
+    lines.push(Line::default());
+}
+
+/// Approximate number of rows `lines` occupy once wrapped to `width`.
+fn wrapped_height(lines: &[Line], width: usize) -> u16 {
+    let rows: usize = lines
+        .iter()
+        .map(|line| line.width().max(1).div_ceil(width))
+        .sum();
+    rows.min(u16::MAX as usize) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SessionsConfig;
+    use crate::logger::HistoryFormat;
+    use crate::store;
+
+    #[test]
+    fn sidebar_opens_and_saves_sessions_in_place() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        let meta = SessionMeta::new("openai", "gpt-4o");
+        logger::save_history(
+            &dir.join("notes.md"),
+            HistoryFormat::Markdown,
+            &meta,
+            Some("Be brief"),
+            &[ChatMessage::user("hi"), ChatMessage::assistant("hello")],
+        )
+        .unwrap();
+        std::fs::write(dir.join("todo.txt"), "not a session").unwrap();
+        // New sessions would be JSON; an opened one must stay Markdown.
+        let store = store::open_store(
+            &SessionsConfig::default(),
+            Some(dir.clone()),
+            HistoryFormat::Json,
+            None,
+        )
+        .unwrap()
+        .unwrap();
+
+        let sessions = store.list().unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(selected_session_id(&sessions, None), None);
+        assert_eq!(selected_session_id(&sessions, Some(1)), None);
+        let id = selected_session_id(&sessions, Some(0)).unwrap();
+        assert_eq!(id, "notes");
+
+        let (mut transcript, target) = load_session(&store, id).unwrap();
+        assert_eq!(transcript.system.as_deref(), Some("Be brief"));
+        transcript.messages.push(ChatMessage::user("again"));
+        target
+            .save(&transcript.meta, None, &transcript.messages)
+            .unwrap();
+
+        assert!(!dir.join("notes.json").exists());
+        let saved = logger::load_history(&dir.join("notes.md")).unwrap();
+        assert_eq!(saved.messages.len(), 3);
+    }
+}
@@ -0,0 +1,410 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Datelike, Local, Months, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::cli::{UsageCommand, UsageGroupArg, UsageReportArgs, UsageReportFormat};
+use crate::config::{BudgetConfig, BudgetEnforcement, UsageConfig, APP_DIR};
+use crate::exit::{self, ExitStatus};
+use crate::pricing;
+use crate::provider::TokenUsage;
+use crate::utils;
+
+const USAGE_FILE: &str = "usage.jsonl";
+
+/// Where requests are recorded; unset (or `None`) when tracking is off.
+static LOG: OnceLock<Option<PathBuf>> = OnceLock::new();
+static BUDGETS: OnceLock<BTreeMap<String, BudgetConfig>> = OnceLock::new();
+/// Only the first failed write is reported.
+static WARNED: AtomicBool = AtomicBool::new(false);
+/// Soft limits already reported, so each is mentioned once per run.
+static BUDGET_WARNED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// One line of the usage log.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct UsageRecord {
+    pub at: DateTime<Utc>,
+    pub provider: String,
+    pub model: String,
+    pub input_tokens: u32,
+    pub output_tokens: u32,
+    /// From the bundled price table; absent for unknown models
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost_usd: Option<f64>,
+}
+
+pub fn log_path(cfg: &UsageConfig) -> Option<PathBuf> {
+    cfg.path.clone().or_else(|| {
+        let base = dirs::data_local_dir().or_else(dirs::config_dir)?;
+        Some(base.join(APP_DIR).join(USAGE_FILE))
+    })
+}
+
+/// Turns recording on for the rest of the process, unless `[usage]` says
+/// `track = false`.
+pub fn init(cfg: &UsageConfig) {
+    let _ = LOG.set(cfg.track.then(|| log_path(cfg)).flatten());
+    if !cfg.track && !cfg.budgets.is_empty() {
+        tracing::warn!("[usage] budgets are not enforced while track = false");
+    }
+    let _ = BUDGETS.set(cfg.budgets.clone());
+}
+
+/// Checks `provider`'s spend so far against its `[usage.budgets]` entry:
+/// a reached soft limit is reported once, a reached hard limit fails the
+/// request until the day or month it covers is over.
+pub fn check_budget(provider: &str) -> Result<()> {
+    let (Some(Some(path)), Some(budget)) = (LOG.get(), BUDGETS.get().and_then(|b| b.get(provider)))
+    else {
+        return Ok(());
+    };
+    let records = read_records(path)?;
+    for overrun in overruns(budget, provider, &records, Local::now().date_naive()) {
+        let summary = format!(
+            "{provider} has spent {} of its {} {} budget",
+            pricing::format_cost(overrun.spent),
+            pricing::format_cost(overrun.limit),
+            overrun.window
+        );
+        match budget.enforce {
+            BudgetEnforcement::Hard => {
+                return Err(exit::tag(
+                    anyhow!(
+                        "{summary}; new requests are refused until {}",
+                        overrun.resets
+                    ),
+                    ExitStatus::OverBudget,
+                ));
+            }
+            BudgetEnforcement::Soft => {
+                let key = format!("{provider}/{}", overrun.window);
+                if BUDGET_WARNED.lock().unwrap().insert(key) {
+                    tracing::warn!("{summary}");
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A budget window whose limit the spend has reached.
+#[derive(Debug, PartialEq)]
+struct Overrun {
+    window: &'static str,
+    limit: f64,
+    spent: f64,
+    /// First day of the next window
+    resets: NaiveDate,
+}
+
+fn overruns(
+    budget: &BudgetConfig,
+    provider: &str,
+    records: &[UsageRecord],
+    today: NaiveDate,
+) -> Vec<Overrun> {
+    let month_start = today.with_day(1).unwrap_or(today);
+    // Longest window first, so a hard refusal names the later reset.
+    let windows = [
+        (
+            "monthly",
+            budget.monthly,
+            month_start,
+            month_start.checked_add_months(Months::new(1)),
+        ),
+        ("daily", budget.daily, today, today.succ_opt()),
+    ];
+    let mut reached = Vec::new();
+    for (window, limit, start, resets) in windows {
+        let (Some(limit), Some(resets)) = (limit, resets) else {
+            continue;
+        };
+        let spent: f64 = records
+            .iter()
+            .filter(|record| record.provider == provider)
+            .filter(|record| record.at.with_timezone(&Local).date_naive() >= start)
+            .filter_map(|record| record.cost_usd)
+            .sum();
+        if spent >= limit {
+            reached.push(Overrun {
+                window,
+                limit,
+                spent,
+                resets,
+            });
+        }
+    }
+    reached
+}
+
+/// Appends a finished request to the usage log. Providers that report no
+/// usage are still counted as a request.
+pub fn record(provider: &str, model: &str, usage: Option<TokenUsage>) {
+    let Some(Some(path)) = LOG.get() else {
+        return;
+    };
+    let usage = usage.unwrap_or_default();
+    let entry = UsageRecord {
+        at: Utc::now(),
+        provider: provider.to_string(),
+        model: model.to_string(),
+        input_tokens: usage.input_tokens,
+        output_tokens: usage.output_tokens,
+        cost_usd: pricing::estimate_cost(model, &usage),
+    };
+    if let Err(err) = append(path, &entry) {
+        if !WARNED.swap(true, Ordering::Relaxed) {
+            tracing::warn!("failed to record usage: {err:#}");
+        }
+    }
+}
+
+fn append(path: &Path, entry: &UsageRecord) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    // One write per line, so concurrent processes do not interleave.
+    let line = format!("{}\n", serde_json::to_string(entry)?);
+    file.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Every record in the log, skipping lines it cannot parse.
+pub fn read_records(path: &Path) -> Result<Vec<UsageRecord>> {
+    let data = match std::fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).with_context(|| format!("failed to read {}", path.display())),
+    };
+    Ok(data
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+pub fn handle_usage(command: UsageCommand, cfg: &UsageConfig) -> Result<()> {
+    match command {
+        UsageCommand::Report(args) => report(args, cfg),
+    }
+}
+
+fn report(args: UsageReportArgs, cfg: &UsageConfig) -> Result<()> {
+    let path = log_path(cfg).context("unable to locate the data directory for the usage log")?;
+    let since = args
+        .since
+        .as_deref()
+        .map(utils::parse_age)
+        .transpose()?
+        .map(|age| Utc::now() - age);
+    let records: Vec<UsageRecord> = read_records(&path)?
+        .into_iter()
+        .filter(|record| since.is_none_or(|since| record.at >= since))
+        .collect();
+    let rows = group(&records, args.by);
+    match args.format {
+        UsageReportFormat::Csv => print!("{}", csv(&rows, args.by)),
+        UsageReportFormat::Table if rows.is_empty() => {
+            println!("No requests recorded in {}.", path.display())
+        }
+        UsageReportFormat::Table => print!("{}", table(&rows, args.by)),
+    }
+    Ok(())
+}
+
+/// Totals for one group of records.
+#[derive(Debug, Default, PartialEq)]
+struct UsageRow {
+    requests: usize,
+    input_tokens: u64,
+    output_tokens: u64,
+    cost_usd: f64,
+    /// Requests to models with no bundled price, left out of `cost_usd`
+    unpriced: usize,
+}
+
+impl UsageRow {
+    fn add(&mut self, record: &UsageRecord) {
+        self.requests += 1;
+        self.input_tokens += u64::from(record.input_tokens);
+        self.output_tokens += u64::from(record.output_tokens);
+        match record.cost_usd {
+            Some(cost) => self.cost_usd += cost,
+            None => self.unpriced += 1,
+        }
+    }
+}
+
+fn group(records: &[UsageRecord], by: UsageGroupArg) -> Vec<(String, UsageRow)> {
+    let mut groups: BTreeMap<String, UsageRow> = BTreeMap::new();
+    for record in records {
+        let key = match by {
+            UsageGroupArg::Model => format!("{}/{}", record.provider, record.model),
+            UsageGroupArg::Provider => record.provider.clone(),
+            UsageGroupArg::Day => record.at.with_timezone(&Local).date_naive().to_string(),
+        };
+        groups.entry(key).or_default().add(record);
+    }
+    let mut rows: Vec<_> = groups.into_iter().collect();
+    // Days read best in order; everything else by spend.
+    if by != UsageGroupArg::Day {
+        rows.sort_by(|a, b| b.1.cost_usd.total_cmp(&a.1.cost_usd));
+    }
+    rows
+}
+
+fn label(by: UsageGroupArg) -> &'static str {
+    match by {
+        UsageGroupArg::Model => "MODEL",
+        UsageGroupArg::Provider => "PROVIDER",
+        UsageGroupArg::Day => "DAY",
+    }
+}
+
+fn table(rows: &[(String, UsageRow)], by: UsageGroupArg) -> String {
+    let mut total = UsageRow::default();
+    for (_, row) in rows {
+        total.requests += row.requests;
+        total.input_tokens += row.input_tokens;
+        total.output_tokens += row.output_tokens;
+        total.cost_usd += row.cost_usd;
+        total.unpriced += row.unpriced;
+    }
+    let width = rows
+        .iter()
+        .map(|(key, _)| key.chars().count())
+        .chain([label(by).len(), "TOTAL".len()])
+        .max()
+        .unwrap_or(0);
+    let mut out = format!(
+        "{:<width$}  {:>8}  {:>12}  {:>12}  {:>10}\n",
+        label(by),
+        "REQUESTS",
+        "INPUT",
+        "OUTPUT",
+        "COST"
+    );
+    for (key, row) in rows.iter().chain([&("TOTAL".to_string(), total)]) {
+        let unpriced = if row.unpriced > 0 { "*" } else { "" };
+        out.push_str(&format!(
+            "{key:<width$}  {:>8}  {:>12}  {:>12}  {:>10}\n",
+            row.requests,
+            row.input_tokens,
+            row.output_tokens,
+            format!("{}{unpriced}", pricing::format_cost(row.cost_usd))
+        ));
+    }
+    if rows.iter().any(|(_, row)| row.unpriced > 0) {
+        out.push_str("* includes requests to models without a bundled price\n");
+    }
+    out
+}
+
+fn csv(rows: &[(String, UsageRow)], by: UsageGroupArg) -> String {
+    let mut out = format!(
+        "{},requests,input_tokens,output_tokens,cost_usd,unpriced_requests\n",
+        label(by).to_ascii_lowercase()
+    );
+    for (key, row) in rows {
+        let key = if key.contains([',', '"']) {
+            format!("\"{}\"", key.replace('"', "\"\""))
+        } else {
+            key.clone()
+        };
+        out.push_str(&format!(
+            "{key},{},{},{},{:.6},{}\n",
+            row.requests, row.input_tokens, row.output_tokens, row.cost_usd, row.unpriced
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(provider: &str, model: &str, input: u32, output: u32) -> UsageRecord {
+        UsageRecord {
+            at: Utc::now(),
+            provider: provider.to_string(),
+            model: model.to_string(),
+            input_tokens: input,
+            output_tokens: output,
+            cost_usd: pricing::estimate_cost(
+                model,
+                &TokenUsage {
+                    input_tokens: input,
+                    output_tokens: output,
+                },
+            ),
+        }
+    }
+
+    #[test]
+    fn records_group_by_model_and_provider() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("usage.jsonl");
+        append(&path, &record("work", "gpt-4o", 1_000_000, 0)).unwrap();
+        append(&path, &record("work", "gpt-4o", 0, 100_000)).unwrap();
+        append(&path, &record("local", "llama-3", 10, 20)).unwrap();
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"at\": \"half a line")
+            .unwrap();
+        let records = read_records(&path).unwrap();
+        assert_eq!(records.len(), 3);
+
+        let rows = group(&records, UsageGroupArg::Model);
+        assert_eq!(rows[0].0, "work/gpt-4o");
+        assert_eq!(rows[0].1.requests, 2);
+        assert!((rows[0].1.cost_usd - 3.5).abs() < 1e-9);
+        assert_eq!(rows[1].1.unpriced, 1);
+        let table = table(&rows, UsageGroupArg::Model);
+        assert!(table.lines().any(|line| line.starts_with("TOTAL")
+            && line.contains(" 3 ")
+            && line.ends_with("$3.50*")));
+        assert!(csv(&rows, UsageGroupArg::Provider).starts_with(
+            "provider,requests,input_tokens,output_tokens,cost_usd,unpriced_requests\n"
+        ));
+
+        assert_eq!(group(&records, UsageGroupArg::Provider).len(), 2);
+        assert!(read_records(&tmp.path().join("missing.jsonl"))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn budgets_count_spend_in_the_current_day_and_month() {
+        let mut old = record("work", "gpt-4o", 4_000_000, 0);
+        old.at -= chrono::Duration::days(40);
+        let records = [
+            old,
+            record("work", "gpt-4o", 400_000, 0),
+            record("home", "gpt-4o", 4_000_000, 0),
+        ];
+        let budget = BudgetConfig {
+            daily: Some(1.0),
+            monthly: Some(5.0),
+            enforce: BudgetEnforcement::Hard,
+        };
+        let today = Local::now().date_naive();
+        let reached = overruns(&budget, "work", &records, today);
+        assert_eq!(reached.len(), 1);
+        assert_eq!(reached[0].window, "daily");
+        assert!((reached[0].spent - 1.0).abs() < 1e-9);
+        assert_eq!(reached[0].resets, today.succ_opt().unwrap());
+        assert!(overruns(&budget, "other", &records, today).is_empty());
+    }
+}
@@ -0,0 +1,299 @@
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use anyhow::{bail, Context, Result};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::provider::{
+    ChatMessage, ChatRequestOptions, ChatResponse, Citation, TokenUsage, ToolCall,
+};
+use crate::streaming::StreamEvent;
+use crate::utils;
+
+/// `--record` saves every provider exchange to a directory; `--replay`
+/// answers from it instead of the network.
+#[derive(Debug, Clone)]
+pub enum Mode {
+    Record(PathBuf),
+    Replay(PathBuf),
+}
+
+static MODE: OnceLock<Mode> = OnceLock::new();
+
+/// Turns on `--record` or `--replay` for the rest of the process.
+pub fn init(record: Option<PathBuf>, replay: Option<PathBuf>) -> Result<()> {
+    let mode = match (record, replay) {
+        (Some(dir), _) => Mode::Record(dir),
+        (None, Some(dir)) => {
+            if !dir.is_dir() {
+                bail!("--replay: {} is not a directory", dir.display());
+            }
+            Mode::Replay(dir)
+        }
+        (None, None) => return Ok(()),
+    };
+    let _ = MODE.set(mode);
+    Ok(())
+}
+
+pub fn mode() -> Option<&'static Mode> {
+    MODE.get()
+}
+
+pub fn replaying() -> bool {
+    matches!(mode(), Some(Mode::Replay(_)))
+}
+
+/// One recorded exchange, saved as `<dir>/<hash of request>.json`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Cassette {
+    pub request: Value,
+    #[serde(flatten)]
+    pub reply: Reply,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reply {
+    Response(RecordedResponse),
+    /// A streamed reply, event by event
+    Events(Vec<RecordedEvent>),
+    Embeddings(Vec<Vec<f32>>),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<TokenUsage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub citations: Vec<Citation>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordedEvent {
+    Text(String),
+    Usage(TokenUsage),
+    Finish(String),
+}
+
+impl From<&ChatResponse> for RecordedResponse {
+    fn from(response: &ChatResponse) -> Self {
+        Self {
+            text: response.text.clone(),
+            usage: response.usage,
+            finish_reason: response.finish_reason.clone(),
+            tool_calls: response.tool_calls.clone(),
+            citations: response.citations.clone(),
+        }
+    }
+}
+
+impl From<&StreamEvent> for RecordedEvent {
+    fn from(event: &StreamEvent) -> Self {
+        match event {
+            StreamEvent::Text(text) => Self::Text(text.clone()),
+            StreamEvent::Usage(usage) => Self::Usage(*usage),
+            StreamEvent::Finish(reason) => Self::Finish(reason.clone()),
+        }
+    }
+}
+
+impl From<RecordedResponse> for ChatResponse {
+    fn from(recorded: RecordedResponse) -> Self {
+        Self {
+            text: recorded.text,
+            usage: recorded.usage,
+            finish_reason: recorded.finish_reason,
+            tool_calls: recorded.tool_calls,
+            citations: recorded.citations,
+        }
+    }
+}
+
+impl From<RecordedEvent> for StreamEvent {
+    fn from(event: RecordedEvent) -> Self {
+        match event {
+            RecordedEvent::Text(text) => Self::Text(text),
+            RecordedEvent::Usage(usage) => Self::Usage(usage),
+            RecordedEvent::Finish(reason) => Self::Finish(reason),
+        }
+    }
+}
+
+impl Reply {
+    /// The reply as `chat` returns it; a streamed recording is joined up.
+    pub fn into_response(self) -> Result<ChatResponse> {
+        match self {
+            Reply::Response(recorded) => Ok(recorded.into()),
+            Reply::Events(events) => {
+                let mut response = ChatResponse::default();
+                for event in events {
+                    match event {
+                        RecordedEvent::Text(text) => response.text.push_str(&text),
+                        RecordedEvent::Usage(usage) => response
+                            .usage
+                            .get_or_insert_with(TokenUsage::default)
+                            .absorb(usage),
+                        RecordedEvent::Finish(reason) => response.finish_reason = Some(reason),
+                    }
+                }
+                Ok(response)
+            }
+            Reply::Embeddings(_) => bail!("the recording holds embeddings, not a chat reply"),
+        }
+    }
+
+    /// The reply as `stream_chat` yields it; a plain reply comes as one chunk.
+    pub fn into_events(self) -> Result<Vec<StreamEvent>> {
+        if let Reply::Events(events) = self {
+            return Ok(events.into_iter().map(StreamEvent::from).collect());
+        }
+        let response = self.into_response()?;
+        let mut events = vec![StreamEvent::Text(response.text)];
+        events.extend(response.usage.map(StreamEvent::Usage));
+        events.extend(response.finish_reason.map(StreamEvent::Finish));
+        Ok(events)
+    }
+}
+
+/// What identifies a chat request, down to the endpoint it is sent to (see
+/// `ProviderConfig::endpoint`); streamed and plain requests share it so
+/// either recording answers both. Timestamps are left out.
+pub fn chat_request(
+    provider: &str,
+    endpoint: &Value,
+    model: &str,
+    system: Option<&str>,
+    messages: &[ChatMessage],
+    options: &ChatRequestOptions,
+) -> Value {
+    let messages: Vec<Value> = messages
+        .iter()
+        .map(|message| {
+            json!({
+                "role": message.role.to_string(),
+                "content": message.content,
+                "tool_calls": message.tool_calls,
+                "tool_call_id": message.tool_call_id,
+            })
+        })
+        .collect();
+    let tools: Vec<Value> = options
+        .tools
+        .iter()
+        .map(|tool| {
+            json!({
+                "name": tool.name,
+                "description": tool.description,
+                "parameters": tool.parameters,
+            })
+        })
+        .collect();
+    json!({
+        "provider": provider,
+        "endpoint": endpoint,
+        "model": model,
+        "system": system,
+        "messages": messages,
+        "temperature": options.temperature,
+        "max_output_tokens": options.max_output_tokens,
+        "tools": tools,
+        "forbid_tool_calls": options.forbid_tool_calls,
+    })
+}
+
+pub fn embed_request(provider: &str, endpoint: &Value, model: &str, inputs: &[String]) -> Value {
+    json!({"provider": provider, "endpoint": endpoint, "model": model, "embed": inputs})
+}
+
+fn cassette_path(dir: &Path, request: &Value) -> PathBuf {
+    let hash = digest::digest(&digest::SHA256, request.to_string().as_bytes());
+    let mut name = String::new();
+    for byte in &hash.as_ref()[..8] {
+        let _ = write!(name, "{byte:02x}");
+    }
+    dir.join(format!("{name}.json"))
+}
+
+pub fn save(dir: &Path, cassette: &Cassette) -> Result<()> {
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("failed to create recording directory {}", dir.display()))?;
+    let path = cassette_path(dir, &cassette.request);
+    utils::write_atomic(&path, &serde_json::to_vec_pretty(cassette)?)
+        .with_context(|| format!("failed to write recording {}", path.display()))
+}
+
+/// The recorded reply to `request`, or an error naming the missing file.
+pub fn load(dir: &Path, request: &Value) -> Result<Reply> {
+    let path = cassette_path(dir, request);
+    if !path.exists() {
+        bail!(
+            "no recording of this request in {} (expected {}); record it with --record",
+            dir.display(),
+            path.display()
+        );
+    }
+    let data = std::fs::read(&path)
+        .with_context(|| format!("failed to read recording {}", path.display()))?;
+    let cassette: Cassette = serde_json::from_slice(&data)
+        .with_context(|| format!("failed to parse recording {}", path.display()))?;
+    Ok(cassette.reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recordings_replay_as_chat_or_stream() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        let mut messages = vec![ChatMessage::user("Capital of France?")];
+        let options = ChatRequestOptions::default();
+        let request = chat_request("openai", &Value::Null, "gpt-4o", None, &messages, &options);
+        let events = [
+            StreamEvent::Text("Par".to_string()),
+            StreamEvent::Text("is.".to_string()),
+            StreamEvent::Usage(TokenUsage {
+                input_tokens: 5,
+                output_tokens: 2,
+            }),
+            StreamEvent::Finish("length".to_string()),
+        ];
+        save(
+            &dir,
+            &Cassette {
+                request,
+                reply: Reply::Events(events.iter().map(RecordedEvent::from).collect()),
+            },
+        )
+        .unwrap();
+
+        // The same question asked later matches; a different one does not.
+        messages[0].timestamp = None;
+        let request = chat_request("openai", &Value::Null, "gpt-4o", None, &messages, &options);
+        let response = load(&dir, &request).unwrap().into_response().unwrap();
+        assert_eq!(response.text, "Paris.");
+        assert_eq!(response.usage.unwrap().output_tokens, 2);
+        assert_eq!(response.finish_reason.as_deref(), Some("length"));
+        assert_eq!(load(&dir, &request).unwrap().into_events().unwrap(), events);
+        let other = chat_request(
+            "openai",
+            &Value::Null,
+            "gpt-4o-mini",
+            None,
+            &messages,
+            &options,
+        );
+        assert!(load(&dir, &other).is_err());
+    }
+}
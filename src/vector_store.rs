@@ -0,0 +1,696 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use reqwest::{Client, Method, RequestBuilder, StatusCode};
+use ring::digest;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::config::{KnowledgeConfig, VectorStoreKind, APP_DIR};
+
+const DATABASE_FILE: &str = "indexes.db";
+/// Where indexes were kept as one JSON file each, before the database.
+const LEGACY_DIR: &str = "indexes";
+/// PostgREST table and function the pgvector backend expects.
+const PG_TABLE: &str = "rustchat_chunks";
+const PG_MATCH: &str = "rustchat_match";
+
+/// A piece of an indexed file with its embedding.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    pub source: String,
+    /// First line of the chunk in its file, from 1
+    pub line: usize,
+    pub text: String,
+    pub embedding: Vec<f32>,
+}
+
+/// A chunk returned by `search`, with its similarity to the query.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Hit {
+    pub score: f32,
+    pub source: String,
+    pub line: usize,
+    pub text: String,
+}
+
+/// Where the embedded chunks of an index live. The list of indexes and the
+/// hash of every indexed file stay in the local `IndexDb` whatever the store.
+#[async_trait]
+pub trait VectorStore: Send + Sync {
+    async fn insert(&self, index: &str, chunks: &[Chunk]) -> Result<()>;
+    /// Drops every chunk of `source` from `index`.
+    async fn remove_source(&self, index: &str, source: &str) -> Result<()>;
+    /// The `top_k` chunks closest to `query` by cosine similarity, best first.
+    async fn search(&self, index: &str, query: &[f32], top_k: usize) -> Result<Vec<Hit>>;
+    /// Where the chunks of `index` are kept, for status messages.
+    fn describe(&self, index: &str) -> String;
+}
+
+pub type DynVectorStore = Arc<dyn VectorStore>;
+
+/// An index as recorded in the database.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexInfo {
+    pub name: String,
+    /// Provider and model the chunks were embedded with; questions must use
+    /// the same ones
+    pub provider: String,
+    pub model: String,
+    pub store: VectorStoreKind,
+    pub files: usize,
+    pub chunks: usize,
+}
+
+pub fn database_path(cfg: &KnowledgeConfig) -> Result<PathBuf> {
+    cfg.database
+        .clone()
+        .or_else(|| Some(data_dir()?.join(DATABASE_FILE)))
+        .ok_or_else(|| anyhow!("no location for the index database - set knowledge.database"))
+}
+
+/// The directory of JSON index files from before the database.
+pub fn legacy_dir() -> Option<PathBuf> {
+    Some(data_dir()?.join(LEGACY_DIR))
+}
+
+fn data_dir() -> Option<PathBuf> {
+    let base = dirs::data_local_dir().or_else(dirs::config_dir)?;
+    Some(base.join(APP_DIR))
+}
+
+/// Opens the backend holding the chunks of an index kept in `kind`.
+pub fn open_store(
+    cfg: &KnowledgeConfig,
+    kind: VectorStoreKind,
+    db: &Arc<IndexDb>,
+) -> Result<DynVectorStore> {
+    if kind == VectorStoreKind::Sqlite {
+        return Ok(db.clone());
+    }
+    let url = cfg
+        .url
+        .as_deref()
+        .ok_or_else(|| anyhow!("the {} store needs knowledge.url", kind.as_str()))?
+        .trim_end_matches('/')
+        .to_string();
+    let api_key = match &cfg.api_key_env {
+        Some(env) => Some(
+            std::env::var(env)
+                .with_context(|| format!("knowledge.api_key_env names {env}, which is not set"))?,
+        ),
+        None => None,
+    };
+    let http = Http {
+        client: Client::new(),
+        url,
+        api_key,
+    };
+    Ok(match kind {
+        VectorStoreKind::Qdrant => Arc::new(Qdrant(http)),
+        _ => Arc::new(Pgvector(http)),
+    })
+}
+
+/// The local index database: every index, the files in it, and the chunks of
+/// indexes kept in the `sqlite` store.
+pub struct IndexDb {
+    conn: Mutex<Connection>,
+    path: PathBuf,
+}
+
+impl IndexDb {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(path)
+            .with_context(|| format!("failed to open index database {}", path.display()))?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS indexes (
+                 name TEXT PRIMARY KEY,
+                 provider TEXT NOT NULL,
+                 model TEXT NOT NULL,
+                 store TEXT NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS sources (
+                 index_name TEXT NOT NULL,
+                 path TEXT NOT NULL,
+                 hash TEXT NOT NULL,
+                 chunks INTEGER NOT NULL,
+                 PRIMARY KEY (index_name, path)
+             );
+             CREATE TABLE IF NOT EXISTS chunks (
+                 id INTEGER PRIMARY KEY,
+                 index_name TEXT NOT NULL,
+                 source TEXT NOT NULL,
+                 line INTEGER NOT NULL,
+                 text TEXT NOT NULL,
+                 embedding BLOB NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS chunks_by_source ON chunks (index_name, source);",
+        )?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn index(&self, name: &str) -> Result<Option<IndexInfo>> {
+        Ok(self
+            .query_indexes("WHERE i.name = ?1", params![name])?
+            .pop())
+    }
+
+    pub fn list(&self) -> Result<Vec<IndexInfo>> {
+        self.query_indexes("", params![])
+    }
+
+    fn query_indexes(&self, filter: &str, args: impl rusqlite::Params) -> Result<Vec<IndexInfo>> {
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT i.name, i.provider, i.model, i.store,
+                    COUNT(s.path), COALESCE(SUM(s.chunks), 0)
+             FROM indexes i LEFT JOIN sources s ON s.index_name = i.name
+             {filter} GROUP BY i.name ORDER BY i.name"
+        ))?;
+        let rows = stmt.query_map(args, |row| {
+            Ok((
+                IndexInfo {
+                    name: row.get(0)?,
+                    provider: row.get(1)?,
+                    model: row.get(2)?,
+                    store: VectorStoreKind::Sqlite,
+                    files: row.get::<_, i64>(4)? as usize,
+                    chunks: row.get::<_, i64>(5)? as usize,
+                },
+                row.get::<_, String>(3)?,
+            ))
+        })?;
+        rows.map(|row| {
+            let (mut info, store) = row?;
+            info.store = VectorStoreKind::parse(&store)
+                .ok_or_else(|| anyhow!("index '{}' has unknown store '{store}'", info.name))?;
+            Ok(info)
+        })
+        .collect()
+    }
+
+    pub fn create(&self, info: &IndexInfo) -> Result<()> {
+        self.conn.lock().execute(
+            "INSERT INTO indexes (name, provider, model, store) VALUES (?1, ?2, ?3, ?4)",
+            params![info.name, info.provider, info.model, info.store.as_str()],
+        )?;
+        Ok(())
+    }
+
+    /// The SHA-256 of every file in `index`, by path.
+    pub fn sources(&self, index: &str) -> Result<BTreeMap<String, String>> {
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare("SELECT path, hash FROM sources WHERE index_name = ?1")?;
+        let rows = stmt.query_map(params![index], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Records that `path` is indexed at `hash` with `chunks` chunks.
+    pub fn set_source(&self, index: &str, path: &str, hash: &str, chunks: usize) -> Result<()> {
+        self.conn.lock().execute(
+            "INSERT OR REPLACE INTO sources (index_name, path, hash, chunks)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![index, path, hash, chunks as i64],
+        )?;
+        Ok(())
+    }
+
+    /// Moves in the indexes earlier versions kept as JSON files in `dir`,
+    /// renaming each file to `*.json.migrated` once it is in.
+    pub fn migrate_json(&self, dir: &Path) -> Result<()> {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return Ok(());
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Some(name) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_suffix(".json"))
+                .map(str::to_string)
+            else {
+                continue;
+            };
+            if self.index(&name)?.is_some() {
+                continue;
+            }
+            let data = std::fs::read(&path)?;
+            let legacy: LegacyIndex = serde_json::from_slice(&data)
+                .with_context(|| format!("failed to parse index {}", path.display()))?;
+            self.import_legacy(&name, legacy)?;
+            std::fs::rename(&path, path.with_extension("json.migrated"))?;
+            eprintln!("[index] moved index '{name}' into {}", self.path.display());
+        }
+        Ok(())
+    }
+
+    fn import_legacy(&self, name: &str, legacy: LegacyIndex) -> Result<()> {
+        let mut conn = self.conn.lock();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO indexes (name, provider, model, store) VALUES (?1, ?2, ?3, 'sqlite')",
+            params![name, legacy.provider, legacy.model],
+        )?;
+        for (path, hash) in &legacy.sources {
+            let chunks = legacy
+                .chunks
+                .iter()
+                .filter(|chunk| &chunk.source == path)
+                .count();
+            tx.execute(
+                "INSERT INTO sources (index_name, path, hash, chunks) VALUES (?1, ?2, ?3, ?4)",
+                params![name, path, hash, chunks as i64],
+            )?;
+        }
+        for chunk in &legacy.chunks {
+            insert_chunk(
+                &tx,
+                name,
+                &chunk.source,
+                chunk.line,
+                &chunk.text,
+                &chunk.embedding,
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+/// An index file written before the database existed.
+#[derive(Deserialize)]
+struct LegacyIndex {
+    provider: String,
+    model: String,
+    #[serde(default)]
+    sources: BTreeMap<String, String>,
+    #[serde(default)]
+    chunks: Vec<LegacyChunk>,
+}
+
+#[derive(Deserialize)]
+struct LegacyChunk {
+    source: String,
+    line: usize,
+    text: String,
+    embedding: Vec<f32>,
+}
+
+fn insert_chunk(
+    conn: &Connection,
+    index: &str,
+    source: &str,
+    line: usize,
+    text: &str,
+    embedding: &[f32],
+) -> Result<()> {
+    let blob: Vec<u8> = embedding.iter().flat_map(|x| x.to_le_bytes()).collect();
+    conn.execute(
+        "INSERT INTO chunks (index_name, source, line, text, embedding)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![index, source, line as i64, text, blob],
+    )?;
+    Ok(())
+}
+
+fn read_embedding(blob: &[u8]) -> Vec<f32> {
+    blob.chunks_exact(4)
+        .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect()
+}
+
+pub fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let (mut dot, mut norm_a, mut norm_b) = (0.0, 0.0, 0.0);
+    for (x, y) in a.iter().zip(b) {
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
+    }
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a.sqrt() * norm_b.sqrt())
+}
+
+#[async_trait]
+impl VectorStore for IndexDb {
+    async fn insert(&self, index: &str, chunks: &[Chunk]) -> Result<()> {
+        let mut conn = self.conn.lock();
+        let tx = conn.transaction()?;
+        for chunk in chunks {
+            insert_chunk(
+                &tx,
+                index,
+                &chunk.source,
+                chunk.line,
+                &chunk.text,
+                &chunk.embedding,
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn remove_source(&self, index: &str, source: &str) -> Result<()> {
+        self.conn.lock().execute(
+            "DELETE FROM chunks WHERE index_name = ?1 AND source = ?2",
+            params![index, source],
+        )?;
+        Ok(())
+    }
+
+    /// Scores every chunk of the index, keeping only ids in memory until the
+    /// best ones are known.
+    async fn search(&self, index: &str, query: &[f32], top_k: usize) -> Result<Vec<Hit>> {
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare("SELECT id, embedding FROM chunks WHERE index_name = ?1")?;
+        let mut rows = stmt.query(params![index])?;
+        let mut scored: Vec<(f32, i64)> = Vec::new();
+        while let Some(row) = rows.next()? {
+            let embedding = read_embedding(row.get_ref(1)?.as_blob()?);
+            scored.push((cosine(query, &embedding), row.get(0)?));
+        }
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored.truncate(top_k);
+        let mut stmt = conn.prepare("SELECT source, line, text FROM chunks WHERE id = ?1")?;
+        scored
+            .into_iter()
+            .map(|(score, id)| {
+                stmt.query_row(params![id], |row| {
+                    Ok(Hit {
+                        score,
+                        source: row.get(0)?,
+                        line: row.get::<_, i64>(1)? as usize,
+                        text: row.get(2)?,
+                    })
+                })
+                .optional()?
+                .ok_or_else(|| anyhow!("chunk {id} vanished during the search"))
+            })
+            .collect()
+    }
+
+    fn describe(&self, _index: &str) -> String {
+        self.path.display().to_string()
+    }
+}
+
+/// An HTTP endpoint of a remote store.
+struct Http {
+    client: Client,
+    url: String,
+    api_key: Option<String>,
+}
+
+impl Http {
+    /// Sends `request`; `None` means the server answered 404.
+    async fn send(&self, request: RequestBuilder, what: &str) -> Result<Option<Value>> {
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("{what} at {} failed", self.url))?;
+        let status = response.status();
+        if status == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let body = response.text().await?;
+        if !status.is_success() {
+            bail!("{what} at {} failed ({status}): {body}", self.url);
+        }
+        if body.trim().is_empty() {
+            return Ok(Some(Value::Null));
+        }
+        serde_json::from_str(&body)
+            .map(Some)
+            .with_context(|| format!("{what} at {} returned invalid JSON", self.url))
+    }
+}
+
+/// Qdrant over its REST API, one collection per index.
+struct Qdrant(Http);
+
+impl Qdrant {
+    fn request(&self, method: Method, index: &str, path: &str) -> RequestBuilder {
+        let url = format!("{}/collections/rustchat-{index}{path}", self.0.url);
+        let request = self.0.client.request(method, url);
+        match &self.0.api_key {
+            Some(key) => request.header("api-key", key),
+            None => request,
+        }
+    }
+
+    /// Point ids must be numbers or UUIDs; one derived from the chunk's place
+    /// makes re-inserting a chunk replace it.
+    fn point_id(source: &str, line: usize) -> u64 {
+        let hash = digest::digest(&digest::SHA256, format!("{source}\0{line}").as_bytes());
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&hash.as_ref()[..8]);
+        u64::from_le_bytes(bytes)
+    }
+}
+
+#[derive(Deserialize)]
+struct QdrantHit {
+    score: f32,
+    payload: QdrantPayload,
+}
+
+#[derive(Deserialize)]
+struct QdrantPayload {
+    source: String,
+    line: usize,
+    text: String,
+}
+
+#[async_trait]
+impl VectorStore for Qdrant {
+    async fn insert(&self, index: &str, chunks: &[Chunk]) -> Result<()> {
+        let Some(first) = chunks.first() else {
+            return Ok(());
+        };
+        let exists = self
+            .0
+            .send(self.request(Method::GET, index, ""), "Qdrant lookup")
+            .await?;
+        if exists.is_none() {
+            let body = json!({"vectors": {"size": first.embedding.len(), "distance": "Cosine"}});
+            self.0
+                .send(
+                    self.request(Method::PUT, index, "").json(&body),
+                    "Qdrant collection create",
+                )
+                .await?;
+        }
+        let points: Vec<Value> = chunks
+            .iter()
+            .map(|chunk| {
+                json!({
+                    "id": Self::point_id(&chunk.source, chunk.line),
+                    "vector": chunk.embedding,
+                    "payload": {"source": chunk.source, "line": chunk.line, "text": chunk.text},
+                })
+            })
+            .collect();
+        self.0
+            .send(
+                self.request(Method::PUT, index, "/points?wait=true")
+                    .json(&json!({"points": points})),
+                "Qdrant upsert",
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn remove_source(&self, index: &str, source: &str) -> Result<()> {
+        let filter = json!({"filter": {"must": [{"key": "source", "match": {"value": source}}]}});
+        self.0
+            .send(
+                self.request(Method::POST, index, "/points/delete?wait=true")
+                    .json(&filter),
+                "Qdrant delete",
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn search(&self, index: &str, query: &[f32], top_k: usize) -> Result<Vec<Hit>> {
+        let body = json!({"vector": query, "limit": top_k, "with_payload": true});
+        let Some(reply) = self
+            .0
+            .send(
+                self.request(Method::POST, index, "/points/search")
+                    .json(&body),
+                "Qdrant search",
+            )
+            .await?
+        else {
+            return Ok(Vec::new());
+        };
+        let hits: Vec<QdrantHit> = serde_json::from_value(reply["result"].clone())
+            .context("Qdrant search returned unexpected results")?;
+        Ok(hits
+            .into_iter()
+            .map(|hit| Hit {
+                score: hit.score,
+                source: hit.payload.source,
+                line: hit.payload.line,
+                text: hit.payload.text,
+            })
+            .collect())
+    }
+
+    fn describe(&self, index: &str) -> String {
+        format!("{}/collections/rustchat-{index}", self.0.url)
+    }
+}
+
+/// pgvector behind PostgREST: rows in `rustchat_chunks`, searched by the
+/// `rustchat_match` function (see the README for the SQL).
+struct Pgvector(Http);
+
+impl Pgvector {
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let request = self
+            .0
+            .client
+            .request(method, format!("{}/{path}", self.0.url));
+        match &self.0.api_key {
+            Some(key) => request.bearer_auth(key),
+            None => request,
+        }
+    }
+
+    fn missing(what: &str) -> anyhow::Error {
+        anyhow!("{what} not found; create the {PG_TABLE} table and {PG_MATCH} function first")
+    }
+}
+
+#[async_trait]
+impl VectorStore for Pgvector {
+    async fn insert(&self, index: &str, chunks: &[Chunk]) -> Result<()> {
+        if chunks.is_empty() {
+            return Ok(());
+        }
+        let rows: Vec<Value> = chunks
+            .iter()
+            .map(|chunk| {
+                json!({
+                    "index_name": index,
+                    "source": chunk.source,
+                    "line": chunk.line,
+                    "text": chunk.text,
+                    "embedding": chunk.embedding,
+                })
+            })
+            .collect();
+        self.0
+            .send(
+                self.request(Method::POST, PG_TABLE)
+                    .header("Prefer", "return=minimal")
+                    .json(&rows),
+                "pgvector insert",
+            )
+            .await?
+            .ok_or_else(|| Self::missing(PG_TABLE))?;
+        Ok(())
+    }
+
+    async fn remove_source(&self, index: &str, source: &str) -> Result<()> {
+        let filter = [
+            ("index_name", format!("eq.{index}")),
+            ("source", format!("eq.{source}")),
+        ];
+        self.0
+            .send(
+                self.request(Method::DELETE, PG_TABLE).query(&filter),
+                "pgvector delete",
+            )
+            .await?
+            .ok_or_else(|| Self::missing(PG_TABLE))?;
+        Ok(())
+    }
+
+    async fn search(&self, index: &str, query: &[f32], top_k: usize) -> Result<Vec<Hit>> {
+        let body = json!({"index_name": index, "query_embedding": query, "match_count": top_k});
+        let reply = self
+            .0
+            .send(
+                self.request(Method::POST, &format!("rpc/{PG_MATCH}"))
+                    .json(&body),
+                "pgvector search",
+            )
+            .await?
+            .ok_or_else(|| Self::missing(PG_MATCH))?;
+        serde_json::from_value(reply).context("pgvector search returned unexpected rows")
+    }
+
+    fn describe(&self, _index: &str) -> String {
+        format!("{}/{PG_TABLE}", self.0.url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn sqlite_store_replaces_sources_and_ranks_chunks() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("indexes").join("indexes.db");
+        let db = IndexDb::open(&path).unwrap();
+        db.create(&IndexInfo {
+            name: "docs".to_string(),
+            provider: "openai".to_string(),
+            model: "text-embedding-3-small".to_string(),
+            store: VectorStoreKind::Sqlite,
+            files: 0,
+            chunks: 0,
+        })
+        .unwrap();
+        let chunk = |source: &str, embedding: Vec<f32>| Chunk {
+            source: source.to_string(),
+            line: 1,
+            text: format!("{source} text"),
+            embedding,
+        };
+        db.insert(
+            "docs",
+            &[
+                chunk("far", vec![0.0, 1.0]),
+                chunk("near", vec![1.0, 0.1]),
+                chunk("middle", vec![1.0, 1.0]),
+            ],
+        )
+        .await
+        .unwrap();
+        for source in ["far", "near", "middle"] {
+            db.set_source("docs", source, "hash", 1).unwrap();
+        }
+
+        let hits = db.search("docs", &[1.0, 0.0], 2).await.unwrap();
+        let sources: Vec<&str> = hits.iter().map(|hit| hit.source.as_str()).collect();
+        assert_eq!(sources, ["near", "middle"]);
+        assert_eq!(hits[0].text, "near text");
+
+        db.remove_source("docs", "near").await.unwrap();
+        let hits = db.search("docs", &[1.0, 0.0], 1).await.unwrap();
+        assert_eq!(hits[0].source, "middle");
+        assert!(db.search("other", &[1.0, 0.0], 1).await.unwrap().is_empty());
+
+        let info = db.index("docs").unwrap().unwrap();
+        assert_eq!((info.files, info.chunks), (3, 3));
+        assert_eq!(db.sources("docs").unwrap()["far"], "hash");
+    }
+}
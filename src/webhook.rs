@@ -0,0 +1,673 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
+use minijinja::{context, Environment, UndefinedBehavior};
+use reqwest::Client;
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+
+use crate::cli::CommonChatArgs;
+use crate::config::{AppConfig, WebhookConfig, WebhookEvent, APP_DIR};
+use crate::exit::{self, ExitStatus};
+use crate::logger::{self, HistoryFormat, SessionMeta};
+use crate::provider::{ChatMessage, TokenUsage};
+use crate::retry::{self, RetryPolicy};
+use crate::trace::ResponseExt;
+use crate::{output, shutdown, trace, utils};
+
+const DEFAULT_API_KEY_HEADER: &str = "X-API-Key";
+const DEFAULT_SIGNATURE_HEADER: &str = "X-Rustchat-Signature";
+const DEFAULT_RETRIES: u32 = 3;
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+const QUEUE_DIR: &str = "webhook-queue";
+const DEFAULT_TEMPLATE_CONTENT_TYPE: &str = "application/json";
+
+/// Posts finished transcripts to `--webhook-url`, authenticated and signed
+/// as `[webhook]` says. Posts that keep failing are queued on disk and sent
+/// again by the next run that has a webhook.
+#[derive(Clone)]
+pub struct Webhook {
+    url: String,
+    bearer_token: Option<String>,
+    api_key: Option<(String, String)>,
+    signing: Option<(String, hmac::Key)>,
+    /// `[webhook] template` source and the content type it produces
+    template: Option<(String, String)>,
+    /// Body format in place of `--save-format`
+    format: Option<HistoryFormat>,
+    events: Vec<WebhookEvent>,
+    retry: RetryPolicy,
+    queue: Option<PathBuf>,
+    client: Client,
+}
+
+/// A transcript waiting in the queue for its webhook to come back.
+#[derive(Debug, Serialize, Deserialize)]
+struct Queued {
+    url: String,
+    #[serde(default)]
+    event: WebhookEvent,
+    content_type: String,
+    body: String,
+    queued_at: DateTime<Utc>,
+}
+
+impl Webhook {
+    /// Reads the configured secrets, failing when a named variable is unset.
+    pub fn new(url: String, cfg: &WebhookConfig) -> Result<Self> {
+        let api_key = secret(&cfg.api_key, &cfg.api_key_env, "api_key")?.map(|key| {
+            let header = cfg.api_key_header.as_deref();
+            (header.unwrap_or(DEFAULT_API_KEY_HEADER).to_string(), key)
+        });
+        let signing = secret(
+            &cfg.signing_secret,
+            &cfg.signing_secret_env,
+            "signing_secret",
+        )?
+        .map(|key| {
+            let header = cfg.signature_header.as_deref();
+            (
+                header.unwrap_or(DEFAULT_SIGNATURE_HEADER).to_string(),
+                hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes()),
+            )
+        });
+        let template = match &cfg.template {
+            Some(path) => {
+                let source = fs::read_to_string(path)
+                    .with_context(|| format!("failed to read {}", path.display()))?;
+                environment()
+                    .template_from_str(&source)
+                    .with_context(|| format!("invalid webhook template {}", path.display()))?;
+                let content_type = cfg.content_type.as_deref();
+                Some((
+                    source,
+                    content_type
+                        .unwrap_or(DEFAULT_TEMPLATE_CONTENT_TYPE)
+                        .to_string(),
+                ))
+            }
+            None => None,
+        };
+        Ok(Self {
+            url,
+            bearer_token: secret(&cfg.bearer_token, &cfg.bearer_token_env, "bearer_token")?,
+            api_key,
+            signing,
+            template,
+            format: None,
+            events: vec![WebhookEvent::Session],
+            retry: RetryPolicy {
+                retries: cfg.retries.unwrap_or(DEFAULT_RETRIES),
+                backoff: RETRY_BACKOFF,
+            },
+            queue: cfg.queue_dir.clone().or_else(|| {
+                let base = dirs::data_local_dir().or_else(dirs::config_dir)?;
+                Some(base.join(APP_DIR).join(QUEUE_DIR))
+            }),
+            client: Client::new(),
+        })
+    }
+
+    /// Posts only `events`; an empty list keeps the default of `session`.
+    pub fn with_events(mut self, events: &[WebhookEvent]) -> Self {
+        if !events.is_empty() {
+            self.events = events.to_vec();
+        }
+        self
+    }
+
+    pub fn with_format(mut self, format: Option<HistoryFormat>) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn posts(&self, event: WebhookEvent) -> bool {
+        self.events.contains(&event)
+    }
+
+    /// The body of a post and its content type: the template rendered over
+    /// the session, or without one the transcript in `format`.
+    fn body(
+        &self,
+        event: WebhookEvent,
+        format: HistoryFormat,
+        meta: &SessionMeta,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+    ) -> Result<(String, String)> {
+        let format = self.format.unwrap_or(format);
+        let transcript = logger::webhook_payload(format, system, messages)?;
+        let Some((source, content_type)) = &self.template else {
+            return Ok((transcript, format.content_type().to_string()));
+        };
+        let usage = messages
+            .iter()
+            .filter_map(|message| message.reply.as_ref()?.usage)
+            .reduce(|total, usage| TokenUsage {
+                input_tokens: total.input_tokens + usage.input_tokens,
+                output_tokens: total.output_tokens + usage.output_tokens,
+            });
+        let body = environment()
+            .render_str(
+                source,
+                context! {
+                    event => event.as_str(),
+                    provider => meta.provider,
+                    model => meta.model,
+                    system => system,
+                    messages => logger::messages_to_json(None, messages),
+                    usage => usage,
+                    session => context! {
+                        title => meta.title,
+                        tags => meta.tags,
+                        created_at => meta.created_at.map(|at| at.to_rfc3339()),
+                    },
+                    transcript => transcript,
+                },
+            )
+            .context("failed to render the webhook template")?;
+        Ok((body, content_type.clone()))
+    }
+
+    /// Posts `body`, retrying rate limits, network failures and server errors
+    /// with backoff until shutdown is requested.
+    pub async fn send(&self, event: WebhookEvent, content_type: &str, body: &str) -> Result<()> {
+        let cancel = shutdown::token();
+        let (result, _) = self
+            .retry
+            .run(&cancel, || self.post(event, content_type, body))
+            .await;
+        result
+    }
+
+    async fn post(&self, event: WebhookEvent, content_type: &str, body: &str) -> Result<()> {
+        let url = &self.url;
+        let mut request = self
+            .client
+            .post(url)
+            .header("content-type", content_type)
+            .header("x-rustchat-event", event.as_str());
+        if let Some(token) = &self.bearer_token {
+            request = request.bearer_auth(token);
+        }
+        if let Some((header, key)) = &self.api_key {
+            request = request.header(header, key);
+        }
+        if let Some((header, key)) = &self.signing {
+            request = request.header(header, signature(key, body.as_bytes()));
+        }
+        trace::send(request.body(body.to_string()))
+            .await
+            .with_context(|| format!("failed to reach webhook {url}"))?
+            .check_status()
+            .with_context(|| format!("webhook {url} returned error status"))?;
+        Ok(())
+    }
+
+    /// Keeps a post that could not be delivered for the next run. Posts hold
+    /// whole transcripts, so the queue is readable by its owner alone.
+    fn enqueue(&self, event: WebhookEvent, content_type: &str, body: &str) -> Result<PathBuf> {
+        let dir = self
+            .queue
+            .as_deref()
+            .ok_or_else(|| anyhow!("no data directory for the webhook queue"))?;
+        let mut builder = fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder
+            .create(dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+        let queued_at = Utc::now();
+        let path = dir.join(format!(
+            "{}-{}.json",
+            queued_at.format("%Y%m%dT%H%M%S%.6f"),
+            std::process::id()
+        ));
+        let entry = Queued {
+            url: self.url.clone(),
+            event,
+            content_type: content_type.to_string(),
+            body: body.to_string(),
+            queued_at,
+        };
+        utils::write_private(&path, &serde_json::to_vec(&entry)?)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        Ok(path)
+    }
+
+    /// Sends what earlier runs queued for this webhook's URL, oldest first,
+    /// once each. Entries for other targets are left for a run that has their
+    /// credentials. Stops at the first post that fails in a way a later run
+    /// might get past; entries the receiver rejects outright are dropped.
+    async fn redeliver(&self) {
+        let Some(dir) = self.queue.as_deref() else {
+            return;
+        };
+        let mut sent = 0;
+        for path in queued(dir) {
+            let entry: Queued = match fs::read(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|bytes| Ok(serde_json::from_slice(&bytes)?))
+            {
+                Ok(entry) => entry,
+                Err(err) => {
+                    tracing::warn!(
+                        "dropping unreadable queued webhook {}: {err:#}",
+                        path.display()
+                    );
+                    let _ = fs::remove_file(&path);
+                    continue;
+                }
+            };
+            if entry.url != self.url {
+                continue;
+            }
+            match self
+                .post(entry.event, &entry.content_type, &entry.body)
+                .await
+            {
+                Ok(()) => sent += 1,
+                Err(err) if should_queue(&err) => {
+                    tracing::debug!("queued webhooks still undeliverable: {err:#}");
+                    break;
+                }
+                Err(err) => tracing::warn!(
+                    "dropping chat history queued at {}: {err:#}",
+                    entry.queued_at.to_rfc3339()
+                ),
+            }
+            let _ = fs::remove_file(&path);
+        }
+        if sent > 0 {
+            output::status(&format!("[pushed {sent} queued chat histories to webhook]"));
+        }
+    }
+}
+
+/// The webhook the flags and profile ask for: `--webhook-url` with the
+/// `[webhook]` settings, or a `[webhooks]` target named by `--webhook`.
+pub fn resolve(args: &CommonChatArgs, cfg: &AppConfig) -> Result<Option<Webhook>> {
+    let (url, target) = match (&args.webhook_url, &args.webhook) {
+        (Some(url), _) => (url.clone(), None),
+        (None, Some(name)) => {
+            let target = cfg
+                .webhooks
+                .get(name)
+                .ok_or_else(|| anyhow!("webhook '{name}' not found in config"))?;
+            (target.url.clone(), Some(target))
+        }
+        (None, None) => {
+            if args.webhook_per_turn || args.webhook_template.is_some() {
+                bail!("--webhook-per-turn and --webhook-template need --webhook-url or --webhook");
+            }
+            return Ok(None);
+        }
+    };
+    let mut settings = match target {
+        Some(target) => target.settings.with_defaults(&cfg.webhook),
+        None => cfg.webhook.clone(),
+    };
+    if let Some(template) = &args.webhook_template {
+        settings.template = Some(template.clone());
+    }
+    let mut events = target.map_or_else(Vec::new, |target| target.events.clone());
+    if args.webhook_per_turn && !events.contains(&WebhookEvent::Turn) {
+        if events.is_empty() {
+            events.push(WebhookEvent::Session);
+        }
+        events.push(WebhookEvent::Turn);
+    }
+    let context = match (&args.webhook, target) {
+        (Some(name), Some(_)) => format!("invalid [webhooks.{name}] settings"),
+        _ => "invalid [webhook] settings".to_string(),
+    };
+    let webhook = Webhook::new(url, &settings)
+        .context(context)?
+        .with_events(&events)
+        .with_format(target.and_then(|target| target.format));
+    Ok(Some(webhook))
+}
+
+/// Posts the transcript if there is a webhook, after anything earlier runs
+/// left queued. Failures are reported rather than failing the command; the
+/// ones worth retrying are queued.
+pub async fn post_finished(
+    webhook: Option<&Webhook>,
+    format: HistoryFormat,
+    meta: &SessionMeta,
+    system: Option<&str>,
+    messages: &[ChatMessage],
+) {
+    let Some(webhook) = webhook else {
+        return;
+    };
+    webhook.redeliver().await;
+    if !webhook.posts(WebhookEvent::Session) {
+        return;
+    }
+    if deliver(
+        webhook,
+        WebhookEvent::Session,
+        format,
+        meta,
+        system,
+        messages,
+    )
+    .await
+    {
+        output::status("[pushed chat history to webhook]");
+    }
+}
+
+/// Posts one finished exchange in the background so the next prompt is not
+/// held up; await the handle before exiting.
+pub fn post_turn(
+    webhook: &Webhook,
+    format: HistoryFormat,
+    meta: SessionMeta,
+    system: Option<String>,
+    exchange: Vec<ChatMessage>,
+) -> JoinHandle<()> {
+    let webhook = webhook.clone();
+    tokio::spawn(async move {
+        let system = system.as_deref();
+        if deliver(
+            &webhook,
+            WebhookEvent::Turn,
+            format,
+            &meta,
+            system,
+            &exchange,
+        )
+        .await
+        {
+            tracing::debug!(
+                "posted a turn of {} messages to the webhook",
+                exchange.len()
+            );
+        }
+    })
+}
+
+/// Sends a post, queuing it when the failure may clear up. Returns whether
+/// it went through.
+async fn deliver(
+    webhook: &Webhook,
+    event: WebhookEvent,
+    format: HistoryFormat,
+    meta: &SessionMeta,
+    system: Option<&str>,
+    messages: &[ChatMessage],
+) -> bool {
+    let (body, content_type) = match webhook.body(event, format, meta, system, messages) {
+        Ok(body) => body,
+        Err(err) => {
+            tracing::warn!("failed to render chat history for the webhook: {err:#}");
+            return false;
+        }
+    };
+    match webhook.send(event, &content_type, &body).await {
+        Ok(()) => return true,
+        Err(err) if should_queue(&err) => match webhook.enqueue(event, &content_type, &body) {
+            Ok(path) => tracing::warn!(
+                "failed to POST chat history: {err:#}; queued as {} for the next run",
+                path.display()
+            ),
+            Err(queue_err) => {
+                tracing::warn!("failed to POST chat history: {err:#}; not queued: {queue_err:#}")
+            }
+        },
+        Err(err) => tracing::warn!("failed to POST chat history: {err:#}"),
+    }
+    false
+}
+
+/// Templates fail on undefined names so a typo is reported instead of
+/// posting an empty field.
+fn environment() -> Environment<'static> {
+    let mut env = Environment::new();
+    env.set_undefined_behavior(UndefinedBehavior::Strict);
+    env
+}
+
+/// Failures a later run may get past: the ones worth retrying, and posts cut
+/// short by shutdown.
+fn should_queue(err: &anyhow::Error) -> bool {
+    retry::is_transient(err) || exit::status_for(err) == ExitStatus::Canceled
+}
+
+/// Queue entries, oldest first.
+fn queued(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+    paths
+}
+
+fn secret(inline: &Option<String>, env: &Option<String>, field: &str) -> Result<Option<String>> {
+    if let Some(value) = inline {
+        return Ok(Some(value.clone()));
+    }
+    env.as_deref()
+        .map(|env| {
+            std::env::var(env)
+                .map_err(|_| anyhow!("webhook.{field}_env names {env}, which is not set"))
+        })
+        .transpose()
+}
+
+/// `sha256=<hex>` of the HMAC over `body`, as GitHub-style receivers expect.
+fn signature(key: &hmac::Key, body: &[u8]) -> String {
+    let tag = hmac::sign(key, body);
+    tag.as_ref()
+        .iter()
+        .fold(String::from("sha256="), |mut out, byte| {
+            let _ = write!(out, "{byte:02x}");
+            out
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::ReplyMeta;
+
+    #[test]
+    fn signs_the_body_with_hmac_sha256() {
+        let cfg = WebhookConfig {
+            signing_secret: Some("It's a Secret to Everybody".to_string()),
+            ..WebhookConfig::default()
+        };
+        let webhook = Webhook::new("https://hooks.example.com".to_string(), &cfg).unwrap();
+        let (header, key) = webhook.signing.as_ref().unwrap();
+        assert_eq!(header, DEFAULT_SIGNATURE_HEADER);
+        // The example from GitHub's webhook documentation.
+        assert_eq!(
+            signature(key, b"Hello, World!"),
+            "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17"
+        );
+
+        let cfg = WebhookConfig {
+            bearer_token_env: Some("RUSTCHAT_TEST_UNSET_WEBHOOK_TOKEN".to_string()),
+            ..WebhookConfig::default()
+        };
+        assert!(Webhook::new(String::new(), &cfg).is_err());
+    }
+
+    #[test]
+    fn profiles_pick_a_configured_target() {
+        use crate::cli::{Cli, Commands};
+        use clap::Parser;
+
+        let cfg: AppConfig = toml::from_str(
+            r#"
+            [webhook]
+            signing_secret = "shared"
+
+            [webhooks.ops]
+            url = "https://hooks.example.com/ops"
+            format = "markdown"
+            events = ["turn"]
+            bearer_token = "abc"
+
+            [profiles.oncall]
+            webhook = "ops"
+            "#,
+        )
+        .unwrap();
+        let cli =
+            Cli::try_parse_from(["rustchat-cli", "message", "--profile", "oncall", "hi"]).unwrap();
+        let Commands::Message(mut args) = cli.command else {
+            panic!("expected message command");
+        };
+        crate::config::apply_overrides(&cfg, &mut args.common).unwrap();
+        let webhook = resolve(&args.common, &cfg).unwrap().unwrap();
+        assert_eq!(webhook.url, "https://hooks.example.com/ops");
+        assert!(webhook.posts(WebhookEvent::Turn) && !webhook.posts(WebhookEvent::Session));
+        assert_eq!(webhook.format, Some(HistoryFormat::Markdown));
+        assert_eq!(webhook.bearer_token.as_deref(), Some("abc"));
+        assert!(webhook.signing.is_some());
+
+        args.common.webhook = Some("missing".to_string());
+        assert!(resolve(&args.common, &cfg).is_err());
+    }
+
+    #[tokio::test]
+    async fn undeliverable_posts_wait_in_the_queue() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("webhook-queue");
+        let cfg = WebhookConfig {
+            retries: Some(0),
+            queue_dir: Some(dir.clone()),
+            ..WebhookConfig::default()
+        };
+        // Nothing listens on the discard port, so every post fails to connect.
+        let webhook = Webhook::new("http://127.0.0.1:9/hook".to_string(), &cfg).unwrap();
+        let messages = [ChatMessage::user("hi")];
+        let meta = SessionMeta::default();
+        post_turn(
+            &webhook,
+            HistoryFormat::Json,
+            meta.clone(),
+            None,
+            messages.to_vec(),
+        )
+        .await
+        .unwrap();
+        post_finished(Some(&webhook), HistoryFormat::Json, &meta, None, &messages).await;
+        let paths = queued(&dir);
+        assert_eq!(paths.len(), 2);
+        let entries: Vec<Queued> = paths
+            .iter()
+            .map(|path| serde_json::from_slice(&fs::read(path).unwrap()).unwrap())
+            .collect();
+        assert_eq!(entries[0].url, "http://127.0.0.1:9/hook");
+        assert_eq!(entries[0].content_type, "application/json");
+        assert_eq!(entries[0].event, WebhookEvent::Turn);
+        assert_eq!(entries[1].event, WebhookEvent::Session);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+            assert_eq!(mode(&dir), 0o700);
+            assert_eq!(mode(&paths[0]), 0o600);
+        }
+    }
+
+    #[tokio::test]
+    async fn queued_posts_go_out_with_their_own_targets_credentials() {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (seen, requests) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = [0u8; 4096];
+                let read = stream.read(&mut request).unwrap_or(0);
+                let _ = seen.send(String::from_utf8_lossy(&request[..read]).to_string());
+                let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+            }
+        });
+        let tmp = tempfile::tempdir().unwrap();
+        let target = |path: &str, token: &str| {
+            let cfg = WebhookConfig {
+                bearer_token: Some(token.to_string()),
+                queue_dir: Some(tmp.path().to_path_buf()),
+                ..WebhookConfig::default()
+            };
+            Webhook::new(format!("http://{addr}/{path}"), &cfg).unwrap()
+        };
+        let (ops, slack) = (target("ops", "ops-token"), target("slack", "slack-token"));
+        ops.enqueue(WebhookEvent::Session, "application/json", "{}")
+            .unwrap();
+
+        slack.redeliver().await;
+        assert_eq!(queued(tmp.path()).len(), 1);
+        assert!(requests.try_recv().is_err());
+
+        ops.redeliver().await;
+        assert!(queued(tmp.path()).is_empty());
+        let request = requests.recv().unwrap();
+        assert!(request.starts_with("POST /ops "));
+        assert!(request.contains("Bearer ops-token"));
+    }
+
+    #[test]
+    fn templates_see_the_session() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("webhook.j2");
+        fs::write(
+            &path,
+            r#"{"text": {{ messages[-1].content | tojson }}, "model": "{{ model }}", "tokens": {{ usage.output_tokens }}, "event": "{{ event }}"}"#,
+        )
+        .unwrap();
+        let cfg = WebhookConfig {
+            template: Some(path.clone()),
+            ..WebhookConfig::default()
+        };
+        let webhook = Webhook::new(String::new(), &cfg).unwrap();
+
+        let reply = ReplyMeta {
+            provider: "openai".to_string(),
+            model: "gpt-4o".to_string(),
+            usage: Some(TokenUsage {
+                input_tokens: 10,
+                output_tokens: 3,
+            }),
+            latency: Duration::ZERO,
+        };
+        let messages = [
+            ChatMessage::user("hi"),
+            ChatMessage::assistant("Hello \"there\"").with_reply(reply),
+        ];
+        let meta = SessionMeta::new("openai", "gpt-4o");
+        let (body, content_type) = webhook
+            .body(
+                WebhookEvent::Turn,
+                HistoryFormat::Json,
+                &meta,
+                None,
+                &messages,
+            )
+            .unwrap();
+        assert_eq!(content_type, "application/json");
+        assert_eq!(
+            body,
+            r#"{"text": "Hello \"there\"", "model": "gpt-4o", "tokens": 3, "event": "turn"}"#
+        );
+    }
+}